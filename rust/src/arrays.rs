@@ -0,0 +1,120 @@
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use crate::canonical::canonicalize;
+use crate::path::{FlowPath, PathError, PathGlob};
+
+// ============================================
+// Array Transforms
+// ============================================
+//
+// Both transforms act on every array whose path matches the glob `at`
+// (a plain path is a glob too). `by` is a path inside each element to
+// compare on, e.g. `name` or `meta.id`; without it whole elements are
+// compared. Values of different types order as null, bool, number, string,
+// array, object; an element missing the `by` path counts as null. Sorting
+// is stable, and deduplication keeps the first of each group of equal
+// elements, where 1 and 1.0 are equal. Deduplicating by a path keeps every
+// element that lacks it: such elements have no key to be duplicates by.
+
+pub fn sort_arrays(doc: &mut Value, at: &str, by: Option<&str>) -> Result<(), PathError> {
+    let by = by.map(FlowPath::parse).transpose()?;
+    for path in array_paths(doc, at)? {
+        if let Some(Value::Array(items)) = path.lookup_mut(doc) {
+            items.sort_by(|a, b| compare(sort_key(a, by.as_ref()), sort_key(b, by.as_ref())));
+        }
+    }
+    Ok(())
+}
+
+// Returns the number of elements removed.
+pub fn dedup_arrays(doc: &mut Value, at: &str, by: Option<&str>) -> Result<usize, PathError> {
+    let by = by.map(FlowPath::parse).transpose()?;
+    let mut removed = 0;
+    for path in array_paths(doc, at)? {
+        if let Some(Value::Array(items)) = path.lookup_mut(doc) {
+            let mut seen = HashSet::new();
+            let before = items.len();
+            items.retain(|item| match &by {
+                Some(by) => by.lookup(item).is_none_or(|key| seen.insert(canonicalize(key))),
+                None => seen.insert(canonicalize(item)),
+            });
+            removed += before - items.len();
+        }
+    }
+    Ok(removed)
+}
+
+// Deepest first, so reordering an outer array never invalidates the path
+// of an inner one still to be processed.
+fn array_paths(doc: &Value, at: &str) -> Result<Vec<FlowPath>, PathError> {
+    let glob = PathGlob::parse(at)?;
+    let mut paths: Vec<FlowPath> = glob.find(doc).into_iter().filter(|(_, v)| v.is_array()).map(|(p, _)| p).collect();
+    paths.sort_by_key(|p| std::cmp::Reverse(p.len()));
+    Ok(paths)
+}
+
+fn sort_key<'a>(item: &'a Value, by: Option<&FlowPath>) -> &'a Value {
+    match by {
+        Some(path) => path.lookup(item).unwrap_or(&Value::Null),
+        None => item,
+    }
+}
+
+fn rank(v: &Value) -> u8 {
+    match v {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        (Value::Number(x), Value::Number(y)) => match (x.as_i64(), y.as_i64()) {
+            (Some(i), Some(j)) => i.cmp(&j),
+            _ => x.as_f64().unwrap_or(0.0).total_cmp(&y.as_f64().unwrap_or(0.0)),
+        },
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        (Value::Array(x), Value::Array(y)) => {
+            x.iter().zip(y).map(|(i, j)| compare(i, j)).find(|o| o.is_ne()).unwrap_or_else(|| x.len().cmp(&y.len()))
+        }
+        (Value::Object(_), Value::Object(_)) => canonicalize(a).cmp(&canonicalize(b)),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn sorting() {
+        let mut doc = json!({ "tags": ["b", 2, null, "a", 1.5, true], "users": [{ "n": "b", "i": 1 }, { "i": 2 }, { "n": "a", "i": 3 }, { "n": "b", "i": 4 }] });
+        sort_arrays(&mut doc, "tags", None).unwrap();
+        assert_eq!(doc["tags"], json!([null, true, 1.5, 2, "a", "b"]));
+        sort_arrays(&mut doc, "users", Some("n")).unwrap();
+        // missing keys sort as null; equal keys keep their order
+        assert_eq!(doc["users"], json!([{ "i": 2 }, { "n": "a", "i": 3 }, { "n": "b", "i": 1 }, { "n": "b", "i": 4 }]));
+    }
+
+    #[test]
+    fn dedup_by_key_keeps_elements_without_it() {
+        let mut doc = json!({ "users": [{ "id": 1, "v": "a" }, { "v": "x" }, { "id": 1.0, "v": "b" }, { "v": "x" }, { "id": null }, { "id": null }] });
+        assert_eq!(dedup_arrays(&mut doc, "users", Some("id")).unwrap(), 2);
+        assert_eq!(doc["users"], json!([{ "id": 1, "v": "a" }, { "v": "x" }, { "v": "x" }, { "id": null }]));
+    }
+
+    #[test]
+    fn dedup_whole_elements_under_a_glob() {
+        let mut doc = json!({ "a": { "xs": [1, 1.0, 2, [1], [1]] }, "b": { "xs": ["x", "x"] } });
+        assert_eq!(dedup_arrays(&mut doc, "*.xs", None).unwrap(), 3);
+        assert_eq!(doc, json!({ "a": { "xs": [1, 2, [1]] }, "b": { "xs": ["x"] } }));
+    }
+}
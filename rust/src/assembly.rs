@@ -0,0 +1,230 @@
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::merge::{Origin, Provenance, TracedFlow};
+use crate::path::{FlowPath, PathError, PathSegment};
+
+// ============================================
+// Fragment Assembly
+// ============================================
+//
+// An Assembly builds one document out of fragments, each mounted under its
+// own prefix: `mount("auth", auth_doc)` puts the fragment at `auth`, and
+// `mount("teams.billing", doc)` creates `teams` on the way. A prefix must be
+// made of keys and must not overlap another mount, neither above nor below
+// it, nor name a node that is already there in the base document. Failed
+// mounts leave the assembly unchanged.
+//
+// A fragment mounted with `mount_traced` keeps its provenance, moved under
+// the prefix, so `get_origin` names the file and line a mounted value came
+// from.
+//
+// `split` goes the other way: every top-level object becomes a fragment
+// mounted at its key. Top-level entries that are not objects belong to no
+// fragment and stay in the document only.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblyError {
+    pub prefix: FlowPath,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot mount at '{}': {}", self.prefix, self.message)
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+impl From<PathError> for AssemblyError {
+    fn from(e: PathError) -> Self {
+        AssemblyError { prefix: FlowPath::root(), message: e.to_string() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assembly {
+    doc: Value,
+    // in mount order
+    mounts: Vec<FlowPath>,
+    provenance: Provenance,
+}
+
+impl Default for Assembly {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Assembly {
+    pub fn new() -> Self {
+        Assembly { doc: Value::Object(Map::new()), mounts: Vec::new(), provenance: Provenance::new() }
+    }
+
+    // Fragments are mounted into `base`, next to what it already holds.
+    pub fn with_base(base: Value) -> Self {
+        Assembly { doc: base, mounts: Vec::new(), provenance: Provenance::new() }
+    }
+
+    pub fn split(doc: &Value) -> Self {
+        let mounts = match doc {
+            Value::Object(map) => map.iter().filter(|(_, v)| v.is_object()).map(|(k, _)| FlowPath::root().child_key(k)).collect(),
+            _ => Vec::new(),
+        };
+        Assembly { doc: doc.clone(), mounts, provenance: Provenance::new() }
+    }
+
+    pub fn mount(&mut self, prefix: &str, fragment: Value) -> Result<(), AssemblyError> {
+        self.mount_at(FlowPath::parse(prefix)?, fragment)
+    }
+
+    pub fn mount_at(&mut self, prefix: FlowPath, fragment: Value) -> Result<(), AssemblyError> {
+        let error = |message: String| AssemblyError { prefix: prefix.clone(), message };
+        if prefix.is_root() {
+            return Err(error("a fragment must be mounted under a key".to_string()));
+        }
+        if prefix.segments().iter().any(|s| matches!(s, PathSegment::Index(_))) {
+            return Err(error("a prefix is made of keys, not array indices".to_string()));
+        }
+        if let Some(other) = self.mounts.iter().find(|m| m.starts_with(&prefix) || prefix.starts_with(m)) {
+            return Err(error(format!("overlaps the fragment mounted at '{}'", other)));
+        }
+        let mut at = FlowPath::root();
+        for segment in prefix.segments() {
+            match at.lookup(&self.doc) {
+                Some(Value::Object(_)) => {}
+                Some(_) => return Err(error(format!("'{}' is not an object", at))),
+                None => break,
+            }
+            if let PathSegment::Key(k) = segment {
+                at.push_key(k);
+            }
+        }
+        if prefix.lookup(&self.doc).is_some() {
+            return Err(error("the document already has a value there".to_string()));
+        }
+        prefix.set(&mut self.doc, fragment)?;
+        self.mounts.push(prefix);
+        Ok(())
+    }
+
+    // Mounts a loaded file, keeping the origin of its values.
+    pub fn mount_traced(&mut self, prefix: &str, fragment: TracedFlow) -> Result<(), AssemblyError> {
+        let prefix = FlowPath::parse(prefix)?;
+        self.mount_at(prefix.clone(), fragment.value)?;
+        self.provenance.graft(&prefix, fragment.provenance);
+        Ok(())
+    }
+
+    // Where the value at `path` came from, for fragments mounted with
+    // `mount_traced`.
+    pub fn get_origin(&self, path: &FlowPath) -> Option<Origin> {
+        self.provenance.get_origin(path)
+    }
+
+    // The fragment mounted at `prefix`, as it is in the document now.
+    pub fn fragment(&self, prefix: &FlowPath) -> Option<&Value> {
+        self.mounts.iter().find(|m| *m == prefix).and_then(|m| m.lookup(&self.doc))
+    }
+
+    // Mounted prefixes with their fragments, in mount order.
+    pub fn fragments(&self) -> impl Iterator<Item = (&FlowPath, &Value)> {
+        self.mounts.iter().filter_map(|m| m.lookup(&self.doc).map(|v| (m, v)))
+    }
+
+    // The mount a path falls under, if any.
+    pub fn owner(&self, path: &FlowPath) -> Option<&FlowPath> {
+        self.mounts.iter().find(|m| path.starts_with(m))
+    }
+
+    pub fn document(&self) -> &Value {
+        &self.doc
+    }
+
+    pub fn into_document(self) -> Value {
+        self.doc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{v2, ParseOptions};
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn fragments_mount_under_their_prefix() {
+        let mut assembly = Assembly::new();
+        assembly.mount("auth", json!({ "provider": "oidc" })).unwrap();
+        assembly.mount("teams.billing", json!({ "plan": "free" })).unwrap();
+        assert_eq!(assembly.document(), &json!({ "auth": { "provider": "oidc" }, "teams": { "billing": { "plan": "free" } } }));
+        let prefixes: Vec<String> = assembly.fragments().map(|(p, _)| p.to_string()).collect();
+        assert_eq!(prefixes, ["auth", "teams.billing"]);
+        assert_eq!(assembly.fragment(&FlowPath::parse("teams.billing").unwrap()), Some(&json!({ "plan": "free" })));
+        assert_eq!(assembly.fragment(&FlowPath::parse("teams").unwrap()), None);
+        assert_eq!(assembly.owner(&FlowPath::parse("teams.billing.plan").unwrap()).map(|p| p.to_string()), Some("teams.billing".to_string()));
+        assert_eq!(assembly.owner(&FlowPath::parse("teams").unwrap()), None);
+    }
+
+    #[test]
+    fn overlaps_and_conflicts_are_rejected() {
+        let mut assembly = Assembly::with_base(json!({ "name": "app", "db": { "host": "x" } }));
+        assembly.mount("teams.billing", json!({ "plan": "free" })).unwrap();
+        let before = assembly.clone();
+
+        // above, below and at an existing mount
+        assert!(assembly.mount("teams", json!({})).unwrap_err().message.contains("'teams.billing'"));
+        assert!(assembly.mount("teams.billing.extra", json!({})).is_err());
+        assert!(assembly.mount("teams.billing", json!({})).is_err());
+        // already in the base document, or under a scalar
+        assert!(assembly.mount("db", json!({})).unwrap_err().message.contains("already has a value"));
+        assert!(assembly.mount("db.host", json!({})).is_err());
+        assert!(assembly.mount("name.first", json!({})).unwrap_err().message.contains("'name' is not an object"));
+        // not a key path
+        assert!(assembly.mount_at(FlowPath::root(), json!({})).is_err());
+        assert!(assembly.mount("list[0]", json!({})).is_err());
+        assert_eq!(assembly, before);
+
+        // next to the base and to other mounts is fine
+        assembly.mount("db.replica", json!({ "host": "y" })).unwrap();
+        assembly.mount("teams.sales", json!({})).unwrap();
+        assert_eq!(assembly.document()["db"], json!({ "host": "x", "replica": { "host": "y" } }));
+    }
+
+    #[test]
+    fn split_by_top_level_key() {
+        let doc = json!({ "name": "app", "auth": { "provider": "oidc" }, "ports": [80], "db": { "host": "x" } });
+        let assembly = Assembly::split(&doc);
+        let prefixes: Vec<String> = assembly.fragments().map(|(p, _)| p.to_string()).collect();
+        assert_eq!(prefixes, ["auth", "db"]);
+        assert_eq!(assembly.owner(&FlowPath::parse("name").unwrap()), None);
+
+        // the fragments mount back into the same document
+        let mut again = Assembly::with_base(json!({ "name": "app", "ports": [80] }));
+        for (prefix, fragment) in assembly.fragments() {
+            again.mount_at(prefix.clone(), fragment.clone()).unwrap();
+        }
+        assert_eq!(again.into_document(), doc);
+        assert_eq!(Assembly::split(&json!([1, 2])).fragments().count(), 0);
+    }
+
+    #[test]
+    fn mounted_files_keep_their_origin() {
+        let dir = std::env::temp_dir().join(format!("flowdoc-assembly-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("auth.flow");
+        fs::write(&path, "provider = \"oidc\"\nclient:\n  id = \"abc\"\n").unwrap();
+        let path = path.display().to_string();
+
+        let mut assembly = Assembly::new();
+        assembly.mount_traced("services.auth", v2::load_traced(&path, &ParseOptions::default()).unwrap()).unwrap();
+        assembly.mount("services.billing", json!({ "plan": "free" })).unwrap();
+        let origin = assembly.get_origin(&FlowPath::parse("services.auth.client.id").unwrap()).unwrap();
+        assert!(origin.source.ends_with("auth.flow"), "{}", origin);
+        assert_eq!(origin.line, Some(3));
+        assert_eq!(assembly.get_origin(&FlowPath::parse("services.auth.provider").unwrap()).unwrap().line, Some(1));
+        assert_eq!(assembly.get_origin(&FlowPath::parse("services.billing.plan").unwrap()), None);
+    }
+}
@@ -0,0 +1,365 @@
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::path::{FlowPath, PathError};
+
+// ============================================
+// Audit Journal
+// ============================================
+//
+// An AuditLog appends one record per mutation to a journal kept next to the
+// document, `<path>.audit.flowb`: when it happened (milliseconds since the
+// Unix epoch), who made it, the path, and the value before and after, either
+// absent when the node did not exist. The journal is a plain sequence of
+// MessagePack maps, only ever appended to and synced after every write.
+//
+// `set` and `remove` change a document only if the record is written: the
+// edit is made, and undone again if the journal cannot be appended to.
+// FlowPath::set_audited and remove_audited do the same from the path side.
+//
+// FlowTransaction::audit records a transaction's edits the same way, writing
+// them to each document's journal, tagged with a transaction id, before any
+// file is replaced. Once the renames are done, or undone after a failure, a
+// commit or abort record for that id follows, and `entries` reports each
+// edit as applied or aborted by it. Edits with neither record, left by a
+// process that stopped in between, are reported as pending.
+
+#[derive(Debug)]
+pub enum AuditError {
+    Io { path: String, source: io::Error },
+    Path(PathError),
+    // a record that cannot be read back, at its byte offset
+    Decode { path: String, offset: u64, message: String },
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditError::Io { path, source } => write!(f, "{}: {}", path, source),
+            AuditError::Path(e) => write!(f, "{}", e),
+            AuditError::Decode { path, offset, message } => write!(f, "{}: invalid audit record at byte {}: {}", path, offset, message),
+        }
+    }
+}
+
+impl std::error::Error for AuditError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AuditError::Io { source, .. } => Some(source),
+            AuditError::Path(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<PathError> for AuditError {
+    fn from(e: PathError) -> Self {
+        AuditError::Path(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditStatus {
+    Applied,
+    // recorded by a transaction that has no commit or abort record
+    Pending,
+    // recorded by a transaction that failed; the document was left as it was
+    Aborted,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    // milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub actor: String,
+    pub path: FlowPath,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+    pub status: AuditStatus,
+    // the transaction that recorded the edit, if any
+    txn: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(actor: &str, path: FlowPath, old: Option<Value>, new: Option<Value>) -> Self {
+        AuditEntry { timestamp: now(), actor: actor.to_string(), path, old, new, status: AuditStatus::Applied, txn: None }
+    }
+
+    fn to_value(&self) -> Value {
+        let mut record = json!({ "ts": self.timestamp, "actor": self.actor, "path": self.path.to_string() });
+        if let Some(txn) = &self.txn {
+            record["txn"] = json!(txn);
+        }
+        if let Some(old) = &self.old {
+            record["old"] = old.clone();
+        }
+        if let Some(new) = &self.new {
+            record["new"] = new.clone();
+        }
+        record
+    }
+
+    fn from_value(mut record: Map<String, Value>) -> Result<Self, String> {
+        let path = record.get("path").and_then(Value::as_str).ok_or("missing path")?;
+        Ok(AuditEntry {
+            timestamp: record.get("ts").and_then(Value::as_u64).ok_or("missing timestamp")?,
+            actor: record.get("actor").and_then(Value::as_str).unwrap_or_default().to_string(),
+            path: FlowPath::parse(path).map_err(|e| e.to_string())?,
+            old: record.remove("old"),
+            new: record.remove("new"),
+            status: AuditStatus::Applied,
+            txn: record.get("txn").and_then(Value::as_str).map(str::to_string),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    journal: PathBuf,
+    actor: String,
+}
+
+impl AuditLog {
+    // A journal at `journal` itself, for one kept apart from the documents.
+    pub fn new(journal: impl AsRef<Path>, actor: &str) -> Self {
+        AuditLog { journal: journal.as_ref().to_path_buf(), actor: actor.to_string() }
+    }
+
+    // The journal of the document at `path`, recording edits made by `actor`.
+    pub fn for_document(path: impl AsRef<Path>, actor: &str) -> Self {
+        AuditLog { journal: journal_path(path.as_ref()), actor: actor.to_string() }
+    }
+
+    pub fn journal(&self) -> &Path {
+        &self.journal
+    }
+
+    pub fn actor(&self) -> &str {
+        &self.actor
+    }
+
+    pub fn set(&self, doc: &mut Value, path: &FlowPath, value: Value) -> Result<Option<Value>, AuditError> {
+        let new = value.clone();
+        let old = path.set(doc, value)?;
+        if let Err(e) = self.append(&[AuditEntry::new(&self.actor, path.clone(), old.clone(), Some(new))]) {
+            match &old {
+                Some(old) => {
+                    path.set(doc, old.clone())?;
+                }
+                None => {
+                    path.remove(doc);
+                }
+            }
+            return Err(e);
+        }
+        Ok(old)
+    }
+
+    pub fn remove(&self, doc: &mut Value, path: &FlowPath) -> Result<Option<Value>, AuditError> {
+        let Some(old) = path.lookup(doc).cloned() else { return Ok(None) };
+        self.append(&[AuditEntry::new(&self.actor, path.clone(), Some(old), None)])?;
+        Ok(path.remove(doc))
+    }
+
+    // Appends `entries` and syncs the journal, creating it if needed.
+    pub fn append(&self, entries: &[AuditEntry]) -> Result<(), AuditError> {
+        self.write(entries.iter().map(AuditEntry::to_value))
+    }
+
+    // Appends `entries` as edits of transaction `txn`, pending until `finish`.
+    pub(crate) fn begin(&self, txn: &str, entries: &[AuditEntry]) -> Result<(), AuditError> {
+        self.write(entries.iter().map(|entry| AuditEntry { txn: Some(txn.to_string()), ..entry.clone() }.to_value()))
+    }
+
+    // Records whether the edits of transaction `txn` were applied.
+    pub(crate) fn finish(&self, txn: &str, applied: bool) -> Result<(), AuditError> {
+        let outcome = if applied { "commit" } else { "abort" };
+        self.write(std::iter::once(json!({ "ts": now(), "actor": self.actor, "txn": txn, "outcome": outcome })))
+    }
+
+    fn write(&self, records: impl Iterator<Item = Value>) -> Result<(), AuditError> {
+        let io_error = |source| AuditError::Io { path: self.journal.display().to_string(), source };
+        let mut data = Vec::new();
+        for record in records {
+            data.extend(rmp_serde::to_vec(&record).map_err(|e| io_error(io::Error::new(io::ErrorKind::InvalidData, e.to_string())))?);
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.journal).map_err(io_error)?;
+        file.write_all(&data).map_err(io_error)?;
+        file.sync_data().map_err(io_error)
+    }
+
+    // Every edit in the journal, oldest first. A missing journal is empty.
+    pub fn entries(&self) -> Result<Vec<AuditEntry>, AuditError> {
+        read_journal(&self.journal)
+    }
+}
+
+impl FlowPath {
+    // `set`, recorded in `log`; the document is unchanged if it cannot be.
+    pub fn set_audited(&self, val: &mut Value, new: Value, log: &AuditLog) -> Result<Option<Value>, AuditError> {
+        log.set(val, self, new)
+    }
+
+    // `remove`, recorded in `log`; the document is unchanged if it cannot be.
+    pub fn remove_audited(&self, val: &mut Value, log: &AuditLog) -> Result<Option<Value>, AuditError> {
+        log.remove(val, self)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+fn journal_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".audit.flowb");
+    PathBuf::from(name)
+}
+
+fn read_journal(journal: &Path) -> Result<Vec<AuditEntry>, AuditError> {
+    let name = journal.display().to_string();
+    let data = match fs::read(journal) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(source) => return Err(AuditError::Io { path: name, source }),
+    };
+    let mut de = rmp_serde::Deserializer::new(Cursor::new(&data[..]));
+    let mut entries = Vec::new();
+    let mut outcomes: HashMap<String, AuditStatus> = HashMap::new();
+    while de.position() < data.len() as u64 {
+        let offset = de.position();
+        let error = |message: String| AuditError::Decode { path: name.clone(), offset, message };
+        let record = match Value::deserialize(&mut de).map_err(|e| error(e.to_string()))? {
+            Value::Object(record) => record,
+            _ => return Err(error("not a map".to_string())),
+        };
+        if let Some(outcome) = record.get("outcome") {
+            let txn = record.get("txn").and_then(Value::as_str).ok_or_else(|| error("missing transaction id".to_string()))?;
+            let status = match outcome.as_str() {
+                Some("commit") => AuditStatus::Applied,
+                Some("abort") => AuditStatus::Aborted,
+                _ => return Err(error(format!("unknown outcome {}", outcome))),
+            };
+            outcomes.insert(txn.to_string(), status);
+            continue;
+        }
+        entries.push(AuditEntry::from_value(record).map_err(error)?);
+    }
+    for entry in &mut entries {
+        if let Some(txn) = &entry.txn {
+            entry.status = outcomes.get(txn).copied().unwrap_or(AuditStatus::Pending);
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::tests::{scratch_dir, FAIL_RENAME};
+    use crate::FlowTransaction;
+
+    fn key(path: &str) -> FlowPath {
+        FlowPath::parse(path).unwrap()
+    }
+
+    #[test]
+    fn set_and_remove_round_trip() {
+        let dir = scratch_dir("audit-edits");
+        let log = AuditLog::for_document(dir.join("app.flow"), "ops");
+        let mut doc = json!({ "port": 80 });
+        assert_eq!(log.set(&mut doc, &key("port"), json!(443)).unwrap(), Some(json!(80)));
+        key("tls.on").set_audited(&mut doc, json!(true), &log).unwrap();
+        assert_eq!(key("port").remove_audited(&mut doc, &log).unwrap(), Some(json!(443)));
+        // nothing to remove, nothing recorded
+        assert_eq!(log.remove(&mut doc, &key("missing")).unwrap(), None);
+        assert_eq!(doc, json!({ "tls": { "on": true } }));
+
+        let entries = log.entries().unwrap();
+        let edits: Vec<(String, Option<Value>, Option<Value>)> = entries.iter().map(|e| (e.path.to_string(), e.old.clone(), e.new.clone())).collect();
+        assert_eq!(
+            edits,
+            [
+                ("port".to_string(), Some(json!(80)), Some(json!(443))),
+                ("tls.on".to_string(), None, Some(json!(true))),
+                ("port".to_string(), Some(json!(443)), None),
+            ]
+        );
+        assert!(entries.iter().all(|e| e.actor == "ops" && e.status == AuditStatus::Applied && e.timestamp > 0));
+    }
+
+    #[test]
+    fn edits_are_undone_when_the_journal_fails() {
+        let dir = scratch_dir("audit-unwritable");
+        let log = AuditLog::new(dir.join("missing").join("journal.flowb"), "ops");
+        let mut doc = json!({ "port": 80, "list": [1] });
+        assert!(matches!(log.set(&mut doc, &key("port"), json!(443)), Err(AuditError::Io { .. })));
+        assert!(log.set(&mut doc, &key("host"), json!("x")).is_err());
+        assert!(key("list[1]").set_audited(&mut doc, json!(2), &log).is_err());
+        assert!(log.remove(&mut doc, &key("port")).is_err());
+        assert_eq!(doc, json!({ "port": 80, "list": [1] }));
+        assert!(log.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn damaged_journals() {
+        let dir = scratch_dir("audit-damaged");
+        let journal = dir.join("j.flowb");
+        let log = AuditLog::new(&journal, "ops");
+        let mut doc = json!({});
+        log.set(&mut doc, &key("a"), json!(1)).unwrap();
+        let first = fs::metadata(&journal).unwrap().len();
+        log.set(&mut doc, &key("b"), json!(2)).unwrap();
+
+        // a record cut short
+        let data = fs::read(&journal).unwrap();
+        fs::write(&journal, &data[..data.len() - 3]).unwrap();
+        assert!(matches!(log.entries(), Err(AuditError::Decode { offset, .. }) if offset == first));
+        // something that is not a record at all
+        fs::write(&journal, [&data[..first as usize], &rmp_serde::to_vec(&json!([1, 2])).unwrap()[..]].concat()).unwrap();
+        assert!(matches!(log.entries(), Err(AuditError::Decode { offset, .. }) if offset == first));
+        fs::write(&journal, rmp_serde::to_vec(&json!({ "ts": 1, "actor": "x" })).unwrap()).unwrap();
+        assert!(matches!(log.entries(), Err(AuditError::Decode { offset: 0, .. })));
+    }
+
+    #[test]
+    fn transactions_mark_their_outcome() {
+        let dir = scratch_dir("audit-txn");
+        let (a, b) = (dir.join("a.flow"), dir.join("b.flow"));
+        fs::write(&a, "x = 1\n").unwrap();
+        fs::write(&b, "y = 1\n").unwrap();
+        let (a_name, b_name) = (a.display().to_string(), b.display().to_string());
+
+        let mut txn = FlowTransaction::new().audit("deploy");
+        txn.set(&a_name, &key("x"), json!(2)).unwrap();
+        txn.commit().unwrap();
+
+        let mut txn = FlowTransaction::new().audit("deploy");
+        txn.set(&a_name, &key("x"), json!(3)).unwrap();
+        txn.set(&b_name, &key("y"), json!(3)).unwrap();
+        FAIL_RENAME.with(|f| *f.borrow_mut() = Some(b.clone()));
+        let result = txn.commit();
+        FAIL_RENAME.with(|f| *f.borrow_mut() = None);
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&a).unwrap(), "x = 2\n");
+
+        let statuses = |path: &str| -> Vec<(Option<Value>, AuditStatus)> {
+            AuditLog::for_document(path, "").entries().unwrap().into_iter().map(|e| (e.new, e.status)).collect()
+        };
+        assert_eq!(statuses(&a_name), [(Some(json!(2)), AuditStatus::Applied), (Some(json!(3)), AuditStatus::Aborted)]);
+        assert_eq!(statuses(&b_name), [(Some(json!(3)), AuditStatus::Aborted)]);
+
+        // records of a commit that never finished
+        let log = AuditLog::for_document(&b, "deploy");
+        log.begin("t1", &[AuditEntry::new("deploy", key("y"), Some(json!(1)), Some(json!(4)))]).unwrap();
+        assert_eq!(log.entries().unwrap().last().map(|e| e.status), Some(AuditStatus::Pending));
+        log.finish("t1", true).unwrap();
+        assert_eq!(log.entries().unwrap().last().map(|e| e.status), Some(AuditStatus::Applied));
+    }
+}
@@ -0,0 +1,501 @@
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::process::{Command, ExitCode, Stdio};
+
+use flowdoc::{format_query, merge_three_way, v2, FlowPath, ModelRegistry, ParseOptions, PathGlob, QueryOutput, ReplOutput, ReplSession, StringifyOptions};
+
+// ============================================
+// Command Line
+// ============================================
+
+const USAGE: &str = "\
+usage: flowdoc <command> [args]
+
+  repl <file.flow> [--models <models.flow>]...
+      explore and edit a document interactively; `help` lists the commands
+  init <model> --models <models.flow> [<file.flow>]
+      write a new document with every field of the model set, to stdout
+      or to a file that does not exist yet
+  query <file> <glob> [--format raw|json|tsv|env] [--columns <path>,...]
+        [--header] [--prefix <prefix>]
+      print the values matching the glob: raw, as JSON lines, as tab-separated
+      rows of objects, or as KEY='value' lines for `eval`
+  merge-driver <base> <ours> <theirs> [<path>]
+      merge two edited versions of a .flow or .flowb file into <ours>; exits
+      with 1 if they conflict. For git: `driver = flowdoc merge-driver %O %A %B %P`
+  textconv <file>
+      print a .flow or .flowb file as sorted .flow text. For git:
+      `textconv = flowdoc textconv`";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("repl") => repl(&args[1..]),
+        Some("init") => init(&args[1..]),
+        Some("query") => query(&args[1..]),
+        Some("merge-driver") => merge_driver(&args[1..]),
+        Some("textconv") => textconv(&args[1..]),
+        Some("-h" | "--help" | "help") => {
+            println!("{}", USAGE);
+            ExitCode::SUCCESS
+        }
+        _ => usage(),
+    }
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{}", USAGE);
+    ExitCode::from(2)
+}
+
+fn fail(what: &str, e: impl std::fmt::Display) -> ExitCode {
+    eprintln!("flowdoc: {}: {}", what, e);
+    ExitCode::FAILURE
+}
+
+// ============================================
+// Scaffolding
+// ============================================
+
+fn init(args: &[String]) -> ExitCode {
+    let mut positional = Vec::new();
+    let mut registry = ModelRegistry::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--models" => {
+                let Some(path) = args.next() else { return usage() };
+                match v2::load(path) {
+                    Ok(doc) => registry.register_models(&doc),
+                    Err(e) => return fail(path, e),
+                };
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let (name, out) = match positional.as_slice() {
+        [name] => (name, None),
+        [name, out] => (name, Some(out)),
+        _ => return usage(),
+    };
+    let Some(model) = registry.get_model(name) else {
+        return fail(name, "no such model in the --models files");
+    };
+    let doc = model.example_document();
+    match out {
+        None => match v2::stringify(&doc) {
+            Ok(text) => print!("{}", text),
+            Err(e) => return fail(name, e),
+        },
+        Some(out) if std::path::Path::new(out).exists() => return fail(out, "already exists"),
+        Some(out) => {
+            if let Err(e) = v2::save(out, &doc) {
+                return fail(out, e);
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+// ============================================
+// Queries
+// ============================================
+//
+// Options take their value as the next argument or after `=`, so
+// `--format env` and `--format=env` are the same.
+
+fn query(args: &[String]) -> ExitCode {
+    let mut positional = Vec::new();
+    let mut output = QueryOutput::default();
+    let mut args = args.iter().flat_map(|arg| match arg.split_once('=') {
+        Some((opt, value)) if opt.starts_with("--") => vec![opt.to_string(), value.to_string()],
+        _ => vec![arg.clone()],
+    });
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => match args.next().map(|f| f.parse()) {
+                Some(Ok(format)) => output.format = format,
+                Some(Err(e)) => return fail("--format", e),
+                None => return usage(),
+            },
+            "--columns" => {
+                let Some(list) = args.next() else { return usage() };
+                for column in list.split(',') {
+                    match FlowPath::parse(column) {
+                        Ok(path) => output.columns.push(path),
+                        Err(e) => return fail("--columns", e),
+                    }
+                }
+            }
+            "--header" => output.header = true,
+            "--prefix" => match args.next() {
+                Some(prefix) => output.prefix = prefix,
+                None => return usage(),
+            },
+            _ => positional.push(arg),
+        }
+    }
+    let [file, glob] = positional.as_slice() else { return usage() };
+    let glob = match PathGlob::parse(glob) {
+        Ok(glob) => glob,
+        Err(e) => return fail(glob, e),
+    };
+    let doc = match is_flowb(file, None) {
+        Ok(true) => v2::load_flowb(file),
+        Ok(false) => v2::load(file),
+        Err(e) => return fail(file, e),
+    };
+    let doc = match doc {
+        Ok(doc) => doc,
+        Err(e) => return fail(file, e),
+    };
+    match format_query(&glob.find(&doc), &output) {
+        Ok(text) => print!("{}", text),
+        Err(e) => return fail(file, e),
+    }
+    ExitCode::SUCCESS
+}
+
+// ============================================
+// Git Integration
+// ============================================
+//
+// A merge driver and a diff text converter, configured per file pattern:
+//
+//   .gitattributes   *.flow merge=flowdoc diff=flowdoc
+//                    *.flowb merge=flowdoc diff=flowdoc
+//   .git/config      [merge "flowdoc"]
+//                        driver = flowdoc merge-driver %O %A %B %P
+//                    [diff "flowdoc"]
+//                        textconv = flowdoc textconv
+//
+// Git hands the merge driver temporary files without the original name, so
+// the format comes from <path> when it is given and otherwise from the
+// first byte: a .flowb document starts with a MessagePack map header. The
+// merged document is written in the deterministic text form or as .flowb;
+// conflicting nodes keep our version and are listed on stderr. `@ref`
+// values are compared as written, not resolved.
+
+fn merge_driver(args: &[String]) -> ExitCode {
+    let (base, ours, theirs, name) = match args {
+        [base, ours, theirs] => (base, ours, theirs, ours),
+        [base, ours, theirs, name] => (base, ours, theirs, name),
+        _ => return usage(),
+    };
+    let binary = match is_flowb(ours, Some(name)) {
+        Ok(binary) => binary,
+        Err(e) => return fail(ours, e),
+    };
+    let mut versions = Vec::new();
+    for path in [base, ours, theirs] {
+        match read_document(path, binary) {
+            Ok(doc) => versions.push(doc),
+            Err(e) => return fail(name, format!("{}: {}", path, e)),
+        }
+    }
+    let merged = merge_three_way(&versions[0], &versions[1], &versions[2]);
+    let written = if binary {
+        v2::save_flowb(ours, &merged.value)
+    } else {
+        v2::stringify_with_options(&merged.value, &StringifyOptions::new().deterministic(true))
+            .and_then(|text| std::fs::write(ours, text).map_err(|source| v2::FlowError::Io { path: ours.clone(), source }))
+    };
+    if let Err(e) = written {
+        return fail(name, e);
+    }
+    for conflict in &merged.conflicts {
+        eprintln!("flowdoc: {}: {}", name, conflict);
+    }
+    if merged.is_clean() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
+
+fn textconv(args: &[String]) -> ExitCode {
+    let [path] = args else { return usage() };
+    let doc = match is_flowb(path, None) {
+        Ok(binary) => read_document(path, binary),
+        Err(e) => return fail(path, e),
+    };
+    match doc.and_then(|doc| v2::stringify_with_options(&doc, &StringifyOptions::new().deterministic(true))) {
+        Ok(text) => print!("{}", text),
+        Err(e) => return fail(path, e),
+    }
+    ExitCode::SUCCESS
+}
+
+fn is_flowb(path: &str, name: Option<&str>) -> io::Result<bool> {
+    match std::path::Path::new(name.unwrap_or(path)).extension().and_then(|e| e.to_str()) {
+        Some("flowb") => return Ok(true),
+        Some("flow") => return Ok(false),
+        _ => {}
+    }
+    let mut first = [0u8; 1];
+    let n = std::fs::File::open(path)?.read(&mut first)?;
+    Ok(n == 1 && matches!(first[0], 0x80..=0x8f | 0xde | 0xdf))
+}
+
+fn read_document(path: &str, binary: bool) -> v2::Result<serde_json::Value> {
+    if binary {
+        return v2::load_flowb(path);
+    }
+    let bytes = std::fs::read(path).map_err(|source| v2::FlowError::Io { path: path.to_string(), source })?;
+    Ok(v2::parse_bytes(&bytes, &ParseOptions::default())?.value)
+}
+
+// ============================================
+// REPL
+// ============================================
+//
+// On a terminal, lines are edited in place with Tab completion and Up/Down
+// history. Anything else, such as a pipe, is read a line at a time without
+// a prompt, so a list of commands can be scripted.
+
+fn repl(args: &[String]) -> ExitCode {
+    let mut file = None;
+    let mut models = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--models" => match args.next() {
+                Some(path) => models.push(path),
+                None => return usage(),
+            },
+            _ if file.is_none() => file = Some(arg),
+            _ => return usage(),
+        }
+    }
+    let Some(file) = file else { return usage() };
+
+    let mut session = match ReplSession::open(file) {
+        Ok(session) => session,
+        Err(e) => return fail(file, e),
+    };
+    for path in models {
+        if let Err(e) = session.load_models(path) {
+            return fail(path, e);
+        }
+    }
+
+    let mut editor = if io::stdin().is_terminal() { RawMode::enable().map(|raw| Editor { _raw: raw, history: Vec::new() }) } else { None };
+    let mut stdin = io::stdin().lock();
+    loop {
+        let line = match &mut editor {
+            Some(editor) => editor.read_line(&session, &mut stdin),
+            None => {
+                let mut line = String::new();
+                stdin.read_line(&mut line).ok().filter(|n| *n > 0).map(|_| line)
+            }
+        };
+        let Some(line) = line else { break };
+        match session.execute(&line) {
+            Ok(ReplOutput::Text(text)) => print!("{}", text),
+            Ok(ReplOutput::Quit) => break,
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+    if session.is_dirty() {
+        eprintln!("flowdoc: unsaved changes discarded");
+    }
+    ExitCode::SUCCESS
+}
+
+// The terminal without line buffering, echo or signals, restored on drop.
+// `stty` does the switching, which keeps this free of platform bindings.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> Option<Self> {
+        let saved = stty(&["-g"])?.trim().to_string();
+        stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+        Some(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> Option<String> {
+    let out = Command::new("stty").args(args).stdin(Stdio::inherit()).output().ok()?;
+    out.status.success().then(|| String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+struct Editor {
+    _raw: RawMode,
+    history: Vec<String>,
+}
+
+impl Editor {
+    // None at Ctrl-D on an empty line or the end of input.
+    fn read_line(&mut self, session: &ReplSession, stdin: &mut impl Read) -> Option<String> {
+        let prompt = session.prompt();
+        let mut line: Vec<u8> = Vec::new();
+        // position in history while stepping with Up/Down
+        let mut recalled = self.history.len();
+        redraw(&prompt, &line);
+        loop {
+            match read_byte(stdin)? {
+                b'\r' | b'\n' => {
+                    print!("\r\n");
+                    let _ = io::stdout().flush();
+                    let text = String::from_utf8_lossy(&line).into_owned();
+                    if !text.trim().is_empty() && self.history.last() != Some(&text) {
+                        self.history.push(text.clone());
+                    }
+                    return Some(text);
+                }
+                // Ctrl-D
+                4 if line.is_empty() => {
+                    print!("\r\n");
+                    return None;
+                }
+                // Ctrl-C drops the line
+                3 => {
+                    print!("^C\r\n");
+                    line.clear();
+                }
+                127 | 8 => {
+                    while let Some(b) = line.pop() {
+                        if b & 0xC0 != 0x80 {
+                            break;
+                        }
+                    }
+                }
+                b'\t' => complete(session, &prompt, &mut line),
+                27 => {
+                    if read_byte(stdin)? != b'[' {
+                        continue;
+                    }
+                    match read_byte(stdin)? {
+                        b'A' if recalled > 0 => recalled -= 1,
+                        b'B' if recalled < self.history.len() => recalled += 1,
+                        _ => continue,
+                    }
+                    line = self.history.get(recalled).map(|s| s.clone().into_bytes()).unwrap_or_default();
+                }
+                b if b >= 0x20 => line.push(b),
+                _ => {}
+            }
+            redraw(&prompt, &line);
+        }
+    }
+}
+
+fn read_byte(input: &mut impl Read) -> Option<u8> {
+    let mut byte = [0];
+    match input.read(&mut byte) {
+        Ok(1) => Some(byte[0]),
+        _ => None,
+    }
+}
+
+fn redraw(prompt: &str, line: &[u8]) {
+    print!("\r\x1b[K{}{}", prompt, String::from_utf8_lossy(line));
+    let _ = io::stdout().flush();
+}
+
+// Substitutes the only candidate, or the prefix all candidates share and
+// lists them when that adds nothing.
+fn complete(session: &ReplSession, prompt: &str, line: &mut Vec<u8>) {
+    let text = String::from_utf8_lossy(line).into_owned();
+    let candidates = session.complete(&text);
+    let start = text.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+    let word = &text[start..];
+    let replacement = match candidates.as_slice() {
+        [] => return,
+        [only] if start == 0 => format!("{} ", only),
+        [only] => only.clone(),
+        many => common_prefix(many),
+    };
+    if replacement.len() > word.len() {
+        *line = format!("{}{}", &text[..start], replacement).into_bytes();
+    } else {
+        print!("\r\n{}\r\n", candidates.join("  "));
+    }
+    redraw(prompt, line);
+}
+
+fn common_prefix(words: &[String]) -> String {
+    let first = &words[0];
+    let mut len = first.len();
+    for w in &words[1..] {
+        len = first.char_indices().zip(w.chars()).take_while(|((_, a), b)| a == b).map(|((i, a), _)| i + a.len_utf8()).last().unwrap_or(0).min(len);
+    }
+    first[..len].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flowdoc-cli-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn args(list: &[&PathBuf]) -> Vec<String> {
+        list.iter().map(|p| p.display().to_string()).collect()
+    }
+
+    #[test]
+    fn init_writes_new_files_only() {
+        let dir = scratch_dir("init");
+        let models = dir.join("models.flow");
+        fs::write(&models, "$models:\n  server:\n    fields:\n      port:\n        type = int\n        default = 8080\n").unwrap();
+        let out = dir.join("server.flow");
+        let run = |name: &str| init(&[vec![name.to_string(), "--models".to_string()], args(&[&models, &out])].concat());
+
+        assert_eq!(run("server"), ExitCode::SUCCESS);
+        assert_eq!(v2::load(&out.display().to_string()).unwrap(), serde_json::json!({"use_model": "server", "port": 8080}));
+        assert_eq!(run("server"), ExitCode::FAILURE);
+        fs::remove_file(&out).unwrap();
+        assert_eq!(run("client"), ExitCode::FAILURE);
+        assert!(!out.exists());
+        assert_eq!(init(&["server".to_string()]), ExitCode::FAILURE);
+        assert_eq!(init(&[]), ExitCode::from(2));
+    }
+
+    #[test]
+    fn merge_driver_exit_codes() {
+        let dir = scratch_dir("merge");
+        let (base, ours, theirs) = (dir.join("base"), dir.join("ours"), dir.join("theirs"));
+        let write = |ours_text: &str, theirs_text: &str| {
+            fs::write(&base, "name = api\nport = 80\n").unwrap();
+            fs::write(&ours, ours_text).unwrap();
+            fs::write(&theirs, theirs_text).unwrap();
+        };
+
+        write("name = api2\nport = 80\n", "port = 8080\nname = api\n");
+        assert_eq!(merge_driver(&args(&[&base, &ours, &theirs])), ExitCode::SUCCESS);
+        assert_eq!(fs::read_to_string(&ours).unwrap(), "name = api2\nport = 8080\n");
+
+        write("name = api\nport = 81\n", "name = api\nport = 82\n");
+        assert_eq!(merge_driver(&args(&[&base, &ours, &theirs])), ExitCode::FAILURE);
+        assert_eq!(fs::read_to_string(&ours).unwrap(), "name = api\nport = 81\n");
+
+        write("name = api\n  stray\n", "name = api\n");
+        assert_eq!(merge_driver(&args(&[&base, &ours, &theirs])), ExitCode::FAILURE);
+        assert_eq!(merge_driver(&args(&[&base, &ours])), ExitCode::from(2));
+        assert_eq!(merge_driver(&args(&[&base, &dir.join("missing"), &theirs])), ExitCode::FAILURE);
+    }
+
+    #[test]
+    fn merge_driver_keeps_flowb() {
+        let dir = scratch_dir("merge-flowb");
+        let paths: Vec<PathBuf> = ["base", "ours", "theirs"].iter().map(|n| dir.join(n)).collect();
+        let docs = [serde_json::json!({"a": 1, "b": 1}), serde_json::json!({"a": 2, "b": 1}), serde_json::json!({"a": 1, "b": 2})];
+        for (path, doc) in paths.iter().zip(&docs) {
+            v2::save_flowb(&path.display().to_string(), doc).unwrap();
+        }
+        // detected from the first byte, as git's temporary files have no extension
+        assert_eq!(merge_driver(&args(&[&paths[0], &paths[1], &paths[2]])), ExitCode::SUCCESS);
+        assert_eq!(v2::load_flowb(&paths[1].display().to_string()).unwrap(), serde_json::json!({"a": 2, "b": 2}));
+        assert!(!is_flowb(&paths[0].display().to_string(), Some("conf.flow")).unwrap());
+    }
+}
@@ -0,0 +1,375 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use crate::path::{FlowPath, PathGlob};
+use crate::{flow_element, flow_value};
+
+// ============================================
+// Size Budgets
+// ============================================
+//
+// Sizes are bytes of StringifyFlow output. An object entry contributes its
+// line (or its `key:` line and everything below it), an array element its
+// text plus the ", " before it. The sizes of the entries of an object add up
+// to the size of the object's body, so the root size is the size of the
+// whole document.
+//
+// `prune_to_budget` shrinks a document to fit a SizeBudget. A string is cut
+// short and ends in "…[truncated N bytes]"; an array keeps its first
+// elements and ends in "…[N more items]"; an object keeps its first entries
+// in key order and gets a `$truncated` entry counting the ones dropped. A
+// pruned node is replaced by "…[pruned N bytes]". Markers count against the
+// budget, except that one is always kept even when the budget is smaller.
+
+pub const TRUNCATED_KEY: &str = "$truncated";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SizeReport {
+    pub total: usize,
+    sizes: BTreeMap<FlowPath, usize>,
+}
+
+impl SizeReport {
+    pub fn size(&self, path: &FlowPath) -> Option<usize> {
+        if path.is_root() {
+            return Some(self.total);
+        }
+        self.sizes.get(path).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&FlowPath, usize)> {
+        self.sizes.iter().map(|(p, s)| (p, *s))
+    }
+
+    // The `n` largest nodes, largest first. A subtree includes its children,
+    // so a large leaf shows up together with its ancestors.
+    pub fn largest(&self, n: usize) -> Vec<(&FlowPath, usize)> {
+        let mut all: Vec<(&FlowPath, usize)> = self.iter().collect();
+        all.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        all.truncate(n);
+        all
+    }
+}
+
+pub fn measure(val: &Value) -> SizeReport {
+    let mut report = SizeReport::default();
+    if let Value::Object(map) = val {
+        report.total = measure_object(map, 0, &mut FlowPath::root(), &mut report.sizes);
+    }
+    report
+}
+
+fn measure_object(map: &Map<String, Value>, indent: usize, path: &mut FlowPath, sizes: &mut BTreeMap<FlowPath, usize>) -> usize {
+    let mut total = 0;
+    for (k, v) in map {
+        path.push_key(k);
+        let size = match v {
+            Value::Object(m) => header_size(k, indent) + measure_object(m, indent + 2, path, sizes),
+            Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    path.push_index(i);
+                    sizes.insert(path.clone(), element_size(i, item));
+                    path.pop();
+                }
+                entry_size(k, v, indent)
+            }
+            _ => entry_size(k, v, indent),
+        };
+        sizes.insert(path.clone(), size);
+        total += size;
+        path.pop();
+    }
+    total
+}
+
+// `key:` and its newline
+fn header_size(key: &str, indent: usize) -> usize {
+    indent + key.len() + 2
+}
+
+fn entry_size(key: &str, val: &Value, indent: usize) -> usize {
+    match val {
+        Value::Object(m) => header_size(key, indent) + m.iter().map(|(k, v)| entry_size(k, v, indent + 2)).sum::<usize>(),
+        _ => flow_value(val).map(|text| indent + key.len() + text.len() + 4).unwrap_or(0),
+    }
+}
+
+fn element_size(index: usize, val: &Value) -> usize {
+    flow_element(val).len() + if index > 0 { 2 } else { 0 }
+}
+
+// ============================================
+// Pruning
+// ============================================
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BudgetAction {
+    // keep as much of the node as fits
+    #[default]
+    Truncate,
+    // replace the whole node with a marker
+    Prune,
+}
+
+#[derive(Debug, Clone)]
+pub struct PathBudget {
+    pub glob: PathGlob,
+    pub max: usize,
+    pub action: BudgetAction,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SizeBudget {
+    // for the whole document, applied after the path budgets
+    pub total: Option<usize>,
+    // for every object entry matching the glob
+    pub paths: Vec<PathBudget>,
+}
+
+impl SizeBudget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn total(mut self, max: usize) -> Self {
+        self.total = Some(max);
+        self
+    }
+
+    pub fn limit(mut self, glob: PathGlob, max: usize) -> Self {
+        self.paths.push(PathBudget { glob, max, action: BudgetAction::Truncate });
+        self
+    }
+
+    pub fn prune(mut self, glob: PathGlob, max: usize) -> Self {
+        self.paths.push(PathBudget { glob, max, action: BudgetAction::Prune });
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Truncation {
+    pub path: FlowPath,
+    pub action: BudgetAction,
+    // sizes before and after, markers included
+    pub original: usize,
+    pub kept: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pruned {
+    pub value: Value,
+    pub size: usize,
+    // the nodes the path budgets shrank, in document order, then the root
+    // if the total budget applied
+    pub truncated: Vec<Truncation>,
+}
+
+pub fn prune_to_budget(val: &Value, budget: &SizeBudget) -> Pruned {
+    let mut truncated = Vec::new();
+    let mut value = val.clone();
+    if let Value::Object(map) = &mut value {
+        if !budget.paths.is_empty() {
+            apply_paths(map, 0, &mut FlowPath::root(), &budget.paths, &mut truncated);
+        }
+        if let Some(max) = budget.total {
+            let size = body_size(map, 0);
+            if size > max {
+                *map = fit_object(map, 0, max);
+                truncated.push(Truncation { path: FlowPath::root(), action: BudgetAction::Truncate, original: size, kept: body_size(map, 0) });
+            }
+        }
+    }
+    let size = measure(&value).total;
+    Pruned { value, size, truncated }
+}
+
+fn body_size(map: &Map<String, Value>, indent: usize) -> usize {
+    map.iter().map(|(k, v)| entry_size(k, v, indent)).sum()
+}
+
+fn apply_paths(map: &mut Map<String, Value>, indent: usize, path: &mut FlowPath, budgets: &[PathBudget], truncated: &mut Vec<Truncation>) {
+    for (k, v) in map.iter_mut() {
+        path.push_key(k);
+        for budget in budgets.iter().filter(|b| b.glob.matches(path)) {
+            let size = entry_size(k, v, indent);
+            if size <= budget.max {
+                continue;
+            }
+            *v = match budget.action {
+                BudgetAction::Truncate => fit_entry(k, v, indent, budget.max).unwrap_or_else(|| pruned(size)),
+                BudgetAction::Prune => pruned(size),
+            };
+            truncated.push(Truncation { path: path.clone(), action: budget.action, original: size, kept: entry_size(k, v, indent) });
+        }
+        if let Value::Object(m) = v {
+            if budgets.iter().any(|b| b.glob.may_match_below(path)) {
+                apply_paths(m, indent + 2, path, budgets, truncated);
+            }
+        }
+        path.pop();
+    }
+}
+
+// The entry shrunk to at most `budget` bytes, or None if not even its
+// line fits.
+fn fit_entry(key: &str, val: &Value, indent: usize, budget: usize) -> Option<Value> {
+    if entry_size(key, val, indent) <= budget {
+        return Some(val.clone());
+    }
+    // what a `key = ` line costs before its value text
+    let line = indent + key.len() + 4;
+    match val {
+        Value::Object(map) => {
+            let room = budget.checked_sub(header_size(key, indent))?;
+            Some(Value::Object(fit_object(map, indent + 2, room)))
+        }
+        Value::Array(items) => fit_array(items, budget.checked_sub(line)?).map(Value::Array),
+        Value::String(s) => fit_string(s, budget.checked_sub(line)?).map(Value::String),
+        _ => None,
+    }
+}
+
+fn fit_object(map: &Map<String, Value>, indent: usize, budget: usize) -> Map<String, Value> {
+    let body = body_size(map, indent);
+    if body <= budget {
+        return map.clone();
+    }
+    // room for the marker, sized for the worst case
+    let reserve = marker_entry_size(map.len(), body, indent);
+    let mut room = budget.saturating_sub(reserve);
+    let mut out = Map::new();
+    let (mut dropped, mut dropped_bytes) = (0, 0);
+    for (k, v) in map {
+        let size = entry_size(k, v, indent);
+        if size <= room {
+            out.insert(k.clone(), v.clone());
+            room -= size;
+            continue;
+        }
+        // a nested marker must fit too, or the entry is dropped
+        match fit_entry(k, v, indent, room).filter(|fitted| entry_size(k, fitted, indent) <= room) {
+            Some(fitted) => {
+                room -= entry_size(k, &fitted, indent);
+                out.insert(k.clone(), fitted);
+            }
+            None => {
+                dropped += 1;
+                dropped_bytes += size;
+            }
+        }
+    }
+    if dropped > 0 {
+        out.insert(TRUNCATED_KEY.to_string(), Value::String(dropped_marker(dropped, dropped_bytes)));
+    }
+    out
+}
+
+// The longest prefix of `items` that fits with a marker for the rest.
+fn fit_array(items: &[Value], budget: usize) -> Option<Vec<Value>> {
+    // "[" and "]"
+    let mut used = 2;
+    let mut keep = None;
+    for (i, item) in items.iter().enumerate() {
+        if used + element_size(i, &Value::String(more_items(items.len() - i))) <= budget {
+            keep = Some(i);
+        }
+        used += element_size(i, item);
+        if used > budget {
+            break;
+        }
+    }
+    let keep = keep?;
+    let mut out = items[..keep].to_vec();
+    out.push(Value::String(more_items(items.len() - keep)));
+    Some(out)
+}
+
+fn fit_string(s: &str, budget: usize) -> Option<String> {
+    // the marker is written quoted, as it contains spaces
+    let overhead = |cut: usize| 2 + "…[truncated  bytes]".len() + (s.len() - cut).to_string().len();
+    let mut cut = budget.checked_sub(overhead(0))?.min(s.len());
+    while !s.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    Some(format!("{}…[truncated {} bytes]", &s[..cut], s.len() - cut))
+}
+
+fn pruned(size: usize) -> Value {
+    Value::String(format!("…[pruned {} bytes]", size))
+}
+
+fn more_items(n: usize) -> String {
+    format!("…[{} more items]", n)
+}
+
+fn dropped_marker(keys: usize, bytes: usize) -> String {
+    format!("{} {}, {} bytes", keys, if keys == 1 { "key" } else { "keys" }, bytes)
+}
+
+fn marker_entry_size(keys: usize, bytes: usize, indent: usize) -> usize {
+    entry_size(TRUNCATED_KEY, &Value::String(dropped_marker(keys, bytes)), indent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stringify_document;
+    use serde_json::json;
+
+    fn doc() -> Value {
+        json!({
+            "name": "service",
+            "server": {"host": "localhost", "ports": [80, 443, 8080], "tls": {"cert": "c"}},
+            "notes": "x".repeat(200),
+        })
+    }
+
+    #[test]
+    fn sizes_add_up_to_the_output() {
+        let doc = doc();
+        let report = measure(&doc);
+        assert_eq!(report.total, stringify_document(&doc).len());
+        let size = |p: &str| report.size(&FlowPath::parse(p).unwrap()).unwrap();
+        assert_eq!(size("name"), "name = service\n".len());
+        assert_eq!(size("server"), size("server.host") + size("server.ports") + size("server.tls") + "server:\n".len());
+        assert_eq!(size("server.ports"), "  ports = [80, 443, 8080]\n".len());
+        assert_eq!((size("server.ports[0]"), size("server.ports[1]")), (2, 5));
+        let largest: Vec<String> = report.largest(2).into_iter().map(|(p, _)| p.to_string()).collect();
+        assert_eq!(largest, ["notes", "server"]);
+    }
+
+    #[test]
+    fn path_budgets() {
+        let budget = SizeBudget::new().limit(PathGlob::parse("notes").unwrap(), 60).prune(PathGlob::parse("server.tls").unwrap(), 5);
+        let pruned = prune_to_budget(&doc(), &budget);
+        let notes = pruned.value["notes"].as_str().unwrap();
+        assert!(notes.starts_with("xxxx") && notes.ends_with("…[truncated 175 bytes]"), "{}", notes);
+        assert_eq!(pruned.value["server"]["tls"], json!("…[pruned 20 bytes]"));
+        let shrunk: Vec<(String, BudgetAction)> = pruned.truncated.iter().map(|t| (t.path.to_string(), t.action)).collect();
+        assert_eq!(shrunk, [("notes".to_string(), BudgetAction::Truncate), ("server.tls".to_string(), BudgetAction::Prune)]);
+        assert!(pruned.truncated[0].kept <= 60);
+        assert_eq!(pruned.size, stringify_document(&pruned.value).len());
+
+        let arrays = prune_to_budget(&json!({"list": (0..100).collect::<Vec<_>>()}), &SizeBudget::new().limit(PathGlob::parse("list").unwrap(), 40));
+        let list = arrays.value["list"].as_array().unwrap();
+        assert_eq!(list.last(), Some(&json!(format!("…[{} more items]", 100 - (list.len() - 1)))));
+        assert!(arrays.size <= 40);
+    }
+
+    #[test]
+    fn total_budget() {
+        for max in [60, 120, 200, 250] {
+            let pruned = prune_to_budget(&doc(), &SizeBudget::new().total(max));
+            assert_eq!(pruned.size, stringify_document(&pruned.value).len());
+            assert!(pruned.size <= max, "{} > {}", pruned.size, max);
+            assert_eq!(pruned.truncated.last().map(|t| t.path.is_root()), Some(true));
+        }
+        // the marker is kept even when nothing fits
+        let small = prune_to_budget(&doc(), &SizeBudget::new().total(0));
+        assert_eq!(small.value, json!({TRUNCATED_KEY: format!("3 keys, {} bytes", measure(&doc()).total)}));
+        let all = prune_to_budget(&doc(), &SizeBudget::new().total(10_000));
+        assert!(all.truncated.is_empty());
+        assert_eq!(all.value, doc());
+    }
+}
@@ -0,0 +1,193 @@
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::digest::{blake3, to_hex};
+use crate::save::{write_file, SaveOptions};
+use crate::v2;
+use crate::{ModelRegistry, ParseOptions};
+
+// ============================================
+// Parse Cache
+// ============================================
+//
+// A FlowCache keeps the parsed form of .flow files as .flowb snapshots in a
+// directory, one per source path. A snapshot is used as is when the file's
+// mtime and length are unchanged, and after a BLAKE3 check of the content
+// when only the mtime moved (a checkout or `touch`). Anything else, including
+// an unreadable snapshot, is a miss: the file is parsed and the snapshot
+// rewritten. Files are parsed strictly, as v2::load reads them. Failing to
+// write a snapshot never fails the load.
+//
+// Snapshots hold the document before `@ref` resolution, so a change in a
+// referenced file is picked up even when the referencing file is cached.
+
+pub struct FlowCache {
+    dir: PathBuf,
+}
+
+impl FlowCache {
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FlowCache { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // The parsed document at `path`, from the snapshot when it is current.
+    pub fn load(&self, path: &str) -> io::Result<Value> {
+        let source = fs::canonicalize(path)?;
+        let meta = fs::metadata(&source)?;
+        let mtime = meta.modified()?.duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        let entry = self.entry_path(&source);
+        let snapshot = self.read_entry(&entry);
+
+        if let Some(s) = &snapshot {
+            if s["mtime"] == mtime && s["len"] == meta.len() {
+                return Ok(s["doc"].clone());
+            }
+        }
+        let bytes = fs::read(&source)?;
+        let hash = to_hex(&blake3(&bytes));
+        let doc = match snapshot {
+            Some(mut s) if s["hash"] == hash.as_str() => s["doc"].take(),
+            _ => v2::parse_bytes(&bytes, &ParseOptions::default()).map_err(|e| io::Error::from(v2::at_path(e, path)))?.value,
+        };
+        let record = json!({
+            "path": source.display().to_string(),
+            "mtime": mtime,
+            "len": meta.len(),
+            "hash": hash,
+            "doc": doc,
+        });
+        let _ = self.write_entry(&entry, &record);
+        Ok(record["doc"].clone())
+    }
+
+    // Removes every snapshot in the cache directory.
+    pub fn clear(&self) -> io::Result<()> {
+        for item in fs::read_dir(&self.dir)? {
+            let p = item?.path();
+            if p.extension().is_some_and(|e| e == "flowb") {
+                fs::remove_file(p)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn entry_path(&self, source: &Path) -> PathBuf {
+        let name = to_hex(&blake3(source.display().to_string().as_bytes()));
+        self.dir.join(format!("{}.flowb", &name[..32]))
+    }
+
+    fn read_entry(&self, entry: &Path) -> Option<Value> {
+        let data = fs::read(entry).ok()?;
+        let v: Value = rmp_serde::from_slice(&data).ok()?;
+        v.get("doc")?;
+        Some(v)
+    }
+
+    // Saved atomically, so readers never see half a snapshot.
+    fn write_entry(&self, entry: &Path, record: &Value) -> io::Result<()> {
+        let buf = rmp_serde::to_vec(record).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_file(entry, &buf, &SaveOptions::new().atomic(true))
+    }
+}
+
+#[deprecated(note = "use v2::load_cached")]
+pub fn LoadFlowCached(path: &str, cache: &FlowCache) -> Result<Value, io::Error> {
+    Ok(v2::load_cached(path, cache)?)
+}
+
+#[deprecated(note = "use v2::load_with_model_cached")]
+pub fn LoadFlowWithModelCached(path: &str, registry: Option<&ModelRegistry>, cache: &FlowCache) -> Result<Value, io::Error> {
+    Ok(v2::load_with_model_cached(path, registry, cache)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    fn setup(name: &str) -> (FlowCache, String) {
+        let dir = std::env::temp_dir().join(format!("flowdoc-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = FlowCache::new(dir.join("cache")).unwrap();
+        let path = dir.join("doc.flow").display().to_string();
+        fs::write(&path, "a = 1\n").unwrap();
+        (cache, path)
+    }
+
+    // Replaces the snapshot's document, so a load that returns it must have
+    // used the snapshot.
+    fn tamper(cache: &FlowCache, path: &str) {
+        let entry = cache.entry_path(&fs::canonicalize(path).unwrap());
+        let mut record = cache.read_entry(&entry).unwrap();
+        record["doc"] = json!({"cached": true});
+        cache.write_entry(&entry, &record).unwrap();
+    }
+
+    fn set_mtime(path: &str, time: SystemTime) {
+        File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+    }
+
+    #[test]
+    fn unchanged_files_come_from_the_snapshot() {
+        let (cache, path) = setup("hit");
+        assert_eq!(cache.load(&path).unwrap(), json!({"a": 1}));
+        tamper(&cache, &path);
+        assert_eq!(cache.load(&path).unwrap(), json!({"cached": true}));
+    }
+
+    #[test]
+    fn a_new_mtime_checks_the_hash() {
+        let (cache, path) = setup("mtime");
+        cache.load(&path).unwrap();
+        let then = fs::metadata(&path).unwrap().modified().unwrap();
+
+        // touched: same content, so the snapshot is still used
+        tamper(&cache, &path);
+        set_mtime(&path, then + Duration::from_secs(10));
+        assert_eq!(cache.load(&path).unwrap(), json!({"cached": true}));
+
+        // same length, different content
+        tamper(&cache, &path);
+        fs::write(&path, "a = 2\n").unwrap();
+        set_mtime(&path, then + Duration::from_secs(20));
+        assert_eq!(cache.load(&path).unwrap(), json!({"a": 2}));
+        assert_eq!(cache.load(&path).unwrap(), json!({"a": 2}));
+    }
+
+    #[test]
+    fn a_new_length_reparses() {
+        let (cache, path) = setup("len");
+        cache.load(&path).unwrap();
+        let then = fs::metadata(&path).unwrap().modified().unwrap();
+        tamper(&cache, &path);
+        fs::write(&path, "a = 10\n").unwrap();
+        set_mtime(&path, then);
+        assert_eq!(cache.load(&path).unwrap(), json!({"a": 10}));
+    }
+
+    #[test]
+    fn damaged_snapshots_and_clear() {
+        let (cache, path) = setup("damaged");
+        cache.load(&path).unwrap();
+        let entry = cache.entry_path(&fs::canonicalize(&path).unwrap());
+        fs::write(&entry, b"\xc1 not msgpack").unwrap();
+        assert_eq!(cache.load(&path).unwrap(), json!({"a": 1}));
+        assert!(cache.read_entry(&entry).is_some());
+
+        cache.clear().unwrap();
+        assert!(!entry.exists());
+        fs::write(&path, "a = \"open\n").unwrap();
+        assert_eq!(cache.load(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(!entry.exists());
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// ============================================
+// Cancellation
+// ============================================
+//
+// A CancelToken is handed to a parse through ParseOptions and checked every
+// few hundred lines, so a huge or pathological input stops soon after
+// `cancel` is called from another thread or after its deadline passes.
+// Clones share the flag: cancelling one cancels all of them.
+
+// lines between two checks
+pub(crate) const CHECK_EVERY: usize = 256;
+
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.check().is_err()
+    }
+
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.flag.load(Ordering::Acquire) {
+            return Err(Cancelled { timed_out: false });
+        }
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(Cancelled { timed_out: true }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled {
+    // the deadline passed, rather than `cancel` being called
+    pub timed_out: bool,
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.timed_out {
+            f.write_str("parse deadline exceeded")
+        } else {
+            f.write_str("parse cancelled")
+        }
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl From<Cancelled> for io::Error {
+    fn from(e: Cancelled) -> Self {
+        let kind = if e.timed_out { io::ErrorKind::TimedOut } else { io::ErrorKind::Interrupted };
+        io::Error::new(kind, e)
+    }
+}
+
+// Checks `token` on every CHECK_EVERY-th call.
+pub(crate) fn checkpoint(token: Option<&CancelToken>, count: usize) -> Result<(), Cancelled> {
+    match token {
+        Some(token) if count.is_multiple_of(CHECK_EVERY) => token.check(),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,91 @@
+use serde_json::Value;
+
+// ============================================
+// Canonical Form
+// ============================================
+//
+// Compact JSON with keys sorted by code point, no insignificant whitespace
+// and one spelling per number: integral floats are written as integers
+// (`8080.0` and `8080` are the same value), `-0` as `0`, and other floats in
+// Rust's shortest round-trip form. Strings use JSON escaping. Two documents
+// that parse to equal values always produce identical bytes.
+
+pub fn canonicalize(val: &Value) -> String {
+    let mut out = String::new();
+    write_canonical(val, &mut out);
+    out
+}
+
+fn write_canonical(val: &Value, out: &mut String) {
+    match val {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => out.push_str(&canonical_number(n)),
+        Value::String(s) => write_string(s, out),
+        Value::Array(items) => {
+            out.push('[');
+            for (i, v) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(v, out);
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, (k, v)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(k, out);
+                out.push(':');
+                write_canonical(v, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+pub(crate) fn canonical_number(n: &serde_json::Number) -> String {
+    if n.is_i64() || n.is_u64() {
+        return n.to_string();
+    }
+    let f = n.as_f64().unwrap_or(0.0);
+    if f == 0.0 {
+        return "0".to_string();
+    }
+    // integral values that fit exactly in an i64 drop the fraction
+    if f.fract() == 0.0 && f.abs() < 9.2e18 {
+        return (f as i64).to_string();
+    }
+    format!("{}", f)
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push_str(&serde_json::to_string(s).unwrap_or_default());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn fixed_output() {
+        let doc = json!({ "b": [1, 2.0, -0.0, 0.1, 1e21, "x\"y\n"], "a": { "z": null, "é": true, "B": false }, "": 1.5 });
+        assert_eq!(canonicalize(&doc), r#"{"":1.5,"a":{"B":false,"z":null,"é":true},"b":[1,2,0,0.1,1000000000000000000000,"x\"y\n"]}"#);
+        assert_eq!(canonicalize(&json!(u64::MAX)), "18446744073709551615");
+        assert_eq!(canonicalize(&json!(-3.25)), "-3.25");
+    }
+
+    #[test]
+    fn equal_values_have_equal_bytes() {
+        let a: Value = serde_json::from_str(r#"{ "port": 8080.0, "tags": ["a", "b"], "nested": { "y": 1, "x": 2 } }"#).unwrap();
+        let b: Value = serde_json::from_str(r#"{"nested":{"x":2,"y":1},"tags":["a","b"],"port":8080}"#).unwrap();
+        assert_eq!(canonicalize(&a), canonicalize(&b));
+        assert_ne!(canonicalize(&json!({ "tags": ["b", "a"] })), canonicalize(&json!({ "tags": ["a", "b"] })));
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{ModelDefinition, ModelRegistry};
+
+// ============================================
+// Editor Completion
+// ============================================
+//
+// Given the source text of a document and a cursor (byte offset), suggests
+// the model fields that can be written at that point. The model is the one
+// named by `use_model`, or every registered model when there is none. Keys
+// already present in the block under the cursor are not suggested again.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionItem {
+    pub label: String,
+    pub alias: String,
+    pub field_type: String,
+    pub description: Option<String>,
+    pub model: String,
+    // text to insert in place of `replace_start..replace_end`
+    pub insert_text: String,
+    pub replace_start: usize,
+    pub replace_end: usize,
+}
+
+pub fn completions(registry: &ModelRegistry, source: &str, cursor: usize) -> Vec<CompletionItem> {
+    let mut cursor = cursor.min(source.len());
+    while !source.is_char_boundary(cursor) {
+        cursor -= 1;
+    }
+    let line_start = source[..cursor].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let before = &source[line_start..cursor];
+
+    if let Some((key, value)) = before.split_once('=') {
+        if key.trim() == "use_model" {
+            return model_name_completions(registry, value, cursor);
+        }
+        return Vec::new();
+    }
+    let prefix_len = before.chars().rev().take_while(|c| is_key_char(*c)).map(char::len_utf8).sum::<usize>();
+    let prefix = &before[before.len() - prefix_len..];
+    if !before[..before.len() - prefix_len].trim().is_empty() {
+        return Vec::new();
+    }
+    let line_end = source[cursor..].find('\n').map(|i| cursor + i).unwrap_or(source.len());
+    let suffix_len = source[cursor..line_end].chars().take_while(|c| is_key_char(*c)).map(char::len_utf8).sum::<usize>();
+    let indent = indent_of(before);
+    let present = sibling_keys(source, line_start, indent);
+
+    let models: Vec<&ModelDefinition> = match used_model(source).and_then(|name| registry.get_model(&name)) {
+        Some(model) => vec![model],
+        None => {
+            let mut all: Vec<&ModelDefinition> = registry.models().collect();
+            all.sort_by(|a, b| a.name.cmp(&b.name));
+            all
+        }
+    };
+
+    let mut items = Vec::new();
+    for model in models {
+        let mut fields: Vec<_> = model.fields.values().collect();
+        fields.sort_by(|a, b| a.full_name.cmp(&b.full_name));
+        for field in fields {
+            if present.iter().any(|k| *k == field.full_name || *k == field.alias) {
+                continue;
+            }
+            if !field.full_name.starts_with(prefix) && !field.alias.starts_with(prefix) {
+                continue;
+            }
+            let key = if field.alias.is_empty() { &field.full_name } else { &field.alias };
+            items.push(CompletionItem {
+                label: field.full_name.clone(),
+                alias: field.alias.clone(),
+                field_type: field.field_type.clone(),
+                description: field.description.clone(),
+                model: model.name.clone(),
+                insert_text: format!("{} = ", key),
+                replace_start: cursor - prefix_len,
+                replace_end: cursor + suffix_len,
+            });
+        }
+    }
+    items
+}
+
+fn model_name_completions(registry: &ModelRegistry, typed: &str, cursor: usize) -> Vec<CompletionItem> {
+    let prefix = typed.trim_start();
+    let mut names: Vec<&str> = registry.models().map(|m| m.name.as_str()).filter(|n| n.starts_with(prefix)).collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| CompletionItem {
+            label: name.to_string(),
+            alias: String::new(),
+            field_type: "model".to_string(),
+            description: None,
+            model: name.to_string(),
+            insert_text: name.to_string(),
+            replace_start: cursor - prefix.len(),
+            replace_end: cursor,
+        })
+        .collect()
+}
+
+fn is_key_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '$'
+}
+
+fn indent_of(line: &str) -> usize {
+    line.replace('\t', "  ").chars().take_while(|c| *c == ' ').count() / 2
+}
+
+fn key_of(line: &str) -> Option<&str> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return None;
+    }
+    match trimmed.find('=') {
+        Some(pos) => Some(trimmed[..pos].trim()),
+        None => trimmed.strip_suffix(':').map(str::trim),
+    }
+}
+
+// Keys on the other lines of the block that contains the line at `line_start`.
+fn sibling_keys(source: &str, line_start: usize, indent: usize) -> Vec<&str> {
+    let mut keys = Vec::new();
+    let above = source[..line_start].lines().rev();
+    let below = source[line_start..].lines().skip(1);
+    for lines in [Box::new(above) as Box<dyn Iterator<Item = &str>>, Box::new(below)] {
+        for line in lines {
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            let level = indent_of(line);
+            if level < indent {
+                break;
+            }
+            if level == indent {
+                if let Some(k) = key_of(line) {
+                    keys.push(k);
+                }
+            }
+        }
+    }
+    keys
+}
+
+fn used_model(source: &str) -> Option<String> {
+    source.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        if indent_of(line) == 0 && key.trim() == "use_model" {
+            Some(value.split('#').next().unwrap_or("").trim().trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FieldDefinition;
+
+    fn registry() -> ModelRegistry {
+        let mut registry = ModelRegistry::new();
+        for (name, fields) in [("server", &[("server_port", "port", "int"), ("server_host", "host", "string")][..]), ("client", &[("timeout_ms", "", "int")][..])] {
+            let mut model = ModelDefinition::new(name.to_string());
+            for (full_name, alias, field_type) in fields {
+                model.add_field(FieldDefinition {
+                    full_name: full_name.to_string(),
+                    alias: alias.to_string(),
+                    field_type: field_type.to_string(),
+                    description: Some(format!("the {}", full_name)),
+                    ..Default::default()
+                });
+            }
+            registry.register_model(model);
+        }
+        registry
+    }
+
+    fn labels(items: &[CompletionItem]) -> Vec<&str> {
+        items.iter().map(|i| i.label.as_str()).collect()
+    }
+
+    #[test]
+    fn fields_of_the_used_model() {
+        let registry = registry();
+        let source = "use_model = server\nho";
+        let items = completions(&registry, source, source.len());
+        assert_eq!(labels(&items), ["server_host"]);
+        let item = &items[0];
+        assert_eq!((item.insert_text.as_str(), item.replace_start, item.replace_end), ("host = ", source.len() - 2, source.len()));
+        assert_eq!((item.field_type.as_str(), item.description.as_deref()), ("string", Some("the server_host")));
+
+        // a key already in the block is not offered again
+        let source = "use_model = server\nport = 1\n";
+        assert_eq!(labels(&completions(&registry, source, source.len())), ["server_host"]);
+    }
+
+    #[test]
+    fn every_model_without_use_model() {
+        let registry = registry();
+        assert_eq!(labels(&completions(&registry, "", 0)), ["timeout_ms", "server_host", "server_port"]);
+        // nothing after `=`, except for the model name itself
+        assert!(completions(&registry, "port = ", 7).is_empty());
+        let source = "use_model = se";
+        let items = completions(&registry, source, source.len());
+        assert_eq!(labels(&items), ["server"]);
+        assert_eq!(items[0].replace_start, source.len() - 2);
+    }
+}
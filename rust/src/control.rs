@@ -0,0 +1,194 @@
+use std::borrow::Cow;
+
+use crate::diagnostic::{ParseError, Span};
+use crate::strip_comment;
+
+// ============================================
+// Control Characters
+// ============================================
+//
+// Documents from outside sources can carry characters that are harmless to
+// the parser but not to whatever prints the values later: an ESC starting a
+// terminal sequence, a carriage return that rewrites a log line, a NUL that
+// truncates it. ParseOptions::control chooses what happens to them:
+//
+//   Allow    kept as they are (the default)
+//   Reject   the parse fails at the first one
+//   Strip    removed
+//   Escape   written out as `\u{1b}`
+//
+// The characters are the C0 controls other than tab, DEL and the C1
+// controls. Values are not unescaped, so `\u001b` in a file is six ordinary
+// characters; only a `\u` escape that could never name a character, a
+// surrogate like `\ud800` or anything past `\u{10ffff}`, is treated the same
+// way, and Escape doubles its backslash. The policy looks at everything
+// before a comment, keys included, and leaves line endings alone.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ControlPolicy {
+    #[default]
+    Allow,
+    Reject,
+    Strip,
+    Escape,
+}
+
+pub(crate) fn is_control(c: char) -> bool {
+    c.is_control() && c != '\t'
+}
+
+enum Found {
+    Control(char),
+    // the escape's length in bytes
+    InvalidEscape(usize),
+}
+
+// The first control character or invalid escape in `text`, by byte offset.
+fn find(text: &str, from: usize) -> Option<(usize, Found)> {
+    let bytes = text.as_bytes();
+    let mut backslashes = 0;
+    for (i, c) in text[from..].char_indices().map(|(i, c)| (i + from, c)) {
+        if is_control(c) {
+            return Some((i, Found::Control(c)));
+        }
+        if c == 'u' && backslashes % 2 == 1 {
+            if let Some(len) = invalid_escape(&bytes[i + 1..]) {
+                return Some((i - 1, Found::InvalidEscape(len + 2)));
+            }
+        }
+        backslashes = if c == '\\' { backslashes + 1 } else { 0 };
+    }
+    None
+}
+
+// The length of the digits after `\u` when they form an escape that names
+// no character: four hex digits or braced ones.
+fn invalid_escape(rest: &[u8]) -> Option<usize> {
+    let (digits, len) = if rest.first() == Some(&b'{') {
+        let close = rest.iter().position(|b| *b == b'}')?;
+        (&rest[1..close], close + 1)
+    } else {
+        (rest.get(..4)?, 4)
+    };
+    if digits.is_empty() || digits.len() > 8 || !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let code = u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    char::from_u32(code).is_none().then_some(len)
+}
+
+// `text` with the policy applied, borrowed when nothing changes.
+pub(crate) fn apply(text: &str, policy: ControlPolicy) -> Result<Cow<'_, str>, ParseError> {
+    if policy == ControlPolicy::Allow {
+        return Ok(Cow::Borrowed(text));
+    }
+    let Cow::Owned(mut out) = apply_once(text, policy)? else {
+        return Ok(Cow::Borrowed(text));
+    };
+    // what is left around a stripped character can form a new escape, as
+    // `\u` ESC `d800` does, so stripping goes on until nothing changes
+    if policy == ControlPolicy::Strip {
+        while let Cow::Owned(next) = apply_once(&out, policy)? {
+            out = next;
+        }
+    }
+    Ok(Cow::Owned(out))
+}
+
+fn apply_once(text: &str, policy: ControlPolicy) -> Result<Cow<'_, str>, ParseError> {
+    let mut out = String::new();
+    // how much of `text` is already in `out`
+    let mut copied = 0;
+    let mut start = 0;
+    for (n, raw) in text.split_inclusive('\n').enumerate() {
+        let end = start + raw.len();
+        let line = raw.strip_suffix('\n').unwrap_or(raw);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let content = strip_comment(line);
+        let mut at = 0;
+        while let Some((i, found)) = find(content, at) {
+            let len = match found {
+                Found::Control(c) => c.len_utf8(),
+                Found::InvalidEscape(len) => len,
+            };
+            if policy == ControlPolicy::Reject {
+                let (message, help) = match found {
+                    Found::Control(c) => (format!("control character U+{:04X}", c as u32), "remove it, or parse with ControlPolicy::Strip or ControlPolicy::Escape"),
+                    Found::InvalidEscape(_) => (format!("invalid unicode escape `{}`", &content[i..i + len]), "an escape must name a character; write `\\\\u` for a literal backslash-u"),
+                };
+                let span = Span { line: n + 1, column: content[..i].chars().count() + 1, len: content[i..i + len].chars().count() };
+                return Err(ParseError { span, message, help: Some(help.to_string()) });
+            }
+            out.push_str(&text[copied..start + i]);
+            match (policy, found) {
+                (ControlPolicy::Escape, Found::Control(c)) => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                (ControlPolicy::Escape, Found::InvalidEscape(_)) => {
+                    out.push('\\');
+                    out.push_str(&content[i..i + len]);
+                }
+                _ => {}
+            }
+            copied = start + i + len;
+            at = i + len;
+        }
+        start = end;
+    }
+    if copied == 0 {
+        return Ok(Cow::Borrowed(text));
+    }
+    out.push_str(&text[copied..]);
+    Ok(Cow::Owned(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn applied(text: &str, policy: ControlPolicy) -> String {
+        apply(text, policy).unwrap().into_owned()
+    }
+
+    #[test]
+    fn each_policy() {
+        let text = "title = \"a\u{1b}[2Jb\" # \u{7}kept\r\nkey\u{0} = x\u{85}\tz\n";
+        assert_eq!(applied(text, ControlPolicy::Allow), text);
+        assert_eq!(applied(text, ControlPolicy::Strip), "title = \"a[2Jb\" # \u{7}kept\r\nkey = x\tz\n");
+        assert_eq!(applied(text, ControlPolicy::Escape), "title = \"a\\u{1b}[2Jb\" # \u{7}kept\r\nkey\\u{0} = x\\u{85}\tz\n");
+        let err = apply(text, ControlPolicy::Reject).unwrap_err();
+        assert_eq!((err.span, err.message.as_str()), (Span { line: 1, column: 11, len: 1 }, "control character U+001B"));
+        assert!(matches!(apply("plain = text\n", ControlPolicy::Strip), Ok(Cow::Borrowed(_))));
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        let text = "a = \"\\ud800 \\u{110000} \\u00e9 \\\\ud800 \\u{1f600} \\uzzzz\"\n";
+        assert_eq!(applied(text, ControlPolicy::Strip), "a = \"  \\u00e9 \\\\ud800 \\u{1f600} \\uzzzz\"\n");
+        assert_eq!(applied(text, ControlPolicy::Escape), "a = \"\\\\ud800 \\\\u{110000} \\u00e9 \\\\ud800 \\u{1f600} \\uzzzz\"\n");
+        let err = apply(text, ControlPolicy::Reject).unwrap_err();
+        assert_eq!((err.span.column, err.span.len, err.message.as_str()), (6, 6, "invalid unicode escape `\\ud800`"));
+        // stripping the ESC joins the rest into an escape, stripped in turn
+        assert_eq!(applied("a = \\u\u{1b}d800x\n", ControlPolicy::Strip), "a = x\n");
+    }
+
+    // Random lines built from the characters the policy cares about: no
+    // panics, and what Strip and Escape leave behind passes Reject.
+    #[test]
+    fn random_input() {
+        const PIECES: &[&str] = &["a", " ", "=", "\"", "#", "\\", "u", "{", "}", "d800", "110000", "41", "\u{1b}", "\u{0}", "\r", "\n", "\u{9f}", "é", "🦀", "\t"];
+        let mut state = 0x2545f4914f6cdd1du64;
+        for _ in 0..2000 {
+            let mut text = String::new();
+            for _ in 0..24 {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                text.push_str(PIECES[(state % PIECES.len() as u64) as usize]);
+            }
+            let _ = apply(&text, ControlPolicy::Reject);
+            for policy in [ControlPolicy::Strip, ControlPolicy::Escape] {
+                let out = applied(&text, policy);
+                assert!(apply(&out, ControlPolicy::Reject).is_ok(), "{:?} left {:?} from {:?}", policy, out, text);
+            }
+        }
+    }
+}
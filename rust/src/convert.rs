@@ -0,0 +1,224 @@
+use serde_json::{Map, Value};
+
+use crate::canonical::canonical_number;
+use crate::path::FlowPath;
+use crate::StringifyOptions;
+
+// ============================================
+// Conversion Options
+// ============================================
+//
+// How v2::flow_to_json_with and v2::json_to_flow_with write their output.
+// JSON is pretty-printed with two-space indentation, as ConvertFlowToJSON
+// does, or compact on one line. Its object keys are sorted, or sorted with
+// plain values ahead of objects and arrays, the order deterministic .flow
+// output uses. Numbers are written as parsed or in the canonical form, where
+// `8080.0` is `8080`.
+//
+// Null has no .flow spelling. By default a null object entry or array
+// element is left out; `Keep` writes it as `null`, which reads back as the
+// string "null", and `Reject` fails with FlowError::Null. The same rule
+// applies in both directions.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JsonStyle {
+    #[default]
+    Pretty,
+    Compact,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyOrder {
+    #[default]
+    Sorted,
+    ValuesFirst,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NullHandling {
+    #[default]
+    Omit,
+    Keep,
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumberFormat {
+    #[default]
+    AsParsed,
+    Canonical,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    pub json: JsonStyle,
+    // of JSON output; .flow output follows `stringify.deterministic`
+    pub key_order: KeyOrder,
+    pub nulls: NullHandling,
+    pub numbers: NumberFormat,
+    // redaction in both directions, and how .flow is written
+    pub stringify: StringifyOptions,
+}
+
+impl ConvertOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compact(mut self, on: bool) -> Self {
+        self.json = if on { JsonStyle::Compact } else { JsonStyle::Pretty };
+        self
+    }
+
+    pub fn key_order(mut self, order: KeyOrder) -> Self {
+        self.key_order = order;
+        self
+    }
+
+    pub fn nulls(mut self, nulls: NullHandling) -> Self {
+        self.nulls = nulls;
+        self
+    }
+
+    pub fn numbers(mut self, numbers: NumberFormat) -> Self {
+        self.numbers = numbers;
+        self
+    }
+
+    pub fn stringify(mut self, stringify: StringifyOptions) -> Self {
+        self.stringify = stringify;
+        self
+    }
+}
+
+// `val` with the null and number rules applied, or the path of the first
+// null when they are rejected. A null kept for .flow output becomes the
+// string it reads back as.
+pub(crate) fn prepare(val: &Value, options: &ConvertOptions, for_flow: bool) -> Result<Value, FlowPath> {
+    Ok(prepare_at(val, &mut FlowPath::root(), options, for_flow)?.unwrap_or(Value::Null))
+}
+
+fn prepare_at(val: &Value, path: &mut FlowPath, options: &ConvertOptions, for_flow: bool) -> Result<Option<Value>, FlowPath> {
+    Ok(Some(match val {
+        Value::Null => match options.nulls {
+            NullHandling::Omit => return Ok(None),
+            NullHandling::Reject => return Err(path.clone()),
+            NullHandling::Keep if for_flow => Value::String("null".to_string()),
+            NullHandling::Keep => Value::Null,
+        },
+        Value::Number(n) if options.numbers == NumberFormat::Canonical => {
+            serde_json::from_str(&canonical_number(n)).unwrap_or_else(|_| val.clone())
+        }
+        Value::Object(map) => {
+            let mut out = Map::new();
+            for (k, v) in map {
+                path.push_key(k);
+                let kept = prepare_at(v, path, options, for_flow)?;
+                path.pop();
+                if let Some(kept) = kept {
+                    out.insert(k.clone(), kept);
+                }
+            }
+            Value::Object(out)
+        }
+        Value::Array(items) => {
+            let mut out = Vec::new();
+            for (i, v) in items.iter().enumerate() {
+                path.push_index(i);
+                out.extend(prepare_at(v, path, options, for_flow)?);
+                path.pop();
+            }
+            Value::Array(out)
+        }
+        _ => val.clone(),
+    }))
+}
+
+pub(crate) fn write_json(val: &Value, options: &ConvertOptions) -> String {
+    let mut out = String::new();
+    write_value(val, options, 0, &mut out);
+    out
+}
+
+fn write_value(val: &Value, options: &ConvertOptions, depth: usize, out: &mut String) {
+    match val {
+        Value::Object(map) if !map.is_empty() => {
+            let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            if options.key_order == KeyOrder::ValuesFirst {
+                entries.sort_by_key(|(_, v)| v.is_object() || v.is_array());
+            }
+            write_block(out, options, depth, ('{', '}'), entries, |(k, v), out| {
+                out.push_str(&serde_json::to_string(k).unwrap_or_default());
+                out.push_str(if options.json == JsonStyle::Pretty { ": " } else { ":" });
+                write_value(v, options, depth + 1, out);
+            });
+        }
+        Value::Array(items) if !items.is_empty() => {
+            write_block(out, options, depth, ('[', ']'), items.iter().collect(), |v, out| write_value(v, options, depth + 1, out));
+        }
+        _ => out.push_str(&serde_json::to_string(val).unwrap_or_default()),
+    }
+}
+
+fn write_block<T>(out: &mut String, options: &ConvertOptions, depth: usize, (open, close): (char, char), items: Vec<T>, mut write: impl FnMut(T, &mut String)) {
+    let pretty = options.json == JsonStyle::Pretty;
+    out.push(open);
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if pretty {
+            out.push('\n');
+            out.push_str(&"  ".repeat(depth + 1));
+        }
+        write(item, out);
+    }
+    if pretty {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    }
+    out.push(close);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v2::{self, FlowError};
+
+    const FLOW: &str = "name = api\nport = 8080.0\nserver:\n  hosts = [a, b]\n  tls = true\n";
+
+    #[test]
+    fn pretty_json() {
+        let expected = "{\n  \"name\": \"api\",\n  \"port\": 8080.0,\n  \"server\": {\n    \"hosts\": [\n      \"a\",\n      \"b\"\n    ],\n    \"tls\": true\n  }\n}";
+        assert_eq!(v2::flow_to_json_with(FLOW, &ConvertOptions::new()).unwrap(), expected);
+        assert_eq!(v2::flow_to_json_with(FLOW, &ConvertOptions::new()).unwrap(), v2::flow_to_json(FLOW).unwrap());
+        assert_eq!(v2::flow_to_json_with("", &ConvertOptions::new()).unwrap(), "{}");
+    }
+
+    #[test]
+    fn compact_canonical_values_first() {
+        let options = ConvertOptions::new().compact(true).numbers(NumberFormat::Canonical).key_order(KeyOrder::ValuesFirst);
+        assert_eq!(
+            v2::flow_to_json_with(FLOW, &options).unwrap(),
+            "{\"name\":\"api\",\"port\":8080,\"server\":{\"tls\":true,\"hosts\":[\"a\",\"b\"]}}"
+        );
+    }
+
+    #[test]
+    fn nulls_to_flow() {
+        let json = "{\"a\": null, \"b\": [1, null, 2], \"c\": {\"d\": null}}";
+        assert_eq!(v2::json_to_flow_with(json, &ConvertOptions::new()).unwrap(), "b = [1, 2]\nc:\n");
+        let kept = v2::json_to_flow_with(json, &ConvertOptions::new().nulls(NullHandling::Keep)).unwrap();
+        assert_eq!(kept, "a = null\nb = [1, null, 2]\nc:\n  d = null\n");
+        // which reads back as strings
+        assert_eq!(v2::parse(&kept).unwrap()["c"]["d"], Value::String("null".to_string()));
+        let deterministic = ConvertOptions::new().stringify(StringifyOptions::new().deterministic(true));
+        assert_eq!(v2::json_to_flow_with(json, &deterministic).unwrap(), "b = [1, 2]\n\nc:\n");
+
+        let err = v2::json_to_flow_with(json, &ConvertOptions::new().nulls(NullHandling::Reject)).unwrap_err();
+        assert!(matches!(&err, FlowError::Null(path) if path.to_string() == "a"), "{:?}", err);
+        assert_eq!(err.to_string(), "null value at 'a'");
+        assert!(matches!(v2::json_to_flow_with("[1]", &ConvertOptions::new()), Err(FlowError::NotAnObject)));
+    }
+}
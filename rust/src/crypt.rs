@@ -0,0 +1,282 @@
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::ChaCha20Poly1305;
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::digest::{blake3_keyed, to_hex};
+use crate::encoding::read_text;
+use crate::path::{FlowPath, PathGlob};
+use crate::{canonicalize, parse_document};
+
+// ============================================
+// Field-level Encryption
+// ============================================
+//
+// Selected values are replaced by `enc:v1:<base64>` strings holding
+// nonce || ciphertext || tag from ChaCha20-Poly1305 (RFC 8439). The
+// plaintext is the value's canonical JSON, so types survive the round trip,
+// and the value's path is bound in as associated data: a ciphertext copied
+// to another path fails to decrypt.
+//
+// Nonces are synthetic (a keyed BLAKE3 hash of path and plaintext), so
+// encrypting the same value at the same path always yields the same text.
+// That keeps re-encrypted files diff-clean at the cost of revealing when
+// a value is unchanged.
+
+const TAG_PREFIX: &str = "enc:v1:";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CryptError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for CryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+impl std::error::Error for CryptError {}
+
+#[derive(Clone)]
+pub struct FieldKey([u8; 32]);
+
+impl FieldKey {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        FieldKey(bytes)
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self, CryptError> {
+        let hex = hex.trim();
+        let err = || CryptError { path: String::new(), message: "key must be 64 hex digits".to_string() };
+        if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(err());
+        }
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| err())?;
+        }
+        Ok(FieldKey(key))
+    }
+
+    // A short fingerprint for logs, safe to print.
+    pub fn id(&self) -> String {
+        to_hex(&blake3_keyed(&self.0, b"flowdoc key id")[..4])
+    }
+
+    fn nonce_key(&self) -> [u8; 32] {
+        blake3_keyed(&self.0, b"flowdoc field encryption nonce")
+    }
+}
+
+impl fmt::Debug for FieldKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FieldKey({})", self.id())
+    }
+}
+
+pub fn is_encrypted(val: &Value) -> bool {
+    val.as_str().map(|s| s.starts_with(TAG_PREFIX)).unwrap_or(false)
+}
+
+pub fn encrypt_fields(val: &Value, globs: &[PathGlob], key: &FieldKey) -> Value {
+    encrypt_at(val, &mut FlowPath::root(), globs, key)
+}
+
+fn encrypt_at(val: &Value, path: &mut FlowPath, globs: &[PathGlob], key: &FieldKey) -> Value {
+    if !path.is_root() && !is_encrypted(val) && globs.iter().any(|g| g.matches(path)) {
+        return Value::String(encrypt_value(val, &path.to_string(), key));
+    }
+    if !globs.iter().any(|g| g.may_match_below(path)) {
+        return val.clone();
+    }
+    match val {
+        Value::Object(map) => {
+            let mut out = Map::new();
+            for (k, v) in map {
+                path.push_key(k);
+                out.insert(k.clone(), encrypt_at(v, path, globs, key));
+                path.pop();
+            }
+            Value::Object(out)
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, v)| {
+                    path.push_index(i);
+                    let r = encrypt_at(v, path, globs, key);
+                    path.pop();
+                    r
+                })
+                .collect(),
+        ),
+        _ => val.clone(),
+    }
+}
+
+// Replaces every `enc:v1:` value in the document with its plaintext.
+pub fn decrypt_fields(val: &Value, key: &FieldKey) -> Result<Value, CryptError> {
+    decrypt_at(val, &mut FlowPath::root(), key)
+}
+
+fn decrypt_at(val: &Value, path: &mut FlowPath, key: &FieldKey) -> Result<Value, CryptError> {
+    match val {
+        Value::String(s) if s.starts_with(TAG_PREFIX) => {
+            let aad = path.to_string();
+            decrypt_value(&s[TAG_PREFIX.len()..], &aad, key).map_err(|message| CryptError { path: aad, message })
+        }
+        Value::Object(map) => {
+            let mut out = Map::new();
+            for (k, v) in map {
+                path.push_key(k);
+                let r = decrypt_at(v, path, key);
+                path.pop();
+                out.insert(k.clone(), r?);
+            }
+            Ok(Value::Object(out))
+        }
+        Value::Array(items) => {
+            let mut out = Vec::with_capacity(items.len());
+            for (i, v) in items.iter().enumerate() {
+                path.push_index(i);
+                let r = decrypt_at(v, path, key);
+                path.pop();
+                out.push(r?);
+            }
+            Ok(Value::Array(out))
+        }
+        _ => Ok(val.clone()),
+    }
+}
+
+#[deprecated(note = "use v2::load_decrypted")]
+pub fn LoadFlowDecrypted(path: &str, key: &FieldKey) -> Result<Value, Box<dyn std::error::Error>> {
+    let s = read_text(path)?;
+    Ok(decrypt_fields(&parse_document(&s), key)?)
+}
+
+#[deprecated(note = "use v2::load_flowb_decrypted")]
+pub fn LoadFlowbDecrypted(path: &str, key: &FieldKey) -> Result<Value, Box<dyn std::error::Error>> {
+    let v = crate::v2::load_flowb(path)?;
+    Ok(decrypt_fields(&v, key)?)
+}
+
+fn encrypt_value(val: &Value, aad: &str, key: &FieldKey) -> String {
+    let plaintext = canonicalize(val);
+    let mut nonce_input = (aad.len() as u64).to_le_bytes().to_vec();
+    nonce_input.extend_from_slice(aad.as_bytes());
+    nonce_input.extend_from_slice(plaintext.as_bytes());
+    let mut nonce = [0u8; 12];
+    nonce.copy_from_slice(&blake3_keyed(&key.nonce_key(), &nonce_input)[..12]);
+    let (ciphertext, tag) = seal(&key.0, &nonce, aad.as_bytes(), plaintext.as_bytes());
+    let mut blob = nonce.to_vec();
+    blob.extend_from_slice(&ciphertext);
+    blob.extend_from_slice(&tag);
+    format!("{}{}", TAG_PREFIX, base64_encode(&blob))
+}
+
+fn decrypt_value(encoded: &str, aad: &str, key: &FieldKey) -> Result<Value, String> {
+    let blob = base64_decode(encoded).ok_or("ciphertext is not valid base64")?;
+    if blob.len() < 28 {
+        return Err("ciphertext is truncated".to_string());
+    }
+    let nonce: [u8; 12] = blob[..12].try_into().unwrap();
+    let tag: [u8; 16] = blob[blob.len() - 16..].try_into().unwrap();
+    let plaintext = open(&key.0, &nonce, aad.as_bytes(), &blob[12..blob.len() - 16], &tag)
+        .ok_or("authentication failed (wrong key, or value moved from another path)")?;
+    serde_json::from_slice(&plaintext).map_err(|e| format!("decrypted value is not valid: {}", e))
+}
+
+fn seal(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> (Vec<u8>, [u8; 16]) {
+    let mut buf = plaintext.to_vec();
+    let tag = ChaCha20Poly1305::new(key.into())
+        .encrypt_in_place_detached(nonce.into(), aad, &mut buf)
+        .expect("field values are far below the ChaCha20 length limit");
+    (buf, tag.into())
+}
+
+fn open(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8], tag: &[u8; 16]) -> Option<Vec<u8>> {
+    let mut buf = ciphertext.to_vec();
+    ChaCha20Poly1305::new(key.into()).decrypt_in_place_detached(nonce.into(), aad, &mut buf, tag.into()).ok()?;
+    Some(buf)
+}
+
+// ============================================
+// Base64 (RFC 4648, padded)
+// ============================================
+
+const B64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(B64[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in text.bytes() {
+        acc = (acc << 6) | B64.iter().position(|&b| b == c)? as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn key() -> FieldKey {
+        FieldKey::from_hex(&"11".repeat(32)).unwrap()
+    }
+
+    #[test]
+    fn round_trip_keeps_types() {
+        let doc = json!({"db": {"password": "hunter2", "n": 3, "hosts": ["a", "b"]}, "name": "svc"});
+        let globs = [PathGlob::parse("**.password").unwrap(), PathGlob::parse("db.n").unwrap(), PathGlob::parse("db.hosts").unwrap()];
+        let encrypted = encrypt_fields(&doc, &globs, &key());
+        assert!(is_encrypted(&encrypted["db"]["password"]) && is_encrypted(&encrypted["db"]["n"]) && is_encrypted(&encrypted["db"]["hosts"]));
+        assert_eq!(encrypted["name"], json!("svc"));
+        assert_eq!(encrypt_fields(&doc, &globs, &key()), encrypted);
+        assert_eq!(decrypt_fields(&encrypted, &key()), Ok(doc));
+    }
+
+    #[test]
+    fn ciphertext_format_is_stable() {
+        let doc = json!({"db": {"password": "hunter2"}});
+        let encrypted = encrypt_fields(&doc, &[PathGlob::parse("**.password").unwrap()], &key());
+        assert_eq!(encrypted["db"]["password"], json!("enc:v1:9Jw6gLAEELs1a6fKP3z8Qou9j3bxCi2kxJieZ0u/WCk6TzKQaw=="));
+    }
+
+    #[test]
+    fn moved_or_wrong_key_fails() {
+        let encrypted = encrypt_fields(&json!({"a": "secret"}), &[PathGlob::parse("a").unwrap()], &key());
+        let moved = json!({"b": encrypted["a"].clone()});
+        assert_eq!(decrypt_fields(&moved, &key()).unwrap_err().path, "b");
+        assert!(decrypt_fields(&encrypted, &FieldKey::from_bytes([2; 32])).is_err());
+    }
+}
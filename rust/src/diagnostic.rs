@@ -0,0 +1,357 @@
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::cancel::{self, CancelToken, Cancelled};
+use crate::path::FlowPath;
+use crate::trace::{self, Operation};
+use crate::{parse_tree_with, strip_comment, ParseOptions};
+
+// ============================================
+// Source Diagnostics
+// ============================================
+//
+// Errors that point into a document carry a Span: the 1-based line and
+// column (counted in characters) and the width of the offending text.
+// `render(source)` prints the line with that text underlined:
+//
+//   error: unterminated string
+//    --> config.flow:3:10
+//     |
+//   3 |   name = "api
+//     |          ^^^^
+//
+// Tabs are shown as two spaces, the same width the parser gives them.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub span: Span,
+    pub message: String,
+    pub help: Option<String>,
+}
+
+impl ParseError {
+    pub fn render(&self, source: &str) -> String {
+        render_snippet(None, source, Some(self.span), &self.message, self.help.as_deref())
+    }
+
+    pub fn render_named(&self, name: &str, source: &str) -> String {
+        render_snippet(Some(name), source, Some(self.span), &self.message, self.help.as_deref())
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.span.line, self.span.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub(crate) fn render_snippet(name: Option<&str>, source: &str, span: Option<Span>, message: &str, help: Option<&str>) -> String {
+    let mut out = format!("error: {}\n", message);
+    let Some(span) = span else {
+        if let Some(help) = help {
+            out.push_str(&format!("  = help: {}\n", help));
+        }
+        return out;
+    };
+    let text = source.split('\n').nth(span.line.saturating_sub(1)).unwrap_or("");
+    let text = text.strip_suffix('\r').unwrap_or(text);
+    let width = span.line.to_string().len();
+    let pad = " ".repeat(width);
+    match name {
+        Some(name) => out.push_str(&format!("{}--> {}:{}:{}\n", pad, name, span.line, span.column)),
+        None => out.push_str(&format!("{}--> {}:{}\n", pad, span.line, span.column)),
+    }
+    out.push_str(&format!("{} |\n", pad));
+    out.push_str(&format!("{} | {}\n", span.line, text.replace('\t', "  ")));
+
+    let chars: Vec<char> = text.chars().collect();
+    let start = span.column.saturating_sub(1).min(chars.len());
+    let end = (start + span.len).min(chars.len());
+    let shown = |cs: &[char]| cs.iter().map(|c| if *c == '\t' { 2 } else { 1 }).sum::<usize>();
+    let marks = shown(&chars[start..end]).max(1);
+    out.push_str(&format!("{} | {}{}\n", pad, " ".repeat(shown(&chars[..start])), "^".repeat(marks)));
+    if let Some(help) = help {
+        out.push_str(&format!("{} = help: {}\n", pad, help));
+    }
+    out
+}
+
+// ============================================
+// Source Lines
+// ============================================
+//
+// The line classification ParseFlow applies, keeping each line's number and
+// the positions of its key and value so they can be pointed at.
+
+pub(crate) struct SourceLine<'a> {
+    pub number: usize,
+    pub level: usize,
+    // leading whitespace that is not a whole number of levels
+    pub odd_indent: bool,
+    pub text: &'a str,
+    pub kind: LineKind<'a>,
+}
+
+pub(crate) enum LineKind<'a> {
+    Section { key: &'a str, colon: usize },
+    Pair { key: &'a str, eq: usize, value: &'a str },
+    Invalid { content: &'a str },
+}
+
+impl<'a> SourceLine<'a> {
+    // `part` must be a slice of `self.text`
+    pub fn span(&self, part: &str) -> Span {
+        let start = part.as_ptr() as usize - self.text.as_ptr() as usize;
+        self.span_at(start, part.chars().count())
+    }
+
+    pub fn span_at(&self, byte: usize, len: usize) -> Span {
+        Span { line: self.number, column: self.text[..byte].chars().count() + 1, len }
+    }
+
+    pub fn key(&self) -> Option<&'a str> {
+        match self.kind {
+            LineKind::Section { key, .. } | LineKind::Pair { key, .. } => Some(key),
+            LineKind::Invalid { .. } => None,
+        }
+    }
+
+    pub fn key_span(&self) -> Span {
+        match self.kind {
+            LineKind::Section { key: "", colon } => self.span_at(colon, 1),
+            LineKind::Pair { key: "", eq, .. } => self.span_at(eq, 1),
+            LineKind::Section { key, .. } | LineKind::Pair { key, .. } => self.span(key),
+            LineKind::Invalid { content } => self.span(content),
+        }
+    }
+}
+
+pub(crate) fn source_lines(text: &str) -> impl Iterator<Item = SourceLine<'_>> {
+    text.split('\n').enumerate().filter_map(|(i, raw)| {
+        let raw = raw.strip_suffix('\r').unwrap_or(raw);
+        let content = strip_comment(raw).trim_end();
+        let trimmed = content.trim_start();
+        if trimmed.is_empty() {
+            return None;
+        }
+        let leading: usize = content[..content.len() - trimmed.len()].chars().map(|c| if c == '\t' { 2 } else { 1 }).sum();
+        let offset = trimmed.as_ptr() as usize - raw.as_ptr() as usize;
+        let kind = if let Some(key) = trimmed.strip_suffix(':') {
+            LineKind::Section { key: key.trim(), colon: offset + key.len() }
+        } else if let Some(pos) = trimmed.find('=') {
+            LineKind::Pair { key: trimmed[..pos].trim(), eq: offset + pos, value: trimmed[pos + 1..].trim() }
+        } else {
+            LineKind::Invalid { content: trimmed }
+        };
+        Some(SourceLine { number: i + 1, level: leading / 2, odd_indent: leading % 2 == 1, text: raw, kind })
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct NodeSpan {
+    pub key: Span,
+    // the value of a `key = value` line; sections have none
+    pub value: Option<Span>,
+}
+
+// Where each object entry of the parsed document is written. When a key is
+// repeated the last occurrence wins, as it does in ParseFlow.
+pub(crate) fn source_spans(text: &str) -> BTreeMap<FlowPath, NodeSpan> {
+    let mut spans = BTreeMap::new();
+    let mut stack: Vec<(usize, FlowPath)> = vec![(0, FlowPath::root())];
+    for line in source_lines(text) {
+        let Some(key) = line.key() else { continue };
+        while stack.len() > 1 && stack.last().map(|(i, _)| *i).unwrap_or(0) > line.level {
+            stack.pop();
+        }
+        let path = stack.last().map(|(_, p)| p.child_key(key)).unwrap_or_default();
+        match line.kind {
+            LineKind::Section { .. } => {
+                spans.retain(|p: &FlowPath, _| !p.starts_with(&path));
+                spans.insert(path.clone(), NodeSpan { key: line.key_span(), value: None });
+                stack.push((line.level + 1, path));
+            }
+            LineKind::Pair { value, .. } => {
+                let value = if value.is_empty() { None } else { Some(line.span(value)) };
+                spans.insert(path, NodeSpan { key: line.key_span(), value });
+            }
+            LineKind::Invalid { .. } => {}
+        }
+    }
+    spans
+}
+
+// ============================================
+// Strict Parsing
+// ============================================
+//
+// ParseFlow never fails: lines it cannot read are skipped and repeated keys
+// overwrite each other. v2::parse reports the first such problem instead,
+// and otherwise returns exactly what ParseFlow would.
+
+#[deprecated(note = "use v2::parse")]
+pub fn ParseFlowStrict(text: &str) -> Result<Value, ParseError> {
+    parse_strict(text, &ParseOptions::default()).map_err(|e| match e {
+        StrictError::Parse(e) => e,
+        StrictError::Cancelled(_) => unreachable!("a parse without a token is never cancelled"),
+    })
+}
+
+pub(crate) enum StrictError {
+    Parse(ParseError),
+    Cancelled(Cancelled),
+}
+
+impl fmt::Display for StrictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StrictError::Parse(e) => write!(f, "{}", e),
+            StrictError::Cancelled(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<ParseError> for StrictError {
+    fn from(e: ParseError) -> Self {
+        StrictError::Parse(e)
+    }
+}
+
+impl From<Cancelled> for StrictError {
+    fn from(e: Cancelled) -> Self {
+        StrictError::Cancelled(e)
+    }
+}
+
+pub(crate) fn parse_strict(text: &str, options: &ParseOptions) -> Result<Value, StrictError> {
+    let mut span = trace::Span::start(Operation::Parse, None);
+    span.bytes(text.len());
+    let result = check_lines(text, options.cancel.as_ref()).and_then(|()| Ok(parse_tree_with(text, options)?));
+    span.finish_with(&result, |v| v);
+    result
+}
+
+fn check_lines(text: &str, cancel: Option<&CancelToken>) -> Result<(), StrictError> {
+    // each entry is the indent level of an object body and the keys defined
+    // in it so far, with the line they are on
+    let mut stack: Vec<(usize, HashMap<&str, usize>)> = vec![(0, HashMap::new())];
+    for (n, line) in source_lines(text).enumerate() {
+        cancel::checkpoint(cancel, n)?;
+        let error = |span: Span, message: &str| ParseError { span, message: message.to_string(), help: None };
+        let indent_span = || Span { line: line.number, column: 1, len: line.text.chars().take_while(|c| c.is_whitespace()).count() };
+        if line.odd_indent {
+            return Err(error(indent_span(), "indentation is not a multiple of two spaces").into());
+        }
+        while stack.len() > 1 && stack.last().map(|(i, _)| *i).unwrap_or(0) > line.level {
+            stack.pop();
+        }
+        let (level, keys) = stack.last_mut().expect("the root frame is never popped");
+        if line.level > *level {
+            let mut e = error(indent_span(), "unexpected indentation");
+            e.help = Some(format!("expected {} spaces", *level * 2));
+            return Err(e.into());
+        }
+        let key = match line.kind {
+            LineKind::Invalid { content } => {
+                let mut e = error(line.span(content), "expected `key = value` or `key:`");
+                e.help = Some("add `=` and a value, or end the line with `:` to start a section".to_string());
+                return Err(e.into());
+            }
+            LineKind::Section { key, .. } | LineKind::Pair { key, .. } if key.is_empty() => {
+                return Err(error(line.key_span(), "missing key").into());
+            }
+            LineKind::Section { key, .. } => key,
+            LineKind::Pair { key, value, .. } => {
+                check_value(&line, value)?;
+                key
+            }
+        };
+        if let Some(first) = keys.insert(key, line.number) {
+            let mut e = error(line.key_span(), &format!("duplicate key '{}'", key));
+            e.help = Some(format!("first defined on line {}", first));
+            return Err(e.into());
+        }
+        if let LineKind::Section { .. } = line.kind {
+            stack.push((line.level + 1, HashMap::new()));
+        }
+    }
+    Ok(())
+}
+
+fn check_value(line: &SourceLine<'_>, value: &str) -> Result<(), ParseError> {
+    let message = if value.starts_with('"') && (value.len() < 2 || !value.ends_with('"')) {
+        "unterminated string"
+    } else if value.starts_with('[') && !value.ends_with(']') {
+        "unterminated array"
+    } else {
+        return Ok(());
+    };
+    Err(ParseError { span: line.span(value), message: message.to_string(), help: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_document, validate, FieldDefinition, ModelDefinition, ModelRegistry};
+
+    fn strict(text: &str) -> ParseError {
+        match parse_strict(text, &ParseOptions::default()) {
+            Err(StrictError::Parse(e)) => e,
+            Err(StrictError::Cancelled(_)) => panic!("cancelled"),
+            Ok(v) => panic!("parsed as {}", v),
+        }
+    }
+
+    #[test]
+    fn snippets() {
+        let source = "server:\n  host = \"db\"\n  name = \"api\n";
+        let err = strict(source);
+        assert_eq!(err.to_string(), "line 3, column 10: unterminated string");
+        assert_eq!(
+            err.render_named("config.flow", source),
+            "error: unterminated string\n --> config.flow:3:10\n  |\n3 |   name = \"api\n  |          ^^^^\n"
+        );
+
+        let source = "a:\n\tb = [1,\n";
+        assert_eq!(strict(source).render(source), "error: unterminated array\n --> 2:6\n  |\n2 |   b = [1,\n  |       ^^^\n");
+    }
+
+    #[test]
+    fn strict_errors() {
+        let err = strict("a = 1\nb:\n  c = 1\n  c = 2\n");
+        assert_eq!((err.span, err.message.as_str()), (Span { line: 4, column: 3, len: 1 }, "duplicate key 'c'"));
+        assert_eq!(err.help.as_deref(), Some("first defined on line 3"));
+        assert_eq!(strict("a:\n  b = 1\n      c = 1\n").message, "unexpected indentation");
+        assert_eq!(strict(" a = 1\n").message, "indentation is not a multiple of two spaces");
+        assert_eq!(strict("a = 1\njust words\n").span, Span { line: 2, column: 1, len: 10 });
+        assert_eq!(strict("= 1\n").message, "missing key");
+        // a key may repeat in different sections
+        let text = "a:\n  x = 1\nb:\n  x = 2 # note\n";
+        assert_eq!(parse_strict(text, &ParseOptions::default()).ok(), Some(parse_document(text)));
+    }
+
+    #[test]
+    fn validation_snippets() {
+        let mut model = ModelDefinition::new("server".to_string());
+        model.add_field(FieldDefinition { full_name: "port".to_string(), field_type: "int".to_string(), ..Default::default() });
+        let mut registry = ModelRegistry::new();
+        registry.register_model(model);
+
+        let source = "use_model = server\nport = \"http\"\n";
+        let errors = validate(source, &registry);
+        assert_eq!(errors.len(), 1);
+        let rendered = errors[0].render_named("server.flow", source);
+        assert!(rendered.starts_with(&format!("error: {}\n --> server.flow:2:8\n", errors[0].message)), "{}", rendered);
+        assert!(rendered.ends_with("2 | port = \"http\"\n  |        ^^^^^^\n"), "{}", rendered);
+    }
+}
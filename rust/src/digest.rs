@@ -0,0 +1,95 @@
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::canonical::canonicalize;
+
+// ============================================
+// Content Digests
+// ============================================
+//
+// A digest is computed over `canonicalize(val)`, so it identifies the
+// document's content regardless of key order or formatting. The result is
+// written as `<algorithm>:<lowercase hex>`, e.g. `sha256:9f86d0...`.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+}
+
+impl Algorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Blake3 => "blake3",
+        }
+    }
+
+    pub fn hash(&self, data: &[u8]) -> [u8; 32] {
+        match self {
+            Algorithm::Sha256 => sha256(data),
+            Algorithm::Blake3 => blake3(data),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "").as_str() {
+            "sha256" => Ok(Algorithm::Sha256),
+            "blake3" => Ok(Algorithm::Blake3),
+            other => Err(format!("unknown digest algorithm '{}'", other)),
+        }
+    }
+}
+
+pub fn flow_digest(val: &Value, algorithm: Algorithm) -> String {
+    format!("{}:{}", algorithm.name(), to_hex(&algorithm.hash(canonicalize(val).as_bytes())))
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+pub(crate) fn blake3(data: &[u8]) -> [u8; 32] {
+    blake3::hash(data).into()
+}
+
+pub(crate) fn blake3_keyed(key: &[u8; 32], data: &[u8]) -> [u8; 32] {
+    blake3::keyed_hash(key, data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn known_vectors() {
+        assert_eq!(to_hex(&sha256(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(to_hex(&blake3(b"")), "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+    }
+
+    #[test]
+    fn digest_ignores_key_order() {
+        let a = json!({"b": [1, 2.5, "x"], "a": {"k": "v"}});
+        let b = json!({"a": {"k": "v"}, "b": [1, 2.5, "x"]});
+        assert_eq!(flow_digest(&a, Algorithm::Sha256), "sha256:f6946c8416d478624a92f07205613c7fba6b07bfdaa66a55daae7059935d2811");
+        assert_eq!(flow_digest(&a, Algorithm::Blake3), "blake3:62ba28a2608e64748fd634d11fac3827e85e66e21e52eff5df7615290c52ebd9");
+        assert_eq!(flow_digest(&a, Algorithm::Blake3), flow_digest(&b, Algorithm::Blake3));
+    }
+}
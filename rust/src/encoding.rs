@@ -0,0 +1,218 @@
+use std::fmt;
+use std::fs;
+use std::io;
+
+// ============================================
+// Text Encoding
+// ============================================
+//
+// Documents are UTF-8, but files written by other tools often carry a byte
+// order mark, arrive as UTF-16 or use CRLF line endings. `decode_text`
+// accepts all of these and returns UTF-8 text with `\n` line endings. UTF-16
+// is recognized by its BOM, or without one when the first character is ASCII
+// (`k\0` or `\0k`). Invalid input is reported with the byte offset of the
+// first bad sequence; `decode_text_lossy` instead replaces each bad sequence
+// with U+FFFD and returns the offsets it replaced.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodingError {
+    pub path: Option<String>,
+    pub encoding: Encoding,
+    // offset into the raw input, counting any BOM
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}: ", path)?;
+        }
+        write!(f, "invalid {} at byte {}: {}", self.encoding, self.offset, self.message)
+    }
+}
+
+impl std::error::Error for EncodingError {}
+
+impl From<EncodingError> for io::Error {
+    fn from(e: EncodingError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+pub fn detect_encoding(bytes: &[u8]) -> (Encoding, usize) {
+    match bytes {
+        [0xEF, 0xBB, 0xBF, ..] => (Encoding::Utf8, 3),
+        [0xFF, 0xFE, ..] => (Encoding::Utf16Le, 2),
+        [0xFE, 0xFF, ..] => (Encoding::Utf16Be, 2),
+        [a, 0, ..] if *a != 0 && a.is_ascii() => (Encoding::Utf16Le, 0),
+        [0, b, ..] if *b != 0 && b.is_ascii() => (Encoding::Utf16Be, 0),
+        _ => (Encoding::Utf8, 0),
+    }
+}
+
+pub fn decode_text(bytes: &[u8]) -> Result<String, EncodingError> {
+    decode(bytes, false).map(|(text, _)| text)
+}
+
+pub fn decode_text_lossy(bytes: &[u8]) -> (String, Vec<usize>) {
+    decode(bytes, true).unwrap_or_default()
+}
+
+// Lossy decoding never fails; strict decoding stops at the first bad sequence.
+pub(crate) fn decode(bytes: &[u8], lossy: bool) -> Result<(String, Vec<usize>), EncodingError> {
+    let (encoding, bom) = detect_encoding(bytes);
+    let body = &bytes[bom..];
+    let mut replaced = Vec::new();
+    let mut bad = |offset: usize, message: &str, text: &mut String| {
+        if !lossy {
+            return Err(EncodingError { path: None, encoding, offset: bom + offset, message: message.to_string() });
+        }
+        replaced.push(bom + offset);
+        text.push(char::REPLACEMENT_CHARACTER);
+        Ok(())
+    };
+    let mut text = String::with_capacity(body.len());
+    match encoding {
+        Encoding::Utf8 => {
+            let mut rest = body;
+            let mut offset = 0;
+            while !rest.is_empty() {
+                match std::str::from_utf8(rest) {
+                    Ok(s) => {
+                        text.push_str(s);
+                        break;
+                    }
+                    Err(e) => {
+                        let valid = e.valid_up_to();
+                        text.push_str(std::str::from_utf8(&rest[..valid]).unwrap_or_default());
+                        bad(offset + valid, "byte sequence is not valid UTF-8", &mut text)?;
+                        let skip = valid + e.error_len().unwrap_or(rest.len() - valid);
+                        rest = &rest[skip..];
+                        offset += skip;
+                    }
+                }
+            }
+        }
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let unit = |i: usize| match encoding {
+                Encoding::Utf16Le => u16::from_le_bytes([body[i], body[i + 1]]),
+                _ => u16::from_be_bytes([body[i], body[i + 1]]),
+            };
+            let even = body.len() - body.len() % 2;
+            let mut i = 0;
+            while i < even {
+                let u = unit(i);
+                if (0xD800..0xDC00).contains(&u) && i + 2 < even && (0xDC00..0xE000).contains(&unit(i + 2)) {
+                    let c = 0x10000 + (((u as u32) - 0xD800) << 10) + ((unit(i + 2) as u32) - 0xDC00);
+                    text.extend(char::from_u32(c));
+                    i += 4;
+                } else if let Some(c) = char::from_u32(u as u32) {
+                    text.push(c);
+                    i += 2;
+                } else {
+                    bad(i, "unpaired surrogate", &mut text)?;
+                    i += 2;
+                }
+            }
+            if even < body.len() {
+                bad(even, "odd number of bytes", &mut text)?;
+            }
+        }
+    }
+    Ok((normalize_newlines(text), replaced))
+}
+
+fn normalize_newlines(text: String) -> String {
+    if text.contains('\r') {
+        text.replace("\r\n", "\n")
+    } else {
+        text
+    }
+}
+
+// Reads and decodes a document file; encoding errors carry the path.
+pub(crate) fn read_text(path: &str) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+    decode_text(&bytes).map_err(|e| with_path(e, path))
+}
+
+pub(crate) fn with_path(e: EncodingError, path: &str) -> io::Error {
+    EncodingError { path: Some(path.to_string()), ..e }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str, big_endian: bool, bom: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        for unit in (if bom { "\u{feff}" } else { "" }).encode_utf16().chain(text.encode_utf16()) {
+            out.extend(if big_endian { unit.to_be_bytes() } else { unit.to_le_bytes() });
+        }
+        out
+    }
+
+    #[test]
+    fn boms_utf16_and_line_endings() {
+        let text = "name = \"café 🚀\"\r\nport = 80\r\n";
+        let expected = "name = \"café 🚀\"\nport = 80\n";
+        assert_eq!(decode_text(text.as_bytes()).unwrap(), expected);
+        assert_eq!(decode_text(&[b"\xEF\xBB\xBF", text.as_bytes()].concat()).unwrap(), expected);
+        for (big_endian, bom) in [(false, true), (true, true), (false, false), (true, false)] {
+            let bytes = utf16(text, big_endian, bom);
+            let encoding = if big_endian { Encoding::Utf16Be } else { Encoding::Utf16Le };
+            assert_eq!(detect_encoding(&bytes), (encoding, if bom { 2 } else { 0 }));
+            assert_eq!(decode_text(&bytes).unwrap(), expected);
+        }
+        // a lone CR is not a line ending
+        assert_eq!(decode_text(b"a = \"x\ry\"\n").unwrap(), "a = \"x\ry\"\n");
+    }
+
+    #[test]
+    fn invalid_input_reports_the_offset() {
+        let err = decode_text(b"\xEF\xBB\xBFab\xFFcd").unwrap_err();
+        assert_eq!((err.encoding, err.offset), (Encoding::Utf8, 5));
+        let mut bytes = utf16("ab", false, true);
+        bytes.extend([0x00, 0xD8, b'c', 0x00]);
+        let err = decode_text(&bytes).unwrap_err();
+        assert_eq!((err.encoding, err.offset, err.message.as_str()), (Encoding::Utf16Le, 6, "unpaired surrogate"));
+        let err = decode_text(&utf16("ab", true, false)[..3]).unwrap_err();
+        assert_eq!((err.encoding, err.offset), (Encoding::Utf16Be, 2));
+        assert_eq!(with_path(err, "x.flow").to_string(), "x.flow: invalid UTF-16BE at byte 2: odd number of bytes");
+    }
+
+    #[test]
+    fn lossy_decoding_replaces_and_reports() {
+        let (text, replaced) = decode_text_lossy(b"\xEF\xBB\xBFa = \"x\xFFy\xC3\"\r\n");
+        assert_eq!(text, "a = \"x\u{fffd}y\u{fffd}\"\n");
+        assert_eq!(replaced, [9, 11]);
+        let mut bytes = utf16("a", false, false);
+        bytes.extend([0x00, 0xDC, b'b']);
+        assert_eq!(decode_text_lossy(&bytes), ("a\u{fffd}\u{fffd}".to_string(), vec![2, 4]));
+        assert_eq!(decode_text_lossy(b"clean\n"), ("clean\n".to_string(), Vec::new()));
+    }
+}
@@ -0,0 +1,172 @@
+use serde_json::Value;
+use std::fmt;
+
+use crate::diagnostic::Span;
+use crate::path::{FlowPath, PathError};
+use crate::stringify::scalar;
+
+// ============================================
+// Fixes
+// ============================================
+//
+// A ValidationError can carry a Fix that resolves it: renaming a misspelled
+// key to the model field it is closest to, replacing a value with the same
+// value in the field's type, or adding a missing required field with its
+// default. Each fix is described twice, as a change to the parsed document
+// for `apply_fixes`, and as a text edit for `apply_fixes_text`, which
+// rewrites the source and leaves comments and formatting elsewhere alone.
+// A fix whose node has no position in the source has no text edit.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FixKind {
+    RenameKey { to: String },
+    ReplaceValue { value: Value },
+    // `path` is the object that gets the new key
+    InsertField { key: String, value: Value },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    // replaced by `text`; a zero-width span inserts before it
+    pub span: Span,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fix {
+    pub path: FlowPath,
+    pub kind: FixKind,
+    pub edit: Option<TextEdit>,
+}
+
+impl fmt::Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            FixKind::RenameKey { to } => write!(f, "rename '{}' to '{}'", self.path, to),
+            FixKind::ReplaceValue { value } => write!(f, "set '{}' to {}", self.path, scalar(value)),
+            FixKind::InsertField { key, value } if self.path.is_root() => write!(f, "add '{} = {}'", key, scalar(value)),
+            FixKind::InsertField { key, value } => write!(f, "add '{} = {}' to '{}'", key, scalar(value), self.path),
+        }
+    }
+}
+
+// Applies `fixes` to `doc` and returns how many took effect. A rename onto a
+// key that already exists, or an insert of one, is skipped. Fixes are applied
+// deepest first so a rename does not move the target of another fix.
+pub fn apply_fixes(doc: &mut Value, fixes: &[Fix]) -> Result<usize, PathError> {
+    let mut ordered: Vec<&Fix> = fixes.iter().collect();
+    ordered.sort_by_key(|fix| std::cmp::Reverse(fix.path.len()));
+    let mut applied = 0;
+    for fix in ordered {
+        match &fix.kind {
+            FixKind::RenameKey { to } => {
+                let Some(parent) = fix.path.parent() else { continue };
+                let target = parent.child_key(to);
+                if target.lookup(doc).is_some() {
+                    continue;
+                }
+                if let Some(val) = fix.path.remove(doc) {
+                    target.set(doc, val)?;
+                    applied += 1;
+                }
+            }
+            FixKind::ReplaceValue { value } => {
+                fix.path.set(doc, value.clone())?;
+                applied += 1;
+            }
+            FixKind::InsertField { key, value } => {
+                let target = fix.path.child_key(key);
+                if target.lookup(doc).is_none() {
+                    target.set(doc, value.clone())?;
+                    applied += 1;
+                }
+            }
+        }
+    }
+    Ok(applied)
+}
+
+// Applies the text edits of `fixes` to `source`. An insert below the last
+// line starts a new line if the source does not end with one.
+pub fn apply_fixes_text(source: &str, fixes: &[Fix]) -> String {
+    let mut line_starts = vec![0];
+    line_starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+    let offset = |line: usize, column: usize| -> Option<usize> {
+        let start = *line_starts.get(line.checked_sub(1)?)?;
+        let text = &source[start..];
+        let text = &text[..text.find('\n').unwrap_or(text.len())];
+        Some(start + text.char_indices().nth(column.saturating_sub(1)).map(|(i, _)| i).unwrap_or(text.len()))
+    };
+
+    let mut edits: Vec<(usize, usize, String)> = Vec::new();
+    let mut open_line = !source.is_empty() && !source.ends_with('\n');
+    for edit in fixes.iter().filter_map(|f| f.edit.as_ref()) {
+        let Some(start) = offset(edit.span.line, edit.span.column) else {
+            let mut text = edit.text.clone();
+            if open_line {
+                text.insert(0, '\n');
+                open_line = false;
+            }
+            edits.push((source.len(), source.len(), text));
+            continue;
+        };
+        let end = source[start..].char_indices().nth(edit.span.len).map(|(i, _)| start + i).unwrap_or(source.len());
+        edits.push((start, end, edit.text.clone()));
+    }
+    // back to front, so earlier offsets stay valid; inserts at the same point
+    // keep their order
+    edits.sort_by_key(|(start, _, _)| *start);
+    let mut out = source.to_string();
+    for (start, end, text) in edits.into_iter().rev() {
+        out.replace_range(start..end, &text);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_document, validate, FieldDefinition, ModelDefinition, ModelRegistry};
+    use serde_json::json;
+
+    fn registry() -> ModelRegistry {
+        let mut model = ModelDefinition::new("server".to_string());
+        let field = |name: &str, field_type: &str| FieldDefinition { full_name: name.to_string(), field_type: field_type.to_string(), ..Default::default() };
+        model.add_field(field("host", "string"));
+        model.add_field(field("port", "int"));
+        model.add_field(FieldDefinition { required: true, default: Some(json!("dev")), ..field("mode", "string") });
+        let mut registry = ModelRegistry::new();
+        registry.register_model(model);
+        registry
+    }
+
+    const SOURCE: &str = "use_model = server\n# where to listen\nhots = \"h\"\nport = \"8080\" # http";
+
+    fn fixes() -> Vec<Fix> {
+        let mut fixes: Vec<Fix> = validate(SOURCE, &registry()).into_iter().filter_map(|e| e.fix).collect();
+        fixes.sort_by_key(|f| f.to_string());
+        fixes
+    }
+
+    #[test]
+    fn each_kind_of_fix() {
+        let described: Vec<String> = fixes().iter().map(Fix::to_string).collect();
+        assert_eq!(described, ["add 'mode = dev'", "rename 'hots' to 'host'", "set 'port' to 8080"]);
+    }
+
+    #[test]
+    fn document_fixes() {
+        let mut doc = parse_document(SOURCE);
+        assert_eq!(apply_fixes(&mut doc, &fixes()).unwrap(), 3);
+        assert_eq!(doc, json!({"use_model": "server", "host": "h", "port": 8080, "mode": "dev"}));
+        // already applied: the rename and insert find their keys taken
+        assert_eq!(apply_fixes(&mut doc, &fixes()).unwrap(), 1);
+    }
+
+    #[test]
+    fn text_fixes_keep_the_rest() {
+        let fixed = apply_fixes_text(SOURCE, &fixes());
+        assert_eq!(fixed, "use_model = server\n# where to listen\nhost = \"h\"\nport = 8080 # http\nmode = dev\n");
+        assert!(validate(&fixed, &registry()).is_empty());
+    }
+}
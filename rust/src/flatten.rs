@@ -0,0 +1,69 @@
+use serde_json::{Map, Value};
+
+use crate::path::{FlowPath, PathError};
+use crate::walk::iter_leaves;
+
+// ============================================
+// Flattening
+// ============================================
+//
+// `flatten` turns a document into one map from path strings to leaf values,
+// e.g. `{"db": {"hosts": ["a"]}}` into `{"db.hosts[0]": "a"}`. Keys use the
+// FlowPath syntax, so a `.` or `[` inside a key comes out escaped and
+// `unflatten` reads it back unchanged. Empty objects and arrays are kept as
+// leaves so the round trip is exact.
+
+pub fn flatten(val: &Value) -> Map<String, Value> {
+    iter_leaves(val).map(|(path, v)| (path.to_string(), v.clone())).collect()
+}
+
+// Array indices must be contiguous from 0 within each array.
+pub fn unflatten(flat: &Map<String, Value>) -> Result<Value, PathError> {
+    let mut entries = flat
+        .iter()
+        .map(|(k, v)| FlowPath::parse(k).map(|p| (p, v)))
+        .collect::<Result<Vec<_>, _>>()?;
+    // by path, so `a[2]` comes before `a[10]`
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut root = Value::Object(Map::new());
+    for (path, v) in entries {
+        path.set(&mut root, v.clone())?;
+    }
+    Ok(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trip() {
+        let doc = json!({"db": {"hosts": ["a", "b"], "port": 5432}, "empty": {}, "none": [], "ver": "1.2"});
+        let flat = flatten(&doc);
+        assert_eq!(Value::Object(flat.clone()), json!({"db.hosts[0]": "a", "db.hosts[1]": "b", "db.port": 5432, "empty": {}, "none": [], "ver": "1.2"}));
+        assert_eq!(unflatten(&flat).unwrap(), doc);
+
+        let list: Vec<Value> = (0..12).map(Value::from).collect();
+        assert_eq!(unflatten(&flatten(&json!({ "list": list }))).unwrap(), json!({ "list": list }));
+    }
+
+    #[test]
+    fn keys_with_path_characters() {
+        let doc = json!({"a.b": {"c[0]": 1, "d": {"e.f": [2]}}});
+        let flat = flatten(&doc);
+        assert_eq!(flat.len(), 2);
+        let mut lengths: Vec<usize> = flat.keys().map(|k| FlowPath::parse(k).unwrap().segments().len()).collect();
+        lengths.sort();
+        assert_eq!(lengths, [2, 4]);
+        assert_eq!(unflatten(&flat).unwrap(), doc);
+    }
+
+    #[test]
+    fn gaps_and_bad_paths_fail() {
+        let flat = |v: Value| v.as_object().cloned().unwrap();
+        assert!(unflatten(&flat(json!({"a[1]": 1}))).is_err());
+        assert!(unflatten(&flat(json!({"a[": 1}))).is_err());
+        assert_eq!(unflatten(&Map::new()).unwrap(), json!({}));
+    }
+}
@@ -0,0 +1,593 @@
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::diagnostic::ParseError;
+use crate::digest::{blake3, to_hex};
+use crate::encoding::EncodingError;
+use crate::save::{write_file, SaveOptions};
+use crate::v2::{self, FlowError};
+use crate::ParseOptions;
+
+// ============================================
+// URL Loading
+// ============================================
+//
+// v2::load_url fetches a document with a GET request and parses it as .flow
+// text, strictly as v2::parse does, or as .flowb when the URL path ends in
+// `.flowb`. Requests go through an HttpTransport, by default UreqTransport,
+// which handles `http://` and `https://`; tests and applications with their
+// own client plug in another. `@ref` values are not resolved.
+//
+// Redirects are followed up to MAX_REDIRECTS times, all within the one
+// timeout. A redirect to another scheme, host or port is sent without the
+// configured headers or `If-None-Match`, so credentials only go to the
+// origin they were given for, and one from https to http is refused.
+//
+// With `etag_cache` set, the last body and ETag for each URL are kept in
+// that directory and sent back as `If-None-Match`; a 304 reply is served
+// from the cache.
+
+const MAX_REDIRECTS: usize = 5;
+
+#[derive(Debug)]
+pub enum HttpError {
+    InvalidUrl(String),
+    Io(io::Error),
+    Timeout(Duration),
+    TooLarge(usize),
+    Status { url: String, status: u16 },
+    Protocol(String),
+    Encoding(EncodingError),
+    Parse(ParseError),
+    Decode(String),
+    // a redirect from https to plain http
+    InsecureRedirect(String),
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::InvalidUrl(url) => write!(f, "invalid URL '{}'", url),
+            HttpError::Io(e) => write!(f, "{}", e),
+            HttpError::Timeout(limit) => write!(f, "request timed out after {:?}", limit),
+            HttpError::TooLarge(limit) => write!(f, "response is larger than {} bytes", limit),
+            HttpError::Status { url, status } => write!(f, "{}: HTTP status {}", url, status),
+            HttpError::Protocol(message) => write!(f, "invalid HTTP response: {}", message),
+            HttpError::Encoding(e) => write!(f, "{}", e),
+            HttpError::Parse(e) => write!(f, "{}", e),
+            HttpError::Decode(message) => write!(f, "invalid document: {}", message),
+            HttpError::InsecureRedirect(url) => write!(f, "refusing to follow a redirect from https to '{}'", url),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HttpError::Io(e) => Some(e),
+            HttpError::Encoding(e) => Some(e),
+            HttpError::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HttpError {
+    fn from(e: io::Error) -> Self {
+        HttpError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    // for this request, connection included: what is left of the timeout
+    // load_url sets for the whole exchange, redirects and all
+    pub timeout: Duration,
+    pub max_bytes: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    // Case-insensitive header lookup.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+pub trait HttpTransport: Send + Sync {
+    fn get(&self, request: &HttpRequest) -> Result<HttpResponse, HttpError>;
+}
+
+#[derive(Clone)]
+pub struct UrlOptions {
+    pub timeout: Duration,
+    pub max_bytes: usize,
+    pub etag_cache: Option<PathBuf>,
+    pub headers: Vec<(String, String)>,
+    pub transport: Arc<dyn HttpTransport>,
+}
+
+impl Default for UrlOptions {
+    fn default() -> Self {
+        UrlOptions {
+            timeout: Duration::from_secs(30),
+            max_bytes: 16 * 1024 * 1024,
+            etag_cache: None,
+            headers: Vec::new(),
+            transport: Arc::new(UreqTransport::default()),
+        }
+    }
+}
+
+impl UrlOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn max_bytes(mut self, limit: usize) -> Self {
+        self.max_bytes = limit;
+        self
+    }
+
+    pub fn etag_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.etag_cache = Some(dir.into());
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn transport(mut self, transport: impl HttpTransport + 'static) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+}
+
+#[deprecated(note = "use v2::load_url")]
+pub fn LoadFlowUrl(url: &str, options: &UrlOptions) -> Result<Value, HttpError> {
+    load_url(url, options)
+}
+
+pub(crate) fn load_url(url: &str, options: &UrlOptions) -> Result<Value, HttpError> {
+    let cached = options.etag_cache.as_ref().map(|dir| CacheEntry::new(dir, url));
+    let mut previous = cached.as_ref().and_then(CacheEntry::read);
+
+    let deadline = Instant::now() + options.timeout;
+    let mut request = HttpRequest {
+        url: url.to_string(),
+        headers: options.headers.clone(),
+        timeout: options.timeout,
+        max_bytes: options.max_bytes,
+    };
+    if let Some((etag, _)) = &previous {
+        request.headers.push(("If-None-Match".to_string(), etag.clone()));
+    }
+
+    let mut response = send(options, &mut request, deadline)?;
+    for _ in 0..MAX_REDIRECTS {
+        if !matches!(response.status, 301 | 302 | 303 | 307 | 308) {
+            break;
+        }
+        let location = response.header("Location").ok_or_else(|| HttpError::Protocol("redirect without Location".to_string()))?;
+        let next = join_url(&request.url, location)?;
+        let (from, to) = (origin(&request.url)?, origin(&next)?);
+        if from.0 == "https" && to.0 != "https" {
+            return Err(HttpError::InsecureRedirect(next));
+        }
+        if from != to {
+            request.headers.clear();
+            previous = None;
+        }
+        request.url = next;
+        response = send(options, &mut request, deadline)?;
+    }
+
+    let body = match (response.status, previous) {
+        (304, Some((_, body))) => body,
+        (200..=299, _) => {
+            if response.body.len() > options.max_bytes {
+                return Err(HttpError::TooLarge(options.max_bytes));
+            }
+            if let (Some(entry), Some(etag)) = (&cached, response.header("ETag")) {
+                // a cache that cannot be written only costs a refetch
+                let _ = entry.write(etag, &response.body);
+            }
+            response.body
+        }
+        (status, _) => return Err(HttpError::Status { url: request.url, status }),
+    };
+
+    if url_path(&request.url).ends_with(".flowb") {
+        return rmp_serde::from_slice(&body).map_err(|e| HttpError::Decode(e.to_string()));
+    }
+    match v2::parse_bytes(&body, &ParseOptions::default()) {
+        Ok(parsed) => Ok(parsed.value),
+        Err(FlowError::Encoding(e)) => Err(HttpError::Encoding(e)),
+        Err(FlowError::Parse { error, .. }) => Err(HttpError::Parse(error)),
+        Err(e) => Err(HttpError::Decode(e.to_string())),
+    }
+}
+
+// One request with whatever time is left before `deadline`.
+fn send(options: &UrlOptions, request: &mut HttpRequest, deadline: Instant) -> Result<HttpResponse, HttpError> {
+    request.timeout = deadline.saturating_duration_since(Instant::now());
+    if request.timeout.is_zero() {
+        return Err(HttpError::Timeout(options.timeout));
+    }
+    options.transport.get(request).map_err(|e| match e {
+        HttpError::Timeout(_) => HttpError::Timeout(options.timeout),
+        e => e,
+    })
+}
+
+struct CacheEntry {
+    etag: PathBuf,
+    body: PathBuf,
+}
+
+impl CacheEntry {
+    fn new(dir: &std::path::Path, url: &str) -> Self {
+        let name = &to_hex(&blake3(url.as_bytes()))[..32];
+        CacheEntry { etag: dir.join(format!("{}.etag", name)), body: dir.join(format!("{}.body", name)) }
+    }
+
+    fn read(&self) -> Option<(String, Vec<u8>)> {
+        let etag = fs::read_to_string(&self.etag).ok()?;
+        Some((etag.trim().to_string(), fs::read(&self.body).ok()?))
+    }
+
+    fn write(&self, etag: &str, body: &[u8]) -> io::Result<()> {
+        if let Some(dir) = self.etag.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let atomic = SaveOptions::new().atomic(true);
+        write_file(&self.body, body, &atomic)?;
+        write_file(&self.etag, etag.as_bytes(), &atomic)
+    }
+}
+
+// ============================================
+// URLs
+// ============================================
+
+struct Url<'a> {
+    scheme: &'a str,
+    host: &'a str,
+    port: Option<u16>,
+    // path and query, always starting with '/'
+    target: String,
+}
+
+fn parse_url(url: &str) -> Result<Url<'_>, HttpError> {
+    let invalid = || HttpError::InvalidUrl(url.to_string());
+    let (scheme, rest) = url.split_once("://").ok_or_else(invalid)?;
+    let (authority, target) = match rest.find(['/', '?', '#']) {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, ""),
+    };
+    let target = target.split('#').next().unwrap_or("");
+    let target = if target.starts_with('/') { target.to_string() } else { format!("/{}", target) };
+    let authority = authority.rsplit('@').next().unwrap_or(authority);
+    // an IPv6 host is bracketed, `[::1]:8080`
+    let split = match authority.strip_prefix('[') {
+        Some(_) => authority.find("]:").map(|i| (&authority[..i + 1], &authority[i + 2..])),
+        None => authority.rsplit_once(':'),
+    };
+    let (host, port) = match split {
+        Some((h, p)) => (h, Some(p.parse().map_err(|_| invalid())?)),
+        None => (authority, None),
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Url { scheme, host, port, target })
+}
+
+// Scheme, host and port, with the scheme's default port filled in.
+fn origin(url: &str) -> Result<(String, String, Option<u16>), HttpError> {
+    let url = parse_url(url)?;
+    let scheme = url.scheme.to_ascii_lowercase();
+    let port = url.port.or(match scheme.as_str() {
+        "http" => Some(80),
+        "https" => Some(443),
+        _ => None,
+    });
+    Ok((scheme, url.host.to_ascii_lowercase(), port))
+}
+
+fn url_path(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn join_url(base: &str, location: &str) -> Result<String, HttpError> {
+    if location.contains("://") {
+        return Ok(location.to_string());
+    }
+    let url = parse_url(base)?;
+    let origin = match url.port {
+        Some(p) => format!("{}://{}:{}", url.scheme, url.host, p),
+        None => format!("{}://{}", url.scheme, url.host),
+    };
+    if location.starts_with('/') {
+        return Ok(format!("{}{}", origin, location));
+    }
+    let dir = url.target.split('?').next().unwrap_or("/");
+    let dir = &dir[..dir.rfind('/').map(|i| i + 1).unwrap_or(1)];
+    Ok(format!("{}{}{}", origin, dir, location))
+}
+
+// ============================================
+// ureq Transport
+// ============================================
+
+// The default transport: ureq, with TLS through rustls and the Mozilla root
+// certificates. Redirects are left to v2::load_url and non-2xx statuses are
+// returned as responses.
+#[derive(Clone)]
+pub struct UreqTransport {
+    agent: ureq::Agent,
+}
+
+impl Default for UreqTransport {
+    fn default() -> Self {
+        let config = ureq::Agent::config_builder().http_status_as_error(false).max_redirects(0).build();
+        UreqTransport { agent: config.into() }
+    }
+}
+
+impl UreqTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Requests go through `agent`, e.g. one configured with a proxy or
+    // extra root certificates. Build it with `http_status_as_error(false)`
+    // and `max_redirects(0)` so statuses and redirects reach v2::load_url.
+    pub fn with_agent(agent: ureq::Agent) -> Self {
+        UreqTransport { agent }
+    }
+}
+
+impl HttpTransport for UreqTransport {
+    fn get(&self, request: &HttpRequest) -> Result<HttpResponse, HttpError> {
+        let mut builder = self.agent.get(&request.url).config().timeout_global(Some(request.timeout)).build();
+        for (k, v) in &request.headers {
+            builder = builder.header(k.as_str(), v.as_str());
+        }
+        let mut response = builder.call().map_err(|e| ureq_error(e, request))?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .map(|(k, v)| (k.as_str().to_string(), String::from_utf8_lossy(v.as_bytes()).into_owned()))
+            .collect();
+        let body = if status == 304 || status == 204 {
+            Vec::new()
+        } else {
+            response.body_mut().with_config().limit(request.max_bytes as u64).read_to_vec().map_err(|e| ureq_error(e, request))?
+        };
+        Ok(HttpResponse { status, headers, body })
+    }
+}
+
+fn ureq_error(e: ureq::Error, request: &HttpRequest) -> HttpError {
+    match e {
+        ureq::Error::Timeout(_) => HttpError::Timeout(request.timeout),
+        ureq::Error::BodyExceedsLimit(_) => HttpError::TooLarge(request.max_bytes),
+        ureq::Error::Io(e) => HttpError::Io(e),
+        ureq::Error::BadUri(_) | ureq::Error::Http(_) => HttpError::InvalidUrl(request.url.clone()),
+        other => HttpError::Protocol(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Serves one canned response per connection, in order, and returns the
+    // requests it saw.
+    fn serve(responses: Vec<String>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut seen = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    request.push_str(&line);
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+                seen.push(request);
+            }
+            seen
+        });
+        (base, handle)
+    }
+
+    fn ok(headers: &str, body: &str) -> String {
+        format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}", body.len(), headers, body)
+    }
+
+    #[test]
+    fn follows_redirects() {
+        let (base, server) = serve(vec![
+            "HTTP/1.1 302 Found\r\nLocation: /conf/app.flow\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            ok("", "name = app\nport = 8080\n"),
+        ]);
+        let doc = load_url(&format!("{}/latest", base), &UrlOptions::new()).unwrap();
+        assert_eq!(doc, json!({"name": "app", "port": 8080}));
+        assert!(server.join().unwrap()[1].starts_with("GET /conf/app.flow "));
+    }
+
+    #[test]
+    fn etag_cache() {
+        let cache = std::env::temp_dir().join(format!("flowdoc-etag-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&cache);
+        let (base, server) = serve(vec![
+            ok("ETag: \"v1\"\r\n", "a = 1\n"),
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n".to_string(),
+        ]);
+        let options = UrlOptions::new().etag_cache(&cache);
+        let url = format!("{}/a.flow", base);
+        assert_eq!(load_url(&url, &options).unwrap(), json!({"a": 1}));
+        assert_eq!(load_url(&url, &options).unwrap(), json!({"a": 1}));
+        assert!(server.join().unwrap()[1].to_ascii_lowercase().contains("if-none-match: \"v1\""));
+    }
+
+    #[test]
+    fn size_limit_and_status() {
+        let (base, server) = serve(vec![ok("", &"x = 1\n".repeat(100)), "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()]);
+        let err = load_url(&format!("{}/big.flow", base), &UrlOptions::new().max_bytes(64)).unwrap_err();
+        assert!(matches!(err, HttpError::TooLarge(64)), "{:?}", err);
+        let err = load_url(&format!("{}/missing.flow", base), &UrlOptions::new()).unwrap_err();
+        assert!(matches!(err, HttpError::Status { status: 404, .. }), "{:?}", err);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/slow.flow", listener.local_addr().unwrap());
+        let err = load_url(&url, &UrlOptions::new().timeout(Duration::from_millis(200))).unwrap_err();
+        assert!(matches!(err, HttpError::Timeout(_)), "{:?}", err);
+        drop(listener);
+    }
+
+    // Replies from a fixed list by URL and records every request.
+    struct Scripted {
+        replies: Vec<(&'static str, HttpResponse)>,
+        seen: std::sync::Mutex<Vec<HttpRequest>>,
+        delay: Duration,
+    }
+
+    impl Scripted {
+        fn new(replies: Vec<(&'static str, HttpResponse)>) -> Self {
+            Scripted { replies, seen: std::sync::Mutex::new(Vec::new()), delay: Duration::ZERO }
+        }
+    }
+
+    impl HttpTransport for Arc<Scripted> {
+        fn get(&self, request: &HttpRequest) -> Result<HttpResponse, HttpError> {
+            self.seen.lock().unwrap().push(request.clone());
+            if self.delay > request.timeout {
+                thread::sleep(request.timeout);
+                return Err(HttpError::Timeout(request.timeout));
+            }
+            thread::sleep(self.delay);
+            let reply = self.replies.iter().find(|(url, _)| *url == request.url).map(|(_, r)| r.clone());
+            reply.ok_or_else(|| HttpError::Status { url: request.url.clone(), status: 404 })
+        }
+    }
+
+    fn redirect(to: &str) -> HttpResponse {
+        HttpResponse { status: 302, headers: vec![("Location".to_string(), to.to_string())], body: Vec::new() }
+    }
+
+    fn body(text: &str) -> HttpResponse {
+        HttpResponse { status: 200, headers: Vec::new(), body: text.as_bytes().to_vec() }
+    }
+
+    #[test]
+    fn headers_stay_with_their_origin() {
+        let transport = Arc::new(Scripted::new(vec![
+            ("https://conf.example/a.flow", redirect("/b.flow")),
+            ("https://conf.example/b.flow", redirect("https://cdn.example/b.flow")),
+            ("https://cdn.example/b.flow", body("a = 1\n")),
+        ]));
+        let options = UrlOptions::new().header("Authorization", "Bearer secret").transport(transport.clone());
+        assert_eq!(load_url("https://conf.example/a.flow", &options).unwrap(), json!({"a": 1}));
+        let seen = transport.seen.lock().unwrap();
+        let auth = |r: &HttpRequest| r.headers.iter().any(|(k, _)| k == "Authorization");
+        assert_eq!(seen.iter().map(auth).collect::<Vec<_>>(), [true, true, false]);
+
+        // a different port is a different origin
+        assert_ne!(origin("http://h:8080/").unwrap(), origin("http://h/").unwrap());
+        assert_eq!(origin("HTTPS://H:443/x").unwrap(), origin("https://h/y").unwrap());
+    }
+
+    #[test]
+    fn etag_is_not_sent_to_another_origin() {
+        let cache = std::env::temp_dir().join(format!("flowdoc-etag-origin-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&cache);
+        let url = "https://conf.example/a.flow";
+        CacheEntry::new(&cache, url).write("\"v1\"", b"a = 1\n").unwrap();
+        let transport = Arc::new(Scripted::new(vec![
+            (url, redirect("https://mirror.example/a.flow")),
+            ("https://mirror.example/a.flow", HttpResponse { status: 304, headers: Vec::new(), body: Vec::new() }),
+        ]));
+        let options = UrlOptions::new().etag_cache(&cache).transport(transport.clone());
+        // a 304 from another origin says nothing about the cached body
+        let err = load_url(url, &options).unwrap_err();
+        assert!(matches!(err, HttpError::Status { status: 304, .. }), "{:?}", err);
+        let seen = transport.seen.lock().unwrap();
+        assert!(seen[0].headers.iter().any(|(k, _)| k == "If-None-Match"));
+        assert!(seen[1].headers.is_empty());
+    }
+
+    #[test]
+    fn no_downgrade_to_http() {
+        let transport = Arc::new(Scripted::new(vec![
+            ("https://conf.example/a.flow", redirect("http://conf.example/a.flow")),
+            ("http://conf.example/a.flow", body("a = 1\n")),
+        ]));
+        let options = UrlOptions::new().header("Authorization", "Bearer secret").transport(transport.clone());
+        let err = load_url("https://conf.example/a.flow", &options).unwrap_err();
+        assert!(matches!(err, HttpError::InsecureRedirect(ref url) if url == "http://conf.example/a.flow"), "{:?}", err);
+        assert_eq!(transport.seen.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn one_deadline_for_all_redirects() {
+        let mut scripted = Scripted::new(vec![
+            ("https://conf.example/1", redirect("/2")),
+            ("https://conf.example/2", redirect("/3")),
+            ("https://conf.example/3", body("a = 1\n")),
+        ]);
+        scripted.delay = Duration::from_millis(80);
+        let transport = Arc::new(scripted);
+        let options = UrlOptions::new().timeout(Duration::from_millis(200)).transport(transport.clone());
+        let err = load_url("https://conf.example/1", &options).unwrap_err();
+        assert!(matches!(err, HttpError::Timeout(t) if t == Duration::from_millis(200)), "{:?}", err);
+        let seen = transport.seen.lock().unwrap();
+        assert!(seen.len() == 3 && seen[2].timeout < Duration::from_millis(200 - 80 * 2), "{:?}", seen.iter().map(|r| r.timeout).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn parses_strictly() {
+        let transport = Arc::new(Scripted::new(vec![("https://conf.example/a.flow", body("a = 1\na = 2\n"))]));
+        let err = load_url("https://conf.example/a.flow", &UrlOptions::new().transport(transport)).unwrap_err();
+        assert!(matches!(err, HttpError::Parse(ref e) if e.span.line == 2), "{:?}", err);
+    }
+}
@@ -0,0 +1,265 @@
+use serde::de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde_json::{Map, Number, Value};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::sync::Arc;
+
+use crate::path::{FlowPath, PathSegment};
+use crate::parse_document;
+
+// ============================================
+// String Interning
+// ============================================
+//
+// An InternedValue stores keys and strings as `Arc<str>` handles from a
+// StringPool, so a key repeated across thousands of records is allocated
+// once. Object members are kept sorted by key. Text is parsed with ParseFlow
+// and then interned, while .flowb data is interned as it is decoded, without
+// building a Value first. A pool can be shared by several documents.
+
+#[derive(Debug, Default)]
+pub struct StringPool {
+    strings: HashSet<Arc<str>>,
+}
+
+impl StringPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(existing) = self.strings.get(s) {
+            return existing.clone();
+        }
+        let shared: Arc<str> = Arc::from(s);
+        self.strings.insert(shared.clone());
+        shared
+    }
+
+    // Distinct strings held by the pool.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InternedValue {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(Arc<str>),
+    Array(Vec<InternedValue>),
+    Object(Vec<(Arc<str>, InternedValue)>),
+}
+
+impl InternedValue {
+    pub fn from_value(val: &Value, pool: &mut StringPool) -> Self {
+        match val {
+            Value::Null => InternedValue::Null,
+            Value::Bool(b) => InternedValue::Bool(*b),
+            Value::Number(n) => InternedValue::Number(n.clone()),
+            Value::String(s) => InternedValue::String(pool.intern(s)),
+            Value::Array(items) => InternedValue::Array(items.iter().map(|v| Self::from_value(v, pool)).collect()),
+            Value::Object(map) => {
+                let members = map.iter().map(|(k, v)| (pool.intern(k), Self::from_value(v, pool))).collect();
+                InternedValue::Object(sorted(members))
+            }
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            InternedValue::Null => Value::Null,
+            InternedValue::Bool(b) => Value::Bool(*b),
+            InternedValue::Number(n) => Value::Number(n.clone()),
+            InternedValue::String(s) => Value::String(s.to_string()),
+            InternedValue::Array(items) => Value::Array(items.iter().map(Self::to_value).collect()),
+            InternedValue::Object(members) => {
+                let mut map = Map::new();
+                for (k, v) in members {
+                    map.insert(k.to_string(), v.to_value());
+                }
+                Value::Object(map)
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&InternedValue> {
+        match self {
+            InternedValue::Object(members) => {
+                members.binary_search_by(|(k, _)| (**k).cmp(key)).ok().map(|i| &members[i].1)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn lookup(&self, path: &FlowPath) -> Option<&InternedValue> {
+        let mut current = self;
+        for seg in path.segments() {
+            current = match (seg, current) {
+                (PathSegment::Key(k), _) => current.get(k)?,
+                (PathSegment::Index(i), InternedValue::Array(items)) => items.get(*i)?,
+                _ => return None,
+            };
+        }
+        Some(current)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            InternedValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+// Later duplicates win, as with a map insert.
+fn sorted(mut members: Vec<(Arc<str>, InternedValue)>) -> Vec<(Arc<str>, InternedValue)> {
+    members.reverse();
+    members.sort_by(|a, b| a.0.cmp(&b.0));
+    members.dedup_by(|later, earlier| later.0 == earlier.0);
+    members
+}
+
+#[deprecated(note = "use v2::parse_interned")]
+pub fn ParseFlowInterned(text: &str, pool: &mut StringPool) -> InternedValue {
+    InternedValue::from_value(&parse_document(text), pool)
+}
+
+#[deprecated(note = "use v2::load_flowb_interned")]
+pub fn LoadFlowbInterned(path: &str, pool: &mut StringPool) -> Result<InternedValue, Box<dyn std::error::Error>> {
+    let data = fs::read(path)?;
+    let mut de = rmp_serde::Deserializer::new(&data[..]);
+    Ok(PoolSeed(pool).deserialize(&mut de)?)
+}
+
+// ============================================
+// Decoding
+// ============================================
+
+pub(crate) struct PoolSeed<'p>(pub(crate) &'p mut StringPool);
+
+impl<'de> DeserializeSeed<'de> for PoolSeed<'_> {
+    type Value = InternedValue;
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for PoolSeed<'_> {
+    type Value = InternedValue;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a document value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E> {
+        Ok(InternedValue::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E> {
+        Ok(InternedValue::Null)
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(InternedValue::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(InternedValue::Number(v.into()))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(InternedValue::Number(v.into()))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(Number::from_f64(v).map(InternedValue::Number).unwrap_or(InternedValue::Null))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(InternedValue::String(self.0.intern(v)))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(item) = seq.next_element_seed(PoolSeed(self.0))? {
+            items.push(item);
+        }
+        Ok(InternedValue::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut members = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(key) = map.next_key_seed(KeySeed(self.0))? {
+            members.push((key, map.next_value_seed(PoolSeed(self.0))?));
+        }
+        Ok(InternedValue::Object(sorted(members)))
+    }
+}
+
+// Interns a map key straight from the decoder's buffer.
+struct KeySeed<'p>(&'p mut StringPool);
+
+impl<'de> DeserializeSeed<'de> for KeySeed<'_> {
+    type Value = Arc<str>;
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de> Visitor<'de> for KeySeed<'_> {
+    type Value = Arc<str>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a string key")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(self.0.intern(v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn same_values_as_parse_flow() {
+        let text = "users:\n  alice:\n    role = admin\n  bob:\n    role = admin\nzone = b\nzone = a\nweights = [1.5, -2, true]\n";
+        let mut pool = StringPool::new();
+        let doc = InternedValue::from_value(&parse_document(text), &mut pool);
+        assert_eq!(doc.to_value(), parse_document(text));
+        let role = FlowPath::parse("users.bob.role").unwrap();
+        assert_eq!(doc.lookup(&role).and_then(InternedValue::as_str), Some("admin"));
+        assert_eq!(doc.get("zone").and_then(InternedValue::as_str), Some("a"));
+        // "role" and "admin" are stored once
+        assert_eq!(pool.len(), 8);
+    }
+
+    #[test]
+    fn flowb_is_interned_while_decoded() {
+        let val = json!({"b": [{"k": "v"}, {"k": "v"}], "a": null, "n": 2.5, "m": -3});
+        let data = rmp_serde::to_vec(&val).unwrap();
+        let mut pool = StringPool::new();
+        let doc = PoolSeed(&mut pool).deserialize(&mut rmp_serde::Deserializer::new(&data[..])).unwrap();
+        assert_eq!(doc, InternedValue::from_value(&val, &mut StringPool::new()));
+        assert_eq!(doc.to_value(), val);
+        match (doc.lookup(&FlowPath::parse("b[0].k").unwrap()), doc.lookup(&FlowPath::parse("b[1].k").unwrap())) {
+            (Some(InternedValue::String(x)), Some(InternedValue::String(y))) => assert!(Arc::ptr_eq(x, y)),
+            other => panic!("expected two strings, got {:?}", other),
+        }
+    }
+}
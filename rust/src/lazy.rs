@@ -0,0 +1,156 @@
+use serde_json::{Map, Value};
+use std::cell::OnceCell;
+use std::io;
+use std::ops::Range;
+
+use crate::encoding::read_text;
+use crate::path::{FlowPath, PathSegment};
+use crate::{parse_document, parse_value, strip_comment};
+
+// ============================================
+// Lazy Documents
+// ============================================
+//
+// Opening a LazyFlowDocument only scans for top-level lines: each `key:`
+// section is recorded as the byte range of its lines, and each `key = value`
+// as the range of that line. A section is parsed the first time it is read
+// and kept for later reads. The parsed values are the same as ParseFlow's;
+// `@ref` values are left unresolved.
+
+pub struct LazyFlowDocument {
+    text: String,
+    entries: Vec<LazyEntry>,
+}
+
+struct LazyEntry {
+    key: String,
+    range: Range<usize>,
+    section: bool,
+    value: OnceCell<Value>,
+}
+
+impl LazyFlowDocument {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Ok(Self::from_text(read_text(path)?))
+    }
+
+    pub fn from_text(text: String) -> Self {
+        let mut entries: Vec<LazyEntry> = Vec::new();
+        let mut start = 0;
+        for line in text.split_inclusive('\n') {
+            let end = start + line.len();
+            let content = strip_comment(line).trim_end();
+            let leading = content.replace('\t', "  ").chars().take_while(|c| c.is_whitespace()).count();
+            if content.trim().is_empty() {
+                // blank lines and comments belong to whatever section is open
+            } else if leading / 2 == 0 && (content.ends_with(':') || content.contains('=')) {
+                let (key, section) = match content.strip_suffix(':') {
+                    Some(k) => (k, true),
+                    None => (content.split('=').next().unwrap_or(""), false),
+                };
+                let key = key.trim().to_string();
+                // a repeated key replaces the earlier one, as in ParseFlow
+                entries.retain(|e| e.key != key);
+                entries.push(LazyEntry { key, range: start..end, section, value: OnceCell::new() });
+            } else if let Some(last) = entries.last_mut() {
+                if last.section {
+                    last.range.end = end;
+                }
+            }
+            start = end;
+        }
+        LazyFlowDocument { text, entries }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|e| e.key.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.entry(key).is_some()
+    }
+
+    // Whether the value under `key` has been parsed yet.
+    pub fn is_parsed(&self, key: &str) -> bool {
+        self.entry(key).is_some_and(|e| e.value.get().is_some())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entry(key).map(|e| self.parse_entry(e))
+    }
+
+    // Parses only the top-level entry the path starts in.
+    pub fn get_path(&self, path: &FlowPath) -> Option<&Value> {
+        match path.segments().first() {
+            Some(PathSegment::Key(key)) => {
+                let rest = FlowPath::from(path.segments()[1..].to_vec());
+                rest.lookup(self.get(key)?)
+            }
+            _ => None,
+        }
+    }
+
+    // Parses every remaining entry and returns the whole document.
+    pub fn to_value(&self) -> Value {
+        let mut map = Map::new();
+        for e in &self.entries {
+            map.insert(e.key.clone(), self.parse_entry(e).clone());
+        }
+        Value::Object(map)
+    }
+
+    fn entry(&self, key: &str) -> Option<&LazyEntry> {
+        self.entries.iter().find(|e| e.key == key)
+    }
+
+    fn parse_entry<'a>(&'a self, e: &'a LazyEntry) -> &'a Value {
+        e.value.get_or_init(|| {
+            let source = &self.text[e.range.clone()];
+            if e.section {
+                parse_document(source).get(&e.key).cloned().unwrap_or_else(|| Value::Object(Map::new()))
+            } else {
+                let line = strip_comment(source).trim();
+                parse_value(line.split_once('=').map(|(_, v)| v).unwrap_or(""))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const TEXT: &str = "# settings\nname = demo\nserver:\n  host = localhost\n\n  # inside the section\n  tls:\n    enabled = true\nports = [80, 443] # web\nname = later\nempty:\n";
+
+    #[test]
+    fn same_values_as_parse_flow() {
+        let doc = LazyFlowDocument::from_text(TEXT.to_string());
+        assert_eq!(doc.keys().collect::<Vec<_>>(), ["server", "ports", "name", "empty"]);
+        assert_eq!(doc.to_value(), parse_document(TEXT));
+        assert_eq!(doc.get("empty"), Some(&json!({})));
+        assert_eq!(doc.get("name"), Some(&json!("later")));
+    }
+
+    #[test]
+    fn sections_are_parsed_when_read() {
+        let doc = LazyFlowDocument::from_text(TEXT.to_string());
+        assert!(!doc.is_parsed("server"));
+        let enabled = FlowPath::parse("server.tls.enabled").unwrap();
+        assert_eq!(doc.get_path(&enabled), Some(&json!(true)));
+        assert!(doc.is_parsed("server"));
+        assert!(!doc.is_parsed("ports"));
+        assert_eq!(doc.get_path(&FlowPath::parse("ports[1]").unwrap()), Some(&json!(443)));
+        assert_eq!(doc.get_path(&FlowPath::parse("server.port").unwrap()), None);
+        assert_eq!(doc.get("missing"), None);
+        assert!(!doc.contains_key("missing"));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::diagnostic::{source_lines, LineKind, ParseError, Span};
+
+// ============================================
+// Line Limits
+// ============================================
+//
+// Guards against documents built to be expensive or ambiguous to read,
+// checked before parsing by the functions that take ParseOptions. None are
+// set by default, so a document reads the same with or without options;
+// LineLimits::untrusted is a starting point for input from outside.
+//
+// A line longer than `max_line_len` bytes, line ending excluded, or
+// indented deeper than `max_depth` levels fails the parse instead of being
+// sliced up or attached to whatever section is open. With `strict_indent` so
+// does a line indented further than the innermost open section's body, such
+// as `a:` followed by a line four levels in, which the parser would quietly
+// file under `a`. With `strict_keys` a key containing `:` or `=` fails too:
+// `a=b:` reads as a section but `a=b = 1` does not read back as the key
+// `a=b`, so such keys do not survive being written out.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineLimits {
+    pub max_line_len: usize,
+    pub max_depth: usize,
+    pub strict_indent: bool,
+    pub strict_keys: bool,
+}
+
+impl Default for LineLimits {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl LineLimits {
+    // No limits at all, as ParseFlow reads.
+    pub fn none() -> Self {
+        LineLimits { max_line_len: usize::MAX, max_depth: usize::MAX, strict_indent: false, strict_keys: false }
+    }
+
+    // 1 MiB lines, 128 levels of nesting and no indentation jumps.
+    pub fn untrusted() -> Self {
+        LineLimits { max_line_len: 1 << 20, max_depth: 128, strict_indent: true, strict_keys: false }
+    }
+}
+
+pub(crate) fn check(text: &str, limits: &LineLimits) -> Result<(), ParseError> {
+    LineChecker::new(limits).check(text)
+}
+
+// Checks a document a piece at a time, for readers that never hold all of
+// it. Indentation is followed across calls; line numbers in errors count
+// from the start of each piece.
+pub(crate) struct LineChecker<'l> {
+    limits: &'l LineLimits,
+    // body levels of the open sections, the document itself first
+    open: Vec<usize>,
+}
+
+impl<'l> LineChecker<'l> {
+    pub fn new(limits: &'l LineLimits) -> Self {
+        LineChecker { limits, open: vec![0] }
+    }
+
+    pub fn check(&mut self, text: &str) -> Result<(), ParseError> {
+        let limits = self.limits;
+        for (n, raw) in text.split('\n').enumerate() {
+            let line = raw.strip_suffix('\r').unwrap_or(raw);
+            if line.len() > limits.max_line_len {
+                let column = line.char_indices().take_while(|(i, _)| *i < limits.max_line_len).count() + 1;
+                return Err(ParseError {
+                    span: Span { line: n + 1, column, len: 1 },
+                    message: format!("line is longer than {} bytes", limits.max_line_len),
+                    help: Some("raise LineLimits::max_line_len to read it".to_string()),
+                });
+            }
+        }
+        for line in source_lines(text) {
+            let indent = line.text.chars().take_while(|c| c.is_whitespace()).count();
+            if line.level > limits.max_depth {
+                return Err(ParseError {
+                    span: Span { line: line.number, column: 1, len: indent },
+                    message: format!("indented {} levels deep", line.level),
+                    help: Some(format!("nesting is limited to {} levels by LineLimits::max_depth", limits.max_depth)),
+                });
+            }
+            while self.open.len() > 1 && self.open.last().is_some_and(|&body| body > line.level) {
+                self.open.pop();
+            }
+            let body = self.open.last().copied().unwrap_or(0);
+            if limits.strict_indent && line.level > body {
+                return Err(ParseError {
+                    span: Span { line: line.number, column: 1, len: indent },
+                    message: format!("indented {} levels deep where at most {} was expected", line.level, body),
+                    help: Some("indent each line one level below the section it belongs to".to_string()),
+                });
+            }
+            if let Some(key) = line.key().filter(|key| limits.strict_keys && key.contains([':', '='])) {
+                return Err(ParseError {
+                    span: line.key_span(),
+                    message: format!("key '{}' contains ':' or '='", key),
+                    help: Some("such keys do not read back the same once written; rename the key".to_string()),
+                });
+            }
+            if let LineKind::Section { .. } = line.kind {
+                self.open.push(line.level + 1);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::FlowPath;
+    use crate::stream::{stream_array, StreamError};
+    use crate::{v2, ParseOptions};
+    use std::fs;
+
+    fn big_array(n: usize) -> String {
+        let items: Vec<String> = (0..n).map(|i| i.to_string()).collect();
+        format!("rows = [{}]\n", items.join(", "))
+    }
+
+    fn strict(limits: LineLimits) -> ParseOptions {
+        ParseOptions::new().limits(limits)
+    }
+
+    #[test]
+    fn no_limits_by_default() {
+        assert_eq!(LineLimits::default(), LineLimits::none());
+        let text = big_array(300_000);
+        assert!(text.len() > LineLimits::untrusted().max_line_len);
+        assert_eq!(v2::parse(&text).unwrap()["rows"].as_array().map(Vec::len), Some(300_000));
+        assert!(check(&format!("a:\n{}b = 1\n", "  ".repeat(500)), &LineLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn long_lines_and_depth() {
+        let text = big_array(300_000);
+        let err = check(&text, &LineLimits::untrusted()).unwrap_err();
+        assert_eq!((err.span.line, err.span.column), (1, (1 << 20) + 1));
+
+        let limits = LineLimits { max_depth: 2, ..LineLimits::none() };
+        assert!(check("a:\n  b:\n    c = 1\n", &limits).is_ok());
+        let err = check("a:\n  b:\n    c:\n      d = 1\n", &limits).unwrap_err();
+        assert_eq!(err.span.line, 4);
+    }
+
+    #[test]
+    fn indentation_jumps() {
+        let limits = LineLimits { strict_indent: true, ..LineLimits::none() };
+        assert!(check("a:\n  b:\n    c = 1\n  d = 2\ne = 3\n", &limits).is_ok());
+        // deeper than the open section's body
+        let err = check("a:\n        b = 1\n", &limits).unwrap_err();
+        assert_eq!((err.span.line, err.span.len), (2, 8));
+        // a pair opens nothing
+        assert_eq!(check("a:\n  b = 1\n    c = 2\n", &limits).unwrap_err().span.line, 3);
+        assert_eq!(check("  a = 1\n", &limits).unwrap_err().span.line, 1);
+        // back out to the root and in again
+        assert_eq!(check("a:\n  b:\n    c = 1\nd:\n    e = 1\n", &limits).unwrap_err().span.line, 5);
+        assert!(check("a:\n        b = 1\n", &LineLimits::none()).is_ok());
+    }
+
+    #[test]
+    fn keys() {
+        let limits = LineLimits { strict_keys: true, ..LineLimits::none() };
+        assert!(check("a=b:\n  c = 1\n", &limits).is_err());
+        assert!(check("x = a:b\n", &limits).is_ok());
+        assert!(check("a=b:\n  c = 1\n", &LineLimits::none()).is_ok());
+    }
+
+    #[test]
+    fn streaming() {
+        let dir = std::env::temp_dir().join(format!("flowdoc-limits-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("big.flow").display().to_string();
+        fs::write(&path, format!("meta:\n  count = 300000\n{}", big_array(300_000))).unwrap();
+        let rows = FlowPath::parse("rows").unwrap();
+
+        assert_eq!(stream_array(&path, &rows, &ParseOptions::default()).unwrap().count(), 300_000);
+        match stream_array(&path, &rows, &strict(LineLimits::untrusted())) {
+            Err(StreamError::Invalid(message)) => assert!(message.starts_with("line 3, column 1048577"), "{}", message),
+            other => panic!("expected a line length error, got {:?}", other.map(|_| ())),
+        }
+
+        let jump = dir.join("jump.flow").display().to_string();
+        fs::write(&jump, "meta:\n      count = 1\nrows = [1, 2]\n").unwrap();
+        let strict_indent = strict(LineLimits { strict_indent: true, ..LineLimits::none() });
+        assert!(matches!(stream_array(&jump, &rows, &strict_indent), Err(StreamError::Invalid(_))));
+        assert_eq!(stream_array(&jump, &rows, &ParseOptions::default()).unwrap().count(), 2);
+    }
+}
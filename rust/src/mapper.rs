@@ -0,0 +1,280 @@
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::path::FlowPath;
+use crate::template::type_matches;
+use crate::validate::{coerce, model_field};
+use crate::{FieldDefinition, ModelDefinition};
+
+// ============================================
+// Model Mapping
+// ============================================
+//
+// A ModelMapper carries documents between two models that describe the same
+// data, e.g. two versions of an event schema, in either direction. Fields
+// are paired one to one: by `field_id`, by full name, or by an explicit
+// table of source and target names (aliases accepted). Pairs may be added on
+// top of `by_field_id` or `by_name`; a field paired twice is an error.
+//
+// `forward` rewrites every instance of the source model in a document, the
+// way `validate` finds them, into an instance of the target; `backward` does
+// the reverse. A paired field is renamed to the other field's full name and
+// its value converted when the types differ and the conversion loses nothing
+// (`"8080"` to 8080, 8080 to `"8080"`); otherwise the mapping fails. Fields
+// with no counterpart are dropped and reported, keys that are not fields are
+// kept, and required fields still missing get their default when they have
+// one. A top-level `use_model` is switched to the other model's name. Two
+// keys that would land on the same target key, such as a field given under
+// both its full name and its alias, or a renamed field and a key already
+// holding the new name, fail the mapping rather than one overwriting the
+// other.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappingError {
+    pub path: FlowPath,
+    pub message: String,
+}
+
+impl fmt::Display for MappingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_root() {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+impl std::error::Error for MappingError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapped {
+    pub value: Value,
+    // fields left out because the other model has no counterpart
+    pub dropped: Vec<FlowPath>,
+}
+
+pub struct ModelMapper<'a> {
+    source: &'a ModelDefinition,
+    target: &'a ModelDefinition,
+    // full names: (source, target)
+    pairs: Vec<(String, String)>,
+}
+
+impl<'a> ModelMapper<'a> {
+    pub fn new(source: &'a ModelDefinition, target: &'a ModelDefinition) -> Self {
+        ModelMapper { source, target, pairs: Vec::new() }
+    }
+
+    // Pairs the fields that have the same `field_id`.
+    pub fn by_field_id(source: &'a ModelDefinition, target: &'a ModelDefinition) -> Result<Self, MappingError> {
+        let mut mapper = Self::new(source, target);
+        for field in sorted(source) {
+            let Some(id) = field.field_id else { continue };
+            if let Some(other) = sorted(target).into_iter().find(|f| f.field_id == Some(id)) {
+                mapper = mapper.pair(&field.full_name, &other.full_name)?;
+            }
+        }
+        Ok(mapper)
+    }
+
+    // Pairs the fields that have the same full name.
+    pub fn by_name(source: &'a ModelDefinition, target: &'a ModelDefinition) -> Result<Self, MappingError> {
+        let mut mapper = Self::new(source, target);
+        for field in sorted(source) {
+            if target.fields.contains_key(&field.full_name) {
+                mapper = mapper.pair(&field.full_name, &field.full_name)?;
+            }
+        }
+        Ok(mapper)
+    }
+
+    pub fn pair(mut self, source_field: &str, target_field: &str) -> Result<Self, MappingError> {
+        let error = |message: String| MappingError { path: FlowPath::root(), message };
+        let from = model_field(self.source, source_field)
+            .ok_or_else(|| error(format!("model '{}' has no field '{}'", self.source.name, source_field)))?;
+        let to = model_field(self.target, target_field)
+            .ok_or_else(|| error(format!("model '{}' has no field '{}'", self.target.name, target_field)))?;
+        if let Some((s, t)) = self.pairs.iter().find(|(s, t)| *s == from.full_name || *t == to.full_name) {
+            return Err(error(format!("'{}' and '{}' are already paired", s, t)));
+        }
+        self.pairs.push((from.full_name.clone(), to.full_name.clone()));
+        Ok(self)
+    }
+
+    // (source, target) full names of the paired fields.
+    pub fn pairs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(s, t)| (s.as_str(), t.as_str()))
+    }
+
+    pub fn forward(&self, doc: &Value) -> Result<Mapped, MappingError> {
+        Direction { from: self.source, to: self.target, pairs: self.pairs.iter().map(|(s, t)| (s.as_str(), t.as_str())).collect() }.run(doc)
+    }
+
+    pub fn backward(&self, doc: &Value) -> Result<Mapped, MappingError> {
+        Direction { from: self.target, to: self.source, pairs: self.pairs.iter().map(|(s, t)| (t.as_str(), s.as_str())).collect() }.run(doc)
+    }
+}
+
+fn sorted(model: &ModelDefinition) -> Vec<&FieldDefinition> {
+    let mut fields: Vec<&FieldDefinition> = model.fields.values().collect();
+    fields.sort_by(|a, b| a.full_name.cmp(&b.full_name));
+    fields
+}
+
+struct Direction<'a> {
+    from: &'a ModelDefinition,
+    to: &'a ModelDefinition,
+    pairs: Vec<(&'a str, &'a str)>,
+}
+
+impl Direction<'_> {
+    fn run(&self, doc: &Value) -> Result<Mapped, MappingError> {
+        let use_model = FlowPath::root().child_key("use_model");
+        match doc.get("use_model").and_then(Value::as_str) {
+            Some(name) if name != self.from.name => {
+                return Err(MappingError { path: use_model, message: format!("document uses model '{}', not '{}'", name, self.from.name) });
+            }
+            _ => {}
+        }
+        let mut dropped = Vec::new();
+        let mut value = self.map(doc, &mut FlowPath::root(), &mut dropped)?;
+        if let Some(Value::String(name)) = value.get_mut("use_model") {
+            *name = self.to.name.clone();
+        }
+        dropped.sort();
+        Ok(Mapped { value, dropped })
+    }
+
+    fn map(&self, val: &Value, path: &mut FlowPath, dropped: &mut Vec<FlowPath>) -> Result<Value, MappingError> {
+        match val {
+            Value::Object(map) => {
+                let instance = map.keys().any(|k| !k.starts_with('$') && model_field(self.from, k).is_some());
+                let mut out = Map::new();
+                // the key each target key was written from
+                let mut written: Vec<(String, &String)> = Vec::new();
+                for (k, v) in map {
+                    path.push_key(k);
+                    let field = model_field(self.from, k).filter(|_| instance && !k.starts_with('$'));
+                    let mapped = match field {
+                        Some(field) => match self.pairs.iter().find(|(from, _)| *from == field.full_name) {
+                            Some((_, to)) => {
+                                let to = &self.to.fields[*to];
+                                Some((to.full_name.clone(), self.convert(self.map(v, path, dropped)?, to, path)?))
+                            }
+                            None => {
+                                dropped.push(path.clone());
+                                None
+                            }
+                        },
+                        None => Some((k.clone(), self.map(v, path, dropped)?)),
+                    };
+                    if let Some((key, value)) = mapped {
+                        if let Some((_, earlier)) = written.iter().find(|(w, _)| *w == key) {
+                            return Err(MappingError { path: path.clone(), message: format!("'{}' and '{}' both map to '{}'", earlier, k, key) });
+                        }
+                        written.push((key.clone(), k));
+                        out.insert(key, value);
+                    }
+                    path.pop();
+                }
+                if instance {
+                    for field in sorted(self.to).into_iter().filter(|f| f.required) {
+                        if let Some(default) = field.default.as_ref().filter(|_| !out.contains_key(&field.full_name)) {
+                            out.insert(field.full_name.clone(), default.clone());
+                        }
+                    }
+                }
+                Ok(Value::Object(out))
+            }
+            Value::Array(items) => {
+                let mut out = Vec::new();
+                for (i, v) in items.iter().enumerate() {
+                    path.push_index(i);
+                    out.push(self.map(v, path, dropped)?);
+                    path.pop();
+                }
+                Ok(Value::Array(out))
+            }
+            _ => Ok(val.clone()),
+        }
+    }
+
+    fn convert(&self, val: Value, to: &FieldDefinition, path: &FlowPath) -> Result<Value, MappingError> {
+        if to.field_type.is_empty() || type_matches(&to.field_type, &val) {
+            return Ok(val);
+        }
+        coerce(&val, &to.field_type, None).ok_or_else(|| MappingError {
+            path: path.clone(),
+            message: format!("{} does not convert to {} for '{}' in model '{}'", val, to.field_type, to.full_name, self.to.name),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ModelRegistry;
+    use serde_json::json;
+
+    fn registry() -> ModelRegistry {
+        let mut registry = ModelRegistry::new();
+        registry.register_models(&json!({ "$models": {
+            "v1": { "fields": {
+                "server_port": { "alias": "port", "type": "string", "id": 1 },
+                "host_name": { "alias": "host", "type": "string", "id": 2 },
+                "legacy": { "type": "bool" },
+            } },
+            "v2": { "fields": {
+                "listen_port": { "alias": "lp", "type": "int", "id": 1 },
+                "host": { "type": "string", "id": 2 },
+                "region": { "type": "string", "required": true, "default": "eu" },
+            } },
+        } }));
+        registry
+    }
+
+    fn mapper(registry: &ModelRegistry) -> ModelMapper<'_> {
+        ModelMapper::by_field_id(registry.get_model("v1").unwrap(), registry.get_model("v2").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn forward_and_back() {
+        let registry = registry();
+        let mapper = mapper(&registry);
+        let doc = json!({ "use_model": "v1", "port": "8080", "host_name": "a", "legacy": true, "note": "kept" });
+        let forward = mapper.forward(&doc).unwrap();
+        assert_eq!(forward.value, json!({ "use_model": "v2", "listen_port": 8080, "host": "a", "note": "kept", "region": "eu" }));
+        assert_eq!(forward.dropped, [FlowPath::parse("legacy").unwrap()]);
+
+        let back = mapper.backward(&forward.value).unwrap();
+        assert_eq!(back.value, json!({ "use_model": "v1", "server_port": "8080", "host_name": "a", "note": "kept" }));
+        assert_eq!(back.dropped, [FlowPath::parse("region").unwrap()]);
+    }
+
+    #[test]
+    fn conversions_that_lose_data_fail() {
+        let registry = registry();
+        let err = mapper(&registry).forward(&json!({ "server_port": "80a" })).unwrap_err();
+        assert_eq!(err.path, FlowPath::parse("server_port").unwrap());
+        let err = mapper(&registry).forward(&json!({ "items": [{ "port": "1.5" }] })).unwrap_err();
+        assert_eq!(err.path, FlowPath::parse("items[0].port").unwrap());
+        let err = mapper(&registry).forward(&json!({ "use_model": "v2" })).unwrap_err();
+        assert_eq!(err.path, FlowPath::parse("use_model").unwrap());
+    }
+
+    #[test]
+    fn colliding_keys_fail() {
+        let registry = registry();
+        let mapper = mapper(&registry);
+        // a field under its full name and its alias
+        let err = mapper.forward(&json!({ "server_port": "1", "port": "2" })).unwrap_err();
+        assert_eq!(err.path, FlowPath::parse("server_port").unwrap());
+        assert!(err.message.contains("'port' and 'server_port' both map to 'listen_port'"), "{}", err);
+        // a renamed field landing on a key that is not a field
+        let err = mapper.forward(&json!({ "server_port": "1", "listen_port": 2 })).unwrap_err();
+        assert!(err.message.contains("both map to 'listen_port'"), "{}", err);
+        let err = mapper.backward(&json!({ "listen_port": 1, "server_port": "x" })).unwrap_err();
+        assert!(err.message.contains("both map to 'server_port'"), "{}", err);
+    }
+}
@@ -0,0 +1,436 @@
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::diagnostic::source_spans;
+use crate::encoding::{self, read_text};
+use crate::path::{FlowPath, PathSegment};
+use crate::refs::{resolve_refs_tracked, FileRefResolver, RefResolver, RefSite};
+use crate::{control, limits, parse_document_with, warnings_for, ParseError, ParseOptions, ParseWarning};
+
+// ============================================
+// Provenance
+// ============================================
+//
+// A Provenance maps the paths at which values were placed to where they
+// came from. The origin of any path is the entry at the path itself or at
+// its nearest recorded ancestor, so a whole section taken from one file
+// needs a single entry. When the source text of a file has been indexed,
+// `get_origin` also finds the line each node below an entry is written on.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    pub source: String,
+    pub line: Option<usize>,
+    // the layer the value came in with, e.g. the file stem in a merged directory
+    pub layer: Option<String>,
+}
+
+impl Origin {
+    pub fn new(source: impl Into<String>) -> Self {
+        Origin { source: source.into(), line: None, layer: None }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if let Some(layer) = &self.layer {
+            write!(f, " ({})", layer)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Placement {
+    origin: Origin,
+    // the path of the value inside its source
+    base: FlowPath,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Provenance {
+    entries: BTreeMap<FlowPath, Placement>,
+    // line of every object entry, per indexed source
+    lines: HashMap<String, BTreeMap<FlowPath, usize>>,
+}
+
+impl Provenance {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The entry recorded at `path` or its nearest ancestor.
+    pub fn origin(&self, path: &FlowPath) -> Option<&Origin> {
+        self.placement(path).map(|(_, p)| &p.origin)
+    }
+
+    // Like `origin`, with the line of `path` itself when its source was
+    // indexed and the node is written on a line of its own.
+    pub fn get_origin(&self, path: &FlowPath) -> Option<Origin> {
+        let (at, placement) = self.placement(path)?;
+        let mut origin = placement.origin.clone();
+        if let Some(line) = self.line_of(&origin.source, &rebase(&placement.base, path, &at), &placement.base) {
+            origin.line = Some(line);
+        }
+        Some(origin)
+    }
+
+    fn placement(&self, path: &FlowPath) -> Option<(FlowPath, &Placement)> {
+        let mut p = Some(path.clone());
+        while let Some(current) = p {
+            if let Some(placement) = self.entries.get(&current) {
+                return Some((current, placement));
+            }
+            p = current.parent();
+        }
+        None
+    }
+
+    // The line of `path` or of its nearest ancestor no higher than `floor`.
+    fn line_of(&self, source: &str, path: &FlowPath, floor: &FlowPath) -> Option<usize> {
+        let lines = self.lines.get(source)?;
+        let mut p = Some(path.clone());
+        while let Some(current) = p.filter(|c| c.starts_with(floor)) {
+            if let Some(line) = lines.get(&current) {
+                return Some(*line);
+            }
+            p = current.parent();
+        }
+        None
+    }
+
+    // Records `origin` for the value now at `path`, replacing whatever was
+    // recorded for the value it replaced.
+    pub fn record(&mut self, path: &FlowPath, origin: Origin) {
+        self.place(path, origin, path.clone());
+    }
+
+    // As `record`, for a value that sits at `base` in its source.
+    pub(crate) fn place(&mut self, path: &FlowPath, mut origin: Origin, base: FlowPath) {
+        let below: Vec<FlowPath> = self.entries.range(path.clone()..).take_while(|(p, _)| p.starts_with(path)).map(|(p, _)| p.clone()).collect();
+        for p in below {
+            self.entries.remove(&p);
+        }
+        if origin.line.is_none() {
+            origin.line = self.line_of(&origin.source, &base, &base);
+        }
+        self.entries.insert(path.clone(), Placement { origin, base });
+    }
+
+    // Remembers where each entry of `text`, the content of `source`, is written.
+    pub(crate) fn index_source(&mut self, source: &str, text: &str) {
+        let lines = source_spans(text).into_iter().map(|(path, span)| (path, span.key.line)).collect();
+        self.lines.insert(source.to_string(), lines);
+    }
+
+    // Takes over the entries of `other`, a provenance for the value now at
+    // `prefix`, along with its line indexes.
+    pub(crate) fn graft(&mut self, prefix: &FlowPath, other: Provenance) {
+        for (source, lines) in other.lines {
+            self.lines.entry(source).or_insert(lines);
+        }
+        for (path, placement) in other.entries {
+            self.place(&rebase(prefix, &path, &FlowPath::root()), placement.origin, placement.base);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&FlowPath, &Origin)> {
+        self.entries.iter().map(|(path, placement)| (path, &placement.origin))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+// Points the values each reference brought in at the file it names; sites
+// are listed outer first, so nested references win.
+pub(crate) fn place_ref_sites(provenance: &mut Provenance, sites: Vec<RefSite>, layer: Option<&str>) {
+    for site in sites {
+        if !provenance.lines.contains_key(&site.id) {
+            if let Ok(text) = read_text(&site.id) {
+                provenance.index_source(&site.id, &text);
+            }
+        }
+        let origin = Origin { source: site.id, line: None, layer: layer.map(str::to_string) };
+        provenance.place(&site.path, origin, site.target);
+    }
+}
+
+// Deep merge as in `when` blocks, recording the origin of every value placed.
+pub(crate) fn merge_tracked(target: &mut Map<String, Value>, source: Map<String, Value>, path: &mut FlowPath, origin: &Origin, append_arrays: bool, provenance: &mut Provenance) {
+    for (k, v) in source {
+        path.push_key(&k);
+        match (target.get_mut(&k), v) {
+            (Some(Value::Object(existing)), Value::Object(incoming)) => {
+                merge_tracked(existing, incoming, path, origin, append_arrays, provenance);
+            }
+            (Some(Value::Array(existing)), Value::Array(incoming)) if append_arrays => {
+                for (i, item) in incoming.into_iter().enumerate() {
+                    let base = path.child_index(i);
+                    path.push_index(existing.len());
+                    provenance.place(path, origin.clone(), base);
+                    path.pop();
+                    existing.push(item);
+                }
+            }
+            (_, v) => {
+                provenance.record(path, origin.clone());
+                target.insert(k, v);
+            }
+        }
+        path.pop();
+    }
+}
+
+// `path` with its prefix `from` replaced by `to`.
+fn rebase(to: &FlowPath, path: &FlowPath, from: &FlowPath) -> FlowPath {
+    let segments: Vec<PathSegment> = to.segments().iter().chain(&path.segments()[from.len()..]).cloned().collect();
+    FlowPath::from(segments)
+}
+
+// ============================================
+// Directory Loading
+// ============================================
+
+#[derive(Debug, Clone, Default)]
+pub struct DirMergeOptions {
+    // arrays present in several files are concatenated instead of replaced
+    pub append_arrays: bool,
+    pub parse: ParseOptions,
+}
+
+impl DirMergeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn append_arrays(mut self, on: bool) -> Self {
+        self.append_arrays = on;
+        self
+    }
+
+    pub fn parse(mut self, parse: ParseOptions) -> Self {
+        self.parse = parse;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MergedFlow {
+    pub value: Value,
+    // the files merged, in merge order
+    pub sources: Vec<PathBuf>,
+    pub provenance: Provenance,
+    // the file each warning was raised for
+    pub warnings: Vec<(PathBuf, ParseWarning)>,
+}
+
+impl MergedFlow {
+    // Which file, line and layer the value at `path` came from. Values
+    // brought in with `@ref` point at the referenced file.
+    pub fn get_origin(&self, path: &FlowPath) -> Option<Origin> {
+        self.provenance.get_origin(path)
+    }
+}
+
+// A single file loaded with the origin of each of its values: the file
+// itself, or the file an `@ref` brought the value in from.
+#[derive(Debug, Clone)]
+pub struct TracedFlow {
+    pub value: Value,
+    pub provenance: Provenance,
+    pub warnings: Vec<ParseWarning>,
+}
+
+impl TracedFlow {
+    pub fn get_origin(&self, path: &FlowPath) -> Option<Origin> {
+        self.provenance.get_origin(path)
+    }
+}
+
+// Loads every `*.flow` file directly inside `dir` in lexical order of file
+// name and deep-merges them, later files winning. Hidden files are skipped.
+#[deprecated(note = "use v2::load_dir_merged")]
+pub fn LoadFlowDirMerged(dir: &str, options: &DirMergeOptions) -> Result<MergedFlow, io::Error> {
+    load_dir_merged(dir, options)
+}
+
+pub(crate) fn load_dir_merged(dir: &str, options: &DirMergeOptions) -> Result<MergedFlow, io::Error> {
+    let mut sources: Vec<PathBuf> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') || !name.ends_with(".flow") || !entry.file_type()?.is_file() {
+            continue;
+        }
+        sources.push(entry.path());
+    }
+    sources.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+
+    let mut root = Map::new();
+    let mut provenance = Provenance::new();
+    let mut warnings = Vec::new();
+    for source in &sources {
+        let location = source.display().to_string();
+        let layer = source.file_stem().map(|s| s.to_string_lossy().into_owned());
+        let bytes = fs::read(source)?;
+        let (text, replaced) = encoding::decode(&bytes, options.parse.lossy).map_err(|e| encoding::with_path(e, &location))?;
+        warnings.extend(warnings_for(replaced).into_iter().map(|w| (source.clone(), w)));
+        let invalid = |e: ParseError| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", location, e));
+        let text = control::apply(&text, options.parse.control).map_err(invalid)?;
+        limits::check(&text, &options.parse.limits).map_err(invalid)?;
+        provenance.index_source(&location, &text);
+
+        let resolver = FileRefResolver;
+        let id = resolver.locate(&location, None)?;
+        let mut sites = Vec::new();
+        let value = resolve_refs_tracked(&parse_document_with(&text, &options.parse)?, Some(&id), &resolver, &mut sites)?;
+        let origin = Origin { source: location, line: None, layer };
+        let mut placed = Provenance::new();
+        if let Value::Object(map) = value {
+            merge_tracked(&mut root, map, &mut FlowPath::root(), &origin, options.append_arrays, &mut placed);
+        }
+        // values that came in through a reference point at its target;
+        // sites are listed outer first, so nested references win
+        let merged: Vec<(FlowPath, FlowPath)> = placed.entries.iter().map(|(p, e)| (p.clone(), e.base.clone())).collect();
+        for site in sites {
+            if !provenance.lines.contains_key(&site.id) {
+                if let Ok(text) = read_text(&site.id) {
+                    provenance.index_source(&site.id, &text);
+                }
+            }
+            let origin = Origin { source: site.id, line: None, layer: origin.layer.clone() };
+            for (at, base) in &merged {
+                if base.starts_with(&site.path) {
+                    placed.entries.insert(at.clone(), Placement { origin: origin.clone(), base: rebase(&site.target, base, &site.path) });
+                } else if site.path.starts_with(base) {
+                    placed.place(&rebase(at, &site.path, base), origin.clone(), site.target.clone());
+                }
+            }
+        }
+        for (path, placement) in placed.entries {
+            provenance.place(&path, placement.origin, placement.base);
+        }
+    }
+    Ok(MergedFlow { value: Value::Object(root), sources, provenance, warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CancelToken, ControlPolicy};
+    use serde_json::json;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flowdoc-merge-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn files_merge_in_lexical_order() {
+        let dir = scratch_dir("order");
+        fs::write(dir.join("20-site.flow"), "server:\n  port = 8080\ntags = [\"site\"]\n").unwrap();
+        fs::write(dir.join("10-base.flow"), "server:\n  host = \"localhost\"\n  port = 80\ntags = [\"base\"]\n").unwrap();
+        fs::write(dir.join(".99-hidden.flow"), "server:\n  port = 1\n").unwrap();
+        fs::write(dir.join("30-notes.txt"), "server:\n  port = 2\n").unwrap();
+        fs::create_dir_all(dir.join("40-dir.flow")).unwrap();
+        let path = dir.to_string_lossy();
+
+        let merged = load_dir_merged(&path, &DirMergeOptions::new()).unwrap();
+        assert_eq!(merged.value, json!({"server": {"host": "localhost", "port": 8080}, "tags": ["site"]}));
+        assert_eq!(merged.sources, vec![dir.join("10-base.flow"), dir.join("20-site.flow")]);
+        assert!(merged.warnings.is_empty());
+
+        let appended = load_dir_merged(&path, &DirMergeOptions::new().append_arrays(true)).unwrap();
+        assert_eq!(appended.value["tags"], json!(["base", "site"]));
+        assert_eq!(load_dir_merged(&dir.join("missing").to_string_lossy(), &DirMergeOptions::new()).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn parse_options_apply_to_every_file() {
+        let dir = scratch_dir("options");
+        fs::write(dir.join("a.flow"), "limits:\n  rows = 1,000\n").unwrap();
+        fs::write(dir.join("b.flow"), "limits:\n  bytes = 2,500,000\n").unwrap();
+        let options = DirMergeOptions::new().parse(ParseOptions::new().thousands(','));
+        let merged = load_dir_merged(&dir.to_string_lossy(), &options).unwrap();
+        assert_eq!(merged.value, json!({ "limits": { "rows": 1000, "bytes": 2500000 } }));
+    }
+
+    #[test]
+    fn control_policy_and_cancel() {
+        let dir = scratch_dir("control");
+        fs::write(dir.join("a.flow"), "title = \"x\u{1b}[2Jy\"\n").unwrap();
+        let dir = dir.to_string_lossy();
+
+        let strip = DirMergeOptions::new().parse(ParseOptions::new().control(ControlPolicy::Strip));
+        assert_eq!(load_dir_merged(&dir, &strip).unwrap().value, json!({ "title": "x[2Jy" }));
+
+        let reject = DirMergeOptions::new().parse(ParseOptions::new().control(ControlPolicy::Reject));
+        let err = load_dir_merged(&dir, &reject).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("a.flow"), "{}", err);
+
+        let token = CancelToken::new();
+        token.cancel();
+        let cancelled = DirMergeOptions::new().parse(ParseOptions::new().cancel(token));
+        assert_eq!(load_dir_merged(&dir, &cancelled).unwrap_err().kind(), io::ErrorKind::Interrupted);
+    }
+
+    #[test]
+    fn origins_in_a_layered_directory() {
+        let dir = scratch_dir("layers");
+        fs::write(dir.join("00-base.flow"), "server:\n  host = \"localhost\"\n  port = 80\nlog = \"info\"\n").unwrap();
+        fs::write(dir.join("10-prod.flow"), "# production\nserver:\n  port = 443\n").unwrap();
+        let merged = load_dir_merged(&dir.to_string_lossy(), &DirMergeOptions::new()).unwrap();
+        let origin = |p: &str| merged.get_origin(&FlowPath::parse(p).unwrap()).unwrap();
+
+        let port = origin("server.port");
+        assert_eq!(port.source, dir.join("10-prod.flow").display().to_string());
+        assert_eq!((port.line, port.layer.as_deref()), (Some(3), Some("10-prod")));
+        let host = origin("server.host");
+        assert_eq!(host.source, dir.join("00-base.flow").display().to_string());
+        assert_eq!((host.line, host.layer.as_deref()), (Some(2), Some("00-base")));
+        assert_eq!(origin("log").line, Some(4));
+    }
+
+    #[test]
+    fn origins_of_referenced_nodes() {
+        let dir = scratch_dir("refs");
+        fs::write(dir.join("shared.flow"), "db:\n  host = \"db1\"\n  pool:\n    size = 4\n").unwrap();
+        fs::write(dir.join("app.flow"), "name = \"app\"\ndatabase = @ref(\"shared.flow#db\")\nfallback = @ref(\"shared.flow#db\")\n").unwrap();
+        let shared = fs::canonicalize(dir.join("shared.flow")).unwrap().display().to_string();
+
+        let merged = load_dir_merged(&dir.to_string_lossy(), &DirMergeOptions::new()).unwrap();
+        let size = merged.get_origin(&FlowPath::parse("database.pool.size").unwrap()).unwrap();
+        assert_eq!((size.source.as_str(), size.line, size.layer.as_deref()), (shared.as_str(), Some(4), Some("app")));
+
+        let app = dir.join("app.flow").display().to_string();
+        let traced = crate::v2::load_traced(&app, &ParseOptions::default()).unwrap();
+        assert_eq!(traced.value["database"]["pool"]["size"], json!(4));
+        let origin = |p: &str| traced.get_origin(&FlowPath::parse(p).unwrap()).unwrap();
+        // the second reference to the same node is traced too
+        for p in ["database.host", "fallback.host"] {
+            assert_eq!((origin(p).source, origin(p).line), (shared.clone(), Some(2)));
+        }
+        assert_eq!(origin("fallback.pool.size").line, Some(4));
+        assert_eq!(origin("name").line, Some(1));
+        assert!(origin("name").source.ends_with("app.flow"));
+        assert_eq!(origin("name").layer, None);
+    }
+}
@@ -0,0 +1,232 @@
+use serde_json::Value;
+use std::fmt;
+
+use crate::path::{FlowPath, PathError, PathGlob, PathSegment};
+
+// ============================================
+// Migrations
+// ============================================
+//
+// A Migration is an ordered list of rules applied to a parsed document.
+// It can be built in code or parsed from text, one rule per line:
+//
+//     rename **.hostname -> host      # every `hostname` key becomes `host`
+//     move db.url -> database.url     # relocate one value
+//     delete **.legacy_*              # drop every matching node
+//
+// `rename` and `delete` take path globs; `move` takes exact paths and does
+// nothing when the source is missing. A renamed or moved value replaces any
+// value already at its destination.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MigrationRule {
+    Rename { from: PathGlob, to: String },
+    Move { from: FlowPath, to: FlowPath },
+    Delete(PathGlob),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationError {
+    // 1-based line of the rule in the migration text, when parsed from text
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "migration line {}: {}", line, self.message),
+            None => write!(f, "migration: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<PathError> for MigrationError {
+    fn from(e: PathError) -> Self {
+        MigrationError { line: None, message: e.to_string() }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Migration {
+    pub rules: Vec<MigrationRule>,
+}
+
+impl Migration {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, MigrationError> {
+        let mut migration = Migration::new();
+        for (i, line) in text.lines().enumerate() {
+            let at = |message: String| MigrationError { line: Some(i + 1), message };
+            let line = crate::strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (verb, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let args = args.trim();
+            let rule = match verb {
+                "delete" => MigrationRule::Delete(PathGlob::parse(args).map_err(|e| at(e.to_string()))?),
+                "rename" | "move" => {
+                    let (from, to) = args.split_once("->").ok_or_else(|| at(format!("expected '{} <from> -> <to>'", verb)))?;
+                    let (from, to) = (from.trim(), to.trim());
+                    if verb == "rename" {
+                        if to.is_empty() || FlowPath::parse(to).map(|p| p.len() != 1).unwrap_or(true) {
+                            return Err(at(format!("'{}' is not a single key", to)));
+                        }
+                        MigrationRule::Rename { from: PathGlob::parse(from).map_err(|e| at(e.to_string()))?, to: unescape_key(to) }
+                    } else {
+                        MigrationRule::Move {
+                            from: FlowPath::parse(from).map_err(|e| at(e.to_string()))?,
+                            to: FlowPath::parse(to).map_err(|e| at(e.to_string()))?,
+                        }
+                    }
+                }
+                other => return Err(at(format!("unknown rule '{}'", other))),
+            };
+            migration.rules.push(rule);
+        }
+        Ok(migration)
+    }
+
+    pub fn rename(mut self, from: &str, to: &str) -> Result<Self, PathError> {
+        self.rules.push(MigrationRule::Rename { from: PathGlob::parse(from)?, to: to.to_string() });
+        Ok(self)
+    }
+
+    pub fn move_path(mut self, from: &str, to: &str) -> Result<Self, PathError> {
+        self.rules.push(MigrationRule::Move { from: FlowPath::parse(from)?, to: FlowPath::parse(to)? });
+        Ok(self)
+    }
+
+    pub fn delete(mut self, glob: &str) -> Result<Self, PathError> {
+        self.rules.push(MigrationRule::Delete(PathGlob::parse(glob)?));
+        Ok(self)
+    }
+
+    // Applies the rules in order and returns how many nodes they changed.
+    pub fn apply(&self, doc: &mut Value) -> Result<usize, MigrationError> {
+        let mut changed = 0;
+        for rule in &self.rules {
+            match rule {
+                MigrationRule::Rename { from, to } => {
+                    for path in matching_keys(from, doc) {
+                        if path.last() == Some(&PathSegment::Key(to.clone())) {
+                            continue;
+                        }
+                        let parent = path.parent().unwrap_or_default();
+                        if let Some(v) = path.remove(doc) {
+                            parent.child_key(to).set(doc, v)?;
+                            changed += 1;
+                        }
+                    }
+                }
+                MigrationRule::Move { from, to } => {
+                    if let Some(v) = from.remove(doc) {
+                        to.set(doc, v)?;
+                        changed += 1;
+                    }
+                }
+                MigrationRule::Delete(glob) => {
+                    for path in matching_keys(glob, doc) {
+                        changed += path.remove(doc).is_some() as usize;
+                    }
+                }
+            }
+        }
+        Ok(changed)
+    }
+}
+
+// Matching non-root paths, deepest and last first, so acting on one never
+// moves another that is still to come.
+fn matching_keys(glob: &PathGlob, doc: &Value) -> Vec<FlowPath> {
+    let mut paths: Vec<FlowPath> = glob.find(doc).into_iter().map(|(p, _)| p).filter(|p| !p.is_root()).collect();
+    paths.sort();
+    paths.reverse();
+    paths
+}
+
+fn unescape_key(key: &str) -> String {
+    match FlowPath::parse(key).ok().and_then(|p| p.last().cloned()) {
+        Some(PathSegment::Key(k)) => k,
+        _ => key.to_string(),
+    }
+}
+
+#[deprecated(note = "use v2::migrate")]
+pub fn MigrateFlow(doc: &Value, migration: &Migration) -> Result<Value, MigrationError> {
+    migrate(doc, migration)
+}
+
+pub(crate) fn migrate(doc: &Value, migration: &Migration) -> Result<Value, MigrationError> {
+    let mut out = doc.clone();
+    migration.apply(&mut out)?;
+    Ok(out)
+}
+
+#[deprecated(note = "use v2::load_migrated")]
+pub fn LoadFlowMigrated(path: &str, migration: &Migration) -> Result<Value, Box<dyn std::error::Error>> {
+    Ok(migrate(&crate::load_document(path, &crate::ParseOptions::default())?.value, migration)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn rules_apply_in_order() {
+        let migration = Migration::parse(
+            "# v1 -> v2\n\nrename **.hostname -> host\nmove db.url -> database.url   # relocate\ndelete **.legacy_*\nmove db.missing -> db.other\n",
+        )
+        .unwrap();
+        assert_eq!(migration.rules.len(), 4);
+        let doc = json!({
+            "web": {"hostname": "w", "legacy_port": 1, "backends": [{"hostname": "b1"}, {"hostname": "b2", "host": "old"}]},
+            "db": {"url": "pg://", "hostname": "d"},
+            "legacy_mode": true,
+        });
+        let mut out = doc.clone();
+        assert_eq!(migration.apply(&mut out).unwrap(), 7);
+        assert_eq!(out, json!({
+            "web": {"host": "w", "backends": [{"host": "b1"}, {"host": "b2"}]},
+            "db": {"host": "d"},
+            "database": {"url": "pg://"},
+        }));
+        assert_eq!(migrate(&doc, &migration).unwrap(), out);
+        // a second run has nothing left to do
+        assert_eq!(migration.apply(&mut out).unwrap(), 0);
+    }
+
+    #[test]
+    fn built_in_code() {
+        let migration = Migration::new().rename("a.*.old", "new").unwrap().move_path("a", "b.a").unwrap().delete("c").unwrap();
+        let mut doc = json!({"a": {"x": {"old": 1}, "y": {"old": 2, "keep": 3}}, "c": 0});
+        assert_eq!(migration.apply(&mut doc).unwrap(), 4);
+        assert_eq!(doc, json!({"b": {"a": {"x": {"new": 1}, "y": {"new": 2, "keep": 3}}}}));
+        assert!(Migration::new().rename("a[", "b").is_err());
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        let err = |text: &str| Migration::parse(text).unwrap_err();
+        assert_eq!(err("delete a\ncopy a -> b\n"), MigrationError { line: Some(2), message: "unknown rule 'copy'".to_string() });
+        assert_eq!(err("rename a b").message, "expected 'rename <from> -> <to>'");
+        assert_eq!(err("rename a -> b.c").message, "'b.c' is not a single key");
+        assert_eq!(err("\n\nmove a -> b[").line, Some(3));
+        assert_eq!(err("rename a -> ").to_string(), "migration line 1: '' is not a single key");
+    }
+
+    #[test]
+    fn escaped_rename_targets() {
+        let migration = Migration::parse("rename host -> host\\.name\n").unwrap();
+        let mut doc = json!({"host": "h"});
+        migration.apply(&mut doc).unwrap();
+        assert_eq!(doc, json!({"host.name": "h"}));
+    }
+}
@@ -0,0 +1,236 @@
+use serde_json::Value;
+
+use crate::stringify::scalar;
+use crate::{FieldDefinition, ModelDefinition, ModelRegistry};
+
+// ============================================
+// Model Reference Docs
+// ============================================
+//
+// Renders the models of a registry as a reference: per model a table of
+// its fields (name, alias, type, default, constraints, notes) followed by
+// an example document. Every field appears in the example with its default,
+// its first choice or a placeholder for its type, and a comment saying what
+// it takes. Models and fields are listed by name, so the output only
+// changes when the models do and can be committed next to them.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DocFormat {
+    #[default]
+    Markdown,
+    Html,
+}
+
+pub fn model_docs(registry: &ModelRegistry, format: DocFormat) -> String {
+    let mut models: Vec<&ModelDefinition> = registry.models().collect();
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+    match format {
+        DocFormat::Markdown => markdown(&models),
+        DocFormat::Html => html(&models),
+    }
+}
+
+fn sorted_fields(model: &ModelDefinition) -> Vec<&FieldDefinition> {
+    let mut fields: Vec<&FieldDefinition> = model.fields.values().collect();
+    fields.sort_by(|a, b| a.full_name.cmp(&b.full_name));
+    fields
+}
+
+// The value the example shows for a field.
+pub(crate) fn example_value(field: &FieldDefinition) -> Value {
+    if let Some(default) = &field.default {
+        return default.clone();
+    }
+    if let Some(first) = field.choices.first() {
+        return first.clone();
+    }
+    match field.field_type.as_str() {
+        "int" => Value::from(0),
+        "float" => Value::from(0.0),
+        "bool" => Value::Bool(false),
+        "date" => Value::String("2024-01-01".to_string()),
+        "datetime" => Value::String("2024-01-01T00:00:00Z".to_string()),
+        "array" => Value::Array(Vec::new()),
+        "object" => Value::Object(Default::default()),
+        _ => Value::String(field.full_name.clone()),
+    }
+}
+
+fn constraints(field: &FieldDefinition) -> Vec<String> {
+    let mut out = Vec::new();
+    if field.required {
+        out.push("required".to_string());
+    }
+    if !field.choices.is_empty() {
+        let choices: Vec<String> = field.choices.iter().map(scalar).collect();
+        out.push(format!("one of {}", choices.join(", ")));
+    }
+    if field.sensitive {
+        out.push("sensitive".to_string());
+    }
+    if field.encrypted {
+        out.push("encrypted".to_string());
+    }
+    if let Some(id) = field.field_id {
+        out.push(format!("id {}", id));
+    }
+    out
+}
+
+fn deprecation(field: &FieldDefinition) -> Option<String> {
+    field.deprecated.as_ref().map(|note| if note.is_empty() { "deprecated".to_string() } else { format!("deprecated: {}", note) })
+}
+
+// The example document, one line per field with a trailing comment.
+fn example(model: &ModelDefinition) -> String {
+    let mut out = format!("use_model = {}\n", model.name);
+    for field in sorted_fields(model) {
+        let mut notes = vec![if field.field_type.is_empty() { "any".to_string() } else { field.field_type.clone() }];
+        notes.extend(constraints(field).into_iter().filter(|c| !c.starts_with("id ")));
+        notes.extend(deprecation(field));
+        match example_value(field) {
+            Value::Object(_) => out.push_str(&format!("{}:  # {}\n", field.full_name, notes.join(", "))),
+            value => out.push_str(&format!("{} = {}  # {}\n", field.full_name, scalar(&value), notes.join(", "))),
+        }
+    }
+    out
+}
+
+// ============================================
+// Markdown
+// ============================================
+
+fn markdown(models: &[&ModelDefinition]) -> String {
+    let mut out = String::from("# Models\n");
+    for model in models {
+        out.push_str(&format!("\n## {}\n\n", model.name));
+        out.push_str("| Field | Alias | Type | Default | Constraints | Notes |\n");
+        out.push_str("|---|---|---|---|---|---|\n");
+        for field in sorted_fields(model) {
+            let code = |s: &str| if s.is_empty() { String::new() } else { format!("`{}`", s) };
+            let mut notes: Vec<String> = deprecation(field).map(|d| format!("**{}**", d)).into_iter().collect();
+            notes.extend(field.description.clone());
+            let cells = [
+                code(&field.full_name),
+                code(&field.alias),
+                field.field_type.clone(),
+                field.default.as_ref().map(|d| code(&scalar(d))).unwrap_or_default(),
+                constraints(field).join(", "),
+                notes.join(" "),
+            ];
+            let cells: Vec<String> = cells.iter().map(|c| c.replace('|', "\\|")).collect();
+            out.push_str(&format!("| {} |\n", cells.join(" | ")));
+        }
+        out.push_str(&format!("\nExample:\n\n```flow\n{}```\n", example(model)));
+    }
+    out
+}
+
+// ============================================
+// HTML
+// ============================================
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn html(models: &[&ModelDefinition]) -> String {
+    let mut out = String::from("<h1>Models</h1>\n");
+    for model in models {
+        out.push_str(&format!("<h2 id=\"{0}\">{0}</h2>\n", escape(&model.name)));
+        out.push_str("<table>\n<tr><th>Field</th><th>Alias</th><th>Type</th><th>Default</th><th>Constraints</th><th>Notes</th></tr>\n");
+        for field in sorted_fields(model) {
+            let code = |s: &str| if s.is_empty() { String::new() } else { format!("<code>{}</code>", escape(s)) };
+            let mut notes: Vec<String> = deprecation(field).map(|d| format!("<strong>{}</strong>", escape(&d))).into_iter().collect();
+            notes.extend(field.description.as_deref().map(escape));
+            out.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                code(&field.full_name),
+                code(&field.alias),
+                escape(&field.field_type),
+                field.default.as_ref().map(|d| code(&scalar(d))).unwrap_or_default(),
+                escape(&constraints(field).join(", ")),
+                notes.join(" "),
+            ));
+        }
+        out.push_str("</table>\n");
+        out.push_str(&format!("<p>Example:</p>\n<pre><code class=\"language-flow\">{}</code></pre>\n", escape(&example(model))));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn registry() -> ModelRegistry {
+        let mut registry = ModelRegistry::new();
+        registry.register_models(&json!({"$models": {
+            "server": {"fields": {
+                "port": {"type": "int", "alias": "p", "default": 8080, "required": true, "id": 3},
+                "mode": {"type": "string", "choices": ["dev", "prod"], "description": "a | b <c>"},
+                "old": {"deprecated": "use mode"},
+                "opts": {"type": "object", "sensitive": true},
+            }},
+            "client": {"fields": {"url": {"type": "string"}}},
+        }}));
+        registry
+    }
+
+    #[test]
+    fn markdown_reference() {
+        let expected = "# Models
+
+## client
+
+| Field | Alias | Type | Default | Constraints | Notes |
+|---|---|---|---|---|---|
+| `url` |  | string |  |  |  |
+
+Example:
+
+```flow
+use_model = client
+url = url  # string
+```
+
+## server
+
+| Field | Alias | Type | Default | Constraints | Notes |
+|---|---|---|---|---|---|
+| `mode` |  | string |  | one of dev, prod | a \\| b <c> |
+| `old` |  |  |  |  | **deprecated: use mode** |
+| `opts` |  | object |  | sensitive |  |
+| `port` | `p` | int | `8080` | required, id 3 |  |
+
+Example:
+
+```flow
+use_model = server
+mode = dev  # string, one of dev, prod
+old = old  # any, deprecated: use mode
+opts:  # object, sensitive
+port = 8080  # int, required
+```
+";
+        assert_eq!(model_docs(&registry(), DocFormat::Markdown), expected);
+    }
+
+    #[test]
+    fn html_reference() {
+        let html = model_docs(&registry(), DocFormat::Html);
+        assert!(html.starts_with("<h1>Models</h1>\n<h2 id=\"client\">client</h2>\n<table>\n"), "{}", html);
+        assert!(html.contains("<tr><td><code>mode</code></td><td></td><td>string</td><td></td><td>one of dev, prod</td><td>a | b &lt;c&gt;</td></tr>\n"));
+        assert!(html.contains("<tr><td><code>port</code></td><td><code>p</code></td><td>int</td><td><code>8080</code></td><td>required, id 3</td><td></td></tr>\n"));
+        assert!(html.ends_with("<pre><code class=\"language-flow\">use_model = server\nmode = dev  # string, one of dev, prod\nold = old  # any, deprecated: use mode\nopts:  # object, sensitive\nport = 8080  # int, required\n</code></pre>\n"));
+    }
+
+    #[test]
+    fn examples_read_back_as_instances() {
+        let registry = registry();
+        let model = registry.get_model("server").unwrap();
+        let doc = crate::parse_document(&example(model));
+        assert_eq!(doc, json!({"use_model": "server", "mode": "dev", "old": "old", "opts": {}, "port": 8080}));
+    }
+}
@@ -0,0 +1,77 @@
+use serde_json::{Number, Value};
+
+// ============================================
+// Number Formatting
+// ============================================
+//
+// By default a float is written the way serde_json writes it, which uses
+// scientific notation for very large and very small values (`1e-7`).
+// FloatFormat, set through StringifyOptions, can instead round to at most
+// `max_precision` digits after the point and write plain decimal notation,
+// so a value reads back and writes out as the same text. Rounded or plain,
+// a float keeps at least one digit after the point so it reads back as a
+// float. Formatting never depends on the locale.
+//
+// On the parsing side, ParseOptions::thousands accepts digit groups such as
+// `1,234,567.5` or `1_000`: a first group of one to three digits, then
+// groups of exactly three, each after the separator. The decimal point is
+// always `.`. Anything else stays a string, as does the same text in quotes.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FloatFormat {
+    // digits after the decimal point at most; trailing zeros are dropped
+    pub max_precision: Option<usize>,
+    // never use scientific notation
+    pub plain: bool,
+}
+
+impl FloatFormat {
+    pub(crate) fn round(&self, f: f64) -> f64 {
+        match self.max_precision {
+            Some(digits) => format!("{:.*}", digits, f).parse().unwrap_or(f),
+            None => f,
+        }
+    }
+}
+
+pub(crate) fn write_number(n: &Number, format: &FloatFormat) -> String {
+    let Some(f) = n.as_f64().filter(|_| n.is_f64() && *format != FloatFormat::default()) else {
+        return n.to_string();
+    };
+    let f = format.round(f);
+    if !format.plain && format.max_precision.is_none() {
+        return Number::from_f64(f).map(|n| n.to_string()).unwrap_or_else(|| n.to_string());
+    }
+    let text = format!("{}", f);
+    if text.contains('.') || !f.is_finite() {
+        text
+    } else {
+        format!("{}.0", text)
+    }
+}
+
+// `v` as a number written with `separator` between digit groups.
+pub(crate) fn parse_grouped(v: &str, separator: char) -> Option<Value> {
+    if !v.contains(separator) {
+        return None;
+    }
+    let unsigned = v.strip_prefix(['-', '+']).unwrap_or(v);
+    let (whole, fraction) = match unsigned.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (unsigned, None),
+    };
+    let mut groups = whole.split(separator);
+    let first = groups.next()?;
+    let digits = |g: &str| g.bytes().all(|b| b.is_ascii_digit());
+    if first.is_empty() || first.len() > 3 || !digits(first) || !groups.all(|g| g.len() == 3 && digits(g)) {
+        return None;
+    }
+    if fraction.is_some_and(|f| f.is_empty() || !digits(f)) {
+        return None;
+    }
+    let plain = v.replace(separator, "");
+    if let Ok(i) = plain.parse::<i64>() {
+        return Some(Value::Number(i.into()));
+    }
+    plain.parse::<f64>().ok().and_then(Number::from_f64).map(Value::Number)
+}
@@ -0,0 +1,457 @@
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+// ============================================
+// Document Paths
+// ============================================
+//
+// Paths are written as dotted keys with bracketed array indices, e.g.
+// `servers[0].host`. A literal `.`, `[`, `]` or `\` inside a key is escaped
+// with a backslash.
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct FlowPath {
+    segments: Vec<PathSegment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid path '{}': {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for PathError {}
+
+impl FlowPath {
+    pub fn root() -> Self {
+        FlowPath { segments: Vec::new() }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, PathError> {
+        let err = |message: &str| PathError { path: text.to_string(), message: message.to_string() };
+        let mut segments = Vec::new();
+        let mut key = String::new();
+        // true once the current key has content or was explicitly terminated
+        let mut pending = false;
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    let escaped = chars.next().ok_or_else(|| err("trailing escape"))?;
+                    key.push(escaped);
+                    pending = true;
+                }
+                '.' => {
+                    if !pending {
+                        return Err(err("empty key"));
+                    }
+                    if !key.is_empty() {
+                        segments.push(PathSegment::Key(std::mem::take(&mut key)));
+                    }
+                    pending = false;
+                }
+                '[' => {
+                    if !key.is_empty() {
+                        segments.push(PathSegment::Key(std::mem::take(&mut key)));
+                    }
+                    let mut digits = String::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some(d) => digits.push(d),
+                            None => return Err(err("unclosed '['")),
+                        }
+                    }
+                    let index = digits.trim().parse::<usize>().map_err(|_| err("array index must be a non-negative integer"))?;
+                    segments.push(PathSegment::Index(index));
+                    pending = true;
+                }
+                ']' => return Err(err("unexpected ']'")),
+                _ => {
+                    key.push(c);
+                    pending = true;
+                }
+            }
+        }
+        if !key.is_empty() {
+            segments.push(PathSegment::Key(key));
+        } else if !pending && !segments.is_empty() {
+            return Err(err("empty key"));
+        }
+        Ok(FlowPath { segments })
+    }
+
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.segments
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn push_key(&mut self, key: &str) {
+        self.segments.push(PathSegment::Key(key.to_string()));
+    }
+
+    pub fn push_index(&mut self, index: usize) {
+        self.segments.push(PathSegment::Index(index));
+    }
+
+    pub fn pop(&mut self) -> Option<PathSegment> {
+        self.segments.pop()
+    }
+
+    pub fn child_key(&self, key: &str) -> Self {
+        let mut p = self.clone();
+        p.push_key(key);
+        p
+    }
+
+    pub fn child_index(&self, index: usize) -> Self {
+        let mut p = self.clone();
+        p.push_index(index);
+        p
+    }
+
+    pub fn parent(&self) -> Option<Self> {
+        if self.segments.is_empty() {
+            return None;
+        }
+        Some(FlowPath { segments: self.segments[..self.segments.len() - 1].to_vec() })
+    }
+
+    pub fn last(&self) -> Option<&PathSegment> {
+        self.segments.last()
+    }
+
+    pub fn starts_with(&self, prefix: &FlowPath) -> bool {
+        self.segments.starts_with(&prefix.segments)
+    }
+
+    pub fn lookup<'a>(&self, val: &'a Value) -> Option<&'a Value> {
+        let mut current = val;
+        for seg in &self.segments {
+            current = match (seg, current) {
+                (PathSegment::Key(k), Value::Object(m)) => m.get(k)?,
+                (PathSegment::Index(i), Value::Array(a)) => a.get(*i)?,
+                _ => return None,
+            };
+        }
+        Some(current)
+    }
+
+    pub fn lookup_mut<'a>(&self, val: &'a mut Value) -> Option<&'a mut Value> {
+        let mut current = val;
+        for seg in &self.segments {
+            current = match (seg, current) {
+                (PathSegment::Key(k), Value::Object(m)) => m.get_mut(k)?,
+                (PathSegment::Index(i), Value::Array(a)) => a.get_mut(*i)?,
+                _ => return None,
+            };
+        }
+        Some(current)
+    }
+
+    // Stores `new` at this path and returns the value it replaced. Missing
+    // keys (and nulls) along the way become objects; an index may be one
+    // past the end of an array to append.
+    pub fn set(&self, val: &mut Value, new: Value) -> Result<Option<Value>, PathError> {
+        let Some((last, parents)) = self.segments.split_last() else {
+            return Ok(Some(std::mem::replace(val, new)));
+        };
+        let mut current = val;
+        for (depth, seg) in parents.iter().enumerate() {
+            let next = &self.segments[depth + 1];
+            current = self.step(current, seg, depth, || match next {
+                PathSegment::Key(_) => Value::Object(Default::default()),
+                PathSegment::Index(_) => Value::Array(Vec::new()),
+            })?;
+        }
+        match (last, current) {
+            (PathSegment::Key(k), Value::Object(m)) => Ok(m.insert(k.clone(), new)),
+            (PathSegment::Index(i), Value::Array(a)) if *i < a.len() => Ok(Some(std::mem::replace(&mut a[*i], new))),
+            (PathSegment::Index(i), Value::Array(a)) if *i == a.len() => {
+                a.push(new);
+                Ok(None)
+            }
+            _ => Err(self.error_at(self.segments.len() - 1)),
+        }
+    }
+
+    // Removes the value at this path; array elements after it shift down.
+    pub fn remove(&self, val: &mut Value) -> Option<Value> {
+        let (last, parents) = self.segments.split_last()?;
+        let parent = FlowPath { segments: parents.to_vec() }.lookup_mut(val)?;
+        match (last, parent) {
+            (PathSegment::Key(k), Value::Object(m)) => m.remove(k),
+            (PathSegment::Index(i), Value::Array(a)) if *i < a.len() => Some(a.remove(*i)),
+            _ => None,
+        }
+    }
+
+    fn step<'a>(&self, current: &'a mut Value, seg: &PathSegment, depth: usize, empty: impl Fn() -> Value) -> Result<&'a mut Value, PathError> {
+        if current.is_null() {
+            *current = match seg {
+                PathSegment::Key(_) => Value::Object(Default::default()),
+                PathSegment::Index(_) => Value::Array(Vec::new()),
+            };
+        }
+        match (seg, current) {
+            (PathSegment::Key(k), Value::Object(m)) => Ok(m.entry(k.clone()).or_insert_with(empty)),
+            (PathSegment::Index(i), Value::Array(a)) => {
+                if *i == a.len() {
+                    a.push(empty());
+                }
+                a.get_mut(*i).ok_or_else(|| self.error_at(depth))
+            }
+            _ => Err(self.error_at(depth)),
+        }
+    }
+
+    fn error_at(&self, depth: usize) -> PathError {
+        let parent = FlowPath { segments: self.segments[..depth].to_vec() };
+        let message = match &self.segments[depth] {
+            PathSegment::Key(_) if parent.is_root() => "the document is not an object".to_string(),
+            PathSegment::Key(_) => format!("'{}' is not an object", parent),
+            PathSegment::Index(i) => format!("'{}' is not an array with an index {}", parent, i),
+        };
+        PathError { path: self.to_string(), message }
+    }
+}
+
+impl From<Vec<PathSegment>> for FlowPath {
+    fn from(segments: Vec<PathSegment>) -> Self {
+        FlowPath { segments }
+    }
+}
+
+impl FromStr for FlowPath {
+    type Err = PathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FlowPath::parse(s)
+    }
+}
+
+impl fmt::Display for FlowPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, seg) in self.segments.iter().enumerate() {
+            match seg {
+                PathSegment::Key(k) => {
+                    if i > 0 {
+                        f.write_str(".")?;
+                    }
+                    for c in k.chars() {
+                        if matches!(c, '.' | '[' | ']' | '\\') {
+                            f.write_str("\\")?;
+                        }
+                        write!(f, "{}", c)?;
+                    }
+                }
+                PathSegment::Index(n) => write!(f, "[{}]", n)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn escape_glob_key(key: &str) -> String {
+    key.chars().fold(String::new(), |mut out, c| {
+        if matches!(c, '.' | '[' | ']' | '\\' | '*') {
+            out.push('\\');
+        }
+        out.push(c);
+        out
+    })
+}
+
+// ============================================
+// Path Globs
+// ============================================
+//
+// Globs use the path syntax with wildcards: `*` matches exactly one segment
+// (any key or index), `**` matches any number of segments (including none),
+// and `*` inside a key matches any run of characters (`*password*`).
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum GlobSegment {
+    Key(String),
+    Index(usize),
+    Pattern(String),
+    AnyOne,
+    AnyMany,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathGlob {
+    source: String,
+    segments: Vec<GlobSegment>,
+}
+
+impl PathGlob {
+    pub fn parse(text: &str) -> Result<Self, PathError> {
+        // wildcards are parsed as literal keys first, then classified; an
+        // escaped `\*` stays literal
+        let path = FlowPath::parse(&text.replace("\\*", "\u{0}"))?;
+        let segments = path
+            .segments
+            .into_iter()
+            .map(|seg| match seg {
+                PathSegment::Index(i) => GlobSegment::Index(i),
+                PathSegment::Key(k) if k == "**" => GlobSegment::AnyMany,
+                PathSegment::Key(k) if k == "*" => GlobSegment::AnyOne,
+                PathSegment::Key(k) if k.contains('*') => GlobSegment::Pattern(k.replace('\u{0}', "\\*")),
+                PathSegment::Key(k) => GlobSegment::Key(k.replace('\u{0}', "*")),
+            })
+            .collect();
+        Ok(PathGlob { source: text.to_string(), segments })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, path: &FlowPath) -> bool {
+        glob_match(&self.segments, path.segments())
+    }
+
+    // True when some descendant of `path` (or `path` itself) could match.
+    pub fn may_match_below(&self, path: &FlowPath) -> bool {
+        glob_prefix(&self.segments, path.segments())
+    }
+
+    // Every node of `val` whose path matches, in document order.
+    pub fn find<'a>(&self, val: &'a Value) -> Vec<(FlowPath, &'a Value)> {
+        let mut found = Vec::new();
+        self.find_at(val, &mut FlowPath::root(), &mut found);
+        found
+    }
+
+    fn find_at<'a>(&self, val: &'a Value, path: &mut FlowPath, found: &mut Vec<(FlowPath, &'a Value)>) {
+        if self.matches(path) {
+            found.push((path.clone(), val));
+        }
+        if !self.may_match_below(path) {
+            return;
+        }
+        match val {
+            Value::Object(map) => {
+                for (k, v) in map {
+                    path.push_key(k);
+                    self.find_at(v, path, found);
+                    path.pop();
+                }
+            }
+            Value::Array(items) => {
+                for (i, v) in items.iter().enumerate() {
+                    path.push_index(i);
+                    self.find_at(v, path, found);
+                    path.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl FromStr for PathGlob {
+    type Err = PathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PathGlob::parse(s)
+    }
+}
+
+impl fmt::Display for PathGlob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+fn segment_matches(glob: &GlobSegment, seg: &PathSegment) -> bool {
+    match (glob, seg) {
+        (GlobSegment::AnyOne, _) => true,
+        (GlobSegment::Key(g), PathSegment::Key(k)) => g == k,
+        (GlobSegment::Index(g), PathSegment::Index(i)) => g == i,
+        (GlobSegment::Pattern(p), PathSegment::Key(k)) => wildcard_match(p, k),
+        _ => false,
+    }
+}
+
+fn glob_match(glob: &[GlobSegment], path: &[PathSegment]) -> bool {
+    match glob.split_first() {
+        None => path.is_empty(),
+        Some((GlobSegment::AnyMany, rest)) => (0..=path.len()).any(|skip| glob_match(rest, &path[skip..])),
+        Some((g, rest)) => match path.split_first() {
+            Some((seg, tail)) => segment_matches(g, seg) && glob_match(rest, tail),
+            None => false,
+        },
+    }
+}
+
+fn glob_prefix(glob: &[GlobSegment], path: &[PathSegment]) -> bool {
+    match (glob.split_first(), path.split_first()) {
+        (_, None) => true,
+        (None, Some(_)) => false,
+        (Some((GlobSegment::AnyMany, _)), Some(_)) => true,
+        (Some((g, rest)), Some((seg, tail))) => segment_matches(g, seg) && glob_prefix(rest, tail),
+    }
+}
+
+// `*` matches any run of characters; `\*` is a literal star.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut tokens: Vec<Option<char>> = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => tokens.push(chars.next()),
+            '*' => tokens.push(None),
+            _ => tokens.push(Some(c)),
+        }
+    }
+    let text: Vec<char> = text.chars().collect();
+    let (mut ti, mut pi) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ti < text.len() {
+        match tokens.get(pi) {
+            Some(None) => {
+                backtrack = Some((pi, ti));
+                pi += 1;
+            }
+            Some(Some(c)) if *c == text[ti] => {
+                pi += 1;
+                ti += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    pi = bp + 1;
+                    ti = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    tokens[pi..].iter().all(|t| t.is_none())
+}
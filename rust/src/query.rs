@@ -0,0 +1,282 @@
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::canonical::canonicalize;
+use crate::path::{FlowPath, PathSegment};
+use crate::walk::iter_leaves;
+
+// ============================================
+// Query Output
+// ============================================
+//
+// Writes the nodes a PathGlob found in the forms shell scripts consume:
+//
+//   raw    one line per match: strings as they are, other scalars as
+//          written in .flow, objects and arrays as compact JSON
+//   json   one compact JSON value per line
+//   tsv    one row per object matched, or per element of an array of
+//          objects; `columns` are paths inside each row, by default every
+//          top-level key seen in any row, sorted. Tabs, newlines and
+//          backslashes in cells are written as `\t`, `\n` and `\\`
+//   env    `KEY='value'` for every leaf below each match, the key made from
+//          the leaf's full path: `db.hosts[0]` is `DB_HOSTS_0`, after
+//          `prefix`. Values are single-quoted, so the output is safe to
+//          `eval`. Two leaves whose keys come out the same, such as
+//          `db.host` and `db_host`, are an error, as is a prefix that is
+//          not a shell name (`[A-Za-z_][A-Za-z0-9_]*`)
+//
+// Every line, the last included, ends in a newline.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueryFormat {
+    #[default]
+    Raw,
+    Json,
+    Tsv,
+    Env,
+}
+
+impl QueryFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            QueryFormat::Raw => "raw",
+            QueryFormat::Json => "json",
+            QueryFormat::Tsv => "tsv",
+            QueryFormat::Env => "env",
+        }
+    }
+}
+
+impl fmt::Display for QueryFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for QueryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "raw" => Ok(QueryFormat::Raw),
+            "json" => Ok(QueryFormat::Json),
+            "tsv" => Ok(QueryFormat::Tsv),
+            "env" => Ok(QueryFormat::Env),
+            other => Err(format!("unknown output format '{}'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct QueryOutput {
+    pub format: QueryFormat,
+    // tsv only
+    pub columns: Vec<FlowPath>,
+    pub header: bool,
+    // env only, e.g. "APP_"
+    pub prefix: String,
+}
+
+impl QueryOutput {
+    pub fn new(format: QueryFormat) -> Self {
+        QueryOutput { format, ..Self::default() }
+    }
+
+    pub fn column(mut self, path: FlowPath) -> Self {
+        self.columns.push(path);
+        self
+    }
+
+    pub fn header(mut self, on: bool) -> Self {
+        self.header = on;
+        self
+    }
+
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    pub path: FlowPath,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_root() {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+// `matches` as PathGlob::find returns them.
+pub fn format_query(matches: &[(FlowPath, &Value)], output: &QueryOutput) -> Result<String, QueryError> {
+    let mut out = String::new();
+    match output.format {
+        QueryFormat::Raw => {
+            for (_, v) in matches {
+                out.push_str(&raw(v));
+                out.push('\n');
+            }
+        }
+        QueryFormat::Json => {
+            for (_, v) in matches {
+                out.push_str(&serde_json::to_string(v).unwrap_or_default());
+                out.push('\n');
+            }
+        }
+        QueryFormat::Tsv => tsv(matches, output, &mut out)?,
+        QueryFormat::Env => {
+            let prefix = &output.prefix;
+            if !prefix.is_empty() && (prefix.starts_with(|c: char| c.is_ascii_digit()) || !prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')) {
+                return Err(QueryError { path: FlowPath::root(), message: format!("env prefix '{}' is not a shell variable name", prefix) });
+            }
+            let mut seen: HashMap<String, FlowPath> = HashMap::new();
+            for (path, v) in matches {
+                // a match with nothing below it is a leaf itself
+                let mut leaves: Vec<(FlowPath, &Value)> = iter_leaves(v).collect();
+                if leaves.is_empty() {
+                    leaves.push((FlowPath::root(), *v));
+                }
+                for (leaf, value) in leaves {
+                    let mut segments = path.segments().to_vec();
+                    segments.extend(leaf.segments().iter().cloned());
+                    let key = env_key(prefix, &segments);
+                    let full = FlowPath::from(segments);
+                    if let Some(earlier) = seen.get(&key) {
+                        return Err(QueryError { path: full, message: format!("'{}' is also written as {}", earlier, key) });
+                    }
+                    out.push_str(&format!("{}={}\n", key, shell_quote(&raw(value))));
+                    seen.insert(key, full);
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn raw(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        Value::Object(_) | Value::Array(_) => canonicalize(v),
+        _ => v.to_string(),
+    }
+}
+
+fn tsv(matches: &[(FlowPath, &Value)], output: &QueryOutput, out: &mut String) -> Result<(), QueryError> {
+    let mut rows = Vec::new();
+    for (path, v) in matches {
+        match v {
+            Value::Object(_) => rows.push(*v),
+            Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if !item.is_object() {
+                        return Err(QueryError { path: path.child_index(i), message: "tsv rows must be objects".to_string() });
+                    }
+                    rows.push(item);
+                }
+            }
+            _ => return Err(QueryError { path: path.clone(), message: "tsv needs an object or an array of objects".to_string() }),
+        }
+    }
+    let columns = if output.columns.is_empty() {
+        let keys: BTreeSet<&String> = rows.iter().filter_map(|r| r.as_object()).flat_map(|m| m.keys()).collect();
+        keys.into_iter().map(|k| FlowPath::root().child_key(k)).collect()
+    } else {
+        output.columns.clone()
+    };
+    if output.header {
+        let names: Vec<String> = columns.iter().map(|c| cell(&c.to_string())).collect();
+        out.push_str(&names.join("\t"));
+        out.push('\n');
+    }
+    for row in rows {
+        let cells: Vec<String> = columns.iter().map(|c| c.lookup(row).map(|v| cell(&raw(v))).unwrap_or_default()).collect();
+        out.push_str(&cells.join("\t"));
+        out.push('\n');
+    }
+    Ok(())
+}
+
+fn cell(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn env_key(prefix: &str, segments: &[PathSegment]) -> String {
+    let mut key = prefix.to_string();
+    for (i, seg) in segments.iter().enumerate() {
+        if i > 0 || !key.is_empty() && !key.ends_with('_') {
+            key.push('_');
+        }
+        match seg {
+            PathSegment::Key(k) => key.extend(k.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })),
+            PathSegment::Index(n) => key.push_str(&n.to_string()),
+        }
+    }
+    if key.is_empty() || key.starts_with(|c: char| c.is_ascii_digit()) {
+        key.insert(0, '_');
+    }
+    key
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::PathGlob;
+    use serde_json::json;
+
+    fn query(doc: &Value, glob: &str, output: &QueryOutput) -> Result<String, QueryError> {
+        format_query(&PathGlob::parse(glob).unwrap().find(doc), output)
+    }
+
+    #[test]
+    fn env_keys_and_quoting() {
+        let doc = json!({ "db": { "hosts": ["a", "b"], "pass-word": "it's $HOME", "port": 5432, "tls": null }, "9lives": true });
+        let env = QueryOutput::new(QueryFormat::Env);
+        assert_eq!(
+            query(&doc, "db", &env).unwrap(),
+            "DB_HOSTS_0='a'\nDB_HOSTS_1='b'\nDB_PASS_WORD='it'\\''s $HOME'\nDB_PORT='5432'\nDB_TLS='null'\n"
+        );
+        assert_eq!(query(&doc, "9lives", &env).unwrap(), "_9LIVES='true'\n");
+        assert_eq!(query(&doc, "db.port", &env.clone().prefix("APP_")).unwrap(), "APP_DB_PORT='5432'\n");
+        assert_eq!(query(&doc, "db.port", &env.clone().prefix("app")).unwrap(), "app_DB_PORT='5432'\n");
+    }
+
+    #[test]
+    fn env_keys_must_be_unique() {
+        let doc = json!({ "db": { "host": "a" }, "db_host": "b" });
+        let err = query(&doc, "*", &QueryOutput::new(QueryFormat::Env)).unwrap_err();
+        assert_eq!(err.path, FlowPath::parse("db_host").unwrap());
+        assert_eq!(err.message, "'db.host' is also written as DB_HOST");
+    }
+
+    #[test]
+    fn env_prefix_must_be_a_name() {
+        let doc = json!({ "a": 1 });
+        for prefix in ["1APP_", "APP-", "A B", "$X"] {
+            assert!(query(&doc, "a", &QueryOutput::new(QueryFormat::Env).prefix(prefix)).is_err(), "{}", prefix);
+        }
+        assert!(query(&doc, "a", &QueryOutput::new(QueryFormat::Env).prefix("_x9")).is_ok());
+    }
+
+    #[test]
+    fn tsv_cells() {
+        let doc = json!({ "rows": [{ "name": "a\tb", "n": 1 }, { "name": "line\nbreak", "extra": [1] }] });
+        let tsv = QueryOutput::new(QueryFormat::Tsv).header(true);
+        assert_eq!(query(&doc, "rows", &tsv).unwrap(), "extra\tn\tname\n\t1\ta\\tb\n[1]\t\tline\\nbreak\n");
+        assert!(query(&json!({ "rows": [1] }), "rows", &tsv).is_err());
+    }
+}
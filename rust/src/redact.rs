@@ -0,0 +1,99 @@
+use serde_json::{Map, Value};
+
+use crate::path::{FlowPath, PathGlob};
+
+// ============================================
+// Redaction
+// ============================================
+
+pub const REDACTED: &str = "***";
+
+#[deprecated(note = "use v2::redact")]
+pub fn RedactFlow(val: &Value, globs: &[PathGlob]) -> Value {
+    redact(val, globs)
+}
+
+// Returns a copy of `val` with every node matching one of `globs` replaced
+// by "***". A matching object or array is replaced as a whole.
+pub(crate) fn redact(val: &Value, globs: &[PathGlob]) -> Value {
+    if globs.is_empty() {
+        return val.clone();
+    }
+    redact_at(val, &mut FlowPath::root(), globs)
+}
+
+fn redact_at(val: &Value, path: &mut FlowPath, globs: &[PathGlob]) -> Value {
+    if !path.is_root() && globs.iter().any(|g| g.matches(path)) {
+        return Value::String(REDACTED.to_string());
+    }
+    if !globs.iter().any(|g| g.may_match_below(path)) {
+        return val.clone();
+    }
+    match val {
+        Value::Object(map) => {
+            let mut out = Map::new();
+            for (k, v) in map {
+                path.push_key(k);
+                out.insert(k.clone(), redact_at(v, path, globs));
+                path.pop();
+            }
+            Value::Object(out)
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, v)| {
+                    path.push_index(i);
+                    let r = redact_at(v, path, globs);
+                    path.pop();
+                    r
+                })
+                .collect(),
+        ),
+        _ => val.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{stringify_document_with, StringifyOptions};
+    use serde_json::json;
+
+    fn globs(patterns: &[&str]) -> Vec<PathGlob> {
+        patterns.iter().map(|p| PathGlob::parse(p).unwrap()).collect()
+    }
+
+    #[test]
+    fn matching_nodes_are_replaced() {
+        let doc = json!({
+            "db": { "password": "hunter2", "host": "db1" },
+            "users": [{ "name": "a", "token": "t1" }, { "name": "b", "token": "t2" }],
+            "keys": { "a": 1, "b": [1, 2] },
+        });
+        let redacted = redact(&doc, &globs(&["**.password", "users.*.token", "keys"]));
+        assert_eq!(
+            redacted,
+            json!({
+                "db": { "password": "***", "host": "db1" },
+                "users": [{ "name": "a", "token": "***" }, { "name": "b", "token": "***" }],
+                "keys": "***",
+            })
+        );
+        assert_eq!(redact(&doc, &[]), doc);
+        assert_eq!(redact(&doc, &globs(&["missing.path"])), doc);
+    }
+
+    #[test]
+    fn stringify_redacts() {
+        let doc = json!({ "api": { "key": "abc", "url": "https://x" } });
+        let options = StringifyOptions::new().redact("api.key").unwrap();
+        let text = stringify_document_with(&doc, &options);
+        assert!(text.contains("key = ***"), "{}", text);
+        assert!(!text.contains("abc"), "{}", text);
+        assert!(text.contains("https://x"), "{}", text);
+        let json = crate::v2::flow_to_json_with_options("api:\n  key = \"abc\"\n", &options).unwrap();
+        assert!(json.contains("\"***\"") && !json.contains("abc"), "{}", json);
+    }
+}
@@ -0,0 +1,325 @@
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::encoding::read_text;
+use crate::path::FlowPath;
+use crate::parse_document;
+
+// ============================================
+// Cross-file References
+// ============================================
+//
+// A value of the form `@ref("shared.flow#credentials.primary")` is replaced
+// by the node at `credentials.primary` inside `shared.flow`. The file part is
+// resolved relative to the referencing document; `@ref("#path")` points into
+// the referencing document itself.
+
+#[derive(Debug)]
+pub enum RefError {
+    Io { location: String, source: std::io::Error },
+    Invalid(String),
+    NotFound { reference: String },
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for RefError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefError::Io { location, source } => write!(f, "cannot load '{}': {}", location, source),
+            RefError::Invalid(msg) => write!(f, "invalid reference: {}", msg),
+            RefError::NotFound { reference } => write!(f, "reference target not found: {}", reference),
+            RefError::Cycle(chain) => write!(f, "reference cycle: {}", chain.join(" -> ")),
+        }
+    }
+}
+
+impl std::error::Error for RefError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RefError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<RefError> for std::io::Error {
+    fn from(err: RefError) -> Self {
+        match err {
+            RefError::Io { source, .. } => source,
+            other => std::io::Error::new(std::io::ErrorKind::InvalidData, other),
+        }
+    }
+}
+
+pub trait RefResolver {
+    // Turns `location` (as written in the reference) into a stable document id.
+    // `base` is the id of the referencing document, if it has one.
+    fn locate(&self, location: &str, base: Option<&str>) -> Result<String, RefError>;
+
+    // Loads and parses the document with the given id.
+    fn load(&self, id: &str) -> Result<Value, RefError>;
+}
+
+pub struct FileRefResolver;
+
+impl RefResolver for FileRefResolver {
+    fn locate(&self, location: &str, base: Option<&str>) -> Result<String, RefError> {
+        let joined = match base.and_then(|b| Path::new(b).parent()) {
+            Some(dir) => dir.join(location),
+            None => Path::new(location).to_path_buf(),
+        };
+        let canonical = fs::canonicalize(&joined).map_err(|source| RefError::Io {
+            location: joined.display().to_string(),
+            source,
+        })?;
+        Ok(canonical.display().to_string())
+    }
+
+    fn load(&self, id: &str) -> Result<Value, RefError> {
+        let text = read_text(id).map_err(|source| RefError::Io { location: id.to_string(), source })?;
+        Ok(parse_document(&text))
+    }
+}
+
+// Parsed documents and resolved targets, shared across resolutions so a
+// fragment referenced from many places is only loaded once.
+#[derive(Default)]
+pub struct RefCache {
+    documents: HashMap<String, Value>,
+    resolved: HashMap<String, Value>,
+}
+
+impl RefCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.documents.clear();
+        self.resolved.clear();
+    }
+}
+
+#[deprecated(note = "use v2::resolve_refs")]
+pub fn ResolveRefs(doc: &Value, origin: Option<&str>, resolver: &dyn RefResolver, cache: &mut RefCache) -> Result<Value, RefError> {
+    resolve_refs(doc, origin, resolver, cache)
+}
+
+pub(crate) fn resolve_refs(doc: &Value, origin: Option<&str>, resolver: &dyn RefResolver, cache: &mut RefCache) -> Result<Value, RefError> {
+    if let Some(id) = origin {
+        cache.documents.insert(id.to_string(), doc.clone());
+    }
+    let mut state = Resolution { resolver, cache, stack: Vec::new(), sites: None, path: FlowPath::root() };
+    state.resolve_value(doc, origin)
+}
+
+// Where a reference was replaced: `path` in the resolved document now holds
+// the node at `target` in document `id`.
+#[derive(Debug, Clone)]
+pub(crate) struct RefSite {
+    pub path: FlowPath,
+    pub id: String,
+    pub target: FlowPath,
+}
+
+// resolve_refs, also reporting every reference it replaced.
+pub(crate) fn resolve_refs_tracked(doc: &Value, origin: Option<&str>, resolver: &dyn RefResolver, sites: &mut Vec<RefSite>) -> Result<Value, RefError> {
+    let mut cache = RefCache::new();
+    if let Some(id) = origin {
+        cache.documents.insert(id.to_string(), doc.clone());
+    }
+    let mut state = Resolution { resolver, cache: &mut cache, stack: Vec::new(), sites: Some(sites), path: FlowPath::root() };
+    state.resolve_value(doc, origin)
+}
+
+#[deprecated(note = "use v2::load_with_resolver")]
+pub fn LoadFlowWithResolver(location: &str, resolver: &dyn RefResolver, cache: &mut RefCache) -> Result<Value, RefError> {
+    load_with_resolver(location, resolver, cache)
+}
+
+pub(crate) fn load_with_resolver(location: &str, resolver: &dyn RefResolver, cache: &mut RefCache) -> Result<Value, RefError> {
+    let id = resolver.locate(location, None)?;
+    let doc = resolver.load(&id)?;
+    resolve_refs(&doc, Some(&id), resolver, cache)
+}
+
+pub(crate) fn parse_ref(s: &str) -> Option<&str> {
+    let inner = s.trim().strip_prefix("@ref(")?.strip_suffix(')')?.trim();
+    Some(inner.strip_prefix('"').and_then(|t| t.strip_suffix('"')).unwrap_or(inner))
+}
+
+struct Resolution<'a> {
+    resolver: &'a dyn RefResolver,
+    cache: &'a mut RefCache,
+    stack: Vec<String>,
+    // when tracking, the replaced references and the path being resolved
+    sites: Option<&'a mut Vec<RefSite>>,
+    path: FlowPath,
+}
+
+impl Resolution<'_> {
+    fn resolve_value(&mut self, val: &Value, origin: Option<&str>) -> Result<Value, RefError> {
+        match val {
+            Value::String(s) => match parse_ref(s) {
+                Some(target) => self.resolve_ref(target, origin),
+                None => Ok(val.clone()),
+            },
+            Value::Array(items) => {
+                let mut out = Vec::with_capacity(items.len());
+                for (i, v) in items.iter().enumerate() {
+                    let tracking = self.sites.is_some();
+                    if tracking {
+                        self.path.push_index(i);
+                    }
+                    let r = self.resolve_value(v, origin);
+                    if tracking {
+                        self.path.pop();
+                    }
+                    out.push(r?);
+                }
+                Ok(Value::Array(out))
+            }
+            Value::Object(map) => {
+                let mut out = Map::new();
+                for (k, v) in map {
+                    let tracking = self.sites.is_some();
+                    if tracking {
+                        self.path.push_key(k);
+                    }
+                    let r = self.resolve_value(v, origin);
+                    if tracking {
+                        self.path.pop();
+                    }
+                    out.insert(k.clone(), r?);
+                }
+                Ok(Value::Object(out))
+            }
+            _ => Ok(val.clone()),
+        }
+    }
+
+    fn resolve_ref(&mut self, target: &str, origin: Option<&str>) -> Result<Value, RefError> {
+        let (location, path_text) = target.split_once('#').unwrap_or((target, ""));
+        let id = if location.is_empty() {
+            origin.map(str::to_string).ok_or_else(|| RefError::Invalid(format!("'{}' has no file and the document has no origin", target)))?
+        } else {
+            self.resolver.locate(location, origin)?
+        };
+        let key = format!("{}#{}", id, path_text);
+        if let Some(sites) = self.sites.as_deref_mut() {
+            let target = FlowPath::parse(path_text).map_err(|e| RefError::Invalid(e.to_string()))?;
+            sites.push(RefSite { path: self.path.clone(), id: id.clone(), target });
+        }
+        // a cached target would skip the sites nested inside it
+        if let Some(v) = self.cache.resolved.get(&key).filter(|_| self.sites.is_none()) {
+            return Ok(v.clone());
+        }
+        if let Some(pos) = self.stack.iter().position(|k| *k == key) {
+            let mut chain = self.stack[pos..].to_vec();
+            chain.push(key);
+            return Err(RefError::Cycle(chain));
+        }
+        let path = FlowPath::parse(path_text).map_err(|e| RefError::Invalid(e.to_string()))?;
+        if !self.cache.documents.contains_key(&id) {
+            let doc = self.resolver.load(&id)?;
+            self.cache.documents.insert(id.clone(), doc);
+        }
+        let node = path
+            .lookup(&self.cache.documents[&id])
+            .cloned()
+            .ok_or_else(|| RefError::NotFound { reference: target.to_string() })?;
+        self.stack.push(key.clone());
+        let result = self.resolve_value(&node, Some(&id));
+        self.stack.pop();
+        let resolved = result?;
+        self.cache.resolved.insert(key, resolved.clone());
+        Ok(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::cell::RefCell;
+
+    // documents by name, counting the loads of each
+    struct Memory {
+        docs: HashMap<&'static str, Value>,
+        loads: RefCell<Vec<String>>,
+    }
+
+    impl Memory {
+        fn new(docs: &[(&'static str, Value)]) -> Self {
+            Memory { docs: docs.iter().cloned().collect(), loads: RefCell::new(Vec::new()) }
+        }
+    }
+
+    impl RefResolver for Memory {
+        fn locate(&self, location: &str, _base: Option<&str>) -> Result<String, RefError> {
+            Ok(location.to_string())
+        }
+
+        fn load(&self, id: &str) -> Result<Value, RefError> {
+            self.loads.borrow_mut().push(id.to_string());
+            self.docs.get(id).cloned().ok_or_else(|| RefError::Io { location: id.to_string(), source: std::io::ErrorKind::NotFound.into() })
+        }
+    }
+
+    #[test]
+    fn references_across_and_within_documents() {
+        let memory = Memory::new(&[
+            ("shared", json!({ "db": { "host": "db1", "auth": "@ref(\"secrets#db\")" }, "port": 5432 })),
+            ("secrets", json!({ "db": { "user": "app" } })),
+        ]);
+        let doc = json!({ "database": "@ref(\"shared#db\")", "port": "@ref(shared#port)", "local": { "a": 1 }, "copy": "@ref(\"#local.a\")", "plain": "@refs" });
+        let resolved = resolve_refs(&doc, Some("main"), &memory, &mut RefCache::new()).unwrap();
+        assert_eq!(
+            resolved,
+            json!({ "database": { "host": "db1", "auth": { "user": "app" } }, "port": 5432, "local": { "a": 1 }, "copy": 1, "plain": "@refs" })
+        );
+        assert!(matches!(resolve_refs(&json!({ "x": "@ref(\"shared#nope\")" }), None, &memory, &mut RefCache::new()), Err(RefError::NotFound { .. })));
+        assert!(matches!(resolve_refs(&json!({ "x": "@ref(\"#a\")" }), None, &memory, &mut RefCache::new()), Err(RefError::Invalid(_))));
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let memory = Memory::new(&[("a", json!({ "x": "@ref(\"b#y\")" })), ("b", json!({ "y": "@ref(\"a#x\")" }))]);
+        match resolve_refs(&json!({ "start": "@ref(\"a#x\")" }), Some("main"), &memory, &mut RefCache::new()) {
+            Err(RefError::Cycle(chain)) => assert_eq!(chain, ["a#x", "b#y", "a#x"]),
+            other => panic!("expected a cycle, got {:?}", other),
+        }
+        let doc = json!({ "a": "@ref(\"#b\")", "b": { "c": "@ref(\"#b\")" } });
+        assert!(matches!(resolve_refs(&doc, Some("main"), &memory, &mut RefCache::new()), Err(RefError::Cycle(_))));
+    }
+
+    #[test]
+    fn each_document_is_loaded_once() {
+        let memory = Memory::new(&[("shared", json!({ "a": 1, "b": { "c": 2 } }))]);
+        let doc = json!({ "one": "@ref(\"shared#a\")", "two": "@ref(\"shared#b\")", "three": "@ref(\"shared#b.c\")", "four": "@ref(\"shared#a\")" });
+        let mut cache = RefCache::new();
+        resolve_refs(&doc, Some("main"), &memory, &mut cache).unwrap();
+        resolve_refs(&doc, Some("main"), &memory, &mut cache).unwrap();
+        assert_eq!(*memory.loads.borrow(), ["shared"]);
+        cache.clear();
+        resolve_refs(&doc, Some("main"), &memory, &mut cache).unwrap();
+        assert_eq!(memory.loads.borrow().len(), 2);
+    }
+
+    #[test]
+    fn files_resolve_relative_to_the_referencing_file() {
+        let dir = std::env::temp_dir().join(format!("flowdoc-refs-{}", std::process::id()));
+        fs::create_dir_all(dir.join("conf")).unwrap();
+        fs::write(dir.join("shared.flow"), "db:\n  host = \"db1\"\n").unwrap();
+        fs::write(dir.join("conf").join("app.flow"), "host = @ref(\"../shared.flow#db.host\")\n").unwrap();
+        let app = dir.join("conf").join("app.flow").display().to_string();
+        assert_eq!(load_with_resolver(&app, &FileRefResolver, &mut RefCache::new()).unwrap(), json!({ "host": "db1" }));
+
+        fs::write(dir.join("conf").join("broken.flow"), "host = @ref(\"missing.flow#x\")\n").unwrap();
+        let broken = dir.join("conf").join("broken.flow").display().to_string();
+        assert!(matches!(load_with_resolver(&broken, &FileRefResolver, &mut RefCache::new()), Err(RefError::Io { .. })));
+    }
+}
@@ -0,0 +1,434 @@
+use serde_json::Value;
+use std::fmt;
+use std::io;
+
+use crate::encoding::read_text;
+use crate::path::{FlowPath, PathError, PathSegment};
+use crate::validate::model_field;
+use crate::{flow_value, parse_document, parse_value, stringify_document, v2, ModelDefinition, ModelRegistry};
+
+// ============================================
+// Interactive Sessions
+// ============================================
+//
+// The state behind `flowdoc repl`: a document, the path the prompt is at,
+// and the undo history. Each command line is one call to `execute`:
+//
+//   ls [path]            entries of an object or array
+//   cd [path]            `..` goes up, `/` or no path goes to the root
+//   get [path]           a value, or a whole section as .flow text
+//   set <path> <value>   the value is read as it would be in a file
+//   rm <path>
+//   undo                 reverts the last set or rm
+//   save [file]
+//   pwd, help, quit
+//
+// Paths are relative to the current one unless they start with `/`. Any
+// number of leading `../` step up first. `complete` suggests the commands,
+// the keys at a path, and for `set` the model fields the object lacks. The
+// model is the one named by `use_model`, from the document's own `$models`
+// or from files given to `load_models`.
+
+const UNDO_LIMIT: usize = 100;
+
+const COMMANDS: [&str; 10] = ["cd", "get", "help", "ls", "pwd", "quit", "rm", "save", "set", "undo"];
+
+const HELP: &str = "\
+ls [path]            list the entries at path
+cd [path]            move to path; `..` goes up, `/` to the root
+get [path]           print the value at path
+set <path> <value>   set a value, e.g. `set server.port 8080`
+rm <path>            remove a value
+undo                 revert the last set or rm
+save [file]          write the document
+pwd                  print the current path
+quit                 leave; asks again if there are unsaved changes
+";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplError {
+    pub message: String,
+}
+
+impl fmt::Display for ReplError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ReplError {}
+
+impl From<PathError> for ReplError {
+    fn from(e: PathError) -> Self {
+        ReplError { message: e.to_string() }
+    }
+}
+
+fn error<T>(message: impl Into<String>) -> Result<T, ReplError> {
+    Err(ReplError { message: message.into() })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplOutput {
+    Text(String),
+    Quit,
+}
+
+pub struct ReplSession {
+    doc: Value,
+    file: Option<String>,
+    cwd: FlowPath,
+    registry: ModelRegistry,
+    history: Vec<(Value, FlowPath)>,
+    dirty: bool,
+    // set by a `quit` refused for unsaved changes; a second one leaves
+    quit_pending: bool,
+}
+
+impl ReplSession {
+    pub fn new(doc: Value) -> Self {
+        let mut registry = ModelRegistry::new();
+        registry.register_models(&doc);
+        ReplSession { doc, file: None, cwd: FlowPath::root(), registry, history: Vec::new(), dirty: false, quit_pending: false }
+    }
+
+    // Lines that cannot be read are skipped, as by ParseFlow, so a file
+    // with a stray line can still be opened and fixed.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let mut session = Self::new(parse_document(&read_text(path)?));
+        session.file = Some(path.to_string());
+        Ok(session)
+    }
+
+    // Registers the `$models` of another file for completion.
+    pub fn load_models(&mut self, path: &str) -> io::Result<usize> {
+        Ok(self.registry.register_models(&parse_document(&read_text(path)?)))
+    }
+
+    pub fn document(&self) -> &Value {
+        &self.doc
+    }
+
+    pub fn cwd(&self) -> &FlowPath {
+        &self.cwd
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn prompt(&self) -> String {
+        let name = self.file.as_deref().unwrap_or("flowdoc");
+        let dirty = if self.dirty { "*" } else { "" };
+        format!("{}{}:/{}> ", name, dirty, self.cwd)
+    }
+
+    pub fn execute(&mut self, line: &str) -> Result<ReplOutput, ReplError> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).map(|(c, r)| (c, r.trim())).unwrap_or((line, ""));
+        if command != "quit" && command != "exit" {
+            self.quit_pending = false;
+        }
+        let text = match command {
+            "" => String::new(),
+            "help" => HELP.to_string(),
+            "pwd" => format!("/{}\n", self.cwd),
+            "ls" => self.ls(rest)?,
+            "cd" => {
+                let path = if rest.is_empty() { FlowPath::root() } else { self.resolve(rest)? };
+                match path.lookup(&self.doc) {
+                    Some(Value::Object(_) | Value::Array(_)) => self.cwd = path,
+                    Some(_) => return error(format!("'{}' is not an object or array", path)),
+                    None => return error(format!("no value at '{}'", path)),
+                }
+                String::new()
+            }
+            "get" => {
+                let path = self.resolve(rest)?;
+                match path.lookup(&self.doc) {
+                    Some(val @ Value::Object(_)) => stringify_document(val),
+                    Some(val) => format!("{}\n", flow_value(val).unwrap_or_else(|| "null".to_string())),
+                    None => return error(format!("no value at '{}'", path)),
+                }
+            }
+            "set" => {
+                let (target, value) = rest.split_once(char::is_whitespace).map(|(p, v)| (p, v.trim())).unwrap_or((rest, ""));
+                if target.is_empty() || value.is_empty() {
+                    return error("usage: set <path> <value>");
+                }
+                let path = self.resolve(target)?;
+                if path.is_root() {
+                    return error("cannot replace the whole document");
+                }
+                let before = self.doc.clone();
+                path.set(&mut self.doc, parse_value(value))?;
+                self.changed(before);
+                String::new()
+            }
+            "rm" => {
+                let path = self.resolve(rest)?;
+                if rest.is_empty() || path.is_root() {
+                    return error("usage: rm <path>");
+                }
+                let before = self.doc.clone();
+                if path.remove(&mut self.doc).is_none() {
+                    return error(format!("no value at '{}'", path));
+                }
+                self.changed(before);
+                // the current path may be gone
+                while self.cwd.lookup(&self.doc).is_none() {
+                    self.cwd = self.cwd.parent().unwrap_or_default();
+                }
+                String::new()
+            }
+            "undo" => {
+                let Some((doc, cwd)) = self.history.pop() else { return error("nothing to undo") };
+                self.doc = doc;
+                self.cwd = cwd;
+                self.dirty = true;
+                String::new()
+            }
+            "save" => {
+                let file = if rest.is_empty() { self.file.clone() } else { Some(rest.to_string()) };
+                let Some(file) = file else { return error("usage: save <file>") };
+                v2::save(&file, &self.doc).map_err(|e| ReplError { message: e.to_string() })?;
+                self.file = Some(file.clone());
+                self.dirty = false;
+                format!("saved {}\n", file)
+            }
+            "quit" | "exit" => {
+                if self.dirty && !self.quit_pending {
+                    self.quit_pending = true;
+                    return error("unsaved changes; `save` them, or `quit` again to discard");
+                }
+                return Ok(ReplOutput::Quit);
+            }
+            other => return error(format!("unknown command '{}'; try `help`", other)),
+        };
+        Ok(ReplOutput::Text(text))
+    }
+
+    fn changed(&mut self, before: Value) {
+        self.history.push((before, self.cwd.clone()));
+        if self.history.len() > UNDO_LIMIT {
+            self.history.remove(0);
+        }
+        self.dirty = true;
+    }
+
+    fn ls(&self, arg: &str) -> Result<String, ReplError> {
+        let path = self.resolve(arg)?;
+        let mut out = String::new();
+        match path.lookup(&self.doc) {
+            Some(Value::Object(map)) => {
+                for (k, v) in map {
+                    let key = FlowPath::root().child_key(k);
+                    match v {
+                        Value::Object(m) => out.push_str(&format!("{}: ({})\n", key, entries(m.len()))),
+                        _ => out.push_str(&format!("{} = {}\n", key, flow_value(v).unwrap_or_else(|| "null".to_string()))),
+                    }
+                }
+            }
+            Some(Value::Array(items)) => {
+                for (i, v) in items.iter().enumerate() {
+                    match v {
+                        Value::Object(m) => out.push_str(&format!("[{}] ({})\n", i, entries(m.len()))),
+                        _ => out.push_str(&format!("[{}] = {}\n", i, flow_value(v).unwrap_or_else(|| "null".to_string()))),
+                    }
+                }
+            }
+            Some(_) => return error(format!("'{}' is not an object or array", path)),
+            None => return error(format!("no value at '{}'", path)),
+        }
+        Ok(out)
+    }
+
+    // A path argument made absolute.
+    fn resolve(&self, arg: &str) -> Result<FlowPath, ReplError> {
+        let (mut base, mut rest) = match arg.strip_prefix('/') {
+            Some(rest) => (FlowPath::root(), rest),
+            None => (self.cwd.clone(), arg),
+        };
+        loop {
+            let (up, after) = match rest.strip_prefix("..") {
+                Some("") => (true, ""),
+                Some(after) if after.starts_with('/') => (true, &after[1..]),
+                _ => (false, rest),
+            };
+            if !up {
+                break;
+            }
+            base = base.parent().unwrap_or_default();
+            rest = after;
+        }
+        if rest.is_empty() {
+            return Ok(base);
+        }
+        let relative = FlowPath::parse(rest)?;
+        Ok(join(&base, &relative))
+    }
+
+    // Replacements for the last word of `line`, sorted. Every candidate is
+    // the whole word, so a single one can be substituted as it is.
+    pub fn complete(&self, line: &str) -> Vec<String> {
+        let (command, word) = match line.rsplit_once(char::is_whitespace) {
+            None => return COMMANDS.iter().filter(|c| c.starts_with(line)).map(|c| c.to_string()).collect(),
+            Some((before, word)) => (before.split_whitespace().next().unwrap_or(""), word),
+        };
+        // only the first argument is a path
+        if line.split_whitespace().count() > 2 || (line.split_whitespace().count() == 2 && word.is_empty()) {
+            return Vec::new();
+        }
+        if !matches!(command, "cd" | "ls" | "get" | "set" | "rm") {
+            return Vec::new();
+        }
+        let (parent_text, prefix) = match word.rfind(['.', '/']) {
+            Some(i) => (&word[..=i], &word[i + 1..]),
+            None => ("", word),
+        };
+        let parent_arg = parent_text.strip_suffix('.').unwrap_or(parent_text);
+        let Ok(parent) = self.resolve(parent_arg) else { return Vec::new() };
+        let mut names: Vec<(String, bool)> = match parent.lookup(&self.doc) {
+            Some(Value::Object(map)) => map.iter().map(|(k, v)| (k.clone(), v.is_object() || v.is_array())).collect(),
+            _ => Vec::new(),
+        };
+        if command == "set" {
+            if let Some(Value::Object(map)) = parent.lookup(&self.doc) {
+                for model in self.models() {
+                    if !map.keys().any(|k| model_field(model, k).is_some()) && !map.is_empty() {
+                        continue;
+                    }
+                    for field in model.fields.values() {
+                        if map.contains_key(&field.full_name) || (!field.alias.is_empty() && map.contains_key(&field.alias)) {
+                            continue;
+                        }
+                        names.push((field.full_name.clone(), false));
+                    }
+                }
+            }
+        }
+        let mut out: Vec<String> = names
+            .into_iter()
+            .filter(|(name, nested)| name.starts_with(prefix) && (command != "cd" || *nested))
+            .map(|(name, _)| format!("{}{}", parent_text, FlowPath::root().child_key(&name)))
+            .collect();
+        out.sort();
+        out.dedup();
+        out
+    }
+
+    // The model named by `use_model`, or every registered one.
+    fn models(&self) -> Vec<&ModelDefinition> {
+        if let Some(model) = self.doc.get("use_model").and_then(Value::as_str).and_then(|name| self.registry.get_model(name)) {
+            return vec![model];
+        }
+        let mut all: Vec<&ModelDefinition> = self.registry.models().collect();
+        all.sort_by(|a, b| a.name.cmp(&b.name));
+        all
+    }
+}
+
+fn entries(n: usize) -> String {
+    format!("{} {}", n, if n == 1 { "entry" } else { "entries" })
+}
+
+fn join(base: &FlowPath, relative: &FlowPath) -> FlowPath {
+    let segments: Vec<PathSegment> = base.segments().iter().chain(relative.segments()).cloned().collect();
+    FlowPath::from(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn session() -> ReplSession {
+        ReplSession::new(json!({
+            "$models": {"server": {"fields": {"host": {"type": "string"}, "port": {"type": "int", "alias": "p"}, "tls": {"type": "bool"}}}},
+            "use_model": "server",
+            "host": "localhost",
+            "db": {"pool": {"size": 4}, "hosts": ["a", "b"]},
+        }))
+    }
+
+    fn run(session: &mut ReplSession, line: &str) -> String {
+        match session.execute(line).unwrap() {
+            ReplOutput::Text(text) => text,
+            ReplOutput::Quit => panic!("quit on '{}'", line),
+        }
+    }
+
+    #[test]
+    fn navigation() {
+        let mut s = session();
+        assert_eq!(run(&mut s, "ls db"), "hosts = [a, b]\npool: (1 entry)\n");
+        run(&mut s, "cd db.pool");
+        assert_eq!(s.prompt(), "flowdoc:/db.pool> ");
+        assert_eq!(run(&mut s, "get size"), "4\n");
+        assert_eq!(run(&mut s, "get ../hosts[1]"), "b\n");
+        assert_eq!(run(&mut s, "get /host"), "localhost\n");
+        assert_eq!(run(&mut s, "ls ../hosts"), "[0] = a\n[1] = b\n");
+        run(&mut s, "cd ..");
+        assert_eq!(run(&mut s, "pwd"), "/db\n");
+        assert_eq!(run(&mut s, "get pool"), "size = 4\n");
+        run(&mut s, "cd");
+        assert_eq!(run(&mut s, "pwd"), "/\n");
+
+        assert_eq!(s.execute("cd host").unwrap_err().message, "'host' is not an object or array");
+        assert_eq!(s.execute("get nope").unwrap_err().message, "no value at 'nope'");
+        assert_eq!(s.execute("frob").unwrap_err().message, "unknown command 'frob'; try `help`");
+    }
+
+    #[test]
+    fn edits_undo_and_quit() {
+        let mut s = session();
+        run(&mut s, "set port 8080");
+        run(&mut s, "cd db.pool");
+        run(&mut s, "rm /db.pool");
+        assert_eq!(s.cwd(), &FlowPath::parse("db").unwrap());
+        assert!(s.is_dirty());
+        assert_eq!(s.document()["port"], json!(8080));
+        assert!(s.document()["db"].get("pool").is_none());
+
+        run(&mut s, "undo");
+        assert_eq!(s.document()["db"]["pool"]["size"], json!(4));
+        assert_eq!(s.cwd(), &FlowPath::parse("db.pool").unwrap());
+        run(&mut s, "undo");
+        assert!(s.document().get("port").is_none());
+        assert_eq!(s.execute("undo").unwrap_err().message, "nothing to undo");
+        assert_eq!(s.execute("set port").unwrap_err().message, "usage: set <path> <value>");
+        assert_eq!(s.execute("rm /").unwrap_err().message, "usage: rm <path>");
+
+        assert!(s.execute("quit").is_err());
+        assert_eq!(s.execute("quit"), Ok(ReplOutput::Quit));
+    }
+
+    #[test]
+    fn save_writes_the_document() {
+        let dir = std::env::temp_dir().join(format!("flowdoc-repl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("doc.flow").display().to_string();
+        std::fs::write(&path, "name = a\nstray line\n").unwrap();
+        let mut s = ReplSession::open(&path).unwrap();
+        run(&mut s, "set name b");
+        assert_eq!(run(&mut s, "save"), format!("saved {}\n", path));
+        assert!(!s.is_dirty());
+        assert_eq!(read_text(&path).unwrap(), "name = b\n");
+        assert_eq!(s.execute("quit"), Ok(ReplOutput::Quit));
+    }
+
+    #[test]
+    fn completion() {
+        let s = session();
+        assert_eq!(s.complete("u"), ["undo"]);
+        assert_eq!(s.complete("get d"), ["db"]);
+        assert_eq!(s.complete("get db."), ["db.hosts", "db.pool"]);
+        assert_eq!(s.complete("cd db."), ["db.hosts", "db.pool"]);
+        assert!(s.complete("cd h").is_empty());
+        // model fields the object lacks; `port` is missing, `host` is set
+        assert_eq!(s.complete("set p"), ["port"]);
+        assert_eq!(s.complete("set t"), ["tls"]);
+        assert!(s.complete("set port 80").is_empty());
+        assert!(s.complete("save d").is_empty());
+    }
+}
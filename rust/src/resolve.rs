@@ -0,0 +1,589 @@
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::scheme::ResolverRegistry;
+use crate::template::{interpolate, interpolate_text, TemplateError};
+use crate::parse_value;
+
+// ============================================
+// Resolve Pass
+// ============================================
+//
+// Runs over an already parsed document:
+// - `${name}` is replaced by context variables (unknown names are kept as-is)
+// - `when <cond>:` / `if <cond>:` blocks are merged into their parent when
+//   the condition holds and dropped otherwise
+// - `for <name> in <list>:` blocks are expanded once per item with `${name}`
+//   bound to the item (`for <i>, <name> in ...` also binds the index; over an
+//   object the pair is key and value) and each expansion is merged into the
+//   parent
+// - `secret://<provider>/<path>` values are replaced by the secret returned
+//   from the resolver registered for `<provider>`
+// - `<scheme>://...` values and `${<scheme>://...}` references are resolved
+//   through the context's ResolverRegistry
+//
+// Conditions compare operands with `==` / `!=` and combine them with
+// `and` / `or` / `not` (or `&&` / `||` / `!`). An operand is `${var}`, a
+// quoted string or a bare value parsed like any other flow value; a lone
+// operand is tested for truthiness.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolveError {
+    UnknownVariable(String),
+    InvalidCondition { condition: String, message: String },
+    InvalidDirective { key: String, message: String },
+    Template(TemplateError),
+    Secret { uri: String, message: String },
+    Scheme { uri: String, message: String },
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::UnknownVariable(name) => write!(f, "unknown variable '{}'", name),
+            ResolveError::InvalidCondition { condition, message } => write!(f, "invalid condition '{}': {}", condition, message),
+            ResolveError::InvalidDirective { key, message } => write!(f, "invalid directive '{}': {}", key, message),
+            ResolveError::Template(e) => write!(f, "{}", e),
+            ResolveError::Secret { uri, message } | ResolveError::Scheme { uri, message } => {
+                write!(f, "cannot resolve '{}': {}", uri, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+impl From<TemplateError> for ResolveError {
+    fn from(e: TemplateError) -> Self {
+        ResolveError::Template(e)
+    }
+}
+
+pub trait SecretResolver {
+    // `path` is everything after `secret://<provider>/`.
+    fn resolve(&self, path: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+// secret://env/NAME reads the environment variable NAME.
+pub struct EnvSecretResolver;
+
+impl SecretResolver for EnvSecretResolver {
+    fn resolve(&self, path: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        std::env::var(path).map_err(|e| format!("environment variable '{}': {}", path, e).into())
+    }
+}
+
+// secret://file/db/main reads `<root>/db/main` with surrounding whitespace
+// trimmed, the layout used by mounted secret volumes.
+pub struct FileSecretResolver {
+    pub root: PathBuf,
+}
+
+impl FileSecretResolver {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FileSecretResolver { root: root.into() }
+    }
+}
+
+impl SecretResolver for FileSecretResolver {
+    fn resolve(&self, path: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let full = confined(&self.root, path).ok_or("path must not leave the secrets directory")?;
+        let text = fs::read_to_string(&full).map_err(|e| format!("{}: {}", full.display(), e))?;
+        Ok(text.trim().to_string())
+    }
+}
+
+// `path` under `root`, or None if it could name a file outside it. Leading
+// slashes are dropped, as in `secret://file//etc/x`, and what remains may
+// only hold plain names: no `..`, root or drive prefix.
+pub(crate) fn confined(root: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    if !relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return None;
+    }
+    Some(root.join(relative))
+}
+
+#[derive(Default)]
+pub struct ResolveContext {
+    pub vars: HashMap<String, Value>,
+    pub secrets: HashMap<String, Box<dyn SecretResolver>>,
+    pub schemes: ResolverRegistry,
+}
+
+impl ResolveContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_var(mut self, name: &str, value: Value) -> Self {
+        self.vars.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn set_var(&mut self, name: &str, value: Value) {
+        self.vars.insert(name.to_string(), value);
+    }
+
+    pub fn with_secret_resolver(mut self, provider: &str, resolver: impl SecretResolver + 'static) -> Self {
+        self.secrets.insert(provider.to_string(), Box::new(resolver));
+        self
+    }
+
+    pub fn with_resolvers(mut self, registry: ResolverRegistry) -> Self {
+        self.schemes = registry;
+        self
+    }
+
+    fn resolve_secret(&self, uri: &str) -> Result<Value, ResolveError> {
+        let err = |message: String| ResolveError::Secret { uri: uri.to_string(), message };
+        let rest = uri.strip_prefix(SECRET_SCHEME).unwrap_or(uri);
+        let (provider, path) = rest.split_once('/').ok_or_else(|| err("expected secret://<provider>/<path>".to_string()))?;
+        let resolver = self.secrets.get(provider).ok_or_else(|| err(format!("no resolver registered for '{}'", provider)))?;
+        resolver.resolve(path).map(Value::String).map_err(|e| err(e.to_string()))
+    }
+}
+
+const SECRET_SCHEME: &str = "secret://";
+
+#[deprecated(note = "use v2::resolve")]
+pub fn ResolveFlow(doc: &Value, ctx: &ResolveContext) -> Result<Value, ResolveError> {
+    resolve(doc, ctx)
+}
+
+pub(crate) fn resolve(doc: &Value, ctx: &ResolveContext) -> Result<Value, ResolveError> {
+    resolve_value(doc, &Scope { ctx, locals: Vec::new() })
+}
+
+// Context variables plus the loop variables bound by enclosing `for` blocks.
+struct Scope<'a> {
+    ctx: &'a ResolveContext,
+    locals: Vec<(String, Value)>,
+}
+
+impl Scope<'_> {
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.locals.iter().rev().find(|(n, _)| n == name).map(|(_, v)| v).or_else(|| self.ctx.vars.get(name))
+    }
+
+    fn lookup_or_keep(&self, name: &str) -> Value {
+        self.get(name).cloned().unwrap_or_else(|| Value::String(format!("${{{}}}", name)))
+    }
+
+    // Interpolates `s`, resolving `${<scheme>://...}` through the registry.
+    fn interpolate(&self, s: &str) -> Result<Value, ResolveError> {
+        let failure = RefCell::new(None);
+        let lookup = |name: &str| {
+            if name.contains("://") {
+                match self.ctx.schemes.resolve(name) {
+                    Ok(Some(v)) => return Some(v),
+                    Ok(None) => {}
+                    Err(e) => {
+                        failure.borrow_mut().get_or_insert(e);
+                    }
+                }
+            }
+            Some(self.lookup_or_keep(name))
+        };
+        let result = interpolate(s, &lookup);
+        match failure.into_inner() {
+            Some(e) => Err(e),
+            None => Ok(result?),
+        }
+    }
+
+    fn with_locals(&self, bindings: Vec<(String, Value)>) -> Self {
+        let mut locals = self.locals.clone();
+        locals.extend(bindings);
+        Scope { ctx: self.ctx, locals }
+    }
+}
+
+fn resolve_value(val: &Value, scope: &Scope) -> Result<Value, ResolveError> {
+    match val {
+        Value::Object(map) => resolve_object(map, scope).map(Value::Object),
+        Value::Array(items) => items.iter().map(|v| resolve_value(v, scope)).collect::<Result<Vec<_>, _>>().map(Value::Array),
+        Value::String(s) if s.starts_with(SECRET_SCHEME) => scope.ctx.resolve_secret(s),
+        Value::String(s) => match scope.ctx.schemes.resolve(s)? {
+            Some(v) => Ok(v),
+            None => scope.interpolate(s),
+        },
+        _ => Ok(val.clone()),
+    }
+}
+
+fn resolve_object(map: &Map<String, Value>, scope: &Scope) -> Result<Map<String, Value>, ResolveError> {
+    let mut out = Map::new();
+    let mut sections = Vec::new();
+    for (key, val) in map {
+        let cond = directive(key, "when").or_else(|| directive(key, "if"));
+        let spec = directive(key, "for");
+        if cond.is_none() && spec.is_none() {
+            let key = interpolate_text(key, &|name| Some(scope.lookup_or_keep(name)))?;
+            out.insert(key, resolve_value(val, scope)?);
+            continue;
+        }
+        let body = match val {
+            Value::Object(m) => m,
+            _ => return Err(ResolveError::InvalidDirective { key: key.clone(), message: "a directive must introduce a block".to_string() }),
+        };
+        if let Some(cond) = cond {
+            if eval_condition(cond, scope)? {
+                sections.push(resolve_object(body, scope)?);
+            }
+        } else if let Some(spec) = spec {
+            for bindings in expand_loop(key, spec, scope)? {
+                sections.push(resolve_object(body, &scope.with_locals(bindings))?);
+            }
+        }
+    }
+    for section in sections {
+        merge_into(&mut out, section);
+    }
+    Ok(out)
+}
+
+fn expand_loop(key: &str, spec: &str, scope: &Scope) -> Result<Vec<Vec<(String, Value)>>, ResolveError> {
+    let err = |message: String| ResolveError::InvalidDirective { key: key.to_string(), message };
+    let (names, source) = spec.split_once(" in ").ok_or_else(|| err("expected 'for <name> in <list>'".to_string()))?;
+    let names: Vec<&str> = names.split(',').map(str::trim).collect();
+    if names.len() > 2 || names.iter().any(|n| n.is_empty() || n.contains(char::is_whitespace)) {
+        return Err(err(format!("invalid loop variables '{}'", names.join(", "))));
+    }
+    let source = source.trim();
+    let items = match source.strip_prefix("${").and_then(|r| r.strip_suffix('}')) {
+        Some(name) => scope.get(name.trim()).cloned().ok_or_else(|| ResolveError::UnknownVariable(name.trim().to_string()))?,
+        None => parse_value(source),
+    };
+    let pairs: Vec<(Value, Value)> = match items {
+        Value::Array(a) => a.into_iter().enumerate().map(|(i, v)| (Value::from(i), v)).collect(),
+        Value::Object(m) => m.into_iter().map(|(k, v)| (Value::String(k), v)).collect(),
+        other => return Err(err(format!("cannot iterate over {}", other))),
+    };
+    Ok(pairs
+        .into_iter()
+        .map(|(index, item)| match names.as_slice() {
+            [item_name] => vec![(item_name.to_string(), item)],
+            [index_name, item_name] => vec![(index_name.to_string(), index), (item_name.to_string(), item)],
+            _ => unreachable!(),
+        })
+        .collect())
+}
+
+pub(crate) fn directive<'a>(key: &'a str, keyword: &str) -> Option<&'a str> {
+    let rest = key.strip_prefix(keyword)?;
+    if rest.starts_with(char::is_whitespace) {
+        Some(rest.trim())
+    } else {
+        None
+    }
+}
+
+// Deep merge: objects are merged key by key, anything else is replaced.
+pub(crate) fn merge_into(target: &mut Map<String, Value>, source: Map<String, Value>) {
+    for (k, v) in source {
+        match (target.get_mut(&k), v) {
+            (Some(Value::Object(existing)), Value::Object(incoming)) => merge_into(existing, incoming),
+            (_, v) => {
+                target.insert(k, v);
+            }
+        }
+    }
+}
+
+// ============================================
+// Conditions
+// ============================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Operand(Value),
+    Eq,
+    Ne,
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn eval_condition(cond: &str, scope: &Scope) -> Result<bool, ResolveError> {
+    let err = |message: &str| ResolveError::InvalidCondition { condition: cond.to_string(), message: message.to_string() };
+    let tokens = tokenize_condition(cond, scope).map_err(|e| match e {
+        ResolveError::InvalidCondition { message, .. } => err(&message),
+        other => other,
+    })?;
+    if tokens.is_empty() {
+        return Err(err("empty condition"));
+    }
+    let mut parser = CondParser { tokens: &tokens, pos: 0 };
+    let result = parser.or_expr().ok_or_else(|| err("malformed expression"))?;
+    if parser.pos != tokens.len() {
+        return Err(err("unexpected trailing tokens"));
+    }
+    Ok(truthy(&result))
+}
+
+fn tokenize_condition(cond: &str, scope: &Scope) -> Result<Vec<Token>, ResolveError> {
+    let err = |message: String| ResolveError::InvalidCondition { condition: cond.to_string(), message };
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = cond.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+        match two.as_str() {
+            "==" | "!=" | "&&" | "||" => {
+                tokens.push(match two.as_str() {
+                    "==" => Token::Eq,
+                    "!=" => Token::Ne,
+                    "&&" => Token::And,
+                    _ => Token::Or,
+                });
+                i += 2;
+                continue;
+            }
+            "${" => {
+                let end = chars[i..].iter().position(|&c| c == '}').ok_or_else(|| err("unterminated '${'".to_string()))?;
+                let name: String = chars[i + 2..i + end].iter().collect::<String>().trim().to_string();
+                let val = scope.get(&name).cloned().ok_or(ResolveError::UnknownVariable(name))?;
+                tokens.push(Token::Operand(val));
+                i += end + 1;
+                continue;
+            }
+            _ => {}
+        }
+        match c {
+            '!' => tokens.push(Token::Not),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '"' => {
+                let end = chars[i + 1..].iter().position(|&c| c == '"').ok_or_else(|| err("unterminated string".to_string()))?;
+                tokens.push(Token::Operand(Value::String(chars[i + 1..i + 1 + end].iter().collect())));
+                i += end + 2;
+                continue;
+            }
+            _ => {
+                let start = i;
+                while i < chars.len() && !chars[i].is_whitespace() && !"=!()&|\"".contains(chars[i]) {
+                    i += 1;
+                }
+                if start == i {
+                    return Err(err(format!("unexpected character '{}'", c)));
+                }
+                let word: String = chars[start..i].iter().collect();
+                tokens.push(match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Operand(parse_value(&word)),
+                });
+                continue;
+            }
+        }
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+struct CondParser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl CondParser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn or_expr(&mut self) -> Option<Value> {
+        let mut left = self.and_expr()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            let right = self.and_expr()?;
+            left = Value::Bool(truthy(&left) || truthy(&right));
+        }
+        Some(left)
+    }
+
+    fn and_expr(&mut self) -> Option<Value> {
+        let mut left = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            let right = self.unary()?;
+            left = Value::Bool(truthy(&left) && truthy(&right));
+        }
+        Some(left)
+    }
+
+    fn unary(&mut self) -> Option<Value> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return self.unary().map(|v| Value::Bool(!truthy(&v)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Option<Value> {
+        let left = self.operand()?;
+        let op = match self.peek() {
+            Some(Token::Eq) => true,
+            Some(Token::Ne) => false,
+            _ => return Some(left),
+        };
+        self.pos += 1;
+        let right = self.operand()?;
+        Some(Value::Bool(values_equal(&left, &right) == op))
+    }
+
+    fn operand(&mut self) -> Option<Value> {
+        match self.tokens.get(self.pos)? {
+            Token::Operand(v) => {
+                self.pos += 1;
+                Some(v.clone())
+            }
+            Token::Open => {
+                self.pos += 1;
+                let v = self.or_expr()?;
+                if self.peek() != Some(&Token::Close) {
+                    return None;
+                }
+                self.pos += 1;
+                Some(v)
+            }
+            _ => None,
+        }
+    }
+}
+
+// Numbers compare by value so `1 == 1.0` holds.
+fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        _ => a == b,
+    }
+}
+
+pub(crate) fn truthy(v: &Value) -> bool {
+    match v {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().map(|f| f != 0.0).unwrap_or(true),
+        Value::String(s) => !s.is_empty() && s != "false",
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(m) => !m.is_empty(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn resolve_with(doc: Value, ctx: &ResolveContext) -> Result<Value, ResolveError> {
+        resolve(&doc, ctx)
+    }
+
+    #[test]
+    fn when_blocks() {
+        let ctx = ResolveContext::new().with_var("env", json!("prod")).with_var("replicas", json!(3)).with_var("debug", json!(false));
+        let doc = json!({
+            "log": "info",
+            "when ${env} == prod": { "log": "warn", "tls": { "on": true } },
+            "when ${env} != prod and not ${debug}": { "log": "debug" },
+            "if ${replicas} == 3 || ${debug}": { "tls": { "port": 443 } },
+            "when (${debug})": { "trace": true },
+            "name": "svc-${env}",
+        });
+        let resolved = resolve_with(doc, &ctx).unwrap();
+        assert_eq!(resolved, json!({ "log": "warn", "tls": { "on": true, "port": 443 }, "name": "svc-prod" }));
+        // unknown variables are kept
+        assert_eq!(resolve_with(json!({ "a": "${missing}" }), &ctx).unwrap(), json!({ "a": "${missing}" }));
+    }
+
+    #[test]
+    fn invalid_directives() {
+        let ctx = ResolveContext::new();
+        assert!(matches!(resolve_with(json!({ "when a ==": { "x": 1 } }), &ctx), Err(ResolveError::InvalidCondition { .. })));
+        assert!(matches!(resolve_with(json!({ "when (a": { "x": 1 } }), &ctx), Err(ResolveError::InvalidCondition { .. })));
+        assert!(matches!(resolve_with(json!({ "when true": 1 }), &ctx), Err(ResolveError::InvalidDirective { .. })));
+    }
+
+    #[test]
+    fn for_blocks() {
+        let ctx = ResolveContext::new().with_var("regions", json!(["eu", "us"])).with_var("ports", json!({ "http": 80, "https": 443 }));
+        let doc = json!({
+            "for r in ${regions}": { "bucket_${r}": "data-${r}" },
+            "for i, r in ${regions}": { "order": { "${r}": "${i}" } },
+            "for name, port in ${ports}": { "listen": { "${name}": "${port}" } },
+            "for n in [1, 2]": { "when ${n} == 2": { "last": "${n}" } },
+        });
+        let resolved = resolve_with(doc, &ctx).unwrap();
+        assert_eq!(
+            resolved,
+            json!({
+                "bucket_eu": "data-eu",
+                "bucket_us": "data-us",
+                "order": { "eu": 0, "us": 1 },
+                "listen": { "http": 80, "https": 443 },
+                "last": 2,
+            })
+        );
+        assert!(matches!(resolve_with(json!({ "for x in ${nope}": {} }), &ctx), Err(ResolveError::UnknownVariable(_))));
+        assert!(matches!(resolve_with(json!({ "for x in 5": {} }), &ctx), Err(ResolveError::InvalidDirective { .. })));
+        assert!(matches!(resolve_with(json!({ "for a, b, c in [1]": {} }), &ctx), Err(ResolveError::InvalidDirective { .. })));
+    }
+
+    #[test]
+    fn secret_resolvers() {
+        struct Vault;
+        impl SecretResolver for Vault {
+            fn resolve(&self, path: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+                match path {
+                    "db/password" => Ok("s3cret".to_string()),
+                    _ => Err(format!("no secret at '{}'", path).into()),
+                }
+            }
+        }
+        let name = format!("FLOWDOC_TEST_SECRET_{}", std::process::id());
+        std::env::set_var(&name, "from-env");
+        let ctx = ResolveContext::new().with_secret_resolver("vault", Vault).with_secret_resolver("env", EnvSecretResolver);
+        let doc = json!({ "db": { "password": "secret://vault/db/password" }, "token": format!("secret://env/{}", name) });
+        assert_eq!(resolve_with(doc, &ctx).unwrap(), json!({ "db": { "password": "s3cret" }, "token": "from-env" }));
+
+        for uri in ["secret://vault/other", "secret://nobody/x", "secret://vault"] {
+            match resolve_with(json!({ "x": uri }), &ctx) {
+                Err(ResolveError::Secret { uri: failed, .. }) => assert_eq!(failed, uri),
+                other => panic!("expected a secret error for {}, got {:?}", uri, other),
+            }
+        }
+    }
+
+
+    #[test]
+    fn file_secrets_stay_under_the_root() {
+        let dir = std::env::temp_dir().join(format!("flowdoc-secrets-{}", std::process::id()));
+        let root = dir.join("secrets");
+        fs::create_dir_all(root.join("db")).unwrap();
+        fs::write(root.join("db/main"), "hunter2\n").unwrap();
+        fs::write(dir.join("outside"), "leaked").unwrap();
+        let resolver = FileSecretResolver::new(&root);
+
+        assert_eq!(resolver.resolve("db/main").unwrap(), "hunter2");
+        // leading slashes are relative to the root, not the filesystem
+        assert_eq!(resolver.resolve("/db/main").unwrap(), "hunter2");
+        assert_eq!(resolver.resolve("//db/main").unwrap(), "hunter2");
+        let outside = dir.join("outside").display().to_string();
+        assert!(resolver.resolve(&outside).is_err());
+        assert!(resolver.resolve("../outside").is_err());
+        assert!(resolver.resolve("db/../../outside").is_err());
+    }
+}
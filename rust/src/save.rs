@@ -0,0 +1,176 @@
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::stringify_document;
+use crate::trace::{self, Operation};
+
+// ============================================
+// Saving
+// ============================================
+//
+// An atomic save writes the new content to a temporary file next to the
+// target, flushes it to disk and renames it over the target, so a crash
+// leaves either the old or the new document and never a partial one. With
+// `backup`, the previous content is first copied to `<path>.bak`.
+
+#[derive(Debug, Clone, Default)]
+pub struct SaveOptions {
+    // write to a temporary file, fsync it and rename it into place
+    pub atomic: bool,
+    // keep the previous content as `<path>.bak`
+    pub backup: bool,
+}
+
+impl SaveOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn atomic(mut self, on: bool) -> Self {
+        self.atomic = on;
+        self
+    }
+
+    pub fn backup(mut self, on: bool) -> Self {
+        self.backup = on;
+        self
+    }
+}
+
+#[deprecated(note = "use v2::save_with_options")]
+pub fn SaveFlowWithOptions(path: &str, val: &Value, options: &SaveOptions) -> Result<(), io::Error> {
+    save_file(path, val, stringify_document(val).as_bytes(), options)
+}
+
+#[deprecated(note = "use v2::save_flowb_with_options")]
+pub fn SaveFlowbWithOptions(path: &str, val: &Value, options: &SaveOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let buf = rmp_serde::to_vec(val)?;
+    save_file(path, val, &buf, options)?;
+    Ok(())
+}
+
+// write_file reported as a save of `val`
+pub(crate) fn save_file(path: &str, val: &Value, data: &[u8], options: &SaveOptions) -> io::Result<()> {
+    let mut span = trace::Span::start(Operation::Save, Some(path));
+    span.bytes(data.len());
+    let result = write_file(Path::new(path), data, options);
+    span.finish_with(&result, |_| val);
+    result
+}
+
+pub(crate) fn write_file(path: &Path, data: &[u8], options: &SaveOptions) -> io::Result<()> {
+    if options.backup {
+        backup(path)?;
+    }
+    if !options.atomic {
+        return fs::write(path, data);
+    }
+    let tmp = write_temp(path, data)?;
+    replace(&tmp, path)
+}
+
+// Copies the current content of `path`, if any, to `<path>.bak`.
+pub(crate) fn backup(path: &Path) -> io::Result<()> {
+    if path.exists() {
+        fs::copy(path, backup_path(path))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+// Writes `data` to a new, synced temporary file in the directory of `path`.
+pub(crate) fn write_temp(path: &Path, data: &[u8]) -> io::Result<PathBuf> {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let tmp = path.with_file_name(format!(".{}.tmp{}", name, unique_suffix()));
+    let result = File::create(&tmp).and_then(|mut f| {
+        f.write_all(data)?;
+        f.sync_all()
+    });
+    match result {
+        Ok(()) => Ok(tmp),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+// Different on every call, so threads saving the same file at the same time
+// each write their own temporary file.
+pub(crate) fn unique_suffix() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    format!("{}.{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+// Renames a temporary file over `path` and syncs the directory entry.
+pub(crate) fn replace(tmp: &Path, path: &Path) -> io::Result<()> {
+    if let Err(e) = fs::rename(tmp, path) {
+        let _ = fs::remove_file(tmp);
+        return Err(e);
+    }
+    sync_dir(path)
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flowdoc-save-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn temp_names_are_unique() {
+        let path = scratch_dir("names").join("doc.flow");
+        let a = write_temp(&path, b"a").unwrap();
+        let b = write_temp(&path, b"b").unwrap();
+        assert_ne!(a, b);
+        assert_eq!(fs::read(&a).unwrap(), b"a");
+        assert_eq!(fs::read(&b).unwrap(), b"b");
+    }
+
+    #[test]
+    fn concurrent_atomic_saves() {
+        let path = scratch_dir("threads").join("doc.flow");
+        let contents: Vec<Vec<u8>> = (0..8u8).map(|i| vec![b'a' + i; 64 * 1024]).collect();
+        thread::scope(|s| {
+            for data in &contents {
+                let path = &path;
+                s.spawn(move || {
+                    for _ in 0..10 {
+                        write_file(path, data, &SaveOptions::new().atomic(true)).unwrap();
+                    }
+                });
+            }
+        });
+        let saved = fs::read(&path).unwrap();
+        assert!(contents.contains(&saved), "saved content is a mix of several writes");
+        let left: Vec<_> = fs::read_dir(path.parent().unwrap()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(left, vec!["doc.flow"]);
+    }
+}
@@ -0,0 +1,208 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::canonical::canonicalize;
+use crate::resolve::ResolveError;
+
+// ============================================
+// Scheme Resolvers
+// ============================================
+//
+// A ResolverRegistry maps URI-like schemes (`env`, `file`, `vault`, ...) to
+// resolvers. In a resolve pass, a string value that is a whole
+// `<scheme>://<reference>` for a registered scheme is replaced by the value
+// the resolver returns, and `${<scheme>://<reference>}` inside a longer
+// string is replaced by its text. Strings with schemes that are not
+// registered, such as ordinary `https://` URLs, are left alone. As in
+// templates, `$${` is a literal `${`.
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub trait SchemeResolver: Send + Sync {
+    // `reference` is everything after `<scheme>://`.
+    fn resolve(&self, reference: &str) -> Result<Value, BoxError>;
+}
+
+impl<F> SchemeResolver for F
+where
+    F: Fn(&str) -> Result<Value, BoxError> + Send + Sync,
+{
+    fn resolve(&self, reference: &str) -> Result<Value, BoxError> {
+        self(reference)
+    }
+}
+
+// env://NAME is the environment variable NAME.
+pub struct EnvResolver;
+
+impl SchemeResolver for EnvResolver {
+    fn resolve(&self, reference: &str) -> Result<Value, BoxError> {
+        std::env::var(reference).map(Value::String).map_err(|e| format!("environment variable '{}': {}", reference, e).into())
+    }
+}
+
+// file://relative/path is the trimmed content of that file under `root`.
+pub struct FileResolver {
+    pub root: PathBuf,
+}
+
+impl FileResolver {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FileResolver { root: root.into() }
+    }
+}
+
+impl SchemeResolver for FileResolver {
+    fn resolve(&self, reference: &str) -> Result<Value, BoxError> {
+        if reference.split('/').any(|part| part == "..") {
+            return Err("path must not leave the resolver's root".into());
+        }
+        let full = self.root.join(reference.trim_start_matches('/'));
+        let text = fs::read_to_string(&full).map_err(|e| format!("{}: {}", full.display(), e))?;
+        Ok(Value::String(text.trim().to_string()))
+    }
+}
+
+#[derive(Default)]
+pub struct ResolverRegistry {
+    schemes: HashMap<String, Box<dyn SchemeResolver>>,
+}
+
+impl ResolverRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, scheme: &str, resolver: impl SchemeResolver + 'static) -> Self {
+        self.register(scheme, resolver);
+        self
+    }
+
+    pub fn register(&mut self, scheme: &str, resolver: impl SchemeResolver + 'static) {
+        self.schemes.insert(scheme.to_ascii_lowercase(), Box::new(resolver));
+    }
+
+    pub fn contains(&self, scheme: &str) -> bool {
+        self.schemes.contains_key(&scheme.to_ascii_lowercase())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schemes.is_empty()
+    }
+
+    // Resolves `uri`, or returns None if its scheme is not registered.
+    pub fn resolve(&self, uri: &str) -> Result<Option<Value>, ResolveError> {
+        let Some((scheme, reference)) = uri.split_once("://") else {
+            return Ok(None);
+        };
+        let Some(resolver) = self.schemes.get(&scheme.to_ascii_lowercase()) else {
+            return Ok(None);
+        };
+        resolver
+            .resolve(reference)
+            .map(Some)
+            .map_err(|e| ResolveError::Scheme { uri: uri.to_string(), message: e.to_string() })
+    }
+}
+
+#[deprecated(note = "use v2::resolve_schemes")]
+pub fn ResolveSchemes(doc: &Value, registry: &ResolverRegistry) -> Result<Value, ResolveError> {
+    resolve_schemes(doc, registry)
+}
+
+// Resolves registered schemes everywhere in `doc` and changes nothing else.
+pub(crate) fn resolve_schemes(doc: &Value, registry: &ResolverRegistry) -> Result<Value, ResolveError> {
+    match doc {
+        Value::Object(map) => {
+            let mut out = serde_json::Map::new();
+            for (k, v) in map {
+                out.insert(k.clone(), resolve_schemes(v, registry)?);
+            }
+            Ok(Value::Object(out))
+        }
+        Value::Array(items) => items.iter().map(|v| resolve_schemes(v, registry)).collect::<Result<Vec<_>, _>>().map(Value::Array),
+        Value::String(s) => resolve_string(s, registry),
+        _ => Ok(doc.clone()),
+    }
+}
+
+fn resolve_string(s: &str, registry: &ResolverRegistry) -> Result<Value, ResolveError> {
+    if let Some(v) = registry.resolve(s)? {
+        return Ok(v);
+    }
+    if !s.contains("${") {
+        return Ok(Value::String(s.to_string()));
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find("${") {
+        if rest[..pos].ends_with('$') {
+            out.push_str(&rest[..pos - 1]);
+            out.push_str("${");
+            rest = &rest[pos + 2..];
+            continue;
+        }
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 2..];
+        let Some(end) = after.find('}') else {
+            out.push_str("${");
+            rest = after;
+            continue;
+        };
+        match registry.resolve(after[..end].trim())? {
+            Some(v) => out.push_str(&as_text(&v)),
+            None => out.push_str(&rest[pos..pos + 2 + end + 1]),
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(Value::String(out))
+}
+
+fn as_text(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => canonicalize(other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn registry() -> ResolverRegistry {
+        ResolverRegistry::new().with("const", |reference: &str| -> Result<Value, BoxError> {
+            match reference {
+                "port" => Ok(json!(8080)),
+                "name" => Ok(json!("api")),
+                _ => Err(format!("no constant '{}'", reference).into()),
+            }
+        })
+    }
+
+    #[test]
+    fn whole_values_and_references() {
+        let doc = json!({ "port": "const://port", "url": "http://${const://name}:${ const://port }/", "site": "https://example.com", "other": "${vault://x}" });
+        let resolved = resolve_schemes(&doc, &registry()).unwrap();
+        assert_eq!(resolved, json!({ "port": 8080, "url": "http://api:8080/", "site": "https://example.com", "other": "${vault://x}" }));
+        assert!(matches!(resolve_schemes(&json!({ "x": "a ${const://missing}" }), &registry()), Err(ResolveError::Scheme { .. })));
+    }
+
+    #[test]
+    fn escapes_match_templates() {
+        let registry = registry();
+        let text = "$${const://name} is ${const://name}, $$${const://name}, $${ open";
+        let resolved = resolve_schemes(&json!(text), &registry).unwrap();
+        assert_eq!(resolved, json!("${const://name} is api, $${const://name}, ${ open"));
+        // an escaped reference is not resolved, so it cannot fail
+        assert_eq!(resolve_schemes(&json!("$${const://missing}"), &registry).unwrap(), json!("${const://missing}"));
+
+        let lookup = |name: &str| (name == "x").then(|| json!("api"));
+        let rendered = crate::template::interpolate_text("$${x} is ${x}, $$${x}, $${ open", &lookup).unwrap();
+        assert_eq!(rendered, "${x} is api, $${x}, ${ open");
+    }
+}
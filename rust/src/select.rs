@@ -0,0 +1,140 @@
+use serde_json::{Map, Value};
+
+use crate::path::{FlowPath, PathError, PathGlob};
+
+// ============================================
+// Projection
+// ============================================
+//
+// `select` keeps the nodes matching any of the globs, each with everything
+// below it, plus the objects and arrays that lead to them; `exclude` keeps
+// everything else. Array elements that are dropped close up, so indices in
+// the result can differ from the source.
+
+pub fn select(val: &Value, globs: &[&str]) -> Result<Value, PathError> {
+    let globs = parse_all(globs)?;
+    Ok(select_at(val, &mut FlowPath::root(), &globs).unwrap_or_else(|| empty_like(val)))
+}
+
+pub fn exclude(val: &Value, globs: &[&str]) -> Result<Value, PathError> {
+    let globs = parse_all(globs)?;
+    Ok(exclude_at(val, &mut FlowPath::root(), &globs).unwrap_or_else(|| empty_like(val)))
+}
+
+fn parse_all(globs: &[&str]) -> Result<Vec<PathGlob>, PathError> {
+    globs.iter().map(|g| PathGlob::parse(g)).collect()
+}
+
+fn empty_like(val: &Value) -> Value {
+    match val {
+        Value::Array(_) => Value::Array(Vec::new()),
+        _ => Value::Object(Map::new()),
+    }
+}
+
+fn select_at(val: &Value, path: &mut FlowPath, globs: &[PathGlob]) -> Option<Value> {
+    if globs.iter().any(|g| g.matches(path)) {
+        return Some(val.clone());
+    }
+    if !globs.iter().any(|g| g.may_match_below(path)) {
+        return None;
+    }
+    match val {
+        Value::Object(map) => {
+            let mut out = Map::new();
+            for (k, v) in map {
+                path.push_key(k);
+                if let Some(kept) = select_at(v, path, globs) {
+                    out.insert(k.clone(), kept);
+                }
+                path.pop();
+            }
+            (!out.is_empty()).then_some(Value::Object(out))
+        }
+        Value::Array(items) => {
+            let mut out = Vec::new();
+            for (i, v) in items.iter().enumerate() {
+                path.push_index(i);
+                out.extend(select_at(v, path, globs));
+                path.pop();
+            }
+            (!out.is_empty()).then_some(Value::Array(out))
+        }
+        _ => None,
+    }
+}
+
+fn exclude_at(val: &Value, path: &mut FlowPath, globs: &[PathGlob]) -> Option<Value> {
+    if !path.is_root() && globs.iter().any(|g| g.matches(path)) {
+        return None;
+    }
+    if !globs.iter().any(|g| g.may_match_below(path)) {
+        return Some(val.clone());
+    }
+    match val {
+        Value::Object(map) => {
+            let mut out = Map::new();
+            for (k, v) in map {
+                path.push_key(k);
+                if let Some(kept) = exclude_at(v, path, globs) {
+                    out.insert(k.clone(), kept);
+                }
+                path.pop();
+            }
+            Some(Value::Object(out))
+        }
+        Value::Array(items) => {
+            let mut out = Vec::new();
+            for (i, v) in items.iter().enumerate() {
+                path.push_index(i);
+                out.extend(exclude_at(v, path, globs));
+                path.pop();
+            }
+            Some(Value::Array(out))
+        }
+        _ => Some(val.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config() -> Value {
+        json!({
+            "server": {"host": "h", "tls": {"cert": "c"}},
+            "logging": {"level": "info", "file": "f"},
+            "users": [{"name": "a", "token": "t1"}, {"name": "b"}, {"token": "t3"}],
+        })
+    }
+
+    #[test]
+    fn select_keeps_matches_and_their_parents() {
+        assert_eq!(
+            select(&config(), &["server.**", "logging.level"]).unwrap(),
+            json!({"server": {"host": "h", "tls": {"cert": "c"}}, "logging": {"level": "info"}})
+        );
+        // elements without a match are dropped and the rest close up
+        assert_eq!(select(&config(), &["users.*.token"]).unwrap(), json!({"users": [{"token": "t1"}, {"token": "t3"}]}));
+        assert_eq!(select(&config(), &["nothing"]).unwrap(), json!({}));
+        assert_eq!(select(&json!([1, {"a": 2}]), &["*.a"]).unwrap(), json!([{"a": 2}]));
+        assert_eq!(select(&json!([1]), &["*.a"]).unwrap(), json!([]));
+    }
+
+    #[test]
+    fn exclude_keeps_everything_else() {
+        assert_eq!(
+            exclude(&config(), &["server.tls", "users.*.token", "logging.*"]).unwrap(),
+            json!({"server": {"host": "h"}, "logging": {}, "users": [{"name": "a"}, {"name": "b"}, {}]})
+        );
+        assert_eq!(exclude(&json!({"a": [1, 2, 3]}), &["a[1]"]).unwrap(), json!({"a": [1, 3]}));
+        assert_eq!(exclude(&config(), &[]).unwrap(), config());
+    }
+
+    #[test]
+    fn invalid_globs_fail() {
+        assert!(select(&config(), &["ok", "a["]).is_err());
+        assert!(exclude(&config(), &["a["]).is_err());
+    }
+}
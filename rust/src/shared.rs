@@ -0,0 +1,344 @@
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::path::FlowPath;
+use crate::v2;
+
+// ============================================
+// Shared Documents
+// ============================================
+//
+// A SharedFlowDocument is a handle many threads can hold to one document.
+// Readers take a snapshot, an Arc of the document as it was at that moment,
+// which later changes never touch. Writers replace the document or edit a
+// copy of it under `update`; the copy becomes current when the closure
+// returns, so readers see all of an edit or none of it.
+//
+// Every change that leaves the document different bumps the version and is
+// reported to the subscribers, in version order, after it is visible to
+// readers. Writes are serialized while the subscribers run, so a subscriber
+// must not write to the same handle. A document loaded from a file can be
+// reloaded, e.g. when a watcher reports the file changed.
+//
+// `subscribe_path` narrows a subscription to one node: the listener hears
+// about a change only when the value at that path was added, removed or
+// became different, whether by an edit at the path, above it or below it.
+
+pub type Subscription = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Replaced,
+    Updated,
+    Reloaded,
+}
+
+#[derive(Debug, Clone)]
+pub struct DocumentChange {
+    pub kind: ChangeKind,
+    pub version: u64,
+    pub old: Arc<Value>,
+    pub new: Arc<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathEvent {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Clone)]
+pub struct PathChange {
+    pub path: FlowPath,
+    pub event: PathEvent,
+    pub kind: ChangeKind,
+    pub version: u64,
+    // the value at the path before and after; None where there was none
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+impl PathChange {
+    // The new value as a `T`, or None if the value was removed.
+    pub fn new_as<T: DeserializeOwned>(&self) -> Option<Result<T, serde_json::Error>> {
+        self.new.clone().map(serde_json::from_value)
+    }
+
+    pub fn old_as<T: DeserializeOwned>(&self) -> Option<Result<T, serde_json::Error>> {
+        self.old.clone().map(serde_json::from_value)
+    }
+}
+
+type Listener = Arc<dyn Fn(&DocumentChange) + Send + Sync>;
+
+struct Shared {
+    current: RwLock<Arc<Value>>,
+    version: AtomicU64,
+    // held from a write until its subscribers have run
+    writer: Mutex<()>,
+    listeners: Mutex<Vec<(Subscription, Listener)>>,
+    next_subscription: AtomicU64,
+    source: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct SharedFlowDocument {
+    shared: Arc<Shared>,
+}
+
+impl SharedFlowDocument {
+    pub fn new(doc: Value) -> Self {
+        Self::with_source(doc, None)
+    }
+
+    // The document at `path`, as `v2::load` reads it; `reload` reads it again.
+    pub fn load(path: &str) -> v2::Result<Self> {
+        Ok(Self::with_source(v2::load(path)?, Some(path.to_string())))
+    }
+
+    fn with_source(doc: Value, source: Option<String>) -> Self {
+        SharedFlowDocument {
+            shared: Arc::new(Shared {
+                current: RwLock::new(Arc::new(doc)),
+                version: AtomicU64::new(0),
+                writer: Mutex::new(()),
+                listeners: Mutex::new(Vec::new()),
+                next_subscription: AtomicU64::new(1),
+                source,
+            }),
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<Value> {
+        self.shared.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    // Starts at 0 and goes up by one with every change.
+    pub fn version(&self) -> u64 {
+        self.shared.version.load(Ordering::Acquire)
+    }
+
+    pub fn source(&self) -> Option<&str> {
+        self.shared.source.as_deref()
+    }
+
+    // Returns the new version, or the current one if `doc` is no different.
+    pub fn replace(&self, doc: Value) -> u64 {
+        let _writing = self.lock_writer();
+        self.swap(Arc::new(doc), ChangeKind::Replaced)
+    }
+
+    pub fn update<R>(&self, edit: impl FnOnce(&mut Value) -> R) -> R {
+        let _writing = self.lock_writer();
+        let mut doc = Value::clone(&self.snapshot());
+        let out = edit(&mut doc);
+        self.swap(Arc::new(doc), ChangeKind::Updated);
+        out
+    }
+
+    // Like `update`, but an error leaves the document as it was.
+    pub fn try_update<R, E>(&self, edit: impl FnOnce(&mut Value) -> Result<R, E>) -> Result<R, E> {
+        let _writing = self.lock_writer();
+        let mut doc = Value::clone(&self.snapshot());
+        let out = edit(&mut doc)?;
+        self.swap(Arc::new(doc), ChangeKind::Updated);
+        Ok(out)
+    }
+
+    // Reads the source file again and returns whether the document changed.
+    // A document not loaded from a file never changes.
+    pub fn reload(&self) -> v2::Result<bool> {
+        let Some(path) = &self.shared.source else { return Ok(false) };
+        let doc = v2::load(path)?;
+        let _writing = self.lock_writer();
+        let before = self.version();
+        Ok(self.swap(Arc::new(doc), ChangeKind::Reloaded) != before)
+    }
+
+    // `listener` is called with every change from now on.
+    pub fn subscribe(&self, listener: impl Fn(&DocumentChange) + Send + Sync + 'static) -> Subscription {
+        let id = self.shared.next_subscription.fetch_add(1, Ordering::Relaxed);
+        self.shared.listeners.lock().unwrap_or_else(|e| e.into_inner()).push((id, Arc::new(listener)));
+        id
+    }
+
+    // `listener` is called whenever the value at `path` changes.
+    pub fn subscribe_path(&self, path: &FlowPath, listener: impl Fn(&PathChange) + Send + Sync + 'static) -> Subscription {
+        let path = path.clone();
+        self.subscribe(move |change| {
+            let old = path.lookup(&change.old);
+            let new = path.lookup(&change.new);
+            let event = match (old, new) {
+                (None, None) => return,
+                (Some(a), Some(b)) if a == b => return,
+                (None, Some(_)) => PathEvent::Added,
+                (Some(_), None) => PathEvent::Removed,
+                (Some(_), Some(_)) => PathEvent::Changed,
+            };
+            listener(&PathChange { path: path.clone(), event, kind: change.kind, version: change.version, old: old.cloned(), new: new.cloned() });
+        })
+    }
+
+    // Returns whether the subscription was still active.
+    pub fn unsubscribe(&self, id: Subscription) -> bool {
+        let mut listeners = self.shared.listeners.lock().unwrap_or_else(|e| e.into_inner());
+        let before = listeners.len();
+        listeners.retain(|(i, _)| *i != id);
+        listeners.len() != before
+    }
+
+    fn lock_writer(&self) -> std::sync::MutexGuard<'_, ()> {
+        self.shared.writer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Makes `new` current and notifies; the writer lock must be held.
+    fn swap(&self, new: Arc<Value>, kind: ChangeKind) -> u64 {
+        let (old, version) = {
+            let mut current = self.shared.current.write().unwrap_or_else(|e| e.into_inner());
+            if **current == *new {
+                return self.version();
+            }
+            let old = std::mem::replace(&mut *current, new.clone());
+            (old, self.shared.version.fetch_add(1, Ordering::AcqRel) + 1)
+        };
+        // copied so a listener can subscribe or unsubscribe
+        let listeners: Vec<Listener> = self.shared.listeners.lock().unwrap_or_else(|e| e.into_inner()).iter().map(|(_, l)| l.clone()).collect();
+        let change = DocumentChange { kind, version, old, new };
+        for listener in listeners {
+            listener(&change);
+        }
+        version
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::thread;
+
+    type Seen<T> = Arc<Mutex<Vec<T>>>;
+
+    // the list the test reads and a handle for the listener to fill it
+    fn recorder<T>() -> (Seen<T>, Seen<T>) {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        (seen.clone(), seen)
+    }
+
+    #[test]
+    fn snapshots_are_not_touched_by_later_changes() {
+        let doc = SharedFlowDocument::new(json!({"n": 1}));
+        let before = doc.snapshot();
+        assert_eq!(doc.update(|d| std::mem::replace(&mut d["n"], json!(2))), json!(1));
+        assert_eq!(*before, json!({"n": 1}));
+        assert_eq!(*doc.snapshot(), json!({"n": 2}));
+        assert_eq!(doc.version(), 1);
+
+        let failed: Result<(), &str> = doc.try_update(|d| {
+            d["n"] = json!(3);
+            Err("no")
+        });
+        assert!(failed.is_err());
+        assert_eq!((doc.snapshot()["n"].clone(), doc.version()), (json!(2), 1));
+    }
+
+    #[test]
+    fn subscribers_hear_every_change_in_order() {
+        let doc = SharedFlowDocument::new(json!({"n": 0}));
+        let (seen, sink) = recorder();
+        let id = doc.subscribe(move |c| sink.lock().unwrap().push((c.kind, c.version, c.old["n"].clone(), c.new["n"].clone())));
+        assert_eq!(doc.replace(json!({"n": 1})), 1);
+        // no difference, no change
+        assert_eq!(doc.replace(json!({"n": 1})), 1);
+        doc.update(|d| d["n"] = json!(1));
+        doc.update(|d| d["n"] = json!(2));
+        assert!(doc.unsubscribe(id));
+        assert!(!doc.unsubscribe(id));
+        doc.replace(json!({"n": 3}));
+        assert_eq!(*seen.lock().unwrap(), [(ChangeKind::Replaced, 1, json!(0), json!(1)), (ChangeKind::Updated, 2, json!(1), json!(2))]);
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        let doc = SharedFlowDocument::new(json!({"count": 0}));
+        let (seen, sink) = recorder();
+        doc.subscribe(move |c| sink.lock().unwrap().push(c.version));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let doc = doc.clone();
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        doc.update(|d| d["count"] = json!(d["count"].as_u64().unwrap() + 1));
+                    }
+                })
+            })
+            .collect();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+        assert_eq!(doc.snapshot()["count"], json!(200));
+        assert_eq!(*seen.lock().unwrap(), (1..=200).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn reload_from_the_source() {
+        let dir = std::env::temp_dir().join(format!("flowdoc-shared-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("doc.flow").display().to_string();
+        std::fs::write(&path, "level = info\n").unwrap();
+        let doc = SharedFlowDocument::load(&path).unwrap();
+        assert_eq!(doc.source(), Some(path.as_str()));
+        let (seen, sink) = recorder();
+        doc.subscribe(move |c| sink.lock().unwrap().push(c.kind));
+        assert!(!doc.reload().unwrap());
+        std::fs::write(&path, "level = debug\n").unwrap();
+        assert!(doc.reload().unwrap());
+        assert_eq!(doc.snapshot()["level"], json!("debug"));
+        assert_eq!(*seen.lock().unwrap(), [ChangeKind::Reloaded]);
+
+        std::fs::write(&path, "level = \"open\n").unwrap();
+        assert!(doc.reload().is_err());
+        assert_eq!(doc.snapshot()["level"], json!("debug"));
+        assert!(!SharedFlowDocument::new(json!({})).reload().unwrap());
+    }
+
+    #[test]
+    fn path_subscriptions() {
+        let doc = SharedFlowDocument::new(json!({"logging": {"level": "info"}, "other": 1}));
+        let level = FlowPath::parse("logging.level").unwrap();
+        let (seen, sink) = recorder();
+        doc.subscribe_path(&level, move |c| {
+            let new: Option<String> = c.new_as().map(Result::unwrap);
+            sink.lock().unwrap().push((c.event, c.version, c.old.clone(), new));
+        });
+        // elsewhere in the document
+        doc.update(|d| d["other"] = json!(2));
+        // at the path, above it and with the same value
+        doc.update(|d| d["logging"]["level"] = json!("debug"));
+        doc.replace(json!({"logging": {"level": "debug"}, "other": 2}));
+        doc.update(|d| d["logging"] = json!("off"));
+        doc.update(|d| d["logging"] = json!({"level": "warn", "file": "x"}));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                (PathEvent::Changed, 2, Some(json!("info")), Some("debug".to_string())),
+                (PathEvent::Removed, 3, Some(json!("debug")), None),
+                (PathEvent::Added, 4, None, Some("warn".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn typed_values() {
+        let doc = SharedFlowDocument::new(json!({"port": 80}));
+        let (seen, sink) = recorder();
+        doc.subscribe_path(&FlowPath::parse("port").unwrap(), move |c| {
+            let old: Option<u16> = c.old_as().map(Result::unwrap);
+            sink.lock().unwrap().push((old, c.new_as::<u16>().map(|r| r.is_err()), c.kind));
+        });
+        doc.replace(json!({"port": 8080}));
+        doc.update(|d| d["port"] = json!("http"));
+        assert_eq!(*seen.lock().unwrap(), [(Some(80), Some(false), ChangeKind::Replaced), (Some(8080), Some(true), ChangeKind::Updated)]);
+    }
+}
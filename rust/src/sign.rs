@@ -0,0 +1,182 @@
+use ed25519_dalek::{Signature, Signer};
+use serde_json::Value;
+use std::fmt;
+
+use crate::canonical::canonicalize;
+use crate::digest::to_hex;
+
+// ============================================
+// Document Signatures (Ed25519, RFC 8032)
+// ============================================
+//
+// Signatures cover `canonicalize(doc)`, so re-formatting a signed document
+// does not invalidate it. A detached signature is the string
+// `ed25519:<128 hex chars>`; an embedded one is stored under the top-level
+// `$signature` key, which is left out of the signed content.
+//
+// The curve arithmetic is ed25519-dalek's. Verification is strict: weak
+// public keys and non-canonical signatures are rejected.
+
+pub const SIGNATURE_KEY: &str = "$signature";
+const SIGNATURE_PREFIX: &str = "ed25519:";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    InvalidKey(String),
+    Malformed(String),
+    Missing,
+    Mismatch,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::InvalidKey(msg) => write!(f, "invalid key: {}", msg),
+            SignatureError::Malformed(msg) => write!(f, "malformed signature: {}", msg),
+            SignatureError::Missing => write!(f, "document has no {} entry", SIGNATURE_KEY),
+            SignatureError::Mismatch => write!(f, "signature does not match document"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+#[derive(Clone)]
+pub struct SigningKey(ed25519_dalek::SigningKey);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyingKey(ed25519_dalek::VerifyingKey);
+
+impl SigningKey {
+    // The 32-byte seed is the private key as defined by RFC 8032 (what
+    // `openssl genpkey -algorithm ed25519` stores).
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        SigningKey(ed25519_dalek::SigningKey::from_bytes(&seed))
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self, SignatureError> {
+        Ok(Self::from_seed(from_hex32(hex).map_err(SignatureError::InvalidKey)?))
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey(self.0.verifying_key())
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; 64] {
+        self.0.sign(message).to_bytes()
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKey").field("public", &self.verifying_key().to_hex()).finish_non_exhaustive()
+    }
+}
+
+impl VerifyingKey {
+    pub fn from_bytes(bytes: [u8; 32]) -> Result<Self, SignatureError> {
+        ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+            .map(VerifyingKey)
+            .map_err(|_| SignatureError::InvalidKey("not a valid curve point".to_string()))
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self, SignatureError> {
+        Self::from_bytes(from_hex32(hex).map_err(SignatureError::InvalidKey)?)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+
+    pub fn to_hex(&self) -> String {
+        to_hex(self.0.as_bytes())
+    }
+
+    pub fn verify(&self, message: &[u8], signature: &[u8; 64]) -> Result<(), SignatureError> {
+        self.0.verify_strict(message, &Signature::from_bytes(signature)).map_err(|_| SignatureError::Mismatch)
+    }
+}
+
+pub fn sign_flow(val: &Value, key: &SigningKey) -> String {
+    let sig = key.sign(canonicalize(&without_signature(val)).as_bytes());
+    format!("{}{}", SIGNATURE_PREFIX, to_hex(&sig))
+}
+
+pub fn verify_flow(val: &Value, signature: &str, key: &VerifyingKey) -> Result<(), SignatureError> {
+    let sig = parse_signature(signature)?;
+    key.verify(canonicalize(&without_signature(val)).as_bytes(), &sig)
+}
+
+// Returns a copy of `val` carrying its signature under `$signature`.
+pub fn sign_flow_embedded(val: &Value, key: &SigningKey) -> Value {
+    let signature = sign_flow(val, key);
+    let mut out = without_signature(val);
+    if let Value::Object(m) = &mut out {
+        m.insert(SIGNATURE_KEY.to_string(), Value::String(signature));
+    }
+    out
+}
+
+// Checks the embedded signature and returns the document without it.
+pub fn verify_flow_embedded(val: &Value, key: &VerifyingKey) -> Result<Value, SignatureError> {
+    let signature = val.get(SIGNATURE_KEY).and_then(Value::as_str).ok_or(SignatureError::Missing)?;
+    verify_flow(val, signature, key)?;
+    Ok(without_signature(val))
+}
+
+fn without_signature(val: &Value) -> Value {
+    let mut out = val.clone();
+    if let Value::Object(m) = &mut out {
+        m.remove(SIGNATURE_KEY);
+    }
+    out
+}
+
+fn parse_signature(text: &str) -> Result<[u8; 64], SignatureError> {
+    let hex = text.trim().strip_prefix(SIGNATURE_PREFIX).ok_or_else(|| SignatureError::Malformed(format!("expected '{}' prefix", SIGNATURE_PREFIX)))?;
+    let bytes = from_hex(hex).map_err(SignatureError::Malformed)?;
+    bytes.try_into().map_err(|_| SignatureError::Malformed("expected 64 bytes".to_string()))
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err("expected an even number of hex digits".to_string());
+    }
+    Ok((0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect())
+}
+
+fn from_hex32(hex: &str) -> Result<[u8; 32], String> {
+    from_hex(hex.trim())?.try_into().map_err(|_| "expected 32 bytes".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // RFC 8032, section 7.1, test 1
+    #[test]
+    fn rfc8032_vector() {
+        let key = SigningKey::from_hex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60").unwrap();
+        assert_eq!(key.verifying_key().to_hex(), "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+        let sig = key.sign(b"");
+        assert_eq!(
+            to_hex(&sig),
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+        );
+        assert_eq!(key.verifying_key().verify(b"", &sig), Ok(()));
+        assert_eq!(key.verifying_key().verify(b"x", &sig), Err(SignatureError::Mismatch));
+    }
+
+    #[test]
+    fn embedded_round_trip() {
+        let key = SigningKey::from_seed([7u8; 32]);
+        let doc = json!({"b": [1, 2.5, "x"], "a": {"k": "v"}});
+        let signed = sign_flow_embedded(&doc, &key);
+        assert_eq!(verify_flow_embedded(&signed, &key.verifying_key()), Ok(doc.clone()));
+        let mut tampered = signed.clone();
+        tampered["a"]["k"] = json!("w");
+        assert_eq!(verify_flow_embedded(&tampered, &key.verifying_key()), Err(SignatureError::Mismatch));
+        assert_eq!(verify_flow_embedded(&doc, &key.verifying_key()), Err(SignatureError::Missing));
+    }
+}
@@ -0,0 +1,107 @@
+use serde_json::Value;
+
+use crate::path::FlowPath;
+
+// ============================================
+// Document Statistics
+// ============================================
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TypeCounts {
+    pub objects: usize,
+    pub arrays: usize,
+    pub strings: usize,
+    pub integers: usize,
+    pub floats: usize,
+    pub bools: usize,
+    pub nulls: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlowStats {
+    // object keys across the whole document
+    pub key_count: usize,
+    // nesting depth of the deepest node; the root is depth 0
+    pub max_depth: usize,
+    pub array_elements: usize,
+    pub max_array_len: usize,
+    pub max_array_path: Option<FlowPath>,
+    // UTF-8 bytes of string values and of keys
+    pub string_bytes: usize,
+    pub key_bytes: usize,
+    pub types: TypeCounts,
+}
+
+pub fn stats(val: &Value) -> FlowStats {
+    let mut s = FlowStats::default();
+    collect(val, 0, &mut FlowPath::root(), &mut s);
+    s
+}
+
+fn collect(val: &Value, depth: usize, path: &mut FlowPath, s: &mut FlowStats) {
+    s.max_depth = s.max_depth.max(depth);
+    match val {
+        Value::Object(map) => {
+            s.types.objects += 1;
+            s.key_count += map.len();
+            for (k, v) in map {
+                s.key_bytes += k.len();
+                path.push_key(k);
+                collect(v, depth + 1, path, s);
+                path.pop();
+            }
+        }
+        Value::Array(items) => {
+            s.types.arrays += 1;
+            s.array_elements += items.len();
+            if items.len() > s.max_array_len || s.max_array_path.is_none() {
+                s.max_array_len = items.len();
+                s.max_array_path = Some(path.clone());
+            }
+            for (i, v) in items.iter().enumerate() {
+                path.push_index(i);
+                collect(v, depth + 1, path, s);
+                path.pop();
+            }
+        }
+        Value::String(st) => {
+            s.types.strings += 1;
+            s.string_bytes += st.len();
+        }
+        Value::Number(n) => {
+            if n.is_f64() {
+                s.types.floats += 1;
+            } else {
+                s.types.integers += 1;
+            }
+        }
+        Value::Bool(_) => s.types.bools += 1,
+        Value::Null => s.types.nulls += 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn counts_every_node() {
+        let doc = json!({ "name": "héllo", "ports": [80, 443, 8080], "db": { "hosts": ["a", "b"], "ratio": 0.5, "tls": true, "x": null } });
+        let s = stats(&doc);
+        assert_eq!((s.key_count, s.key_bytes), (7, 4 + 5 + 2 + 5 + 5 + 3 + 1));
+        assert_eq!(s.max_depth, 3);
+        assert_eq!((s.array_elements, s.max_array_len), (5, 3));
+        assert_eq!(s.max_array_path, Some(FlowPath::parse("ports").unwrap()));
+        assert_eq!(s.string_bytes, 6 + 1 + 1);
+        assert_eq!(s.types, TypeCounts { objects: 2, arrays: 2, strings: 3, integers: 3, floats: 1, bools: 1, nulls: 1 });
+    }
+
+    #[test]
+    fn empty_and_scalar_documents() {
+        assert_eq!(stats(&json!({})), FlowStats { types: TypeCounts { objects: 1, ..Default::default() }, ..Default::default() });
+        let s = stats(&json!({ "a": [] }));
+        assert_eq!((s.max_array_len, s.max_array_path), (0, Some(FlowPath::parse("a").unwrap())));
+        assert_eq!(stats(&json!(1)).max_depth, 0);
+    }
+}
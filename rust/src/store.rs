@@ -0,0 +1,165 @@
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::path::{FlowPath, PathError, PathGlob};
+use crate::save::{write_file, SaveOptions};
+
+// ============================================
+// Document Store
+// ============================================
+//
+// A FlowStore holds named documents in memory. Documents are addressed by
+// name, nodes inside them by FlowPath, and `query` runs a PathGlob over all
+// of them. The whole store can be saved to and restored from one .flowb
+// bundle: a map `{"$flowstore": 1, "documents": {name: document, ...}}`.
+
+const BUNDLE_MARKER: &str = "$flowstore";
+const BUNDLE_VERSION: u64 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlowStore {
+    documents: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryMatch<'a> {
+    pub document: &'a str,
+    pub path: FlowPath,
+    pub value: &'a Value,
+}
+
+impl FlowStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: &str, doc: Value) -> Option<Value> {
+        self.documents.insert(name.to_string(), doc)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.documents.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Value> {
+        self.documents.get_mut(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.documents.remove(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.documents.contains_key(name)
+    }
+
+    // Document names in sorted order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.documents.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    pub fn get_path(&self, name: &str, path: &FlowPath) -> Option<&Value> {
+        path.lookup(self.documents.get(name)?)
+    }
+
+    // Sets a value inside a document, creating the document if needed.
+    pub fn set_path(&mut self, name: &str, path: &FlowPath, value: Value) -> Result<Option<Value>, PathError> {
+        let doc = self.documents.entry(name.to_string()).or_insert_with(|| Value::Object(Map::new()));
+        path.set(doc, value)
+    }
+
+    // Matches across all documents, by document name and then document order.
+    pub fn query(&self, glob: &PathGlob) -> Vec<QueryMatch<'_>> {
+        self.documents
+            .iter()
+            .flat_map(|(name, doc)| {
+                glob.find(doc).into_iter().map(move |(path, value)| QueryMatch { document: name, path, value })
+            })
+            .collect()
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let bundle = json!({ BUNDLE_MARKER: BUNDLE_VERSION, "documents": self.documents });
+        let buf = rmp_serde::to_vec(&bundle)?;
+        write_file(Path::new(path), &buf, &SaveOptions::new().atomic(true))?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let data = fs::read(path)?;
+        let mut bundle: Value = rmp_serde::from_slice(&data)?;
+        match bundle.get(BUNDLE_MARKER).and_then(Value::as_u64) {
+            Some(BUNDLE_VERSION) => {}
+            Some(v) => return Err(format!("{}: unsupported store bundle version {}", path, v).into()),
+            None => return Err(format!("{}: not a store bundle", path).into()),
+        }
+        let documents = match bundle["documents"].take() {
+            Value::Object(map) => map.into_iter().collect(),
+            _ => return Err(format!("{}: store bundle has no documents", path).into()),
+        };
+        Ok(FlowStore { documents })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> FlowStore {
+        let mut store = FlowStore::new();
+        store.insert("web", json!({"server": {"port": 80}, "users": [{"name": "ann"}]}));
+        store.insert("api", json!({"server": {"port": 8080}}));
+        store
+    }
+
+    #[test]
+    fn paths_and_queries() {
+        let mut store = store();
+        assert_eq!(store.names().collect::<Vec<_>>(), ["api", "web"]);
+        let port = FlowPath::parse("server.port").unwrap();
+        assert_eq!(store.get_path("web", &port), Some(&json!(80)));
+        assert_eq!(store.get_path("db", &port), None);
+
+        assert_eq!(store.set_path("web", &port, json!(443)).unwrap(), Some(json!(80)));
+        assert_eq!(store.set_path("db", &port, json!(5432)).unwrap(), None);
+        assert_eq!(store.get("db"), Some(&json!({"server": {"port": 5432}})));
+
+        let found: Vec<(&str, String, &Value)> = store
+            .query(&PathGlob::parse("server.port").unwrap())
+            .into_iter()
+            .map(|m| (m.document, m.path.to_string(), m.value))
+            .collect();
+        assert_eq!(found, [("api", "server.port".to_string(), &json!(8080)), ("db", "server.port".to_string(), &json!(5432)), ("web", "server.port".to_string(), &json!(443))]);
+        assert_eq!(store.query(&PathGlob::parse("users.*.name").unwrap()).len(), 1);
+
+        assert!(store.remove("db").is_some());
+        assert!(!store.contains("db"));
+    }
+
+    #[test]
+    fn bundles_round_trip() {
+        let dir = std::env::temp_dir().join(format!("flowdoc-store-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("store.flowb").display().to_string();
+        let store = store();
+        store.save(&path).unwrap();
+        assert_eq!(FlowStore::load(&path).unwrap(), store);
+
+        fs::write(&path, rmp_serde::to_vec(&json!({"documents": {}})).unwrap()).unwrap();
+        assert!(FlowStore::load(&path).unwrap_err().to_string().ends_with("not a store bundle"));
+        fs::write(&path, rmp_serde::to_vec(&json!({"$flowstore": 2, "documents": {}})).unwrap()).unwrap();
+        assert!(FlowStore::load(&path).unwrap_err().to_string().ends_with("unsupported store bundle version 2"));
+        fs::write(&path, rmp_serde::to_vec(&json!({"$flowstore": 1, "documents": []})).unwrap()).unwrap();
+        assert!(FlowStore::load(&path).unwrap_err().to_string().ends_with("store bundle has no documents"));
+    }
+}
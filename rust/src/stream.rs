@@ -0,0 +1,435 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+
+use crate::cancel::{self, CancelToken, Cancelled};
+use crate::control;
+use crate::diagnostic::ParseError;
+use crate::encoding::{self, Encoding};
+use crate::limits::LineChecker;
+use crate::path::{FlowPath, PathSegment};
+use crate::{element_end, parse_value, strip_comment, ParseOptions};
+
+// ============================================
+// Array Streaming
+// ============================================
+//
+// Iterates over the elements of one array in a document without building the
+// document. In a .flow file the lines before the array are read and
+// discarded, and the array's line is split into elements as they are taken;
+// the first line matching the path is used. In a .flowb file the MessagePack
+// data is skipped up to the array header and each element is decoded only
+// when the iterator reaches it.
+//
+// v2::stream_array and v2::stream_flowb_array take a ParseOptions: its
+// CancelToken is checked while the file is scanned and before every element,
+// and a cancelled stream yields StreamError::Cancelled once and then ends.
+// Errors opening the stream come back as a FlowError. `lossy` lets a
+// .flow file with invalid UTF-8 be read with U+FFFD in its place, and
+// `control` and `limits` are applied to each line before it is looked at.
+// No more than `limits.max_line_len` bytes of a line are read into memory.
+
+#[derive(Debug)]
+pub enum StreamError {
+    Io(io::Error),
+    NotFound(FlowPath),
+    NotArray(FlowPath),
+    Invalid(String),
+    Cancelled(Cancelled),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Io(e) => write!(f, "{}", e),
+            StreamError::NotFound(path) => write!(f, "no value at '{}'", path),
+            StreamError::NotArray(path) => write!(f, "value at '{}' is not an array", path),
+            StreamError::Invalid(message) => write!(f, "invalid document: {}", message),
+            StreamError::Cancelled(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreamError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Cancelled> for StreamError {
+    fn from(e: Cancelled) -> Self {
+        StreamError::Cancelled(e)
+    }
+}
+
+impl From<io::Error> for StreamError {
+    fn from(e: io::Error) -> Self {
+        StreamError::Io(e)
+    }
+}
+
+pub struct ArrayStream {
+    source: Source,
+    cancel: Option<CancelToken>,
+}
+
+enum Source {
+    // the array's line and the byte offset of the next element in it
+    Text { line: String, pos: usize, done: bool },
+    Binary { reader: BufReader<File>, remaining: u32 },
+}
+
+impl Iterator for ArrayStream {
+    type Item = Result<Value, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(Err(e)) = self.cancel.as_ref().map(CancelToken::check) {
+            match &mut self.source {
+                Source::Text { done: true, .. } | Source::Binary { remaining: 0, .. } => return None,
+                Source::Text { done, .. } => *done = true,
+                Source::Binary { remaining, .. } => *remaining = 0,
+            }
+            return Some(Err(e.into()));
+        }
+        match &mut self.source {
+            Source::Text { line, pos, done } => {
+                if *done {
+                    return None;
+                }
+                let rest = &line[*pos..];
+                let (element, next) = match element_end(rest) {
+                    Some(i) => (&rest[..i], *pos + i + 1),
+                    None => {
+                        *done = true;
+                        (rest, line.len())
+                    }
+                };
+                let value = parse_value(element);
+                *pos = next;
+                Some(Ok(value))
+            }
+            Source::Binary { reader, remaining } => {
+                if *remaining == 0 {
+                    return None;
+                }
+                *remaining -= 1;
+                let mut de = rmp_serde::Deserializer::new(&mut *reader);
+                match Value::deserialize(&mut de) {
+                    Ok(v) => Some(Ok(v)),
+                    Err(e) => {
+                        *remaining = 0;
+                        Some(Err(StreamError::Invalid(e.to_string())))
+                    }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match &self.source {
+            Source::Binary { remaining, .. } => (*remaining as usize, Some(*remaining as usize)),
+            Source::Text { done: true, .. } => (0, Some(0)),
+            Source::Text { .. } => (1, None),
+        }
+    }
+}
+
+// ============================================
+// .flow
+// ============================================
+
+#[deprecated(note = "use v2::stream_array")]
+pub fn StreamFlowArray(path: &str, array: &FlowPath) -> Result<ArrayStream, StreamError> {
+    stream_array(path, array, &ParseOptions::default())
+}
+
+pub(crate) fn stream_array(path: &str, array: &FlowPath, options: &ParseOptions) -> Result<ArrayStream, StreamError> {
+    let cancel = options.cancel.as_ref();
+    let mut keys = Vec::new();
+    for segment in array.segments() {
+        match segment {
+            PathSegment::Key(k) => keys.push(k.clone()),
+            PathSegment::Index(_) => return Err(StreamError::NotArray(array.clone())),
+        }
+    }
+    let Some(target) = keys.pop() else {
+        return Err(StreamError::NotArray(array.clone()));
+    };
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let head = reader.fill_buf()?;
+    let (enc, bom) = encoding::detect_encoding(head);
+    let mut lines: Box<dyn BufRead> = if enc == Encoding::Utf8 {
+        reader.consume(bom);
+        Box::new(reader)
+    } else {
+        // UTF-16 is rare enough that decoding it up front is acceptable
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let (text, _) = encoding::decode(&bytes, options.lossy).map_err(|e| encoding::with_path(e, path))?;
+        Box::new(Cursor::new(text.into_bytes()))
+    };
+
+    // the same (indent, key path) stack as ParseFlow
+    let mut stack: Vec<(usize, Vec<String>)> = vec![(0, Vec::new())];
+    let mut checker = LineChecker::new(&options.limits);
+    let mut buf = Vec::new();
+    let mut offset = bom;
+    for n in 0.. {
+        cancel::checkpoint(cancel, n)?;
+        buf.clear();
+        // a line ending may follow the longest line allowed
+        let max = (options.limits.max_line_len as u64).saturating_add(2);
+        if (&mut lines).take(max).read_until(b'\n', &mut buf)? == 0 {
+            return Err(StreamError::NotFound(array.clone()));
+        }
+        let raw = match std::str::from_utf8(&buf) {
+            Ok(raw) => std::borrow::Cow::Borrowed(raw),
+            Err(_) if options.lossy => String::from_utf8_lossy(&buf),
+            Err(e) => return Err(StreamError::Invalid(format!("invalid UTF-8 at byte {}", offset + e.valid_up_to()))),
+        };
+        let invalid = |e: ParseError| StreamError::Invalid(format!("line {}, column {}: {}", n + 1, e.span.column, e.message));
+        let raw = control::apply(&raw, options.control).map_err(invalid)?;
+        checker.check(&raw).map_err(invalid)?;
+        offset += buf.len();
+        let line = strip_comment(&raw.replace('\t', "  ")).trim_end().to_string();
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let indent = line.chars().take_while(|c| c.is_whitespace()).count() / 2;
+        while stack.len() > 1 && stack.last().map(|(i, _)| *i).unwrap_or(0) > indent {
+            stack.pop();
+        }
+        let parent = stack.last().map(|(_, p)| p.clone()).unwrap_or_default();
+        if let Some(key) = trimmed.strip_suffix(':') {
+            let mut child = parent;
+            child.push(key.trim().to_string());
+            if child.len() == keys.len() + 1 && child[..keys.len()] == keys[..] && child[keys.len()] == target {
+                return Err(StreamError::NotArray(array.clone()));
+            }
+            stack.push((indent + 1, child));
+        } else if let Some(pos) = trimmed.find('=') {
+            if parent != keys || trimmed[..pos].trim() != target {
+                continue;
+            }
+            let value = trimmed[pos + 1..].trim();
+            let Some(inner) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) else {
+                return Err(StreamError::NotArray(array.clone()));
+            };
+            let inner = inner.trim().to_string();
+            let done = inner.is_empty();
+            return Ok(ArrayStream { source: Source::Text { line: inner, pos: 0, done }, cancel: options.cancel.clone() });
+        }
+    }
+    unreachable!("the line loop only ends by returning")
+}
+
+// ============================================
+// .flowb
+// ============================================
+
+#[deprecated(note = "use v2::stream_flowb_array")]
+pub fn StreamFlowbArray(path: &str, array: &FlowPath) -> Result<ArrayStream, StreamError> {
+    stream_flowb_array(path, array, &ParseOptions::default())
+}
+
+pub(crate) fn stream_flowb_array(path: &str, array: &FlowPath, options: &ParseOptions) -> Result<ArrayStream, StreamError> {
+    let cancel = options.cancel.as_ref();
+    let mut reader = BufReader::new(File::open(path)?);
+    for segment in array.segments() {
+        if let Some(token) = cancel {
+            token.check()?;
+        }
+        match segment {
+            PathSegment::Key(k) => {
+                let Some(len) = map_len(&mut reader)? else {
+                    return Err(StreamError::NotFound(array.clone()));
+                };
+                let mut found = false;
+                for n in 0..len as usize {
+                    cancel::checkpoint(cancel, n + 1)?;
+                    if read_key(&mut reader)?.as_deref() == Some(k.as_str()) {
+                        found = true;
+                        break;
+                    }
+                    skip(&mut reader)?;
+                }
+                if !found {
+                    return Err(StreamError::NotFound(array.clone()));
+                }
+            }
+            PathSegment::Index(i) => {
+                let Some(len) = array_len(&mut reader)? else {
+                    return Err(StreamError::NotFound(array.clone()));
+                };
+                if *i as u64 >= len as u64 {
+                    return Err(StreamError::NotFound(array.clone()));
+                }
+                for n in 0..*i {
+                    cancel::checkpoint(cancel, n + 1)?;
+                    skip(&mut reader)?;
+                }
+            }
+        }
+    }
+    match array_len(&mut reader)? {
+        Some(remaining) => Ok(ArrayStream { source: Source::Binary { reader, remaining }, cancel: options.cancel.clone() }),
+        None => Err(StreamError::NotArray(array.clone())),
+    }
+}
+
+// MessagePack decoding, just enough to walk to a path without decoding the
+// values along the way.
+
+fn byte(r: &mut impl Read) -> io::Result<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn be(r: &mut impl Read, n: usize) -> io::Result<u64> {
+    let mut v = 0u64;
+    for _ in 0..n {
+        v = (v << 8) | byte(r)? as u64;
+    }
+    Ok(v)
+}
+
+fn discard(r: &mut impl Read, n: u64) -> io::Result<()> {
+    let copied = io::copy(&mut r.take(n), &mut io::sink())?;
+    if copied < n {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated MessagePack data"));
+    }
+    Ok(())
+}
+
+// Returns the element count if the next value is a map, and otherwise skips it.
+fn map_len(r: &mut impl Read) -> io::Result<Option<u32>> {
+    let marker = byte(r)?;
+    match marker {
+        0x80..=0x8f => Ok(Some((marker & 0x0f) as u32)),
+        0xde => Ok(Some(be(r, 2)? as u32)),
+        0xdf => Ok(Some(be(r, 4)? as u32)),
+        _ => skip_after(r, marker).map(|_| None),
+    }
+}
+
+fn array_len(r: &mut impl Read) -> io::Result<Option<u32>> {
+    let marker = byte(r)?;
+    match marker {
+        0x90..=0x9f => Ok(Some((marker & 0x0f) as u32)),
+        0xdc => Ok(Some(be(r, 2)? as u32)),
+        0xdd => Ok(Some(be(r, 4)? as u32)),
+        _ => skip_after(r, marker).map(|_| None),
+    }
+}
+
+// Reads a map key; keys that are not strings are skipped and read as None.
+fn read_key(r: &mut impl Read) -> io::Result<Option<String>> {
+    let marker = byte(r)?;
+    let len = match marker {
+        0xa0..=0xbf => (marker & 0x1f) as u64,
+        0xd9 => be(r, 1)?,
+        0xda => be(r, 2)?,
+        0xdb => be(r, 4)?,
+        _ => return skip_after(r, marker).map(|_| None),
+    };
+    let mut bytes = Vec::new();
+    r.take(len).read_to_end(&mut bytes)?;
+    Ok(String::from_utf8(bytes).ok())
+}
+
+fn skip(r: &mut impl Read) -> io::Result<()> {
+    let marker = byte(r)?;
+    skip_after(r, marker)
+}
+
+fn skip_after(r: &mut impl Read, marker: u8) -> io::Result<()> {
+    let (items, bytes) = match marker {
+        0x00..=0x7f | 0xe0..=0xff | 0xc0 | 0xc2 | 0xc3 => (0, 0),
+        0x80..=0x8f => (2 * (marker & 0x0f) as u64, 0),
+        0x90..=0x9f => ((marker & 0x0f) as u64, 0),
+        0xa0..=0xbf => (0, (marker & 0x1f) as u64),
+        0xc4 | 0xd9 => (0, be(r, 1)?),
+        0xc5 | 0xda => (0, be(r, 2)?),
+        0xc6 | 0xdb => (0, be(r, 4)?),
+        0xc7 => (0, be(r, 1)? + 1),
+        0xc8 => (0, be(r, 2)? + 1),
+        0xc9 => (0, be(r, 4)? + 1),
+        0xcc | 0xd0 => (0, 1),
+        0xcd | 0xd1 => (0, 2),
+        0xca | 0xce | 0xd2 => (0, 4),
+        0xcb | 0xcf | 0xd3 => (0, 8),
+        0xd4 => (0, 2),
+        0xd5 => (0, 3),
+        0xd6 => (0, 5),
+        0xd7 => (0, 9),
+        0xd8 => (0, 17),
+        0xdc => (be(r, 2)?, 0),
+        0xdd => (be(r, 4)?, 0),
+        0xde => (2 * be(r, 2)?, 0),
+        0xdf => (2 * be(r, 4)?, 0),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid MessagePack marker 0x{:02x}", marker))),
+    };
+    discard(r, bytes)?;
+    for _ in 0..items {
+        skip(r)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+
+    fn scratch(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("flowdoc-stream-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name).display().to_string()
+    }
+
+    fn collect(stream: Result<ArrayStream, StreamError>) -> Vec<Value> {
+        stream.unwrap().map(|v| v.unwrap()).collect()
+    }
+
+    #[test]
+    fn flow_arrays() {
+        let path = scratch("doc.flow");
+        fs::write(&path, "rows = [0]\nbatch:\n  size = 3\n  rows = [1, \"two, three\", true] # trailing\nlast:\n  rows = []\n").unwrap();
+        let options = ParseOptions::default();
+        let rows = FlowPath::parse("batch.rows").unwrap();
+        assert_eq!(collect(stream_array(&path, &rows, &options)), vec![json!(1), json!("two, three"), json!(true)]);
+        assert_eq!(collect(stream_array(&path, &FlowPath::parse("rows").unwrap(), &options)), vec![json!(0)]);
+        assert!(collect(stream_array(&path, &FlowPath::parse("last.rows").unwrap(), &options)).is_empty());
+
+        let size = FlowPath::parse("batch.size").unwrap();
+        assert!(matches!(stream_array(&path, &size, &options), Err(StreamError::NotArray(_))));
+        assert!(matches!(stream_array(&path, &FlowPath::parse("batch").unwrap(), &options), Err(StreamError::NotArray(_))));
+        assert!(matches!(stream_array(&path, &FlowPath::parse("batch.cols").unwrap(), &options), Err(StreamError::NotFound(_))));
+    }
+
+    #[test]
+    fn flowb_arrays() {
+        let doc = json!({"name": "jobs", "jobs": [{"rows": [1]}, {"meta": {"k": [1, 2]}, "rows": [{"id": 1}, "x", null]}]});
+        let path = scratch("doc.flowb");
+        fs::write(&path, rmp_serde::to_vec(&doc).unwrap()).unwrap();
+        let options = ParseOptions::default();
+        let rows = FlowPath::parse("jobs[1].rows").unwrap();
+        let stream = stream_flowb_array(&path, &rows, &options).unwrap();
+        assert_eq!(stream.size_hint(), (3, Some(3)));
+        assert_eq!(Value::Array(stream.map(|v| v.unwrap()).collect()), doc["jobs"][1]["rows"]);
+        assert_eq!(collect(stream_flowb_array(&path, &FlowPath::parse("jobs").unwrap(), &options)).len(), 2);
+
+        assert!(matches!(stream_flowb_array(&path, &FlowPath::parse("name").unwrap(), &options), Err(StreamError::NotArray(_))));
+        assert!(matches!(stream_flowb_array(&path, &FlowPath::parse("jobs[2].rows").unwrap(), &options), Err(StreamError::NotFound(_))));
+        assert!(matches!(stream_flowb_array(&path, &FlowPath::parse("name.rows").unwrap(), &options), Err(StreamError::NotFound(_))));
+    }
+}
@@ -0,0 +1,163 @@
+use serde_json::{Map, Value};
+
+use crate::canonical::canonicalize;
+use crate::numbers::{write_number, FloatFormat};
+
+// ============================================
+// Deterministic Output
+// ============================================
+//
+// Output meant to be committed and diffed: keys are sorted explicitly (so
+// the result does not depend on how the map stores them), plain values come
+// before nested blocks, top-level blocks are separated by a blank line, and
+// every value has exactly one spelling. Strings are quoted whenever they
+// would otherwise read back as something else; numbers are written as by
+// the default writer with `plain_floats`, after the same rounding to
+// FloatFormat::max_precision, so floats keep a digit after the point; array
+// elements are separated by ", ", and those holding a comma are quoted.
+
+pub(crate) fn write_deterministic(val: &Value, floats: &FloatFormat) -> String {
+    let mut out = String::new();
+    if let Value::Object(m) = val {
+        write_object(m, 0, floats, &mut out);
+    }
+    out
+}
+
+fn write_object(map: &Map<String, Value>, indent: usize, floats: &FloatFormat, out: &mut String) {
+    let pad = " ".repeat(indent);
+    let mut entries: Vec<(&String, &Value)> = map.iter().filter(|(_, v)| !v.is_null()).collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    let (blocks, values): (Vec<_>, Vec<_>) = entries.into_iter().partition(|(_, v)| v.is_object());
+    for (k, v) in values {
+        out.push_str(&format!("{}{} = {}\n", pad, k, scalar_with(v, floats)));
+    }
+    for (k, v) in blocks {
+        if indent == 0 && !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!("{}{}:\n", pad, k));
+        if let Value::Object(m) = v {
+            write_object(m, indent + 2, floats, out);
+        }
+    }
+}
+
+pub(crate) fn scalar(v: &Value) -> String {
+    scalar_with(v, &FloatFormat::default())
+}
+
+fn scalar_with(v: &Value, floats: &FloatFormat) -> String {
+    match v {
+        Value::String(s) => string(s),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => match write_number(n, &FloatFormat { plain: true, ..*floats }) {
+            // -0.0 compares equal to 0.0, so it is written the same way
+            text if text == "-0.0" => "0.0".to_string(),
+            text => text,
+        },
+        Value::Array(items) => {
+            let parts: Vec<String> = items.iter().map(|e| element(e, floats)).collect();
+            format!("[{}]", parts.join(", "))
+        }
+        Value::Null => "null".to_string(),
+        Value::Object(_) => canonicalize(v),
+    }
+}
+
+fn element(v: &Value, floats: &FloatFormat) -> String {
+    match v {
+        Value::String(s) if s.contains(',') || s.contains(']') => format!("\"{}\"", s),
+        Value::Object(_) | Value::Array(_) => canonicalize(v),
+        _ => scalar_with(v, floats),
+    }
+}
+
+fn string(s: &str) -> String {
+    if needs_quotes(s) {
+        format!("\"{}\"", s)
+    } else {
+        s.to_string()
+    }
+}
+
+// Would the bare text parse back as something other than this string?
+fn needs_quotes(s: &str) -> bool {
+    s.is_empty()
+        || s.trim() != s
+        || s.contains(char::is_whitespace)
+        || s.contains('#')
+        || s.starts_with('"')
+        || s.starts_with('[')
+        || s.ends_with(':')
+        || s == "true"
+        || s == "false"
+        || s.parse::<f64>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_document;
+    use serde_json::json;
+
+    fn round_trip(val: Value) {
+        let text = write_deterministic(&val, &FloatFormat::default());
+        assert_eq!(parse_document(&text), val, "written as:\n{}", text);
+    }
+
+    #[test]
+    fn review_case() {
+        let val = json!({"arr": ["a,b"], "f": 1.0, "u": "http:"});
+        assert_eq!(write_deterministic(&val, &FloatFormat::default()), "arr = [\"a,b\"]\nf = 1.0\nu = \"http:\"\n");
+        round_trip(val);
+    }
+
+    #[test]
+    fn strings_that_look_like_something_else() {
+        round_trip(json!({
+            "empty": "", "padded": " x ", "spaced": "a b", "comment": "a#b", "bool": "true", "int": "12",
+            "float": "1.5", "bracket": "[x", "quote": "\"q", "section": "key:", "url": "https://example.com/a?b=c",
+        }));
+    }
+
+    #[test]
+    fn numbers() {
+        round_trip(json!({"zero": 0.0, "one": 1.0, "neg": -2.5, "small": 1e-7, "big": 1e300, "int": 42, "max": i64::MAX, "min": i64::MIN}));
+    }
+
+    #[test]
+    fn arrays() {
+        round_trip(json!({
+            "empty": [], "mixed": [1, 2.0, "a,b", "x y", true, "1", "", "c"], "commas": [",", "a,", ",b", "a, b"],
+        }));
+    }
+
+    #[test]
+    fn nested_blocks() {
+        round_trip(json!({"b": {"y": {"deep": [1, 2]}, "x": "v"}, "a": 1, "c": {}}));
+    }
+
+    #[test]
+    fn output_is_sorted_and_stable() {
+        let a = json!({"b": 1, "a": {"d": 1.5, "c": "x"}});
+        let b = json!({"a": {"c": "x", "d": 1.5}, "b": 1});
+        let text = write_deterministic(&a, &FloatFormat::default());
+        assert_eq!(text, "b = 1\n\na:\n  c = x\n  d = 1.5\n");
+        assert_eq!(text, write_deterministic(&b, &FloatFormat::default()));
+    }
+
+    #[test]
+    fn rounding_matches_the_default_writer() {
+        let val = json!({"a": 2.001, "b": 0.126, "c": -0.001, "d": 1e21, "e": 7});
+        let floats = FloatFormat { max_precision: Some(2), plain: false };
+        let text = write_deterministic(&val, &floats);
+        assert_eq!(text, "a = 2.0\nb = 0.13\nc = 0.0\nd = 1000000000000000000000.0\ne = 7\n");
+        assert_eq!(parse_document(&text), json!({"a": 2.0, "b": 0.13, "c": 0.0, "d": 1e21, "e": 7}));
+        for key in ["a", "b", "d", "e"] {
+            let one = json!({ key: val[key] });
+            assert_eq!(write_deterministic(&one, &floats), crate::write_flow(&one, &floats));
+        }
+    }
+
+}
@@ -0,0 +1,154 @@
+use serde_json::{Map, Value};
+
+// ============================================
+// Summaries
+// ============================================
+//
+// Short views of a document for log lines and listings. `summarize` keeps
+// the first `max_items` entries of each object, in key order, and elements
+// of each array; the rest are counted by a marker: an array ends in
+// "… N more" and an object gets a MORE_KEY ("…") entry with the same text.
+// An object or array more than `depth` levels below the root is collapsed to
+// "{… N keys}" or "[… N items]"; the root itself never is.
+//
+// `summary_line` renders the same view on one line, e.g.
+// `{db: {… 2 keys}, name: api, ports: [80, 443, … 3 more]}`. Strings that
+// would not read back on their own, including any with a control character,
+// are written as JSON strings.
+
+pub const MORE_KEY: &str = "…";
+
+pub fn summarize(val: &Value, depth: usize, max_items: usize) -> Value {
+    summarize_at(val, 0, depth, max_items)
+}
+
+fn summarize_at(val: &Value, level: usize, depth: usize, max_items: usize) -> Value {
+    match val {
+        Value::Object(map) if level > depth && !map.is_empty() => Value::String(collapsed(val)),
+        Value::Array(items) if level > depth && !items.is_empty() => Value::String(collapsed(val)),
+        Value::Object(map) => {
+            let mut out: Map<String, Value> =
+                map.iter().take(max_items).map(|(k, v)| (k.clone(), summarize_at(v, level + 1, depth, max_items))).collect();
+            if map.len() > max_items {
+                out.insert(MORE_KEY.to_string(), Value::String(more(map.len() - max_items)));
+            }
+            Value::Object(out)
+        }
+        Value::Array(items) => {
+            let mut out: Vec<Value> = items.iter().take(max_items).map(|v| summarize_at(v, level + 1, depth, max_items)).collect();
+            if items.len() > max_items {
+                out.push(Value::String(more(items.len() - max_items)));
+            }
+            Value::Array(out)
+        }
+        _ => val.clone(),
+    }
+}
+
+pub fn summary_line(val: &Value, depth: usize, max_items: usize) -> String {
+    let mut out = String::new();
+    write_line(val, 0, depth, max_items, &mut out);
+    out
+}
+
+fn write_line(val: &Value, level: usize, depth: usize, max_items: usize, out: &mut String) {
+    match val {
+        Value::Object(map) if level > depth && !map.is_empty() => out.push_str(&collapsed(val)),
+        Value::Array(items) if level > depth && !items.is_empty() => out.push_str(&collapsed(val)),
+        Value::Object(map) => {
+            out.push('{');
+            for (i, (k, v)) in map.iter().take(max_items).enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(&text(k));
+                out.push_str(": ");
+                write_line(v, level + 1, depth, max_items, out);
+            }
+            if map.len() > max_items {
+                out.push_str(if max_items > 0 { ", " } else { "" });
+                out.push_str(&more(map.len() - max_items));
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, v) in items.iter().take(max_items).enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_line(v, level + 1, depth, max_items, out);
+            }
+            if items.len() > max_items {
+                out.push_str(if max_items > 0 { ", " } else { "" });
+                out.push_str(&more(items.len() - max_items));
+            }
+            out.push(']');
+        }
+        Value::String(s) => out.push_str(&text(s)),
+        _ => out.push_str(&val.to_string()),
+    }
+}
+
+fn more(n: usize) -> String {
+    format!("… {} more", n)
+}
+
+fn collapsed(val: &Value) -> String {
+    match val {
+        Value::Object(map) => format!("{{… {} {}}}", map.len(), if map.len() == 1 { "key" } else { "keys" }),
+        Value::Array(items) => format!("[… {} {}]", items.len(), if items.len() == 1 { "item" } else { "items" }),
+        _ => val.to_string(),
+    }
+}
+
+fn text(s: &str) -> String {
+    let plain = !s.is_empty()
+        && s.trim() == s
+        && !s.chars().any(|c| c.is_control() || matches!(c, '"' | ',' | ':' | '{' | '}' | '[' | ']'))
+        && s != "true"
+        && s != "false"
+        && s != "null"
+        && s.parse::<f64>().is_err();
+    if plain {
+        s.to_string()
+    } else {
+        serde_json::to_string(s).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn doc() -> Value {
+        json!({"db": {"host": "h", "pool": {"size": 4}}, "name": "api", "ports": [80, 443, 8080, 8443, 9000]})
+    }
+
+    #[test]
+    fn summaries() {
+        assert_eq!(summarize(&doc(), 0, 2), json!({"db": "{… 2 keys}", "name": "api", MORE_KEY: "… 1 more"}));
+        assert_eq!(
+            summarize(&doc(), 1, 2),
+            json!({"db": {"host": "h", "pool": "{… 1 key}"}, "name": "api", MORE_KEY: "… 1 more"})
+        );
+        assert_eq!(summarize(&doc()["ports"], 0, 3), json!([80, 443, 8080, "… 2 more"]));
+        assert_eq!(summarize(&doc(), 5, 10), doc());
+        assert_eq!(summarize(&json!({"e": {}, "l": []}), 0, 1), json!({"e": {}, MORE_KEY: "… 1 more"}));
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(summary_line(&doc(), 0, 2), "{db: {… 2 keys}, name: api, … 1 more}");
+        assert_eq!(summary_line(&doc(), 1, 3), "{db: {host: h, pool: {… 1 key}}, name: api, ports: [80, 443, 8080, … 2 more]}");
+        assert_eq!(summary_line(&doc(), 0, 0), "{… 3 more}");
+        assert_eq!(summary_line(&json!([[1], []]), 0, 5), "[[… 1 item], []]");
+    }
+
+    #[test]
+    fn strings_that_need_quotes() {
+        let val = json!(["plain text", "", " padded", "a, b", "true", "1.5", "x\u{1b}[2J", "null", "é"]);
+        assert_eq!(summary_line(&val, 1, 10), r#"[plain text, "", " padded", "a, b", "true", "1.5", "x\u001b[2J", "null", é]"#);
+    }
+}
@@ -0,0 +1,262 @@
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::encoding::read_text;
+use crate::parse_document;
+
+// ============================================
+// Template Documents
+// ============================================
+//
+// A template declares its parameters in a `$params` section, either as
+// `name = type` or as a block with `type` and an optional `default`:
+//
+//   $params:
+//     env = string
+//     replicas:
+//       type = int
+//       default = 1
+//
+// Everywhere else `${name}` is substituted. A value that is exactly
+// `${name}` takes the parameter's typed value; inside longer strings and keys
+// the parameter is spliced in as text. `$${` renders a literal `${`.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateError {
+    InvalidParam { name: String, message: String },
+    MissingParam(String),
+    UnknownParam(String),
+    TypeMismatch { name: String, expected: String, found: Value },
+    UndeclaredReference(String),
+    Unterminated(String),
+    Io(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::InvalidParam { name, message } => write!(f, "invalid declaration of parameter '{}': {}", name, message),
+            TemplateError::MissingParam(name) => write!(f, "missing value for parameter '{}'", name),
+            TemplateError::UnknownParam(name) => write!(f, "unknown parameter '{}'", name),
+            TemplateError::TypeMismatch { name, expected, found } => write!(f, "parameter '{}' expects {}, got {}", name, expected, found),
+            TemplateError::UndeclaredReference(name) => write!(f, "'${{{}}}' does not name a declared parameter", name),
+            TemplateError::Unterminated(text) => write!(f, "unterminated '${{' in '{}'", text),
+            TemplateError::Io(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateParam {
+    pub name: String,
+    pub param_type: String,
+    pub default: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub params: Vec<TemplateParam>,
+    pub body: Value,
+}
+
+const PARAM_TYPES: &[&str] = &["string", "int", "float", "bool", "date", "datetime", "array", "object", "any"];
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Self, TemplateError> {
+        Self::from_value(parse_document(text))
+    }
+
+    pub fn load(path: &str) -> Result<Self, TemplateError> {
+        let text = read_text(path).map_err(|e| TemplateError::Io(format!("cannot read '{}': {}", path, e)))?;
+        Self::parse(&text)
+    }
+
+    pub fn from_value(mut doc: Value) -> Result<Self, TemplateError> {
+        let mut params = Vec::new();
+        if let Some(Value::Object(decls)) = doc.as_object_mut().and_then(|m| m.remove("$params")) {
+            for (name, decl) in decls {
+                params.push(parse_param(name, decl)?);
+            }
+        }
+        let template = Template { params, body: doc };
+        for p in &template.params {
+            if let Some(d) = &p.default {
+                check_type(&p.name, &p.param_type, d)?;
+            }
+        }
+        Ok(template)
+    }
+
+    pub fn param(&self, name: &str) -> Option<&TemplateParam> {
+        self.params.iter().find(|p| p.name == name)
+    }
+}
+
+fn parse_param(name: String, decl: Value) -> Result<TemplateParam, TemplateError> {
+    let (param_type, default) = match decl {
+        Value::String(t) => (t, None),
+        Value::Object(mut m) => {
+            let t = match m.remove("type") {
+                Some(Value::String(t)) => t,
+                None => "any".to_string(),
+                Some(other) => return Err(TemplateError::InvalidParam { name, message: format!("type must be a name, got {}", other) }),
+            };
+            (t, m.remove("default"))
+        }
+        other => return Err(TemplateError::InvalidParam { name, message: format!("expected a type or a block, got {}", other) }),
+    };
+    if !PARAM_TYPES.contains(&param_type.as_str()) {
+        return Err(TemplateError::InvalidParam { name, message: format!("unknown type '{}'", param_type) });
+    }
+    Ok(TemplateParam { name, param_type, default })
+}
+
+fn check_type(name: &str, expected: &str, val: &Value) -> Result<(), TemplateError> {
+    if type_matches(expected, val) {
+        Ok(())
+    } else {
+        Err(TemplateError::TypeMismatch { name: name.to_string(), expected: expected.to_string(), found: val.clone() })
+    }
+}
+
+// Unknown type names match anything.
+pub(crate) fn type_matches(expected: &str, val: &Value) -> bool {
+    match expected {
+        "string" => val.is_string(),
+        "int" => val.is_i64() || val.is_u64(),
+        "float" => val.is_number(),
+        "bool" => val.is_boolean(),
+        "date" => val.as_str().map(is_date).unwrap_or(false),
+        "datetime" => val.as_str().map(is_datetime).unwrap_or(false),
+        "array" => val.is_array(),
+        "object" => val.is_object(),
+        _ => true,
+    }
+}
+
+fn is_date(s: &str) -> bool {
+    let b = s.as_bytes();
+    b.len() == 10 && b[4] == b'-' && b[7] == b'-' && b.iter().enumerate().all(|(i, c)| i == 4 || i == 7 || c.is_ascii_digit())
+}
+
+fn is_datetime(s: &str) -> bool {
+    s.len() >= 19 && s.get(..10).is_some_and(is_date) && s.as_bytes()[10] == b'T'
+}
+
+pub fn render(template: &Template, params: &HashMap<String, Value>) -> Result<Value, TemplateError> {
+    for name in params.keys() {
+        if template.param(name).is_none() {
+            return Err(TemplateError::UnknownParam(name.clone()));
+        }
+    }
+    let mut values = HashMap::new();
+    for p in &template.params {
+        let v = match params.get(&p.name).or(p.default.as_ref()) {
+            Some(v) => v.clone(),
+            None => return Err(TemplateError::MissingParam(p.name.clone())),
+        };
+        check_type(&p.name, &p.param_type, &v)?;
+        values.insert(p.name.clone(), v);
+    }
+    substitute_value(&template.body, &|name| values.get(name).cloned())
+}
+
+pub(crate) fn substitute_value<F>(val: &Value, lookup: &F) -> Result<Value, TemplateError>
+where
+    F: Fn(&str) -> Option<Value>,
+{
+    match val {
+        Value::String(s) => interpolate(s, lookup),
+        Value::Array(items) => items.iter().map(|v| substitute_value(v, lookup)).collect::<Result<Vec<_>, _>>().map(Value::Array),
+        Value::Object(map) => {
+            let mut out = Map::new();
+            for (k, v) in map {
+                out.insert(interpolate_text(k, lookup)?, substitute_value(v, lookup)?);
+            }
+            Ok(Value::Object(out))
+        }
+        _ => Ok(val.clone()),
+    }
+}
+
+// `${name}` on its own keeps the parameter's type; anything else is text.
+pub(crate) fn interpolate<F>(s: &str, lookup: &F) -> Result<Value, TemplateError>
+where
+    F: Fn(&str) -> Option<Value>,
+{
+    if let Some(name) = s.strip_prefix("${").and_then(|r| r.strip_suffix('}')) {
+        if !name.contains('}') && !name.contains("${") {
+            return lookup(name.trim()).ok_or_else(|| TemplateError::UndeclaredReference(name.trim().to_string()));
+        }
+    }
+    interpolate_text(s, lookup).map(Value::String)
+}
+
+pub(crate) fn interpolate_text<F>(s: &str, lookup: &F) -> Result<String, TemplateError>
+where
+    F: Fn(&str) -> Option<Value>,
+{
+    if !s.contains("${") {
+        return Ok(s.to_string());
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find("${") {
+        if rest[..pos].ends_with('$') {
+            out.push_str(&rest[..pos - 1]);
+            out.push_str("${");
+            rest = &rest[pos + 2..];
+            continue;
+        }
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 2..];
+        let end = after.find('}').ok_or_else(|| TemplateError::Unterminated(s.to_string()))?;
+        let name = after[..end].trim();
+        match lookup(name) {
+            Some(Value::String(v)) => out.push_str(&v),
+            Some(v @ (Value::Number(_) | Value::Bool(_))) => out.push_str(&v.to_string()),
+            Some(Value::Null) => {}
+            Some(other) => {
+                return Err(TemplateError::TypeMismatch { name: name.to_string(), expected: "a scalar inside text".to_string(), found: other })
+            }
+            None => return Err(TemplateError::UndeclaredReference(name.to_string())),
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render_one(param_type: &str, value: Value) -> Result<Value, TemplateError> {
+        let template = Template::parse(&format!("$params:\n  p = {}\nv = ${{p}}\n", param_type)).unwrap();
+        render(&template, &HashMap::from([("p".to_string(), value)]))
+    }
+
+    #[test]
+    fn datetime_params() {
+        assert_eq!(render_one("datetime", json!("2024-05-01T10:00:00Z")).unwrap(), json!({"v": "2024-05-01T10:00:00Z"}));
+        assert!(matches!(render_one("datetime", json!("2024-05-01 10:00:00")), Err(TemplateError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn non_ascii_params() {
+        assert!(matches!(render_one("datetime", json!("aéééééééééé")), Err(TemplateError::TypeMismatch { .. })));
+        assert!(matches!(render_one("date", json!("20é4-05-01")), Err(TemplateError::TypeMismatch { .. })));
+        assert_eq!(render_one("string", json!("naïve — ünïcode")).unwrap(), json!({"v": "naïve — ünïcode"}));
+    }
+
+    #[test]
+    fn non_ascii_text_interpolation() {
+        let template = Template::parse("$params:\n  name = string\ngreeting = héllo ${name}, ça va?\n").unwrap();
+        let out = render(&template, &HashMap::from([("name".to_string(), json!("Zoë"))])).unwrap();
+        assert_eq!(out, json!({"greeting": "héllo Zoë, ça va?"}));
+    }
+}
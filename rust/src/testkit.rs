@@ -0,0 +1,469 @@
+//! Helpers for testing code built on Flow documents: seeded generators of
+//! arbitrary documents and of model instances, round-trip checks and fixture
+//! loading.
+
+use serde_json::{Map, Number, Value};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::path::FlowPath;
+use crate::{parse_document, stringify_document, FieldDefinition, ModelDefinition};
+
+// ============================================
+// Generation
+// ============================================
+//
+// The .flow text format does not preserve every JSON value: nulls are
+// dropped, integral floats read back as integers, array elements must be
+// scalars without `,` or `]`, and strings that look like numbers or
+// booleans, end in `:` or have leading or trailing spaces read back
+// differently.
+// With `text_safe` set (the default) the generator stays inside what text
+// round-trips exactly; without it any JSON value can appear, which only
+// .flowb preserves.
+
+// SplitMix64, so a failing case can be replayed from its seed.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in `0..n`; `n` must not be 0.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    pub fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GenOptions {
+    pub max_depth: usize,
+    // most keys per object and elements per array
+    pub max_width: usize,
+    pub text_safe: bool,
+}
+
+impl Default for GenOptions {
+    fn default() -> Self {
+        GenOptions { max_depth: 4, max_width: 6, text_safe: true }
+    }
+}
+
+// A document: always an object at the root.
+pub fn arbitrary_document(rng: &mut Rng, options: &GenOptions) -> Value {
+    arbitrary_object(rng, options, 0)
+}
+
+fn arbitrary_object(rng: &mut Rng, options: &GenOptions, depth: usize) -> Value {
+    let mut map = Map::new();
+    for _ in 0..rng.below(options.max_width as u64 + 1) {
+        let key = arbitrary_key(rng);
+        let value = if depth < options.max_depth && rng.chance(30) {
+            arbitrary_object(rng, options, depth + 1)
+        } else if rng.chance(20) {
+            arbitrary_array(rng, options, depth + 1)
+        } else {
+            arbitrary_scalar(rng, options, false)
+        };
+        map.insert(key, value);
+    }
+    Value::Object(map)
+}
+
+fn arbitrary_array(rng: &mut Rng, options: &GenOptions, depth: usize) -> Value {
+    let items = (0..rng.below(options.max_width as u64 + 1))
+        .map(|_| {
+            if !options.text_safe && depth < options.max_depth && rng.chance(20) {
+                if rng.chance(50) {
+                    arbitrary_object(rng, options, depth + 1)
+                } else {
+                    arbitrary_array(rng, options, depth + 1)
+                }
+            } else {
+                arbitrary_scalar(rng, options, true)
+            }
+        })
+        .collect();
+    Value::Array(items)
+}
+
+fn arbitrary_scalar(rng: &mut Rng, options: &GenOptions, in_array: bool) -> Value {
+    match rng.below(if options.text_safe { 4 } else { 5 }) {
+        0 => Value::Bool(rng.chance(50)),
+        1 => Value::Number(((rng.next_u64() >> 40) as i64 - (1 << 23)).into()),
+        2 => arbitrary_float(rng),
+        3 => Value::String(arbitrary_string(rng, options.text_safe, in_array)),
+        _ => Value::Null,
+    }
+}
+
+// a non-integral value with a short decimal form
+fn arbitrary_float(rng: &mut Rng) -> Value {
+    let f = ((rng.next_u64() >> 44) as i64 - (1 << 19)) as f64 + 0.25 * (1 + rng.below(3)) as f64;
+    Number::from_f64(f).map(Value::Number).unwrap_or(Value::Null)
+}
+
+fn arbitrary_key(rng: &mut Rng) -> String {
+    const FIRST: &[u8] = b"abcdefghijklmnopqrstuvwxyz_";
+    const REST: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789_";
+    let mut key = String::new();
+    key.push(FIRST[rng.below(FIRST.len() as u64) as usize] as char);
+    for _ in 0..rng.below(8) {
+        key.push(REST[rng.below(REST.len() as u64) as usize] as char);
+    }
+    key
+}
+
+fn arbitrary_string(rng: &mut Rng, text_safe: bool, in_array: bool) -> String {
+    const SAFE: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_-./:@ ";
+    const ANY: &[&str] = &["a", "Z", "7", " ", ",", "]", "[", "#", "\"", "=", ":", "\t", "\\", "é", "日", "🙂"];
+    let len = rng.below(12) as usize;
+    if !text_safe {
+        return (0..len).map(|_| ANY[rng.below(ANY.len() as u64) as usize]).collect();
+    }
+    // starts with a letter so it never reads as a number or a boolean
+    let mut s = String::from((b'a' + rng.below(26) as u8) as char);
+    for _ in 0..len {
+        let c = SAFE[rng.below(SAFE.len() as u64) as usize] as char;
+        if c == ' ' && in_array {
+            continue;
+        }
+        s.push(c);
+    }
+    // a trailing ':' would read as a section header
+    let trimmed = s.trim_end().trim_end_matches(':');
+    if trimmed == "true" || trimmed == "false" { format!("{}x", trimmed) } else { trimmed.to_string() }
+}
+
+// Runs `check` on `cases` documents generated from `seed`. A panic inside
+// `check` is reported with the seed and case number that reproduce it.
+pub fn for_each_document(seed: u64, cases: usize, options: &GenOptions, mut check: impl FnMut(&Value)) {
+    let mut rng = Rng::new(seed);
+    for case in 0..cases {
+        let doc = arbitrary_document(&mut rng, options);
+        let outcome = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| check(&doc)));
+        if let Err(panic) = outcome {
+            eprintln!("failing case {} for seed {}: {}", case, seed, doc);
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+// ============================================
+// Model Instances
+// ============================================
+//
+// `model_document` generates a document that `validate` accepts for the
+// model: `use_model` names it, every required field is set and each optional
+// one about half the time, all under their full names or, in some documents,
+// all under their aliases. A field with choices takes one of them; otherwise
+// the value fits the field's type, with `array`, `object` and untyped fields
+// filled like arbitrary_document output from which any key naming a field of
+// the model is removed, so nested objects are never taken for instances.
+// Fields are visited in name order, so a seed always gives the same document.
+
+pub fn model_document(rng: &mut Rng, model: &ModelDefinition, options: &GenOptions) -> Value {
+    let mut fields: Vec<&FieldDefinition> = model.fields.values().collect();
+    fields.sort_by(|a, b| a.full_name.cmp(&b.full_name));
+    let aliased = rng.chance(30);
+    let mut map = Map::new();
+    map.insert("use_model".to_string(), Value::String(model.name.clone()));
+    for field in fields {
+        if !field.required && rng.chance(50) {
+            continue;
+        }
+        let key = if aliased && !field.alias.is_empty() { &field.alias } else { &field.full_name };
+        map.insert(key.clone(), field_value(rng, model, field, options));
+    }
+    Value::Object(map)
+}
+
+// Runs `check` on `cases` instances of `model`, reporting a panic like
+// for_each_document.
+pub fn for_each_model_document(seed: u64, cases: usize, model: &ModelDefinition, options: &GenOptions, mut check: impl FnMut(&Value)) {
+    let mut rng = Rng::new(seed);
+    for case in 0..cases {
+        let doc = model_document(&mut rng, model, options);
+        let outcome = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| check(&doc)));
+        if let Err(panic) = outcome {
+            eprintln!("failing case {} for seed {} of model '{}': {}", case, seed, model.name, doc);
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+fn field_value(rng: &mut Rng, model: &ModelDefinition, field: &FieldDefinition, options: &GenOptions) -> Value {
+    if !field.choices.is_empty() {
+        return field.choices[rng.below(field.choices.len() as u64) as usize].clone();
+    }
+    let text_safe = GenOptions { text_safe: true, ..options.clone() };
+    let mut val = match field.field_type.as_str() {
+        "string" => Value::String(arbitrary_string(rng, options.text_safe, false)),
+        "int" => Value::Number(((rng.next_u64() >> 40) as i64 - (1 << 23)).into()),
+        "float" => arbitrary_float(rng),
+        "bool" => Value::Bool(rng.chance(50)),
+        "date" => Value::String(arbitrary_date(rng)),
+        "datetime" => Value::String(format!("{}T{:02}:{:02}:{:02}Z", arbitrary_date(rng), rng.below(24), rng.below(60), rng.below(60))),
+        "array" => arbitrary_array(rng, options, 1),
+        "object" => arbitrary_object(rng, options, 1),
+        _ if rng.chance(20) => arbitrary_object(rng, options, 1),
+        _ => arbitrary_scalar(rng, &text_safe, false),
+    };
+    strip_fields(&mut val, model);
+    val
+}
+
+fn arbitrary_date(rng: &mut Rng) -> String {
+    format!("{:04}-{:02}-{:02}", 1970 + rng.below(130), 1 + rng.below(12), 1 + rng.below(28))
+}
+
+fn strip_fields(val: &mut Value, model: &ModelDefinition) {
+    match val {
+        Value::Object(map) => {
+            map.retain(|k, _| crate::validate::model_field(model, k).is_none());
+            map.values_mut().for_each(|v| strip_fields(v, model));
+        }
+        Value::Array(items) => items.iter_mut().for_each(|v| strip_fields(v, model)),
+        _ => {}
+    }
+}
+
+// ============================================
+// Round Trips
+// ============================================
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub format: &'static str,
+    // the first path at which the values differ
+    pub path: FlowPath,
+    pub expected: Option<Value>,
+    pub actual: Option<Value>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |v: &Option<Value>| v.as_ref().map(|v| v.to_string()).unwrap_or_else(|| "(missing)".to_string());
+        let at = if self.path.is_root() { "the root".to_string() } else { format!("'{}'", self.path) };
+        write!(f, "{} round trip differs at {}: expected {}, got {}", self.format, at, show(&self.expected), show(&self.actual))
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+// parse(stringify(v)) == v for the .flow text format.
+pub fn roundtrip_text(val: &Value) -> Result<(), Mismatch> {
+    compare("text", val, &parse_document(&stringify_document(val)))
+}
+
+// encode then decode through .flowb.
+pub fn roundtrip_binary(val: &Value) -> Result<(), Mismatch> {
+    let back: Value = rmp_serde::to_vec(val).ok().and_then(|b| rmp_serde::from_slice(&b).ok()).unwrap_or(Value::Null);
+    compare("binary", val, &back)
+}
+
+// Panics with the first difference if either format loses information.
+pub fn assert_roundtrip(val: &Value) {
+    if let Err(e) = roundtrip_text(val).and_then(|_| roundtrip_binary(val)) {
+        panic!("{}\ndocument: {}", e, val);
+    }
+}
+
+pub fn assert_roundtrip_binary(val: &Value) {
+    if let Err(e) = roundtrip_binary(val) {
+        panic!("{}\ndocument: {}", e, val);
+    }
+}
+
+fn compare(format: &'static str, expected: &Value, actual: &Value) -> Result<(), Mismatch> {
+    match first_difference(expected, actual, &mut FlowPath::root()) {
+        None => Ok(()),
+        Some(path) => Err(Mismatch { format, expected: path.lookup(expected).cloned(), actual: path.lookup(actual).cloned(), path }),
+    }
+}
+
+fn first_difference(a: &Value, b: &Value, path: &mut FlowPath) -> Option<FlowPath> {
+    match (a, b) {
+        (Value::Object(x), Value::Object(y)) => {
+            let mut keys: Vec<&String> = x.keys().chain(y.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for k in keys {
+                path.push_key(k);
+                let found = match (x.get(k), y.get(k)) {
+                    (Some(i), Some(j)) => first_difference(i, j, path),
+                    _ => Some(path.clone()),
+                };
+                path.pop();
+                if found.is_some() {
+                    return found;
+                }
+            }
+            None
+        }
+        (Value::Array(x), Value::Array(y)) => {
+            for i in 0..x.len().max(y.len()) {
+                path.push_index(i);
+                let found = match (x.get(i), y.get(i)) {
+                    (Some(p), Some(q)) => first_difference(p, q, path),
+                    _ => Some(path.clone()),
+                };
+                path.pop();
+                if found.is_some() {
+                    return found;
+                }
+            }
+            None
+        }
+        _ if a == b => None,
+        _ => Some(path.clone()),
+    }
+}
+
+// ============================================
+// Fixtures
+// ============================================
+
+// Loads a `.flow`, `.flowb` or `.json` fixture. A relative path is taken
+// from the crate under test (CARGO_MANIFEST_DIR). Panics on failure.
+pub fn fixture(path: impl AsRef<Path>) -> Value {
+    let full = fixture_path(path.as_ref());
+    load(&full).unwrap_or_else(|e| panic!("cannot load fixture {}: {}", full.display(), e))
+}
+
+// Every fixture directly inside `dir`, sorted by file name.
+pub fn fixtures(dir: impl AsRef<Path>) -> Vec<(PathBuf, Value)> {
+    let dir = fixture_path(dir.as_ref());
+    let entries = fs::read_dir(&dir).unwrap_or_else(|e| panic!("cannot read fixture directory {}: {}", dir.display(), e));
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "flow" || e == "flowb" || e == "json"))
+        .collect();
+    paths.sort();
+    paths.into_iter().map(|p| (p.clone(), fixture(&p))).collect()
+}
+
+fn fixture_path(path: &Path) -> PathBuf {
+    match std::env::var_os("CARGO_MANIFEST_DIR") {
+        Some(root) if path.is_relative() => PathBuf::from(root).join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn load(path: &Path) -> Result<Value, Box<dyn std::error::Error>> {
+    let location = path.to_string_lossy();
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("flowb") => Ok(crate::v2::load_flowb(&location)?),
+        Some("json") => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+        _ => Ok(crate::load_document(&location, &crate::ParseOptions::default())?.value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn seeds_replay() {
+        let options = GenOptions::default();
+        let docs = |seed| (0..20).map(|_| arbitrary_document(&mut Rng::new(seed), &options)).collect::<Vec<_>>();
+        assert_eq!(docs(7), docs(7));
+        let mut rng = Rng::new(7);
+        let (a, b) = (arbitrary_document(&mut rng, &options), arbitrary_document(&mut rng, &options));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn generated_documents_round_trip() {
+        for_each_document(1, 300, &GenOptions::default(), assert_roundtrip);
+        let any = GenOptions { text_safe: false, ..GenOptions::default() };
+        for_each_document(2, 300, &any, assert_roundtrip_binary);
+    }
+
+    #[test]
+    fn mismatches_name_the_first_difference() {
+        let err = roundtrip_text(&json!({"a": 1, "b": {"c": null}})).unwrap_err();
+        assert_eq!(err, Mismatch { format: "text", path: FlowPath::parse("b.c").unwrap(), expected: Some(json!(null)), actual: None });
+        assert_eq!(err.to_string(), "text round trip differs at 'b.c': expected null, got (missing)");
+        assert!(roundtrip_binary(&json!({"n": 2.0, "z": null, "l": [[{}]]})).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "assertion failed")]
+    fn failing_cases_are_reported() {
+        for_each_document(3, 10, &GenOptions::default(), |doc| assert!(doc.as_object().is_some_and(|m| m.len() < 2)));
+    }
+
+    #[test]
+    fn fixtures_by_extension() {
+        let dir = std::env::temp_dir().join(format!("flowdoc-testkit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.flow"), "x = 1\n").unwrap();
+        fs::write(dir.join("b.json"), "{\"x\": 2}").unwrap();
+        fs::write(dir.join("c.flowb"), rmp_serde::to_vec(&json!({"x": 3})).unwrap()).unwrap();
+        fs::write(dir.join("d.txt"), "x = 4\n").unwrap();
+        let loaded: Vec<Value> = fixtures(&dir).into_iter().map(|(_, v)| v).collect();
+        assert_eq!(loaded, [json!({"x": 1}), json!({"x": 2}), json!({"x": 3})]);
+        assert_eq!(fixture(dir.join("b.json")), json!({"x": 2}));
+    }
+
+    fn server_model() -> crate::ModelRegistry {
+        let mut registry = crate::ModelRegistry::new();
+        registry.register_models(&json!({"$models": {"server": {"fields": {
+            "host": {"type": "string", "alias": "h", "required": true},
+            "port": {"type": "int", "alias": "p"},
+            "ratio": {"type": "float"},
+            "mode": {"choices": ["dev", "prod"], "required": true},
+            "started": {"type": "datetime"},
+            "tags": {"type": "array"},
+            "extra": {"type": "object"},
+            "anything": {},
+        }}}}));
+        registry
+    }
+
+    #[test]
+    fn model_documents_validate() {
+        let registry = server_model();
+        let model = registry.get_model("server").unwrap();
+        let mut aliased = 0;
+        for_each_model_document(11, 300, model, &GenOptions::default(), |doc| {
+            let text = stringify_document(doc);
+            assert_eq!(crate::validate(&text, &registry), [], "{}", text);
+            assert_eq!(parse_document(&text), *doc);
+            assert!(doc.get("host").or(doc.get("h")).is_some());
+            assert!(["dev", "prod"].contains(&doc["mode"].as_str().unwrap()));
+            aliased += doc.get("h").is_some() as usize;
+        });
+        assert!(aliased > 0);
+    }
+
+    #[test]
+    fn model_seeds_replay() {
+        let registry = server_model();
+        let model = registry.get_model("server").unwrap();
+        let docs = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..10).map(|_| model_document(&mut rng, model, &GenOptions::default())).collect::<Vec<_>>()
+        };
+        assert_eq!(docs(5), docs(5));
+        assert_ne!(docs(5), docs(6));
+    }
+}
@@ -0,0 +1,188 @@
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::canonical::canonicalize;
+use crate::merge::{Origin, Provenance};
+use crate::path::FlowPath;
+
+// ============================================
+// Three-Way Merge
+// ============================================
+//
+// Merges two edited versions of a document against the version both started
+// from. Objects are merged key by key, so edits to different keys never
+// conflict; anything else, arrays included, is replaced as a whole. A node
+// changed on one side only takes that side's value, and one changed the same
+// way on both sides takes that value. Values are compared in canonical form,
+// so `8080.0` and `8080` are the same. Where the two sides made different
+// changes, including one side removing a node the other changed, the merge
+// keeps ours and records a MergeConflict.
+//
+// The provenance of the result names the version each value was taken
+// from: "base", "ours" or "theirs". A node both sides changed the same way
+// is ours, as is a conflicted one.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeConflict {
+    pub path: FlowPath,
+    // None where the node is absent from that version
+    pub base: Option<Value>,
+    pub ours: Option<Value>,
+    pub theirs: Option<Value>,
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |v: &Option<Value>| v.as_ref().map(canonicalize).unwrap_or_else(|| "(removed)".to_string());
+        let at = if self.path.is_root() { "the root".to_string() } else { format!("'{}'", self.path) };
+        write!(f, "conflict at {}: ours {}, theirs {}", at, show(&self.ours), show(&self.theirs))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreeWayMerge {
+    pub value: Value,
+    pub conflicts: Vec<MergeConflict>,
+    pub provenance: Provenance,
+}
+
+impl ThreeWayMerge {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+
+    // The version the value at `path` was taken from.
+    pub fn get_origin(&self, path: &FlowPath) -> Option<Origin> {
+        self.provenance.get_origin(path)
+    }
+}
+
+pub fn merge_three_way(base: &Value, ours: &Value, theirs: &Value) -> ThreeWayMerge {
+    let mut state = Merge { conflicts: Vec::new(), provenance: Provenance::new() };
+    state.provenance.record(&FlowPath::root(), Origin::new("base"));
+    let value = state.merge_at(Some(base), Some(ours), Some(theirs), &mut FlowPath::root()).unwrap_or(Value::Null);
+    ThreeWayMerge { value, conflicts: state.conflicts, provenance: state.provenance }
+}
+
+struct Merge {
+    conflicts: Vec<MergeConflict>,
+    provenance: Provenance,
+}
+
+impl Merge {
+    fn merge_at(&mut self, base: Option<&Value>, ours: Option<&Value>, theirs: Option<&Value>, path: &mut FlowPath) -> Option<Value> {
+        if same(base, ours) && same(base, theirs) {
+            return ours.cloned();
+        }
+        if same(ours, theirs) || same(base, theirs) {
+            return self.take(path, "ours", base, ours);
+        }
+        if same(base, ours) {
+            return self.take(path, "theirs", base, theirs);
+        }
+        let empty = Value::Object(Map::new());
+        match (base.unwrap_or(&empty), ours, theirs) {
+            (Value::Object(b), Some(Value::Object(o)), Some(Value::Object(t))) => {
+                let mut keys: Vec<&String> = b.keys().chain(o.keys()).chain(t.keys()).collect();
+                keys.sort();
+                keys.dedup();
+                let mut out = Map::new();
+                for k in keys {
+                    path.push_key(k);
+                    if let Some(v) = self.merge_at(b.get(k), o.get(k), t.get(k), path) {
+                        out.insert(k.clone(), v);
+                    }
+                    path.pop();
+                }
+                Some(Value::Object(out))
+            }
+            _ => {
+                self.conflicts.push(MergeConflict { path: path.clone(), base: base.cloned(), ours: ours.cloned(), theirs: theirs.cloned() });
+                self.take(path, "ours", base, ours)
+            }
+        }
+    }
+
+    fn take(&mut self, path: &mut FlowPath, version: &str, base: Option<&Value>, value: Option<&Value>) -> Option<Value> {
+        self.trace(path, version, base, value);
+        value.cloned()
+    }
+
+    // Records `version` for the nodes of `value` that differ from `base`.
+    fn trace(&mut self, path: &mut FlowPath, version: &str, base: Option<&Value>, value: Option<&Value>) {
+        match (base, value) {
+            (Some(Value::Object(b)), Some(Value::Object(v))) => {
+                for (k, x) in v {
+                    if !same(b.get(k), Some(x)) {
+                        path.push_key(k);
+                        self.trace(path, version, b.get(k), Some(x));
+                        path.pop();
+                    }
+                }
+            }
+            (_, Some(_)) => self.provenance.record(path, Origin::new(version)),
+            (_, None) => {}
+        }
+    }
+}
+
+fn same(a: Option<&Value>, b: Option<&Value>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a == b || canonicalize(a) == canonicalize(b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn clean_merges() {
+        let base = json!({"name": "api", "port": 80, "tags": ["a"], "old": 1, "db": {"host": "h", "pool": 4}});
+        let ours = json!({"name": "api2", "port": 80.0, "tags": ["a"], "db": {"host": "h2", "pool": 4}});
+        let theirs = json!({"name": "api", "port": 80, "tags": ["a", "b"], "old": 1, "new": true, "db": {"host": "h", "pool": 8}});
+        let merged = merge_three_way(&base, &ours, &theirs);
+        assert!(merged.is_clean(), "{:?}", merged.conflicts);
+        assert_eq!(
+            merged.value,
+            json!({"name": "api2", "port": 80.0, "tags": ["a", "b"], "new": true, "db": {"host": "h2", "pool": 8}})
+        );
+        // the same change on both sides
+        let both = json!({"name": "x"});
+        assert!(merge_three_way(&json!({"name": "a"}), &both, &both).is_clean());
+    }
+
+    #[test]
+    fn conflicts_keep_ours() {
+        let base = json!({"port": 80, "tags": ["a"], "db": {"host": "h"}, "gone": 1});
+        let ours = json!({"port": 81, "tags": ["a", "b"], "db": {"host": "h"}});
+        let theirs = json!({"port": 82, "tags": ["c"], "db": "none", "gone": 2});
+        let merged = merge_three_way(&base, &ours, &theirs);
+        let paths: Vec<String> = merged.conflicts.iter().map(|c| c.path.to_string()).collect();
+        assert_eq!(paths, ["gone", "port", "tags"]);
+        assert_eq!(merged.value, json!({"port": 81, "tags": ["a", "b"], "db": "none"}));
+        assert_eq!(merged.conflicts[0].to_string(), "conflict at 'gone': ours (removed), theirs 2");
+        assert_eq!(merged.conflicts[1], MergeConflict { path: FlowPath::parse("port").unwrap(), base: Some(json!(80)), ours: Some(json!(81)), theirs: Some(json!(82)) });
+
+        let root = merge_three_way(&json!({}), &json!([1]), &json!("x"));
+        assert_eq!(root.conflicts[0].to_string(), "conflict at the root: ours [1], theirs \"x\"");
+    }
+
+    #[test]
+    fn origins_name_the_version_taken() {
+        let base = json!({ "a": 1, "b": { "c": 1, "d": 1 }, "e": 1 });
+        let ours = json!({ "a": 2, "b": { "c": 1, "d": 1 }, "e": 3 });
+        let theirs = json!({ "a": 1, "b": { "c": 2, "d": 1 }, "e": 4 });
+        let merged = merge_three_way(&base, &ours, &theirs);
+        let source = |p: &str| merged.get_origin(&FlowPath::parse(p).unwrap()).unwrap().source;
+        assert_eq!(source("a"), "ours");
+        assert_eq!(source("b.c"), "theirs");
+        assert_eq!(source("b.d"), "base");
+        // conflicted, ours kept
+        assert_eq!(source("e"), "ours");
+        assert_eq!(merged.value, json!({ "a": 2, "b": { "c": 2, "d": 1 }, "e": 3 }));
+    }
+}
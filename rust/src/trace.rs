@@ -0,0 +1,319 @@
+use serde_json::Value;
+use std::fmt;
+
+// ============================================
+// Instrumentation
+// ============================================
+//
+// With the `tracing` feature, parsing, stringifying, loading, saving and
+// validation report to a process-wide FlowObserver: `on_start` when an
+// operation begins and `on_finish` with a FlowEvent (bytes, nodes, duration
+// and the error, if any) when it ends. Operations nest, so a load reports
+// the parse it performs as well. FlowCounters is an observer that keeps
+// running totals for dashboards.
+//
+// Without the feature the Span below is empty and every call compiles away.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Parse,
+    Stringify,
+    Load,
+    Save,
+    Validate,
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Parse => "parse",
+            Operation::Stringify => "stringify",
+            Operation::Load => "load",
+            Operation::Save => "save",
+            Operation::Validate => "validate",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(feature = "tracing")]
+pub use observer::*;
+
+#[cfg(feature = "tracing")]
+mod observer {
+    use serde_json::Value;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::{Arc, RwLock};
+    use std::time::{Duration, Instant};
+
+    use super::Operation;
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct FlowEvent {
+        pub operation: Operation,
+        pub path: Option<String>,
+        // input read for parse and load, output written for stringify and save
+        pub bytes: usize,
+        // values in the document, counting the root
+        pub nodes: usize,
+        pub duration: Duration,
+        pub error: Option<String>,
+    }
+
+    pub trait FlowObserver: Send + Sync {
+        fn on_start(&self, _operation: Operation, _path: Option<&str>) {}
+
+        fn on_finish(&self, event: &FlowEvent);
+    }
+
+    impl<F> FlowObserver for F
+    where
+        F: Fn(&FlowEvent) + Send + Sync,
+    {
+        fn on_finish(&self, event: &FlowEvent) {
+            self(event)
+        }
+    }
+
+    static ENABLED: AtomicBool = AtomicBool::new(false);
+    static OBSERVER: RwLock<Option<Arc<dyn FlowObserver>>> = RwLock::new(None);
+
+    pub fn set_observer(observer: Arc<dyn FlowObserver>) {
+        *OBSERVER.write().unwrap_or_else(|e| e.into_inner()) = Some(observer);
+        ENABLED.store(true, Ordering::Release);
+    }
+
+    pub fn clear_observer() {
+        ENABLED.store(false, Ordering::Release);
+        *OBSERVER.write().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    fn current() -> Option<Arc<dyn FlowObserver>> {
+        if !ENABLED.load(Ordering::Acquire) {
+            return None;
+        }
+        OBSERVER.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct OperationCounts {
+        pub calls: u64,
+        pub failures: u64,
+        pub bytes: u64,
+        pub nodes: u64,
+        pub duration: Duration,
+    }
+
+    #[derive(Default)]
+    struct Counters {
+        calls: AtomicU64,
+        failures: AtomicU64,
+        bytes: AtomicU64,
+        nodes: AtomicU64,
+        nanos: AtomicU64,
+    }
+
+    // Running totals per operation.
+    #[derive(Default)]
+    pub struct FlowCounters {
+        counters: [Counters; 5],
+    }
+
+    impl FlowCounters {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn get(&self, operation: Operation) -> OperationCounts {
+            let c = &self.counters[operation as usize];
+            OperationCounts {
+                calls: c.calls.load(Ordering::Relaxed),
+                failures: c.failures.load(Ordering::Relaxed),
+                bytes: c.bytes.load(Ordering::Relaxed),
+                nodes: c.nodes.load(Ordering::Relaxed),
+                duration: Duration::from_nanos(c.nanos.load(Ordering::Relaxed)),
+            }
+        }
+
+        pub fn reset(&self) {
+            for c in &self.counters {
+                for n in [&c.calls, &c.failures, &c.bytes, &c.nodes, &c.nanos] {
+                    n.store(0, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    impl FlowObserver for FlowCounters {
+        fn on_finish(&self, event: &FlowEvent) {
+            let c = &self.counters[event.operation as usize];
+            c.calls.fetch_add(1, Ordering::Relaxed);
+            if event.error.is_some() {
+                c.failures.fetch_add(1, Ordering::Relaxed);
+            }
+            c.bytes.fetch_add(event.bytes as u64, Ordering::Relaxed);
+            c.nodes.fetch_add(event.nodes as u64, Ordering::Relaxed);
+            c.nanos.fetch_add(event.duration.as_nanos().min(u64::MAX as u128) as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) struct Span {
+        observer: Option<Arc<dyn FlowObserver>>,
+        operation: Operation,
+        path: Option<String>,
+        start: Instant,
+        bytes: usize,
+        nodes: usize,
+    }
+
+    impl Span {
+        pub(crate) fn start(operation: Operation, path: Option<&str>) -> Self {
+            let observer = current();
+            if let Some(o) = &observer {
+                o.on_start(operation, path);
+            }
+            Span { observer, operation, path: path.map(str::to_string), start: Instant::now(), bytes: 0, nodes: 0 }
+        }
+
+        pub(crate) fn bytes(&mut self, n: usize) {
+            self.bytes = n;
+        }
+
+        pub(crate) fn document(&mut self, doc: &Value) {
+            if self.observer.is_some() {
+                self.nodes = count_nodes(doc);
+            }
+        }
+
+        pub(crate) fn finish(self) {
+            self.end(None);
+        }
+
+        pub(crate) fn fail(self, error: &dyn std::fmt::Display) {
+            if self.observer.is_some() {
+                let message = error.to_string();
+                self.end(Some(message));
+            }
+        }
+
+        fn end(self, error: Option<String>) {
+            let Some(observer) = self.observer else { return };
+            observer.on_finish(&FlowEvent {
+                operation: self.operation,
+                path: self.path,
+                bytes: self.bytes,
+                nodes: self.nodes,
+                duration: self.start.elapsed(),
+                error,
+            });
+        }
+    }
+
+    fn count_nodes(val: &Value) -> usize {
+        1 + match val {
+            Value::Object(map) => map.values().map(count_nodes).sum(),
+            Value::Array(items) => items.iter().map(count_nodes).sum(),
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    #[inline]
+    pub(crate) fn start(_operation: Operation, _path: Option<&str>) -> Self {
+        Span
+    }
+
+    #[inline]
+    pub(crate) fn bytes(&mut self, _n: usize) {}
+
+    #[inline]
+    pub(crate) fn document(&mut self, _doc: &Value) {}
+
+    #[inline]
+    pub(crate) fn finish(self) {}
+
+    #[inline]
+    pub(crate) fn fail(self, _error: &dyn fmt::Display) {}
+}
+
+impl Span {
+    // Finishes with the outcome of `result`, counting the nodes of the
+    // document `doc` picks out of a success.
+    pub(crate) fn finish_with<'a, T, E: fmt::Display>(mut self, result: &'a Result<T, E>, doc: impl FnOnce(&'a T) -> &'a Value) {
+        match result {
+            Ok(v) => {
+                self.document(doc(v));
+                self.finish();
+            }
+            Err(e) => self.fail(e),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use super::*;
+    use crate::diagnostic::parse_strict;
+    use crate::{parse_document, stringify_document, ParseOptions};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    // The observer is process-wide, so this is the only test that sets one
+    // and it keeps only the events of its own thread.
+    #[test]
+    fn operations_are_reported() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let me = thread::current().id();
+        let sink = events.clone();
+        set_observer(Arc::new(move |e: &FlowEvent| {
+            if thread::current().id() == me {
+                sink.lock().unwrap().push(e.clone());
+            }
+        }));
+        let text = "a = 1\nb:\n  c = [1, 2]\n";
+        let doc = parse_document(text);
+        let out = stringify_document(&doc);
+        assert!(parse_strict("a = 1\na = 2\n", &ParseOptions::default()).is_err());
+        clear_observer();
+        parse_document(text);
+
+        let events = events.lock().unwrap();
+        let summary: Vec<(Operation, usize, usize, bool)> = events.iter().map(|e| (e.operation, e.bytes, e.nodes, e.error.is_some())).collect();
+        assert_eq!(summary, [(Operation::Parse, text.len(), 6, false), (Operation::Stringify, out.len(), 6, false), (Operation::Parse, 12, 0, true)]);
+        assert_eq!(events[2].error.as_deref(), Some("line 2, column 1: duplicate key 'a'"));
+    }
+
+    #[test]
+    fn counters_add_up() {
+        let counters = FlowCounters::new();
+        let event = |error: Option<&str>| FlowEvent {
+            operation: Operation::Load,
+            path: Some("a.flow".to_string()),
+            bytes: 10,
+            nodes: 3,
+            duration: Duration::from_millis(2),
+            error: error.map(str::to_string),
+        };
+        counters.on_finish(&event(None));
+        counters.on_finish(&event(Some("missing")));
+        assert_eq!(
+            counters.get(Operation::Load),
+            OperationCounts { calls: 2, failures: 1, bytes: 20, nodes: 6, duration: Duration::from_millis(4) }
+        );
+        assert_eq!(counters.get(Operation::Save), OperationCounts::default());
+        counters.reset();
+        assert_eq!(counters.get(Operation::Load), OperationCounts::default());
+    }
+}
@@ -0,0 +1,406 @@
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::audit::{AuditEntry, AuditError, AuditLog};
+use crate::encoding::read_text;
+use crate::path::{FlowPath, PathError};
+use crate::save::{backup_path, replace, unique_suffix, write_temp};
+use crate::{parse_document, stringify_document};
+
+// ============================================
+// Transactions
+// ============================================
+//
+// A FlowTransaction loads documents as they are first touched, applies
+// edits to them in memory and writes them all out on `commit`. Every changed
+// document is first written to a synced temporary file; only when all of
+// them are written are they renamed over their targets. If a rename fails,
+// the documents already replaced are restored from copies taken before the
+// renames, so the set ends up either all old or all new. A document that
+// changed on disk since it was loaded fails the commit with a conflict
+// before anything is replaced.
+//
+// Files ending in `.flowb` are read and written as MessagePack, anything
+// else as .flow text. Documents are staged without `@ref` resolution, so
+// references are written back as references.
+//
+// With `audit(actor)` every edit is also recorded in the document's audit
+// journal (see AuditLog). The records are appended once all temporary files
+// are written and before any document is replaced, and marked committed or
+// aborted once the renames are done or undone. A journal that cannot take
+// the mark leaves its records pending. Edits made through `document_mut` are
+// recorded as one change of the whole document.
+
+#[derive(Debug)]
+pub enum TransactionError {
+    Io { path: String, source: io::Error },
+    Decode { path: String, message: String },
+    Path(PathError),
+    Conflict(String),
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::Io { path, source } => write!(f, "{}: {}", path, source),
+            TransactionError::Decode { path, message } => write!(f, "{}: {}", path, message),
+            TransactionError::Path(e) => write!(f, "{}", e),
+            TransactionError::Conflict(path) => write!(f, "{}: changed on disk since it was loaded", path),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransactionError::Io { source, .. } => Some(source),
+            TransactionError::Path(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<PathError> for TransactionError {
+    fn from(e: PathError) -> Self {
+        TransactionError::Path(e)
+    }
+}
+
+struct Staged {
+    path: PathBuf,
+    value: Value,
+    // (mtime, length) when loaded, or None for a file that did not exist
+    loaded: Option<(SystemTime, u64)>,
+    dirty: bool,
+    // audit records of edits made through the path API
+    entries: Vec<AuditEntry>,
+    // the value before the first `document_mut`, when auditing
+    before_raw: Option<Value>,
+}
+
+#[derive(Default)]
+pub struct FlowTransaction {
+    docs: Vec<Staged>,
+    backup: bool,
+    actor: Option<String>,
+}
+
+impl FlowTransaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Keep each replaced document's previous content as `<path>.bak`.
+    pub fn backup(mut self, on: bool) -> Self {
+        self.backup = on;
+        self
+    }
+
+    // Record every edit in each document's audit journal, made by `actor`.
+    pub fn audit(mut self, actor: &str) -> Self {
+        self.actor = Some(actor.to_string());
+        self
+    }
+
+    pub fn get(&mut self, file: &str, path: &FlowPath) -> Result<Option<&Value>, TransactionError> {
+        let doc = self.stage(file)?;
+        Ok(path.lookup(&doc.value))
+    }
+
+    pub fn set(&mut self, file: &str, path: &FlowPath, value: Value) -> Result<Option<Value>, TransactionError> {
+        let actor = self.actor.clone();
+        let doc = self.stage(file)?;
+        let new = actor.as_ref().map(|_| value.clone());
+        let old = path.set(&mut doc.value, value)?;
+        doc.dirty = true;
+        if let Some(actor) = actor {
+            doc.entries.push(AuditEntry::new(&actor, path.clone(), old.clone(), new));
+        }
+        Ok(old)
+    }
+
+    pub fn remove(&mut self, file: &str, path: &FlowPath) -> Result<Option<Value>, TransactionError> {
+        let actor = self.actor.clone();
+        let doc = self.stage(file)?;
+        let old = path.remove(&mut doc.value);
+        doc.dirty |= old.is_some();
+        if let (Some(actor), Some(_)) = (actor, &old) {
+            doc.entries.push(AuditEntry::new(&actor, path.clone(), old.clone(), None));
+        }
+        Ok(old)
+    }
+
+    // The staged document, for edits the path API does not cover.
+    pub fn document_mut(&mut self, file: &str) -> Result<&mut Value, TransactionError> {
+        let auditing = self.actor.is_some();
+        let doc = self.stage(file)?;
+        doc.dirty = true;
+        if auditing && doc.before_raw.is_none() {
+            doc.before_raw = Some(doc.value.clone());
+        }
+        Ok(&mut doc.value)
+    }
+
+    // Files with staged changes, in the order they were first touched.
+    pub fn changed(&self) -> impl Iterator<Item = &Path> {
+        self.docs.iter().filter(|d| d.dirty).map(|d| d.path.as_path())
+    }
+
+    pub fn commit(self) -> Result<(), TransactionError> {
+        let docs: Vec<&Staged> = self.docs.iter().filter(|d| d.dirty).collect();
+        for doc in &docs {
+            if file_state(&doc.path) != doc.loaded {
+                return Err(TransactionError::Conflict(doc.path.display().to_string()));
+            }
+        }
+
+        let mut temps: Vec<PathBuf> = Vec::new();
+        for doc in &docs {
+            match encode(doc).and_then(|data| write_temp(&doc.path, &data).map_err(|e| io_error(&doc.path, e))) {
+                Ok(tmp) => temps.push(tmp),
+                Err(e) => {
+                    remove_all(&temps);
+                    return Err(e);
+                }
+            }
+        }
+
+        // journals holding records of this commit, told the outcome at the end
+        let txn = format!("{}.{}", SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0), unique_suffix());
+        let mut journals: Vec<AuditLog> = Vec::new();
+        let finish = |journals: &[AuditLog], applied: bool| {
+            for log in journals {
+                let _ = log.finish(&txn, applied);
+            }
+        };
+        if let Some(actor) = &self.actor {
+            for doc in &docs {
+                let mut entries = doc.entries.clone();
+                if let Some(before) = doc.before_raw.as_ref().filter(|b| **b != doc.value) {
+                    let old = doc.loaded.map(|_| before.clone());
+                    entries.push(AuditEntry::new(actor, FlowPath::root(), old, Some(doc.value.clone())));
+                }
+                if entries.is_empty() {
+                    continue;
+                }
+                let log = AuditLog::for_document(&doc.path, actor);
+                if let Err(e) = log.begin(&txn, &entries) {
+                    remove_all(&temps);
+                    finish(&journals, false);
+                    return Err(match e {
+                        AuditError::Io { path, source } => TransactionError::Io { path, source },
+                        AuditError::Path(e) => TransactionError::Path(e),
+                        AuditError::Decode { path, message, .. } => TransactionError::Decode { path, message },
+                    });
+                }
+                journals.push(log);
+            }
+        }
+
+        // copies of the current content, for undoing a partial set of renames
+        let mut saved: Vec<Option<PathBuf>> = Vec::new();
+        for doc in &docs {
+            let copy = if doc.loaded.is_some() {
+                let copy = doc.path.with_file_name(format!(".{}.orig{}", file_name(&doc.path), unique_suffix()));
+                if let Err(e) = fs::copy(&doc.path, &copy) {
+                    remove_all(&temps);
+                    remove_all(saved.iter().flatten());
+                    finish(&journals, false);
+                    return Err(io_error(&doc.path, e));
+                }
+                Some(copy)
+            } else {
+                None
+            };
+            saved.push(copy);
+        }
+
+        for (i, (doc, tmp)) in docs.iter().zip(&temps).enumerate() {
+            if let Err(e) = replace_doc(tmp, &doc.path) {
+                for (done, copy) in docs[..i].iter().zip(&saved) {
+                    match copy {
+                        Some(copy) => {
+                            let _ = fs::rename(copy, &done.path);
+                        }
+                        None => {
+                            let _ = fs::remove_file(&done.path);
+                        }
+                    }
+                }
+                remove_all(&temps[i..]);
+                remove_all(saved[i..].iter().flatten());
+                finish(&journals, false);
+                return Err(io_error(&doc.path, e));
+            }
+        }
+        finish(&journals, true);
+
+        for (doc, copy) in docs.iter().zip(saved) {
+            if let Some(copy) = copy {
+                if self.backup {
+                    fs::rename(&copy, backup_path(&doc.path)).map_err(|e| io_error(&doc.path, e))?;
+                } else {
+                    let _ = fs::remove_file(copy);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn stage(&mut self, file: &str) -> Result<&mut Staged, TransactionError> {
+        let path = PathBuf::from(file);
+        let key = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if let Some(i) = self.docs.iter().position(|d| fs::canonicalize(&d.path).unwrap_or_else(|_| d.path.clone()) == key) {
+            return Ok(&mut self.docs[i]);
+        }
+        let loaded = file_state(&path);
+        let value = if loaded.is_some() { decode(&path)? } else { Value::Object(Map::new()) };
+        self.docs.push(Staged { path, value, loaded, dirty: false, entries: Vec::new(), before_raw: None });
+        Ok(self.docs.last_mut().expect("just pushed"))
+    }
+}
+
+fn replace_doc(tmp: &Path, path: &Path) -> io::Result<()> {
+    #[cfg(test)]
+    if tests::FAIL_RENAME.with(|f| f.borrow().as_deref() == Some(path)) {
+        return Err(io::Error::other("rename failed"));
+    }
+    replace(tmp, path)
+}
+
+fn is_flowb(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "flowb")
+}
+
+fn decode(path: &Path) -> Result<Value, TransactionError> {
+    if is_flowb(path) {
+        let data = fs::read(path).map_err(|e| io_error(path, e))?;
+        return rmp_serde::from_slice(&data).map_err(|e| TransactionError::Decode {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        });
+    }
+    let text = read_text(&path.to_string_lossy()).map_err(|e| io_error(path, e))?;
+    Ok(parse_document(&text))
+}
+
+fn encode(doc: &Staged) -> Result<Vec<u8>, TransactionError> {
+    if is_flowb(&doc.path) {
+        return rmp_serde::to_vec(&doc.value).map_err(|e| TransactionError::Decode {
+            path: doc.path.display().to_string(),
+            message: e.to_string(),
+        });
+    }
+    Ok(stringify_document(&doc.value).into_bytes())
+}
+
+fn file_state(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+fn io_error(path: &Path, source: io::Error) -> TransactionError {
+    TransactionError::Io { path: path.display().to_string(), source }
+}
+
+fn remove_all<'a>(paths: impl IntoIterator<Item = &'a PathBuf>) {
+    for p in paths {
+        let _ = fs::remove_file(p);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    thread_local! {
+        // a document whose rename fails, to exercise the rollback
+        pub(crate) static FAIL_RENAME: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+    }
+
+    pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flowdoc-txn-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    pub(crate) fn listing(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        names
+    }
+
+    fn key(path: &str) -> FlowPath {
+        FlowPath::parse(path).unwrap()
+    }
+
+    #[test]
+    fn commits_every_document() {
+        let dir = scratch_dir("commit");
+        let (a, b, c) = (dir.join("a.flow"), dir.join("b.flow"), dir.join("c.flowb"));
+        fs::write(&a, "port = 80\n").unwrap();
+        fs::write(&b, "name = \"b\"\n").unwrap();
+        let (a, b, c) = (a.display().to_string(), b.display().to_string(), c.display().to_string());
+
+        let mut txn = FlowTransaction::new().backup(true);
+        assert_eq!(txn.set(&a, &key("port"), serde_json::json!(443)).unwrap(), Some(serde_json::json!(80)));
+        txn.remove(&b, &key("name")).unwrap();
+        txn.set(&c, &key("created"), serde_json::json!(true)).unwrap();
+        assert_eq!(txn.changed().count(), 3);
+        txn.commit().unwrap();
+
+        assert_eq!(fs::read_to_string(&a).unwrap(), "port = 443\n");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "");
+        assert_eq!(rmp_serde::from_slice::<Value>(&fs::read(&c).unwrap()).unwrap(), serde_json::json!({ "created": true }));
+        assert_eq!(listing(&dir), ["a.flow", "a.flow.bak", "b.flow", "b.flow.bak", "c.flowb"]);
+    }
+
+    #[test]
+    fn a_conflict_replaces_nothing() {
+        let dir = scratch_dir("conflict");
+        let (a, b) = (dir.join("a.flow"), dir.join("b.flow"));
+        fs::write(&a, "x = 1\n").unwrap();
+        fs::write(&b, "y = 1\n").unwrap();
+        let (a_name, b_name) = (a.display().to_string(), b.display().to_string());
+
+        let mut txn = FlowTransaction::new();
+        txn.set(&a_name, &key("x"), serde_json::json!(2)).unwrap();
+        txn.set(&b_name, &key("y"), serde_json::json!(2)).unwrap();
+        fs::write(&b, "y = 10\n").unwrap();
+        assert!(matches!(txn.commit(), Err(TransactionError::Conflict(p)) if p == b_name));
+        assert_eq!(fs::read_to_string(&a).unwrap(), "x = 1\n");
+        assert_eq!(listing(&dir), ["a.flow", "b.flow"]);
+    }
+
+    #[test]
+    fn a_failed_rename_restores_the_others() {
+        let dir = scratch_dir("rollback");
+        let (a, b, c) = (dir.join("a.flow"), dir.join("b.flow"), dir.join("c.flow"));
+        fs::write(&a, "x = 1\n").unwrap();
+        fs::write(&c, "z = 1\n").unwrap();
+
+        let mut txn = FlowTransaction::new();
+        for (file, k) in [(&a, "x"), (&b, "y"), (&c, "z")] {
+            txn.set(&file.display().to_string(), &key(k), serde_json::json!(2)).unwrap();
+        }
+        FAIL_RENAME.with(|f| *f.borrow_mut() = Some(c.clone()));
+        let result = txn.commit();
+        FAIL_RENAME.with(|f| *f.borrow_mut() = None);
+
+        assert!(matches!(result, Err(TransactionError::Io { path, .. }) if path == c.display().to_string()));
+        // a is back as it was, b (new) is gone again, c was never replaced
+        assert_eq!(fs::read_to_string(&a).unwrap(), "x = 1\n");
+        assert_eq!(fs::read_to_string(&c).unwrap(), "z = 1\n");
+        assert_eq!(listing(&dir), ["a.flow", "c.flow"]);
+    }
+}
@@ -0,0 +1,637 @@
+use serde::de::DeserializeSeed;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use crate::cache::FlowCache;
+use crate::cancel::Cancelled;
+use crate::control;
+use crate::convert::{self, ConvertOptions};
+use crate::crypt::{decrypt_fields, CryptError, FieldKey};
+use crate::diagnostic::{parse_strict, ParseError, StrictError};
+use crate::encoding::{self, EncodingError};
+#[cfg(feature = "http")]
+use crate::http::{self, HttpError, UrlOptions};
+use crate::intern::{InternedValue, PoolSeed, StringPool};
+use crate::limits;
+use crate::merge::{self, DirMergeOptions, MergedFlow, Origin, Provenance, TracedFlow};
+use crate::migrate::{self, Migration, MigrationError};
+use crate::path::{FlowPath, PathGlob};
+use crate::redact;
+use crate::refs::{self, FileRefResolver, RefCache, RefError, RefResolver};
+use crate::resolve::{self, ResolveContext, ResolveError};
+use crate::save::{save_file, SaveOptions};
+use crate::scheme::{self, ResolverRegistry};
+use crate::stream::{self, ArrayStream, StreamError};
+use crate::trace::{self, Operation};
+use crate::{apply_model, parse_document, resolve_file_refs, stringify_document_with, warnings_for};
+use crate::{ModelRegistry, ParseOptions, ParseWarning, ParsedFlow, StringifyOptions};
+
+// ============================================
+// Result-based API
+// ============================================
+//
+// The core load, parse and convert functions under snake_case names, each
+// returning a Result with one error type. Text is parsed strictly: a line
+// ParseFlow would skip or a repeated key is a FlowError::Parse rather than
+// silently dropped data. `parse_lenient` keeps the old behaviour for files
+// that rely on it.
+//
+// The PascalCase functions at the crate root are deprecated wrappers kept for
+// one release.
+
+pub type Result<T> = std::result::Result<T, FlowError>;
+
+#[derive(Debug)]
+pub enum FlowError {
+    Io { path: String, source: io::Error },
+    Encoding(EncodingError),
+    Parse { path: Option<String>, error: ParseError },
+    Ref(RefError),
+    Json(serde_json::Error),
+    Decode { path: String, message: String },
+    Encode(String),
+    // only an object can be written as a .flow document
+    NotAnObject,
+    Cancelled(Cancelled),
+    // a null rejected by ConvertOptions::nulls
+    Null(FlowPath),
+    Crypt(CryptError),
+    Migration(MigrationError),
+    Resolve(ResolveError),
+    // the array to stream is missing, not an array or unreadable
+    Stream { path: String, error: StreamError },
+    #[cfg(feature = "http")]
+    Http(HttpError),
+}
+
+impl fmt::Display for FlowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlowError::Io { path, source } => write!(f, "{}: {}", path, source),
+            FlowError::Encoding(e) => write!(f, "{}", e),
+            FlowError::Parse { path: Some(path), error } => write!(f, "{}: {}", path, error),
+            FlowError::Parse { path: None, error } => write!(f, "{}", error),
+            FlowError::Ref(e) => write!(f, "{}", e),
+            FlowError::Json(e) => write!(f, "invalid JSON: {}", e),
+            FlowError::Decode { path, message } => write!(f, "{}: invalid .flowb data: {}", path, message),
+            FlowError::Encode(message) => write!(f, "cannot encode .flowb data: {}", message),
+            FlowError::NotAnObject => f.write_str("only an object can be written as a .flow document"),
+            FlowError::Cancelled(e) => write!(f, "{}", e),
+            FlowError::Null(path) => write!(f, "null value at '{}'", path),
+            FlowError::Crypt(e) => write!(f, "{}", e),
+            FlowError::Migration(e) => write!(f, "{}", e),
+            FlowError::Resolve(e) => write!(f, "{}", e),
+            FlowError::Stream { path, error } => write!(f, "{}: {}", path, error),
+            #[cfg(feature = "http")]
+            FlowError::Http(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for FlowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FlowError::Io { source, .. } => Some(source),
+            FlowError::Encoding(e) => Some(e),
+            FlowError::Parse { error, .. } => Some(error),
+            FlowError::Ref(e) => Some(e),
+            FlowError::Json(e) => Some(e),
+            FlowError::Cancelled(e) => Some(e),
+            FlowError::Crypt(e) => Some(e),
+            FlowError::Migration(e) => Some(e),
+            FlowError::Resolve(e) => Some(e),
+            FlowError::Stream { error, .. } => Some(error),
+            #[cfg(feature = "http")]
+            FlowError::Http(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl FlowError {
+    // The error with the offending source line, when it points into one.
+    pub fn render(&self, source: &str) -> String {
+        match self {
+            FlowError::Parse { path: Some(path), error } => error.render_named(path, source),
+            FlowError::Parse { path: None, error } => error.render(source),
+            other => format!("error: {}\n", other),
+        }
+    }
+}
+
+impl From<EncodingError> for FlowError {
+    fn from(e: EncodingError) -> Self {
+        FlowError::Encoding(e)
+    }
+}
+
+impl From<ParseError> for FlowError {
+    fn from(error: ParseError) -> Self {
+        FlowError::Parse { path: None, error }
+    }
+}
+
+impl From<StrictError> for FlowError {
+    fn from(e: StrictError) -> Self {
+        match e {
+            StrictError::Parse(error) => FlowError::Parse { path: None, error },
+            StrictError::Cancelled(e) => FlowError::Cancelled(e),
+        }
+    }
+}
+
+impl From<Cancelled> for FlowError {
+    fn from(e: Cancelled) -> Self {
+        FlowError::Cancelled(e)
+    }
+}
+
+impl From<RefError> for FlowError {
+    fn from(e: RefError) -> Self {
+        FlowError::Ref(e)
+    }
+}
+
+impl From<CryptError> for FlowError {
+    fn from(e: CryptError) -> Self {
+        FlowError::Crypt(e)
+    }
+}
+
+impl From<MigrationError> for FlowError {
+    fn from(e: MigrationError) -> Self {
+        FlowError::Migration(e)
+    }
+}
+
+impl From<ResolveError> for FlowError {
+    fn from(e: ResolveError) -> Self {
+        FlowError::Resolve(e)
+    }
+}
+
+#[cfg(feature = "http")]
+impl From<HttpError> for FlowError {
+    fn from(e: HttpError) -> Self {
+        FlowError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for FlowError {
+    fn from(e: serde_json::Error) -> Self {
+        FlowError::Json(e)
+    }
+}
+
+impl From<FlowError> for io::Error {
+    fn from(e: FlowError) -> Self {
+        match e {
+            FlowError::Io { source, .. } => source,
+            FlowError::Encoding(e) => e.into(),
+            FlowError::Ref(e) => e.into(),
+            FlowError::Cancelled(e) => e.into(),
+            other => io::Error::new(io::ErrorKind::InvalidData, other.to_string()),
+        }
+    }
+}
+
+// ============================================
+// Parsing and Writing
+// ============================================
+
+pub fn parse(text: &str) -> Result<Value> {
+    parse_with_options(text, &ParseOptions::default())
+}
+
+// `parse` stopping early with FlowError::Cancelled once `options.cancel` is
+// cancelled or past its deadline, with `options.control` applied and
+// `options.limits` checked first.
+pub fn parse_with_options(text: &str, options: &ParseOptions) -> Result<Value> {
+    let text = control::apply(text, options.control)?;
+    limits::check(&text, &options.limits)?;
+    Ok(parse_strict(&text, options)?)
+}
+
+// Never fails: lines that cannot be read are skipped and a repeated key
+// overwrites the earlier one.
+pub fn parse_lenient(text: &str) -> Value {
+    parse_document(text)
+}
+
+pub fn parse_bytes(bytes: &[u8], options: &ParseOptions) -> Result<ParsedFlow> {
+    let (text, replaced) = encoding::decode(bytes, options.lossy)?;
+    Ok(ParsedFlow { value: parse_with_options(&text, options)?, warnings: warnings_for(replaced) })
+}
+
+pub fn parse_with_model(text: &str, registry: Option<&ModelRegistry>) -> Result<Value> {
+    Ok(apply_model(parse(text)?, registry))
+}
+
+pub fn stringify(val: &Value) -> Result<String> {
+    stringify_with_options(val, &StringifyOptions::default())
+}
+
+pub fn stringify_with_options(val: &Value, options: &StringifyOptions) -> Result<String> {
+    if !val.is_object() {
+        return Err(FlowError::NotAnObject);
+    }
+    Ok(stringify_document_with(val, options))
+}
+
+pub fn flow_to_json(text: &str) -> Result<String> {
+    flow_to_json_with_options(text, &StringifyOptions::default())
+}
+
+pub fn flow_to_json_with_options(text: &str, options: &StringifyOptions) -> Result<String> {
+    let val = redact::redact(&parse(text)?, &options.redact);
+    Ok(serde_json::to_string_pretty(&val)?)
+}
+
+pub fn json_to_flow(text: &str) -> Result<String> {
+    stringify(&serde_json::from_str(text)?)
+}
+
+pub fn flow_to_json_with(text: &str, options: &ConvertOptions) -> Result<String> {
+    let val = redact::redact(&parse(text)?, &options.stringify.redact);
+    let val = convert::prepare(&val, options, false).map_err(FlowError::Null)?;
+    Ok(convert::write_json(&val, options))
+}
+
+pub fn json_to_flow_with(text: &str, options: &ConvertOptions) -> Result<String> {
+    let val: Value = serde_json::from_str(text)?;
+    if !val.is_object() {
+        return Err(FlowError::NotAnObject);
+    }
+    let val = convert::prepare(&val, options, true).map_err(FlowError::Null)?;
+    stringify_with_options(&val, &options.stringify)
+}
+
+// ============================================
+// Files
+// ============================================
+
+pub fn load(path: &str) -> Result<Value> {
+    Ok(load_with_options(path, &ParseOptions::default())?.value)
+}
+
+pub fn load_with_options(path: &str, options: &ParseOptions) -> Result<ParsedFlow> {
+    let mut span = trace::Span::start(Operation::Load, Some(path));
+    let result = read(path, &mut span).and_then(|bytes| {
+        let mut parsed = parse_bytes(&bytes, options).map_err(|e| at_path(e, path))?;
+        parsed.value = resolve_file_refs(path, &parsed.value)?;
+        Ok(parsed)
+    });
+    span.finish_with(&result, |parsed| &parsed.value);
+    result
+}
+
+// `load_with_options`, also recording where each value came from: the file
+// and line it is written on, or for values brought in with `@ref` the file
+// and line of the node referenced.
+pub fn load_traced(path: &str, options: &ParseOptions) -> Result<TracedFlow> {
+    let mut span = trace::Span::start(Operation::Load, Some(path));
+    let result = read(path, &mut span).and_then(|bytes| {
+        let (text, doc, warnings) = parse_source(&bytes, options).map_err(|e| at_path(e, path))?;
+
+        let resolver = FileRefResolver;
+        let id = resolver.locate(path, None)?;
+        let mut sites = Vec::new();
+        let value = refs::resolve_refs_tracked(&doc, Some(&id), &resolver, &mut sites)?;
+        let mut provenance = Provenance::new();
+        provenance.index_source(&id, &text);
+        provenance.record(&FlowPath::root(), Origin::new(id));
+        merge::place_ref_sites(&mut provenance, sites, None);
+        Ok(TracedFlow { value, provenance, warnings })
+    });
+    span.finish_with(&result, |traced| &traced.value);
+    result
+}
+
+// `load`, then the model the document names applied.
+pub fn load_with_model(path: &str, registry: Option<&ModelRegistry>) -> Result<Value> {
+    Ok(apply_model(load(path)?, registry))
+}
+
+pub fn save(path: &str, val: &Value) -> Result<()> {
+    save_with_options(path, val, &SaveOptions::default())
+}
+
+pub fn save_with_options(path: &str, val: &Value, options: &SaveOptions) -> Result<()> {
+    let text = stringify(val)?;
+    write(path, val, text.as_bytes(), options)
+}
+
+// Bytes left over after the first value are an error, not ignored.
+pub fn load_flowb(path: &str) -> Result<Value> {
+    let mut span = trace::Span::start(Operation::Load, Some(path));
+    let result = read(path, &mut span).and_then(|bytes| {
+        let error = |message: String| FlowError::Decode { path: path.to_string(), message };
+        let mut de = rmp_serde::Deserializer::new(io::Cursor::new(&bytes[..]));
+        let val = Value::deserialize(&mut de).map_err(|e| error(e.to_string()))?;
+        if de.position() < bytes.len() as u64 {
+            return Err(error(format!("{} trailing bytes after the document", bytes.len() as u64 - de.position())));
+        }
+        Ok(val)
+    });
+    span.finish_with(&result, |v| v);
+    result
+}
+
+pub fn save_flowb(path: &str, val: &Value) -> Result<()> {
+    save_flowb_with_options(path, val, &SaveOptions::default())
+}
+
+pub fn save_flowb_with_options(path: &str, val: &Value, options: &SaveOptions) -> Result<()> {
+    let bytes = rmp_serde::to_vec(val).map_err(|e| FlowError::Encode(e.to_string()))?;
+    write(path, val, &bytes, options)
+}
+
+// `load_flowb` with repeated keys and strings stored once in `pool`.
+pub fn load_flowb_interned(path: &str, pool: &mut StringPool) -> Result<InternedValue> {
+    let mut span = trace::Span::start(Operation::Load, Some(path));
+    let result = read(path, &mut span).and_then(|bytes| {
+        let error = |message: String| FlowError::Decode { path: path.to_string(), message };
+        let mut de = rmp_serde::Deserializer::new(io::Cursor::new(&bytes[..]));
+        let val = PoolSeed(pool).deserialize(&mut de).map_err(|e| error(e.to_string()))?;
+        if de.position() < bytes.len() as u64 {
+            return Err(error(format!("{} trailing bytes after the document", bytes.len() as u64 - de.position())));
+        }
+        Ok(val)
+    });
+    match &result {
+        Ok(_) => span.finish(),
+        Err(e) => span.fail(e),
+    }
+    result
+}
+
+// ============================================
+// Loading with Extras
+// ============================================
+
+// `load` through `cache`: an unchanged file is read from its snapshot.
+pub fn load_cached(path: &str, cache: &FlowCache) -> Result<Value> {
+    let doc = cache.load(path).map_err(|source| FlowError::Io { path: path.to_string(), source })?;
+    Ok(resolve_file_refs(path, &doc)?)
+}
+
+pub fn load_with_model_cached(path: &str, registry: Option<&ModelRegistry>, cache: &FlowCache) -> Result<Value> {
+    Ok(apply_model(load_cached(path, cache)?, registry))
+}
+
+// An encrypted value is bound to its path in the file that holds it, so
+// `@ref` values are not resolved here.
+pub fn load_decrypted(path: &str, key: &FieldKey) -> Result<Value> {
+    let mut span = trace::Span::start(Operation::Load, Some(path));
+    let result = read(path, &mut span).and_then(|bytes| {
+        let parsed = parse_bytes(&bytes, &ParseOptions::default()).map_err(|e| at_path(e, path))?;
+        Ok(decrypt_fields(&parsed.value, key)?)
+    });
+    span.finish_with(&result, |v| v);
+    result
+}
+
+pub fn load_flowb_decrypted(path: &str, key: &FieldKey) -> Result<Value> {
+    Ok(decrypt_fields(&load_flowb(path)?, key)?)
+}
+
+pub fn load_migrated(path: &str, migration: &Migration) -> Result<Value> {
+    migrate(&load(path)?, migration)
+}
+
+pub fn load_dir_merged(dir: &str, options: &DirMergeOptions) -> Result<MergedFlow> {
+    merge::load_dir_merged(dir, options).map_err(|source| FlowError::Io { path: dir.to_string(), source })
+}
+
+#[cfg(feature = "http")]
+pub fn load_url(url: &str, options: &UrlOptions) -> Result<Value> {
+    http::load_url(url, options).map_err(|e| match e {
+        HttpError::Parse(error) => FlowError::Parse { path: Some(url.to_string()), error },
+        e => FlowError::Http(e),
+    })
+}
+
+// ============================================
+// Transforms
+// ============================================
+
+// `text` parsed strictly, with repeated keys and strings stored once in `pool`.
+pub fn parse_interned(text: &str, pool: &mut StringPool) -> Result<InternedValue> {
+    Ok(InternedValue::from_value(&parse(text)?, pool))
+}
+
+// A copy of `val` with every node matching one of `globs` replaced by
+// "***". A matching object or array is replaced as a whole.
+pub fn redact(val: &Value, globs: &[PathGlob]) -> Value {
+    redact::redact(val, globs)
+}
+
+// Replaces the `@ref` values in `doc`, read from `origin`, through
+// `resolver`; documents read along the way are kept in `cache`.
+pub fn resolve_refs(doc: &Value, origin: Option<&str>, resolver: &dyn RefResolver, cache: &mut RefCache) -> Result<Value> {
+    Ok(refs::resolve_refs(doc, origin, resolver, cache)?)
+}
+
+pub fn load_with_resolver(location: &str, resolver: &dyn RefResolver, cache: &mut RefCache) -> Result<Value> {
+    Ok(refs::load_with_resolver(location, resolver, cache)?)
+}
+
+// The resolve pass: variables, `when`/`if` and `for` blocks, secrets and
+// registered schemes.
+pub fn resolve(doc: &Value, ctx: &ResolveContext) -> Result<Value> {
+    Ok(resolve::resolve(doc, ctx)?)
+}
+
+pub fn resolve_schemes(doc: &Value, registry: &ResolverRegistry) -> Result<Value> {
+    Ok(scheme::resolve_schemes(doc, registry)?)
+}
+
+pub fn migrate(doc: &Value, migration: &Migration) -> Result<Value> {
+    Ok(migrate::migrate(doc, migration)?)
+}
+
+// ============================================
+// Streaming
+// ============================================
+//
+// The elements of the array at `array` one at a time, without loading the
+// rest of the document (see StreamFlowArray). The elements themselves are
+// still yielded as Result<Value, StreamError>.
+
+pub fn stream_array(path: &str, array: &FlowPath, options: &ParseOptions) -> Result<ArrayStream> {
+    stream::stream_array(path, array, options).map_err(|e| stream_error(path, e))
+}
+
+pub fn stream_flowb_array(path: &str, array: &FlowPath, options: &ParseOptions) -> Result<ArrayStream> {
+    stream::stream_flowb_array(path, array, options).map_err(|e| stream_error(path, e))
+}
+
+fn stream_error(path: &str, error: StreamError) -> FlowError {
+    match error {
+        // an undecodable file comes back as an io::Error carrying the EncodingError
+        StreamError::Io(source) => match source.get_ref().and_then(|e| e.downcast_ref::<EncodingError>()) {
+            Some(e) => FlowError::Encoding(e.clone()),
+            None => FlowError::Io { path: path.to_string(), source },
+        },
+        StreamError::Cancelled(e) => FlowError::Cancelled(e),
+        error => FlowError::Stream { path: path.to_string(), error },
+    }
+}
+
+// As `parse_bytes`, also returning the text the document was read from.
+fn parse_source(bytes: &[u8], options: &ParseOptions) -> Result<(String, Value, Vec<ParseWarning>)> {
+    let (text, replaced) = encoding::decode(bytes, options.lossy)?;
+    let text = control::apply(&text, options.control)?.into_owned();
+    limits::check(&text, &options.limits)?;
+    let value = parse_strict(&text, options)?;
+    Ok((text, value, warnings_for(replaced)))
+}
+
+fn read(path: &str, span: &mut trace::Span) -> Result<Vec<u8>> {
+    let bytes = fs::read(path).map_err(|source| FlowError::Io { path: path.to_string(), source })?;
+    span.bytes(bytes.len());
+    Ok(bytes)
+}
+
+fn write(path: &str, val: &Value, data: &[u8], options: &SaveOptions) -> Result<()> {
+    save_file(path, val, data, options).map_err(|source| FlowError::Io { path: path.to_string(), source })
+}
+
+pub(crate) fn at_path(e: FlowError, at: &str) -> FlowError {
+    match e {
+        FlowError::Encoding(mut e) => {
+            e.path = Some(at.to_string());
+            FlowError::Encoding(e)
+        }
+        FlowError::Parse { error, .. } => FlowError::Parse { path: Some(at.to_string()), error },
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypt::encrypt_fields;
+    use crate::path::PathGlob;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flowdoc-v2-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn load_with_model_resolves_refs() {
+        let dir = scratch_dir("model-refs");
+        fs::write(dir.join("shared.flow"), "db:\n  port = 5432\n").unwrap();
+        fs::write(dir.join("app.flow"), "name = app\ndb = @ref(\"shared.flow#db\")\n").unwrap();
+        let app = dir.join("app.flow").display().to_string();
+        let expected = json!({"name": "app", "db": {"port": 5432}});
+        assert_eq!(load(&app).unwrap(), expected);
+        assert_eq!(load_with_model(&app, None).unwrap(), expected);
+    }
+
+    #[test]
+    fn save_and_load_with_extras() {
+        let dir = scratch_dir("extras");
+        let doc = json!({"db": {"password": "hunter2", "port": 5432}});
+        let key = FieldKey::from_bytes([9; 32]);
+        let encrypted = encrypt_fields(&doc, &[PathGlob::parse("**.password").unwrap()], &key);
+
+        let text = dir.join("secret.flow").display().to_string();
+        save_with_options(&text, &encrypted, &SaveOptions::new().atomic(true).backup(true)).unwrap();
+        assert_eq!(load_decrypted(&text, &key).unwrap(), doc);
+        assert!(matches!(load_decrypted(&text, &FieldKey::from_bytes([1; 32])), Err(FlowError::Crypt(_))));
+
+        let binary = dir.join("secret.flowb").display().to_string();
+        save_flowb_with_options(&binary, &encrypted, &SaveOptions::new().atomic(true)).unwrap();
+        assert_eq!(load_flowb_decrypted(&binary, &key).unwrap(), doc);
+        let mut pool = StringPool::new();
+        assert_eq!(load_flowb_interned(&binary, &mut pool).unwrap().to_value(), encrypted);
+
+        let migration = Migration::new().rename("db.port", "listen_port").unwrap();
+        assert_eq!(load_migrated(&text, &migration).unwrap()["db"]["listen_port"], json!(5432));
+    }
+
+    #[test]
+    fn missing_files_name_the_path() {
+        let missing = scratch_dir("missing").join("nope.flow").display().to_string();
+        for err in [load_decrypted(&missing, &FieldKey::from_bytes([0; 32])).unwrap_err(), load_migrated(&missing, &Migration::new()).unwrap_err()] {
+            assert!(matches!(&err, FlowError::Io { path, .. } if *path == missing), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn stream_arrays() {
+        let dir = scratch_dir("stream");
+        let doc = json!({"batch": {"rows": [1, "two, three", true], "size": 3}});
+        let flow = dir.join("doc.flow").display().to_string();
+        let flowb = dir.join("doc.flowb").display().to_string();
+        save(&flow, &doc).unwrap();
+        save_flowb(&flowb, &doc).unwrap();
+        let rows = FlowPath::parse("batch.rows").unwrap();
+        let options = ParseOptions::default();
+        for stream in [stream_array(&flow, &rows, &options), stream_flowb_array(&flowb, &rows, &options)] {
+            let items: Vec<Value> = stream.unwrap().map(|v| v.unwrap()).collect();
+            assert_eq!(Value::Array(items), doc["batch"]["rows"]);
+        }
+
+        let size = FlowPath::parse("batch.size").unwrap();
+        let missing = FlowPath::parse("batch.cols").unwrap();
+        assert!(matches!(stream_array(&flow, &size, &options), Err(FlowError::Stream { error: StreamError::NotArray(_), .. })));
+        assert!(matches!(stream_flowb_array(&flowb, &missing, &options), Err(FlowError::Stream { error: StreamError::NotFound(_), .. })));
+        let absent = dir.join("absent.flow").display().to_string();
+        assert!(matches!(stream_array(&absent, &rows, &options), Err(FlowError::Io { path, .. }) if path == absent));
+
+        fs::write(&flow, b"batch:\n  rows = [\xff]\n").unwrap();
+        assert!(matches!(stream_array(&flow, &rows, &options), Err(FlowError::Stream { error: StreamError::Invalid(_), .. })));
+    }
+
+    #[test]
+    fn transforms() {
+        let doc = json!({"db": {"password": "hunter2", "port": 5432}, "env": "${stage}"});
+        assert_eq!(redact(&doc, &[PathGlob::parse("db.password").unwrap()])["db"]["password"], json!("***"));
+        let ctx = ResolveContext::new().with_var("stage", json!("prod"));
+        assert_eq!(resolve(&doc, &ctx).unwrap()["env"], json!("prod"));
+        let registry = ResolverRegistry::new().with("const", |r: &str| -> std::result::Result<Value, Box<dyn std::error::Error + Send + Sync>> { Ok(json!(r.len())) });
+        assert_eq!(resolve_schemes(&json!({"n": "const://abc"}), &registry).unwrap(), json!({"n": 3}));
+        let migration = Migration::new().rename("db.port", "listen_port").unwrap();
+        assert_eq!(migrate(&doc, &migration).unwrap()["db"]["listen_port"], json!(5432));
+
+        let mut pool = StringPool::new();
+        assert_eq!(parse_interned("a = x\nb = x\n", &mut pool).unwrap().to_value(), json!({"a": "x", "b": "x"}));
+        assert!(matches!(parse_interned("a = 1\na = 2\n", &mut pool), Err(FlowError::Parse { .. })));
+    }
+
+    #[test]
+    fn resolver_loading() {
+        let dir = scratch_dir("resolver");
+        fs::write(dir.join("shared.flow"), "port = 5432\n").unwrap();
+        fs::write(dir.join("app.flow"), "port = @ref(\"shared.flow#port\")\n").unwrap();
+        let app = dir.join("app.flow").display().to_string();
+        let mut cache = RefCache::new();
+        assert_eq!(load_with_resolver(&app, &crate::FileRefResolver, &mut cache).unwrap(), json!({"port": 5432}));
+        let doc = json!({"port": "@ref(\"missing.flow#port\")"});
+        assert!(matches!(resolve_refs(&doc, Some(&app), &crate::FileRefResolver, &mut cache), Err(FlowError::Ref(_))));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn deprecated_model_loaders_match_v2() {
+        let dir = scratch_dir("model-wrappers");
+        fs::write(dir.join("shared.flow"), "port = 5432\n").unwrap();
+        fs::write(dir.join("app.flow"), "port = @ref(\"shared.flow#port\")\n").unwrap();
+        let app = dir.join("app.flow").display().to_string();
+        let cache = FlowCache::new(dir.join("cache")).unwrap();
+        let expected = json!({"port": 5432});
+        assert_eq!(crate::LoadFlowWithModel(&app, None).unwrap(), expected);
+        assert_eq!(crate::LoadFlowWithModelCached(&app, None, &cache).unwrap(), expected);
+        assert_eq!(load_with_model_cached(&app, None, &cache).unwrap(), expected);
+    }
+}
@@ -0,0 +1,303 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::diagnostic::{render_snippet, source_spans, NodeSpan, Span};
+use crate::fix::{Fix, FixKind, TextEdit};
+use crate::path::FlowPath;
+use crate::stringify::scalar;
+use crate::template::type_matches;
+use crate::trace::{self, Operation};
+use crate::{parse_document, FieldDefinition, ModelDefinition, ModelRegistry};
+
+// ============================================
+// Model Validation
+// ============================================
+//
+// Checks a document against the model named by its `use_model`. An object
+// is taken to be an instance of the model when one of its keys is a field
+// name or alias; its other keys are reported as unknown, values that do
+// not fit their field's type or choices as mismatches, and required fields
+// it lacks as missing. Keys starting with `$` are directives and left alone.
+// Where the intent is clear the error carries a Fix (see fix.rs).
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub path: FlowPath,
+    // where the offending key or value is written, when it is known
+    pub span: Option<Span>,
+    pub message: String,
+    pub fix: Option<Fix>,
+}
+
+impl ValidationError {
+    pub fn render(&self, source: &str) -> String {
+        render_snippet(None, source, self.span, &self.message, self.help().as_deref())
+    }
+
+    pub fn render_named(&self, name: &str, source: &str) -> String {
+        render_snippet(Some(name), source, self.span, &self.message, self.help().as_deref())
+    }
+
+    // without a span the path is the only pointer into the document
+    fn help(&self) -> Option<String> {
+        match self.span {
+            Some(_) => None,
+            None if self.path.is_root() => None,
+            None => Some(format!("at {}", self.path)),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "line {}, column {}: {}", span.line, span.column, self.message),
+            None if self.path.is_root() => f.write_str(&self.message),
+            None => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+pub fn validate(source: &str, registry: &ModelRegistry) -> Vec<ValidationError> {
+    let mut span = trace::Span::start(Operation::Validate, None);
+    span.bytes(source.len());
+    let doc = parse_document(source);
+    span.document(&doc);
+    let errors = check_document(&doc, source, registry);
+    match errors.len() {
+        0 => span.finish(),
+        1 => span.fail(&errors[0]),
+        n => span.fail(&format!("{} validation errors", n)),
+    }
+    errors
+}
+
+fn check_document(doc: &Value, source: &str, registry: &ModelRegistry) -> Vec<ValidationError> {
+    let spans = source_spans(source);
+    let Some(name) = doc.get("use_model").and_then(Value::as_str) else {
+        return Vec::new();
+    };
+    let use_model = FlowPath::root().child_key("use_model");
+    let Some(model) = registry.get_model(name) else {
+        let span = spans.get(&use_model).map(|s| s.value.unwrap_or(s.key));
+        return vec![ValidationError { path: use_model, span, message: format!("unknown model '{}'", name), fix: None }];
+    };
+
+    let mut checker = Checker { model, spans, lines: source.split('\n').collect(), errors: Vec::new() };
+    checker.check(doc, &mut FlowPath::root());
+    let mut errors = checker.errors;
+    errors.sort_by_key(|e| (e.span.is_none(), e.span));
+    errors
+}
+
+pub(crate) fn model_field<'a>(model: &'a ModelDefinition, key: &str) -> Option<&'a FieldDefinition> {
+    model.fields.get(key).or_else(|| model.alias_map.get(key).and_then(|name| model.fields.get(name)))
+}
+
+struct Checker<'a> {
+    model: &'a ModelDefinition,
+    spans: BTreeMap<FlowPath, NodeSpan>,
+    lines: Vec<&'a str>,
+    errors: Vec<ValidationError>,
+}
+
+impl Checker<'_> {
+    fn check(&mut self, val: &Value, path: &mut FlowPath) {
+        match val {
+            Value::Object(map) => {
+                let instance = map.keys().any(|k| model_field(self.model, k).is_some());
+                // names already suggested for other keys of this object
+                let mut claimed = Vec::new();
+                for (k, v) in map {
+                    if k.starts_with('$') {
+                        continue;
+                    }
+                    path.push_key(k);
+                    match model_field(self.model, k) {
+                        Some(field) if !type_matches(&field.field_type, v) => self.mismatch(path, field, v),
+                        Some(field) if !field.choices.is_empty() && !field.choices.contains(v) => self.not_a_choice(path, field),
+                        None if instance && !(path.len() == 1 && k == "use_model") => self.unknown(path, k, map, &mut claimed),
+                        _ => {}
+                    }
+                    self.check(v, path);
+                    path.pop();
+                }
+                if instance {
+                    self.missing(path, map);
+                }
+            }
+            Value::Array(items) => {
+                for (i, v) in items.iter().enumerate() {
+                    path.push_index(i);
+                    self.check(v, path);
+                    path.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn mismatch(&mut self, path: &FlowPath, field: &FieldDefinition, val: &Value) {
+        let span = self.spans.get(path).map(|s| s.value.unwrap_or(s.key));
+        let fix = coerce(val, &field.field_type, span.map(|s| self.text(s))).map(|value| Fix {
+            path: path.clone(),
+            edit: span.map(|span| TextEdit { span, text: scalar(&value) }),
+            kind: FixKind::ReplaceValue { value },
+        });
+        self.errors.push(ValidationError {
+            path: path.clone(),
+            span,
+            message: format!("field '{}' expects {}, found {}", field.full_name, field.field_type, kind_of(val)),
+            fix,
+        });
+    }
+
+    fn not_a_choice(&mut self, path: &FlowPath, field: &FieldDefinition) {
+        let choices: Vec<String> = field.choices.iter().map(scalar).collect();
+        self.errors.push(ValidationError {
+            path: path.clone(),
+            span: self.spans.get(path).map(|s| s.value.unwrap_or(s.key)),
+            message: format!("field '{}' must be one of {}", field.full_name, choices.join(", ")),
+            fix: None,
+        });
+    }
+
+    fn unknown(&mut self, path: &FlowPath, key: &str, siblings: &Map<String, Value>, claimed: &mut Vec<String>) {
+        let span = self.spans.get(path).map(|s| s.key);
+        let taken = |name: &str| siblings.contains_key(name) || claimed.iter().any(|c| c == name);
+        let nearest = nearest_field(self.model, key, taken);
+        claimed.extend(nearest.clone());
+        let fix = nearest.map(|to| Fix {
+            path: path.clone(),
+            edit: span.map(|span| TextEdit { span, text: to.clone() }),
+            kind: FixKind::RenameKey { to },
+        });
+        let message = match &fix {
+            Some(Fix { kind: FixKind::RenameKey { to }, .. }) => format!("unknown field '{}' for model '{}', did you mean '{}'?", key, self.model.name, to),
+            _ => format!("unknown field '{}' for model '{}'", key, self.model.name),
+        };
+        self.errors.push(ValidationError { path: path.clone(), span, message, fix });
+    }
+
+    fn missing(&mut self, path: &FlowPath, map: &Map<String, Value>) {
+        let mut fields: Vec<&FieldDefinition> = self.model.fields.values().filter(|f| f.required).collect();
+        fields.sort_by(|a, b| a.full_name.cmp(&b.full_name));
+        // new keys follow the object's spelling: aliases if it uses any
+        let aliased = map.keys().any(|k| self.model.alias_map.get(k).is_some_and(|name| name != k));
+        for field in fields {
+            if map.contains_key(&field.full_name) || (!field.alias.is_empty() && map.contains_key(&field.alias)) {
+                continue;
+            }
+            let key = if aliased && !field.alias.is_empty() { &field.alias } else { &field.full_name };
+            let fix = field.default.clone().map(|value| Fix {
+                path: path.clone(),
+                edit: self.insert_edit(path, key, &value),
+                kind: FixKind::InsertField { key: key.clone(), value },
+            });
+            self.errors.push(ValidationError {
+                path: path.clone(),
+                span: self.spans.get(path).map(|s| s.key),
+                message: format!("missing required field '{}'", field.full_name),
+                fix,
+            });
+        }
+    }
+
+    // A new line after the object's last line, indented like its entries.
+    fn insert_edit(&self, path: &FlowPath, key: &str, value: &Value) -> Option<TextEdit> {
+        let entries = self.spans.iter().filter(|(p, _)| p.starts_with(path) && *p != path);
+        let first = entries.clone().find(|(p, _)| p.len() == path.len() + 1)?.1.key;
+        let last = entries.map(|(_, s)| s.key.line).max()?;
+        let indent: String = self.lines.get(first.line - 1)?.chars().take(first.column - 1).collect();
+        Some(TextEdit { span: Span { line: last + 1, column: 1, len: 0 }, text: format!("{}{} = {}\n", indent, key, scalar(value)) })
+    }
+
+    fn text(&self, span: Span) -> &str {
+        let line = self.lines.get(span.line - 1).copied().unwrap_or("");
+        let start = line.char_indices().nth(span.column - 1).map(|(i, _)| i).unwrap_or(line.len());
+        let end = line[start..].char_indices().nth(span.len).map(|(i, _)| start + i).unwrap_or(line.len());
+        &line[start..end]
+    }
+}
+
+// The value in `field_type`, when the conversion loses nothing. A string
+// field takes the source text of a number or bool as it was written.
+pub(crate) fn coerce(val: &Value, field_type: &str, text: Option<&str>) -> Option<Value> {
+    let s = val.as_str().map(str::trim);
+    let converted = match field_type {
+        "int" => s.and_then(|s| s.parse::<i64>().ok()).map(Value::from),
+        "float" => s.and_then(|s| s.parse::<f64>().ok()).and_then(|f| serde_json::Number::from_f64(f).map(Value::Number)),
+        "bool" => s.and_then(|s| match s.to_ascii_lowercase().as_str() {
+            "true" => Some(Value::Bool(true)),
+            "false" => Some(Value::Bool(false)),
+            _ => None,
+        }),
+        "string" if val.is_number() || val.is_boolean() => Some(Value::String(text.map(str::to_string).unwrap_or_else(|| val.to_string()))),
+        _ => None,
+    };
+    converted.filter(|v| type_matches(field_type, v))
+}
+
+// The closest field name or alias of a field the object does not have yet,
+// if it is within a third of the key's length in edits. Keys shorter than
+// three characters get no suggestion.
+fn nearest_field(model: &ModelDefinition, key: &str, taken: impl Fn(&str) -> bool) -> Option<String> {
+    let limit = key.chars().count() / 3;
+    let mut best: Option<(usize, &str)> = None;
+    let mut fields: Vec<&FieldDefinition> = model.fields.values().collect();
+    fields.sort_by(|a, b| a.full_name.cmp(&b.full_name));
+    for field in fields {
+        if taken(&field.full_name) || (!field.alias.is_empty() && taken(&field.alias)) {
+            continue;
+        }
+        for name in [&field.full_name, &field.alias] {
+            if name.is_empty() {
+                continue;
+            }
+            let d = distance(key, name);
+            if d > 0 && d <= limit && best.is_none_or(|(b, _)| d < b) {
+                best = Some((d, name));
+            }
+        }
+    }
+    best.map(|(_, name)| name.to_string())
+}
+
+// Edit distance over chars where swapping two neighbours counts as one edit,
+// so `hots` is one away from `host`.
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+fn kind_of(val: &Value) -> String {
+    match val {
+        Value::Null => "null".to_string(),
+        Value::Bool(_) => "bool".to_string(),
+        Value::Number(n) if n.is_f64() => format!("float {}", n),
+        Value::Number(n) => format!("int {}", n),
+        Value::String(s) => format!("string {:?}", s),
+        Value::Array(_) => "array".to_string(),
+        Value::Object(_) => "object".to_string(),
+    }
+}
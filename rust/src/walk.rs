@@ -0,0 +1,230 @@
+use serde_json::Value;
+
+use crate::path::FlowPath;
+
+// ============================================
+// Walking
+// ============================================
+//
+// `walk` and `walk_mut` call the visitor for every node, the root included,
+// in document order and before the node's children. The visitor's return
+// value decides whether to descend into the node, skip its children or end
+// the walk. `walk_mut` descends into the node as the visitor left it, so
+// children added or renamed by the visitor are visited too.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Walk {
+    Continue,
+    SkipChildren,
+    Stop,
+}
+
+// Returns false if the visitor stopped the walk.
+pub fn walk(val: &Value, mut visitor: impl FnMut(&FlowPath, &Value) -> Walk) -> bool {
+    walk_at(val, &mut FlowPath::root(), &mut visitor)
+}
+
+pub fn walk_mut(val: &mut Value, mut visitor: impl FnMut(&FlowPath, &mut Value) -> Walk) -> bool {
+    walk_mut_at(val, &mut FlowPath::root(), &mut visitor)
+}
+
+fn walk_at(val: &Value, path: &mut FlowPath, visitor: &mut impl FnMut(&FlowPath, &Value) -> Walk) -> bool {
+    match visitor(path, val) {
+        Walk::Stop => return false,
+        Walk::SkipChildren => return true,
+        Walk::Continue => {}
+    }
+    match val {
+        Value::Object(map) => {
+            for (k, v) in map {
+                path.push_key(k);
+                let go_on = walk_at(v, path, visitor);
+                path.pop();
+                if !go_on {
+                    return false;
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (i, v) in items.iter().enumerate() {
+                path.push_index(i);
+                let go_on = walk_at(v, path, visitor);
+                path.pop();
+                if !go_on {
+                    return false;
+                }
+            }
+        }
+        _ => {}
+    }
+    true
+}
+
+fn walk_mut_at(val: &mut Value, path: &mut FlowPath, visitor: &mut impl FnMut(&FlowPath, &mut Value) -> Walk) -> bool {
+    match visitor(path, val) {
+        Walk::Stop => return false,
+        Walk::SkipChildren => return true,
+        Walk::Continue => {}
+    }
+    match val {
+        Value::Object(map) => {
+            for (k, v) in map.iter_mut() {
+                path.push_key(k);
+                let go_on = walk_mut_at(v, path, visitor);
+                path.pop();
+                if !go_on {
+                    return false;
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (i, v) in items.iter_mut().enumerate() {
+                path.push_index(i);
+                let go_on = walk_mut_at(v, path, visitor);
+                path.pop();
+                if !go_on {
+                    return false;
+                }
+            }
+        }
+        _ => {}
+    }
+    true
+}
+
+// ============================================
+// Iterators
+// ============================================
+
+// Every node below the root, in the same order as `walk`.
+pub fn iter_paths(val: &Value) -> NodeIter<'_> {
+    NodeIter::new(val, false)
+}
+
+// Nodes without children: scalars and empty objects and arrays.
+pub fn iter_leaves(val: &Value) -> NodeIter<'_> {
+    NodeIter::new(val, true)
+}
+
+pub struct NodeIter<'a> {
+    // pending nodes, the next one last
+    stack: Vec<(FlowPath, &'a Value)>,
+    leaves_only: bool,
+}
+
+impl<'a> NodeIter<'a> {
+    fn new(root: &'a Value, leaves_only: bool) -> Self {
+        let mut iter = NodeIter { stack: Vec::new(), leaves_only };
+        iter.push_children(&FlowPath::root(), root);
+        iter
+    }
+
+    fn push_children(&mut self, path: &FlowPath, val: &'a Value) {
+        match val {
+            Value::Object(map) => {
+                for (k, v) in map.iter().rev() {
+                    self.stack.push((path.child_key(k), v));
+                }
+            }
+            Value::Array(items) => {
+                for (i, v) in items.iter().enumerate().rev() {
+                    self.stack.push((path.child_index(i), v));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a> Iterator for NodeIter<'a> {
+    type Item = (FlowPath, &'a Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, val)) = self.stack.pop() {
+            self.push_children(&path, val);
+            let is_leaf = match val {
+                Value::Object(m) => m.is_empty(),
+                Value::Array(a) => a.is_empty(),
+                _ => true,
+            };
+            if is_leaf || !self.leaves_only {
+                return Some((path, val));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn doc() -> Value {
+        json!({"a": {"b": 1, "c": [true, {"d": null}]}, "e": "x"})
+    }
+
+    #[test]
+    fn every_node_before_its_children() {
+        let mut seen = Vec::new();
+        assert!(walk(&doc(), |path, _| {
+            seen.push(path.to_string());
+            Walk::Continue
+        }));
+        assert_eq!(seen, ["", "a", "a.b", "a.c", "a.c[0]", "a.c[1]", "a.c[1].d", "e"]);
+    }
+
+    #[test]
+    fn skip_and_stop() {
+        let mut seen = Vec::new();
+        assert!(walk(&doc(), |path, _| {
+            seen.push(path.to_string());
+            if path.to_string() == "a.c" { Walk::SkipChildren } else { Walk::Continue }
+        }));
+        assert_eq!(seen, ["", "a", "a.b", "a.c", "e"]);
+
+        seen.clear();
+        assert!(!walk(&doc(), |path, _| {
+            seen.push(path.to_string());
+            if path.to_string() == "a.c[0]" { Walk::Stop } else { Walk::Continue }
+        }));
+        assert_eq!(seen, ["", "a", "a.b", "a.c", "a.c[0]"]);
+    }
+
+    #[test]
+    fn mutations_are_descended_into() {
+        let mut val = json!({"Server": {"Host": "h"}, "list": [1, 2]});
+        assert!(walk_mut(&mut val, |_, node| {
+            match node {
+                Value::Object(map) => *map = std::mem::take(map).into_iter().map(|(k, v)| (k.to_lowercase(), v)).collect(),
+                Value::Number(n) => *node = json!(n.as_i64().unwrap_or(0) * 10),
+                Value::Array(items) => items.push(json!(3)),
+                _ => {}
+            }
+            Walk::Continue
+        }));
+        assert_eq!(val, json!({"server": {"host": "h"}, "list": [10, 20, 30]}));
+
+        let mut count = 0;
+        assert!(!walk_mut(&mut val, |_, _| {
+            count += 1;
+            if count == 2 { Walk::Stop } else { Walk::Continue }
+        }));
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn paths_and_leaves() {
+        let val = doc();
+        let paths: Vec<String> = iter_paths(&val).map(|(p, _)| p.to_string()).collect();
+        assert_eq!(paths, ["a", "a.b", "a.c", "a.c[0]", "a.c[1]", "a.c[1].d", "e"]);
+        let leaves: Vec<(String, &Value)> = iter_leaves(&val).map(|(p, v)| (p.to_string(), v)).collect();
+        assert_eq!(leaves, [("a.b".to_string(), &json!(1)), ("a.c[0]".to_string(), &json!(true)), ("a.c[1].d".to_string(), &json!(null)), ("e".to_string(), &json!("x"))]);
+
+        let empty = json!({"o": {}, "l": [], "n": {"m": []}});
+        let leaves: Vec<String> = iter_leaves(&empty).map(|(p, _)| p.to_string()).collect();
+        assert_eq!(leaves, ["l", "n.m", "o"]);
+        assert_eq!(iter_paths(&json!(5)).count(), 0);
+        assert_eq!(iter_leaves(&json!({})).count(), 0);
+    }
+}